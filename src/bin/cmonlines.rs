@@ -1,6 +1,247 @@
-use std::env;
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Angular reconstitution of a stack of projections by common lines.
+//!
+
+use std::path::Path;
+
+use rim::cryoem::cli::{self, parse_number, round};
+use rim::cryoem::common_lines::{relative_error, CommonLines};
+use rim::cryoem::euler::{read_angles, Convention, Euler};
+use rim::grayscale::Gray32;
+use rim::image_stack::ImageStack;
+use rim::io::image_reader::extension;
+use rim::io::mrc_decoder::MrcDecoder;
+use rim::io::star::*;
+use rim::io::star_writer::StarWriter;
+use rim::io::text_reader::TextReader;
+use rim::io::text_writer::TextWriter;
+use rim::io::tiff_decoder::TiffDecoder;
+use rim::results_table::{Cell, ResultsTable};
+use rim::vecmath::matrix3::Matrix3;
+
+fn help() {
+    println!(
+        "usage:
+cmonlines -i <projections> -o <angles> [options]
+
+  -i, --input <file>        stack of projections (.mrc, .mrcs or .tif)
+  -o, --output <file>       estimated Euler angles (.csv or .star)
+  -s, --step <degrees>      angular step of the sinograms (default: 2)
+  -c, --convention <name>   Euler convention of the CSV output: relion, spider, frealign,
+                            cistem, eman or yxz (default: relion)
+  -r, --reference <file>    known angles (.csv with psi, theta, phi columns) to compare with
+      --ref-convention <n>  Euler convention of the reference angles (default: relion)
+      --flip-y              the reference angles are defined with the y-axis pointing up
+                            (samples/psi-theta-phi-50.csv with --ref-convention relion)
+  -n, --iterations <n>      sweeps of the least-squares refinement (default: 50)
+  -h, --help                prints this help
+
+The orientations are relative to the first projection and defined up to the handedness."
+    );
+}
+
+struct Options {
+    input: String,
+    output: String,
+    step: f32,
+    convention: Convention,
+    reference: Option<String>,
+    ref_convention: Convention,
+    flip_y: bool,
+    iterations: usize,
+}
+
+fn parse_convention(value: &str) -> Result<Convention, String> {
+    match &value.to_lowercase()[..] {
+        "relion" => Ok(Convention::Relion),
+        "spider" => Ok(Convention::Spider),
+        "frealign" => Ok(Convention::Frealign),
+        "cistem" => Ok(Convention::CisTem),
+        "eman" => Ok(Convention::Eman),
+        "yxz" => Ok(Convention::Yxz),
+        _ => Err(format!("Unknown Euler convention '{}'", value)),
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        input: String::new(),
+        output: String::new(),
+        step: 2.0,
+        convention: Convention::Relion,
+        reference: None,
+        ref_convention: Convention::Relion,
+        flip_y: false,
+        iterations: 50,
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i][..];
+        if arg == "--flip-y" {
+            options.flip_y = true;
+            i += 1;
+            continue;
+        }
+        let val = args
+            .get(i + 1)
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg {
+            "-i" | "--input" => options.input = val.to_string(),
+            "-o" | "--output" => options.output = val.to_string(),
+            "-s" | "--step" => options.step = parse_number(arg, val)?,
+            "-c" | "--convention" => options.convention = parse_convention(val)?,
+            "-r" | "--reference" => options.reference = Some(val.to_string()),
+            "--ref-convention" => options.ref_convention = parse_convention(val)?,
+            "-n" | "--iterations" => options.iterations = parse_number(arg, val)?,
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+        i += 2;
+    }
+    if options.input.is_empty() || options.output.is_empty() {
+        return Err(String::from("The options -i and -o are required"));
+    }
+    if !(options.step > 0.0 && options.step <= 45.0) {
+        return Err(format!(
+            "Invalid angular step {}, expected a value in ]0, 45]",
+            options.step
+        ));
+    }
+    Ok(options)
+}
+
+fn read_projections(filename: &str) -> Result<ImageStack<f32, Gray32>, String> {
+    if !Path::new(filename).is_file() {
+        return Err(format!("File not found: {}", filename));
+    }
+    let error = |e: std::io::Error| format!("Unable to read {}: {}", filename, e);
+    let (image, _) = match &extension(filename)[..] {
+        "mrc" | "mrcs" => MrcDecoder::open(filename).map_err(error)?,
+        "tif" | "tiff" => TiffDecoder::open(filename).map_err(error)?,
+        ext => return Err(format!("Unsupported file format '.{}'", ext)),
+    };
+    image.into_float_stack()
+}
+
+// RELION particles block: _rlnAngleRot (phi), _rlnAngleTilt (theta), _rlnAnglePsi (psi)
+fn to_star(angles: &[Euler]) -> DataSet {
+    let mut table = Table::new(String::new());
+    for head in ["rlnImageName", "rlnAngleRot", "rlnAngleTilt", "rlnAnglePsi"] {
+        table.add_column_head(head.to_string());
+    }
+    for (i, e) in angles.iter().enumerate() {
+        let mut row = Row::new(i);
+        row.push(Field::Text(format!("{}@projections", i + 1)));
+        row.push(Field::Number(round(e.phi)));
+        row.push(Field::Number(round(e.theta)));
+        row.push(Field::Number(round(e.psi)));
+        table.add_row(row);
+    }
+    let mut block = DataSet::new(String::from("particles"));
+    block.push(Container::Table(table));
+    block
+}
+
+fn to_table(angles: &[Euler]) -> ResultsTable {
+    let mut table = ResultsTable::new(String::from("Angles"));
+    for e in angles.iter() {
+        table.add_row();
+        table.add_value(&String::from("psi"), Cell::Number(round(e.psi)));
+        table.add_value(&String::from("theta"), Cell::Number(round(e.theta)));
+        table.add_value(&String::from("phi"), Cell::Number(round(e.phi)));
+    }
+    table
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let projections = read_projections(&options.input)?;
+    let n = projections.n_slices() as usize;
+    if n < 3 {
+        return Err(format!(
+            "At least 3 projections are required, {} found in {}",
+            n, options.input
+        ));
+    }
+    println!(
+        "Projections: {}x{}x{}",
+        projections.width, projections.height, n
+    );
+    let mut common_lines = CommonLines::new(options.step);
+    common_lines.set_iterations(options.iterations);
+    let lines = common_lines.find(&projections);
+    let mean = lines.iter().map(|l| l.score).sum::<f64>() / lines.len() as f64;
+    println!(
+        "Common lines: {} pairs, mean score {:.3}",
+        lines.len(),
+        mean
+    );
+    let rotations = common_lines.orientations(&lines, n);
+
+    if let Some(reference) = &options.reference {
+        let table = TextReader::open_csv(reference, None)
+            .map_err(|e| format!("Unable to read {}: {}", reference, e))?;
+        // Rows of the images from bottom to top: rotation of 180 degrees around x
+        let flip = if options.flip_y {
+            Matrix3::from_array([1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, -1.0])
+        } else {
+            Matrix3::identity()
+        };
+        let known: Vec<_> = read_angles(&table)?
+            .iter()
+            .map(|e| flip.build() * e.to_matrix(options.ref_convention))
+            .collect();
+        if known.len() != n {
+            return Err(format!(
+                "{} angles in {} for {} projections",
+                known.len(),
+                reference,
+                n
+            ));
+        }
+        println!(
+            "Mean error of the relative orientations: {:.2} degrees",
+            relative_error(&rotations, &known)
+        );
+    }
+
+    let filename = &options.output[..];
+    let error = |e: std::io::Error| format!("Unable to write {}: {}", filename, e);
+    match &extension(filename)[..] {
+        "star" => {
+            let angles: Vec<Euler> = rotations
+                .iter()
+                .map(|a| Euler::from_matrix(a, Convention::Relion))
+                .collect();
+            StarWriter::save(&[to_star(&angles)], filename).map_err(error)
+        }
+        _ => {
+            let angles: Vec<Euler> = rotations
+                .iter()
+                .map(|a| Euler::from_matrix(a, options.convention))
+                .collect();
+            TextWriter::save_csv(&to_table(&angles), filename, None).map_err(error)
+        }
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    dbg!(args);
+    cli::main("cmonlines", help, parse_args, run);
 }
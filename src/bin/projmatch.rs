@@ -1,6 +1,197 @@
-use std::env;
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Projection matching of particle images against a reference volume.
+//!
+
+use std::path::Path;
+
+use rim::color_space::ColorSpace;
+use rim::cryoem::cli::{self, parse_number};
+use rim::cryoem::projection_matching::*;
+use rim::cryoem::symmetry::PointGroup;
+use rim::grayscale::Gray32;
+use rim::image_stack::ImageStack;
+use rim::io::image_reader::extension;
+use rim::io::mrc_decoder::MrcDecoder;
+use rim::io::star_reader::StarReader;
+use rim::io::star_writer::StarWriter;
+use rim::io::tiff_decoder::TiffDecoder;
+use rim::volume_processor::VolumeProcessor;
+
+fn help() {
+    println!(
+        "usage:
+projmatch -v <volume> -i <particles> -o <assignments.star> [options]
+
+  -v, --volume <file>      reference volume (.mrc or .tif)
+  -i, --input <file>       stack of particle images (.mrcs, .mrc or .tif)
+  -o, --output <file>      orientations, shifts and scores (.star)
+  -s, --step <degrees>     angular step of the reference directions (default: 10)
+      --psi-step <degrees> step of the in-plane angles (default: angular step)
+      --max-shift <pixels> maximum shift (default: 10)
+      --sym <group>        point group of the volume: C<n>, D<n>, T, O or I (default: C1)
+  -l, --local <file>       previous assignments (.star): local refinement around them
+  -r, --range <degrees>    angular range of the local refinement (default: 2 x step)
+  -h, --help               prints this help"
+    );
+}
+
+struct Options {
+    volume: String,
+    input: String,
+    output: String,
+    step: f64,
+    psi_step: Option<f64>,
+    max_shift: f64,
+    symmetry: PointGroup,
+    local: Option<String>,
+    range: Option<f64>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        volume: String::new(),
+        input: String::new(),
+        output: String::new(),
+        step: 10.0,
+        psi_step: None,
+        max_shift: 10.0,
+        symmetry: PointGroup::C(1),
+        local: None,
+        range: None,
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i][..];
+        let val = args
+            .get(i + 1)
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg {
+            "-v" | "--volume" => options.volume = val.to_string(),
+            "-i" | "--input" => options.input = val.to_string(),
+            "-o" | "--output" => options.output = val.to_string(),
+            "-s" | "--step" => options.step = parse_number(arg, val)?,
+            "--psi-step" => options.psi_step = Some(parse_number(arg, val)?),
+            "--max-shift" => options.max_shift = parse_number(arg, val)?,
+            "--sym" => options.symmetry = val.parse()?,
+            "-l" | "--local" => options.local = Some(val.to_string()),
+            "-r" | "--range" => options.range = Some(parse_number(arg, val)?),
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+        i += 2;
+    }
+    if options.volume.is_empty() || options.input.is_empty() || options.output.is_empty() {
+        return Err(String::from("The options -v, -i and -o are required"));
+    }
+    let angles = [Some(options.step), options.psi_step, options.range];
+    if angles.iter().flatten().any(|a| !(*a > 0.0 && *a <= 180.0)) {
+        return Err(String::from(
+            "The angular steps and range must be in ]0, 180] degrees",
+        ));
+    }
+    if options.max_shift < 0.0 {
+        return Err(String::from("The maximum shift must be positive"));
+    }
+    Ok(options)
+}
+
+fn read_stack(filename: &str) -> Result<ImageStack<f32, Gray32>, String> {
+    if !Path::new(filename).is_file() {
+        return Err(format!("File not found: {}", filename));
+    }
+    let error = |e: std::io::Error| format!("Unable to read {}: {}", filename, e);
+    let (image, _) = match &extension(filename)[..] {
+        "mrc" | "mrcs" | "map" => MrcDecoder::open(filename).map_err(error)?,
+        "tif" | "tiff" => TiffDecoder::open(filename).map_err(error)?,
+        ext => return Err(format!("Unsupported file format '.{}'", ext)),
+    };
+    image.into_float_stack()
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let stack = read_stack(&options.volume)?;
+    let (w, h, d) = (stack.get_width(), stack.get_height(), stack.n_slices());
+    let volume = VolumeProcessor::new_volume(w, h, d, stack.data.concat(), Gray32::new());
+    let particles = read_stack(&options.input)?;
+    if particles.get_width() != w || particles.get_height() != h {
+        return Err(format!(
+            "The particles ({}x{}) and the projections of the volume ({}x{}) differ in size",
+            particles.get_width(),
+            particles.get_height(),
+            w,
+            h
+        ));
+    }
+    println!("Volume: {}x{}x{}", w, h, d);
+    println!("Particles: {}", particles.n_slices());
+
+    let mut matching = ProjectionMatching::new(options.step);
+    if let Some(step) = options.psi_step {
+        matching.set_psi_step(step);
+    }
+    matching.set_max_shift(options.max_shift);
+    matching.set_symmetry(options.symmetry);
+    let assignments = match &options.local {
+        Some(filename) => {
+            let blocks = StarReader::open(filename)
+                .map_err(|e| format!("Unable to read {}: {}", filename, e))?;
+            let block = blocks
+                .iter()
+                .find(|b| b.name() == "particles")
+                .or_else(|| blocks.first())
+                .ok_or_else(|| format!("No data block in {}", filename))?;
+            let previous = from_star(block).map_err(|e| format!("{}: {}", filename, e))?;
+            if previous.len() != particles.n_slices() as usize {
+                return Err(format!(
+                    "{} assignments in {} for {} particles",
+                    previous.len(),
+                    filename,
+                    particles.n_slices()
+                ));
+            }
+            let range = options.range.unwrap_or(2.0 * options.step);
+            println!("Local refinement within {} degrees", range);
+            matching.refine_stack(&volume, &particles, &previous, range)
+        }
+        None => {
+            let references = matching.references(&volume);
+            println!(
+                "References: {} directions ({}) x {} in-plane angles",
+                references.len(),
+                options.symmetry,
+                (360.0 / matching.get_psi_step()).round()
+            );
+            matching.align_stack(&particles, &references)
+        }
+    };
+    let mean = assignments.iter().map(|a| a.score).sum::<f64>() / assignments.len().max(1) as f64;
+    println!("Mean score: {:.4}", mean);
+
+    let names: Vec<String> = (1..=assignments.len())
+        .map(|i| format!("{:06}@{}", i, options.input))
+        .collect();
+    StarWriter::save(&[to_star(&assignments, &names)], &options.output)
+        .map_err(|e| format!("Unable to write {}: {}", options.output, e))
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    dbg!(args);
+    cli::main("projmatch", help, parse_args, run);
 }
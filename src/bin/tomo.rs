@@ -1,6 +1,330 @@
-use std::env;
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Reconstruction of a tilt series (tilt axis parallel to the y-axis).
+//!
+
+use std::fs;
+use std::path::Path;
+
+use rim::color_space::ColorSpace;
+use rim::cryoem::backprojection::{FilteredBackProjection, RampFilter};
+use rim::cryoem::cli::{self, parse_number};
+use rim::cryoem::iterative::{Algorithm, IterativeReconstruction};
+use rim::cryoem::tomography::*;
+use rim::grayscale::{Gray16, Gray32, Gray8};
+use rim::image_stack::ImageStack;
+use rim::io::file_info::FileInfo;
+use rim::io::image_reader::*;
+use rim::io::mrc_decoder::MrcDecoder;
+use rim::io::mrc_encoder::MrcEncoder;
+use rim::io::tiff_decoder::TiffDecoder;
+use rim::io::tiff_encoder::TiffEncoder;
+use rim::transformable::InterpolationMode;
+
+fn help() {
+    println!(
+        "usage:
+tomo -i <tilt-series> -a <angles> -o <volume> [options]
+
+  -i, --input <file>       tilt series (.mrc, .mrcs, .st, .ali, .tif, .tiff or raw binary)
+  -a, --angles <file>      tilt angles in degrees (.rawtlt text file or .csv)
+  -o, --output <file>      reconstructed volume (.mrc, .tif or raw binary)
+  -s, --size <w>x<h>       size of the projections (raw input only)
+  -b, --bpp <8|16|32>      bits per pixel (raw input only)
+  -m, --method <wbp|sirt>  reconstruction algorithm (default: wbp)
+  -n, --iterations <n>     number of SIRT iterations (default: 20)
+  -t, --thickness <n>      thickness of the volume in unbinned pixels (default: width)
+      --bin <n>            binning factor of the projections (default: 1)
+      --type <8|16|32>     bits per voxel of the output (default: 32)
+      --align              aligns the neighbouring tilts by cross-correlation
+  -h, --help               prints this help"
+    );
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Method {
+    Wbp,
+    Sirt,
+}
+
+struct Options {
+    input: String,
+    angles: String,
+    output: String,
+    size: Option<(u32, u32)>,
+    bpp: Option<u32>,
+    method: Method,
+    iterations: usize,
+    thickness: Option<u32>,
+    bin: u32,
+    output_type: u32,
+    align: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        input: String::new(),
+        angles: String::new(),
+        output: String::new(),
+        size: None,
+        bpp: None,
+        method: Method::Wbp,
+        iterations: 20,
+        thickness: None,
+        bin: 1,
+        output_type: 32,
+        align: false,
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i][..];
+        if arg == "--align" {
+            options.align = true;
+            i += 1;
+            continue;
+        }
+        let val = args
+            .get(i + 1)
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg {
+            "-i" | "--input" => options.input = val.to_string(),
+            "-a" | "--angles" => options.angles = val.to_string(),
+            "-o" | "--output" => options.output = val.to_string(),
+            "-s" | "--size" => {
+                let (w, h) = val
+                    .split_once('x')
+                    .ok_or_else(|| format!("Invalid size '{}', expected <w>x<h>", val))?;
+                options.size = Some((parse_number(arg, w)?, parse_number(arg, h)?));
+            }
+            "-b" | "--bpp" => options.bpp = Some(parse_number(arg, val)?),
+            "-m" | "--method" => {
+                options.method = match &val.to_lowercase()[..] {
+                    "wbp" => Method::Wbp,
+                    "sirt" => Method::Sirt,
+                    _ => return Err(format!("Unknown method '{}', expected wbp or sirt", val)),
+                }
+            }
+            "-n" | "--iterations" => options.iterations = parse_number(arg, val)?,
+            "-t" | "--thickness" => options.thickness = Some(parse_number(arg, val)?),
+            "--bin" => options.bin = parse_number(arg, val)?,
+            "--type" => options.output_type = parse_number(arg, val)?,
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+        i += 2;
+    }
+    if options.input.is_empty() || options.angles.is_empty() || options.output.is_empty() {
+        return Err(String::from("The options -i, -a and -o are required"));
+    }
+    if options.bin == 0 {
+        return Err(String::from("The binning factor must be at least 1"));
+    }
+    if ![8, 16, 32].contains(&options.output_type) {
+        return Err(format!(
+            "Invalid output type {}, expected 8, 16 or 32",
+            options.output_type
+        ));
+    }
+    Ok(options)
+}
+
+// Returns the tilt series and its pixel size (in Å for MRC files, 1.0 otherwise)
+fn read_tilt_series(options: &Options) -> Result<(ImageStack<f32, Gray32>, f64), String> {
+    let filename = &options.input[..];
+    if !Path::new(filename).is_file() {
+        return Err(format!("File not found: {}", filename));
+    }
+    let error = |e: std::io::Error| format!("Unable to read {}: {}", filename, e);
+    match &extension(filename)[..] {
+        "mrc" | "mrcs" | "st" | "ali" => {
+            let (image, info) = MrcDecoder::open(filename).map_err(error)?;
+            Ok((image.into_float_stack()?, info.pixel_width))
+        }
+        "tif" | "tiff" => {
+            let (image, _) = TiffDecoder::open(filename).map_err(error)?;
+            Ok((image.into_float_stack()?, 1.0))
+        }
+        _ => {
+            let (w, h) = options
+                .size
+                .ok_or("The size (-s <w>x<h>) of a raw tilt series is required")?;
+            let (ty, bytes) = match options.bpp {
+                Some(8) => (FileInfo::GRAY8, 1),
+                Some(16) => (FileInfo::GRAY16_UNSIGNED, 2),
+                Some(32) => (FileInfo::GRAY32_FLOAT, 4),
+                Some(bpp) => return Err(format!("Invalid bits per pixel {}", bpp)),
+                None => {
+                    return Err(String::from(
+                        "The bits per pixel (-b) of a raw tilt series is required",
+                    ))
+                }
+            };
+            let length = fs::metadata(filename).map_err(error)?.len();
+            let slice = w as u64 * h as u64 * bytes;
+            if slice == 0 || length == 0 || length % slice != 0 {
+                return Err(format!(
+                    "The size of {} ({} bytes) is not a multiple of {}x{}x{} bytes",
+                    filename, length, w, h, bytes
+                ));
+            }
+            Ok((
+                FileOpener::open_stack(filename, w, h, ty).into_float_stack()?,
+                1.0,
+            ))
+        }
+    }
+}
+
+// Rescales the voxels to the range of the output type
+fn convert_volume(volume: ImageStack<f32, Gray32>, output_type: u32) -> OutputProcessor {
+    let (min, max) = volume
+        .data
+        .iter()
+        .flatten()
+        .fold((f32::MAX, f32::MIN), |(a, b), v| (a.min(*v), b.max(*v)));
+    let scale = |range: f32| {
+        let (min, max) = (min, max);
+        move |v: &f32| {
+            if max > min {
+                ((v - min) / (max - min) * range).round()
+            } else {
+                0.0
+            }
+        }
+    };
+    let (w, h) = (volume.width, volume.height);
+    match output_type {
+        8 => OutputProcessor::ByteStack(ImageStack::new(
+            w,
+            h,
+            volume
+                .data
+                .iter()
+                .map(|s| s.iter().map(scale(255.0)).map(|v| v as u8).collect())
+                .collect(),
+            Gray8::new(),
+        )),
+        16 => OutputProcessor::ShortStack(ImageStack::new(
+            w,
+            h,
+            volume
+                .data
+                .iter()
+                .map(|s| s.iter().map(scale(65535.0)).map(|v| v as u16).collect())
+                .collect(),
+            Gray16::new(),
+        )),
+        _ => OutputProcessor::FloatStack(volume),
+    }
+}
+
+// Raw binary file (big-endian) as written by FileSaver
+fn raw_bytes(image: &OutputProcessor) -> Vec<u8> {
+    match image {
+        OutputProcessor::ByteStack(s) => s.data.concat(),
+        OutputProcessor::ShortStack(s) => s
+            .data
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_be_bytes())
+            .collect(),
+        OutputProcessor::FloatStack(s) => s
+            .data
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_be_bytes())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let angles = read_tilt_angles(&options.angles)?;
+    let (mut tilt_series, pixel_size) = read_tilt_series(options)?;
+    if tilt_series.n_slices() as usize != angles.len() {
+        return Err(format!(
+            "The tilt series has {} projections but {} angles were read from {}",
+            tilt_series.n_slices(),
+            angles.len(),
+            options.angles
+        ));
+    }
+    println!(
+        "Tilt series: {}x{}x{}, angles from {} to {}",
+        tilt_series.width,
+        tilt_series.height,
+        angles.len(),
+        angles.iter().cloned().fold(f32::MAX, f32::min),
+        angles.iter().cloned().fold(f32::MIN, f32::max)
+    );
+    if options.bin > 1 {
+        tilt_series = bin_stack(&tilt_series, options.bin);
+        println!("Binned: {}x{}", tilt_series.width, tilt_series.height);
+    }
+    if options.align {
+        let shifts = align_tilt_series(&tilt_series, &angles);
+        for (angle, (dx, dy)) in angles.iter().zip(shifts.iter()) {
+            println!("Shift at {:7.2}: {:7.2} {:7.2}", angle, dx, dy);
+        }
+        tilt_series = apply_shifts(&tilt_series, &shifts);
+    }
+    let thickness = options
+        .thickness
+        .map(|t| (t / options.bin).max(1))
+        .unwrap_or(tilt_series.width);
+    let slices = match options.method {
+        Method::Wbp => {
+            FilteredBackProjection::new(RampFilter::SheppLogan, InterpolationMode::Bilinear)
+                .reconstruct_stack(&tilt_series, &angles, thickness)
+        }
+        Method::Sirt => {
+            let mut sirt = IterativeReconstruction::new(Algorithm::Sirt);
+            sirt.set_iterations(options.iterations);
+            let (slices, residuals) = sirt.reconstruct_stack(&tilt_series, &angles, thickness);
+            if let Ok(r) = residuals.get_column_as_floats(String::from("Residual")) {
+                if let (Some(first), Some(last)) = (r.first(), r.last()) {
+                    println!("SIRT residual: {:.4} -> {:.4}", first, last);
+                }
+            }
+            slices
+        }
+    };
+    let volume = to_volume(&slices);
+    println!(
+        "Volume: {}x{}x{}",
+        volume.width,
+        volume.height,
+        volume.n_slices()
+    );
+    let image = convert_volume(volume, options.output_type);
+    let filename = &options.output[..];
+    let error = |e: std::io::Error| format!("Unable to write {}: {}", filename, e);
+    match &extension(filename)[..] {
+        "mrc" | "rec" => {
+            MrcEncoder::save(&image, pixel_size * options.bin as f64, filename).map_err(error)
+        }
+        "tif" | "tiff" => TiffEncoder::save(&image, filename).map_err(error),
+        _ => fs::write(filename, raw_bytes(&image)).map_err(error),
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    dbg!(args);
+    cli::main("tomo", help, parse_args, run);
 }
@@ -40,25 +40,20 @@ impl Statistics<u8> for ByteProcessor {
         if self.metadata.stats.is_dirty() {
             let mut sum: f64 = 0.0;
             let mut sum2: f64 = 0.0;
-            let mut hist = vec![0u32; 255];
-            let start = (self.metadata.roi.y() * self.width + self.metadata.roi.x()) as usize;
-            let mut mi: f64 = self.data[start] as f64;
-            let mut mx = mi;
+            let mut hist = vec![0u32; 256];
+            let mut mi = f64::MAX;
+            let mut mx = f64::MIN;
             let mut count: u32 = 0;
-            for y in self.metadata.roi.y()..(self.metadata.roi.y() + self.metadata.roi.height()) {
-                let mut i = y * self.width + self.metadata.roi.x();
-                for x in self.metadata.roi.x()..(self.metadata.roi.x() + self.metadata.roi.width())
-                {
-                    let v: f64 = self.getf(i as usize) as f64;
-                    let index: usize = self.get(i as usize) as usize;
-                    sum += v as f64;
-                    sum2 += (v * v) as f64;
-                    i += 1;
-                    hist[v as usize] += 1;
-                    mi = if v < mi { v } else { mi };
-                    mx = if v > mx { v } else { mx };
-                    count += 1;
-                }
+            // Pixels of the active ROI (whole image without ROI)
+            for i in self.metadata.roi_indices() {
+                let v: f64 = self.getf(i) as f64;
+                let index: usize = self.get(i) as usize;
+                sum += v as f64;
+                sum2 += (v * v) as f64;
+                hist[v as usize] += 1;
+                mi = if v < mi { v } else { mi };
+                mx = if v > mx { v } else { mx };
+                count += 1;
             }
             let mut std_dev = (count as f64 * sum2 - sum * sum) / count as f64;
             std_dev = if std_dev > 0.0 {
@@ -99,6 +94,7 @@ mod tests {
     use crate::image_processor::*;
     use crate::image_traits::Access;
     use crate::operator::Operator;
+    use crate::roi::Roi;
 
     #[test]
     fn get_pixel_at_xy() {
@@ -248,5 +244,15 @@ mod tests {
         assert_eq!(ip.get(3), 1);
     }
 
+    #[test]
+    fn add_in_polygon_roi() {
+        let mut ip = ByteProcessor::new(4, 4, vec![0u8; 16], Gray8::new());
+        // Upper-left triangle
+        ip.set_roi(Roi::Polygon(vec![(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)]));
+        ip.add(1);
+        let answer = vec![1u8, 1, 1, 0, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(*ip.data(), answer);
+    }
+
     //gamma
 }
@@ -0,0 +1,307 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Complex images: Fourier transforms of 2D images and volumes.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::fft::{fft_3d, fftshift, ifftshift, irfft_3d, rfft_3d};
+use crate::grayscale::{Gray32, Gray8};
+use crate::image_processor::ImageProcessor;
+use crate::pixel::PixelType;
+use num::complex::Complex64;
+
+///
+/// Complex image or volume (X varies fastest, then Y and Z)
+///
+/// The full spectrum has the size of the image with the zero frequency at the first
+/// element, or at the center `(w / 2, h / 2, d / 2)` after [shift()](ComplexImage::shift).
+/// The half-spectrum of [rfft()](ComplexImage::rfft) has a width of `w / 2 + 1` and
+/// cannot be shifted.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::complex_image::ComplexImage;
+/// use rim::grayscale::Gray32;
+/// use rim::image_processor::ImageProcessor;
+///
+/// // Cosine of period 4 pixels along X
+/// let pixels = (0..6 * 8).map(|i| ((i % 8) as f32 * std::f32::consts::PI / 2.0).cos()).collect();
+/// let ip = ImageProcessor::<f32, Gray32>::new(8, 6, pixels, Gray32::new());
+/// let mut spectrum = ComplexImage::fft(&ip);
+/// spectrum.shift();
+/// let power = spectrum.power_spectrum();
+/// // Peaks at +/- 2 pixels of the center (4, 3)
+/// assert!(power.data[6 + 3 * 8] > 500.0 && power.data[2 + 3 * 8] > 500.0);
+/// let display = spectrum.log_amplitude();
+/// assert_eq!(display.data[6 + 3 * 8], 255);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ComplexImage {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub data: Vec<Complex64>,
+    centered: bool,
+    half: bool,
+}
+
+impl ComplexImage {
+    pub fn new(width: u32, height: u32, depth: u32, data: Vec<Complex64>) -> Self {
+        assert_eq!(
+            data.len(),
+            (width * height * depth) as usize,
+            "Complex image size"
+        );
+        ComplexImage {
+            width,
+            height,
+            depth,
+            data,
+            centered: false,
+            half: false,
+        }
+    }
+
+    /// Creates a complex image with the pixels of `ip` as real parts.
+    pub fn from_real<T: PixelType, C: ColorSpace>(ip: &ImageProcessor<T, C>) -> Self {
+        let data = ip
+            .data
+            .iter()
+            .map(|v| Complex64::new(v.to_f32() as f64, 0.0))
+            .collect();
+        ComplexImage::new(ip.width, ip.height, ip.depth.max(1), data)
+    }
+
+    /// Returns the full spectrum of the image or volume `ip`.
+    pub fn fft<T: PixelType, C: ColorSpace>(ip: &ImageProcessor<T, C>) -> Self {
+        let mut spectrum = ComplexImage::from_real(ip);
+        spectrum.forward();
+        spectrum
+    }
+
+    ///
+    /// Returns the half-spectrum (width `w / 2 + 1`) of the image or volume `ip`.
+    ///
+    pub fn rfft<T: PixelType, C: ColorSpace>(ip: &ImageProcessor<T, C>) -> Self {
+        let (w, h, d) = (
+            ip.width as usize,
+            ip.height as usize,
+            ip.depth.max(1) as usize,
+        );
+        let pixels: Vec<f64> = ip.data.iter().map(|v| v.to_f32() as f64).collect();
+        let data = rfft_3d(&pixels, w, h, d);
+        let mut spectrum = ComplexImage::new(ip.width / 2 + 1, ip.height, d as u32, data);
+        spectrum.half = true;
+        spectrum
+    }
+
+    ///
+    /// Returns the image or volume of width `width` of this half-spectrum (inverse of
+    /// [rfft()](ComplexImage::rfft)).
+    ///
+    pub fn irfft(&self, width: u32) -> ImageProcessor<f32, Gray32> {
+        assert_eq!(width / 2 + 1, self.width, "Width of the half-spectrum");
+        let data = self.unshifted();
+        let pixels = irfft_3d(
+            &data,
+            width as usize,
+            self.height as usize,
+            self.depth as usize,
+        );
+        self.to_processor(width, pixels.iter().map(|v| *v as f32).collect())
+    }
+
+    /// Forward FFT in place. A centered spectrum is unshifted first.
+    pub fn forward(&mut self) {
+        self.transform(false);
+    }
+
+    /// Inverse FFT in place (normalized by the number of pixels). A centered spectrum is unshifted first.
+    pub fn inverse(&mut self) {
+        self.transform(true);
+    }
+
+    /// Returns the real part of the inverse FFT of this spectrum.
+    pub fn ifft(&self) -> ImageProcessor<f32, Gray32> {
+        let mut image = self.clone();
+        image.inverse();
+        image.real()
+    }
+
+    ///
+    /// Moves the zero frequency to the center (fftshift). Does nothing if already centered.
+    /// Applies to full spectra only: panics with the half-spectrum of [rfft()](ComplexImage::rfft)
+    /// whose X axis has no negative frequencies.
+    ///
+    pub fn shift(&mut self) {
+        assert!(!self.half, "Cannot shift a half-spectrum");
+        if !self.centered {
+            let (w, h, d) = self.dims();
+            self.data = fftshift(&self.data, w, h, d);
+            self.centered = true;
+        }
+    }
+
+    ///
+    /// Moves the zero frequency back to the first element (ifftshift). Does nothing if not centered.
+    ///
+    pub fn unshift(&mut self) {
+        if self.centered {
+            self.data = self.unshifted();
+            self.centered = false;
+        }
+    }
+
+    pub fn is_centered(&self) -> bool {
+        self.centered
+    }
+
+    pub fn real(&self) -> ImageProcessor<f32, Gray32> {
+        self.map(|v| v.re)
+    }
+
+    pub fn imaginary(&self) -> ImageProcessor<f32, Gray32> {
+        self.map(|v| v.im)
+    }
+
+    pub fn amplitude(&self) -> ImageProcessor<f32, Gray32> {
+        self.map(|v| v.norm())
+    }
+
+    /// Returns the phases in radians.
+    pub fn phase(&self) -> ImageProcessor<f32, Gray32> {
+        self.map(|v| v.arg())
+    }
+
+    /// Returns the squared amplitudes.
+    pub fn power_spectrum(&self) -> ImageProcessor<f32, Gray32> {
+        self.map(|v| v.norm_sqr())
+    }
+
+    ///
+    /// Returns the display image of `ln(1 + amplitude)` scaled from 0 to 255.
+    ///
+    pub fn log_amplitude(&self) -> ImageProcessor<u8, Gray8> {
+        let values: Vec<f64> = self.data.iter().map(|v| v.norm().ln_1p()).collect();
+        let (min, max) = values
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(a, b), v| (a.min(*v), b.max(*v)));
+        let range = if max > min { max - min } else { 1.0 };
+        let pixels = values
+            .iter()
+            .map(|v| ((v - min) / range * 255.0).round() as u8)
+            .collect();
+        ImageProcessor::new_volume(self.width, self.height, self.depth, pixels, Gray8::new())
+    }
+
+    //
+    // Private methods
+    //
+
+    fn dims(&self) -> (usize, usize, usize) {
+        (
+            self.width as usize,
+            self.height as usize,
+            self.depth as usize,
+        )
+    }
+
+    fn unshifted(&self) -> Vec<Complex64> {
+        if self.centered {
+            let (w, h, d) = self.dims();
+            ifftshift(&self.data, w, h, d)
+        } else {
+            self.data.clone()
+        }
+    }
+
+    // The transforms are computed with the origin at the first element
+    fn transform(&mut self, inverse: bool) {
+        self.unshift();
+        let (w, h, d) = self.dims();
+        fft_3d(&mut self.data, w, h, d, inverse);
+    }
+
+    fn map<F: Fn(&Complex64) -> f64>(&self, f: F) -> ImageProcessor<f32, Gray32> {
+        self.to_processor(self.width, self.data.iter().map(|v| f(v) as f32).collect())
+    }
+
+    fn to_processor(&self, width: u32, pixels: Vec<f32>) -> ImageProcessor<f32, Gray32> {
+        ImageProcessor::new_volume(width, self.height, self.depth, pixels, Gray32::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(w: u32, h: u32, d: u32) -> ImageProcessor<f32, Gray32> {
+        let pixels = (0..w * h * d).map(|i| ((i * 13) % 17) as f32).collect();
+        ImageProcessor::new_volume(w, h, d, pixels, Gray32::new())
+    }
+
+    #[test]
+    fn round_trip_any_size() {
+        for (w, h, d) in [(7, 5, 1), (6, 9, 1), (5, 4, 3)] {
+            let ip = image(w, h, d);
+            let mut spectrum = ComplexImage::fft(&ip);
+            spectrum.shift();
+            let back = spectrum.ifft();
+            assert_eq!((back.width, back.height, back.depth), (w, h, d));
+            assert!(back
+                .data
+                .iter()
+                .zip(ip.data.iter())
+                .all(|(a, b)| (a - b).abs() < 1e-3));
+            let half = ComplexImage::rfft(&ip);
+            assert_eq!(half.width, w / 2 + 1);
+            let back = half.irfft(w);
+            assert!(back
+                .data
+                .iter()
+                .zip(ip.data.iter())
+                .all(|(a, b)| (a - b).abs() < 1e-3));
+        }
+    }
+
+    #[test]
+    fn centered_zero_frequency() {
+        let ip = image(6, 5, 1);
+        let sum: f32 = ip.data.iter().sum();
+        let mut spectrum = ComplexImage::fft(&ip);
+        assert!((spectrum.real().data[0] - sum).abs() < 1e-3);
+        spectrum.shift();
+        assert!(spectrum.is_centered());
+        assert!((spectrum.real().data[3 + 2 * 6] - sum).abs() < 1e-3);
+        // Parseval
+        let power: f32 = spectrum.power_spectrum().data.iter().sum();
+        let energy: f32 = ip.data.iter().map(|v| v * v).sum();
+        assert!((power / 30.0 - energy).abs() < 1e-2 * energy);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot shift a half-spectrum")]
+    fn half_spectrum_is_not_shifted() {
+        ComplexImage::rfft(&image(6, 5, 1)).shift();
+    }
+}
@@ -0,0 +1,410 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Filtered back-projection (FBP), inverse of the Radon transform computed by
+//! [Sinogram](crate::cryoem::sinogram::Sinogram).
+//!
+//! A sinogram has one row per angle (in degrees) and one column per detector bin.
+//! The detector is centered on the bin `width/2`, the slice on the pixel `(w/2, h/2)`:
+//! the pixel (x,y) is projected on `t = (x - cx) cos(angle) + (y - cy) sin(angle)`.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::fft::fft;
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::transformable::{cubic, InterpolationMode};
+use num::complex::Complex64;
+use std::f64::consts::PI;
+
+///
+/// Filters applied to the projections before back-projection. All of them are the
+/// ramp filter `|f|` multiplied by a window attenuating the high frequencies (except `Ramp`).
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RampFilter {
+    /// Ram-Lak filter
+    Ramp,
+    SheppLogan,
+    Cosine,
+    Hamming,
+    Hann,
+}
+
+///
+/// Filtered back-projection of parallel beam sinograms
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::backprojection::{FilteredBackProjection, RampFilter};
+/// use rim::cryoem::sinogram::Sinogram;
+/// use rim::float_processor::FloatProcessor;
+/// use rim::grayscale::Gray32;
+/// use rim::transformable::InterpolationMode;
+///
+/// let mut pixels = vec![0.0f32; 81];
+/// pixels[40] = 1.0;
+/// let ip = FloatProcessor::new(9, 9, pixels, Gray32::new());
+/// let angles: Vec<f32> = (0..180).map(|a| a as f32).collect();
+/// let sinogram = Sinogram::new(&ip, &angles);
+/// let fbp = FilteredBackProjection::new(RampFilter::Ramp, InterpolationMode::Bilinear);
+/// let slice = fbp.reconstruct(&sinogram, &angles, 9, 9);
+/// assert!(slice.data()[40] > 0.5);
+/// ```
+pub struct FilteredBackProjection {
+    filter: RampFilter,
+    interpolation: InterpolationMode,
+}
+
+impl FilteredBackProjection {
+    ///
+    /// Creates a FBP with the given filter and interpolation of the projections
+    /// (`Nearest` or `Bilinear` for linear, `Bicubic` uses the kernel [cubic()]).
+    ///
+    pub fn new(filter: RampFilter, interpolation: InterpolationMode) -> Self {
+        FilteredBackProjection {
+            filter,
+            interpolation,
+        }
+    }
+
+    ///
+    /// Reconstructs a slice of size `width` x `height` from `sinogram` acquired at `angles`
+    /// (evenly spaced over 180 or 360 degrees).
+    ///
+    pub fn reconstruct(
+        &self,
+        sinogram: &FloatProcessor,
+        angles: &[f32],
+        width: u32,
+        height: u32,
+    ) -> FloatProcessor {
+        let filtered = filter_sinogram(sinogram, self.filter);
+        let mut slice = back_project(&filtered, angles, width, height, self.interpolation);
+        let scale = (PI / angles.len() as f64) as f32;
+        slice.data.iter_mut().for_each(|v| *v *= scale);
+        slice
+    }
+
+    ///
+    /// Reconstructs a tilt series slice-by-slice (weighted back-projection): each slice of
+    /// `tilt_series` is the projection at the corresponding angle, the tilt axis is parallel
+    /// to the y-axis.
+    ///
+    /// The row `y` of all the projections is the sinogram of the slice `y` of the output stack
+    /// (of size `width` x `thickness`, with `width` the width of the projections).
+    ///
+    pub fn reconstruct_stack(
+        &self,
+        tilt_series: &ImageStack<f32, Gray32>,
+        angles: &[f32],
+        thickness: u32,
+    ) -> ImageStack<f32, Gray32> {
+        let (width, height) = (tilt_series.get_width(), tilt_series.get_height());
+        let n = width as usize;
+        let slices = (0..height as usize)
+            .map(|y| {
+                let rows: Vec<f32> = tilt_series
+                    .data()
+                    .iter()
+                    .flat_map(|projection| projection[y * n..(y + 1) * n].iter().copied())
+                    .collect();
+                let sinogram =
+                    ImageProcessor::new(width, tilt_series.n_slices(), rows, Gray32::new());
+                self.reconstruct(&sinogram, angles, width, thickness).data
+            })
+            .collect();
+        ImageStack::new(width, thickness, slices, Gray32::new())
+    }
+}
+
+///
+/// Filters each row of the sinogram in Fourier space (zero-padded to avoid wrap-around).
+///
+pub fn filter_sinogram(sinogram: &FloatProcessor, filter: RampFilter) -> FloatProcessor {
+    let n = sinogram.width as usize;
+    let size = (2 * n).next_power_of_two().max(64);
+    let response = filter_response(size, filter);
+    let mut data = Vec::<f32>::with_capacity(sinogram.data.len());
+    let mut line = vec![Complex64::new(0.0, 0.0); size];
+    for row in sinogram.data.chunks(n) {
+        line.iter_mut().for_each(|v| *v = Complex64::new(0.0, 0.0));
+        for (i, v) in row.iter().enumerate() {
+            line[i] = Complex64::new(*v as f64, 0.0);
+        }
+        fft(&mut line, false);
+        for (v, r) in line.iter_mut().zip(response.iter()) {
+            *v *= r;
+        }
+        fft(&mut line, true);
+        data.extend(line[..n].iter().map(|v| v.re as f32));
+    }
+    ImageProcessor::new(sinogram.width, sinogram.height, data, Gray32::new())
+}
+
+///
+/// Unfiltered back-projection: each pixel of the slice sums the (interpolated) detector
+/// values of its projections.
+///
+pub fn back_project(
+    sinogram: &FloatProcessor,
+    angles: &[f32],
+    width: u32,
+    height: u32,
+    interpolation: InterpolationMode,
+) -> FloatProcessor {
+    let n = sinogram.width as usize;
+    let (cx, cy) = ((width / 2) as f64, (height / 2) as f64);
+    let center = (n / 2) as f64;
+    let mut sums = vec![0.0f64; (width * height) as usize];
+    for (row, angle) in sinogram.data.chunks(n).zip(angles.iter()) {
+        let (sin, cos) = (*angle as f64).to_radians().sin_cos();
+        for y in 0..height as usize {
+            let dy = (y as f64 - cy) * sin + center;
+            for x in 0..width as usize {
+                let t = (x as f64 - cx) * cos + dy;
+                sums[x + y * width as usize] += interpolate(row, t, interpolation);
+            }
+        }
+    }
+    ImageProcessor::new(
+        width,
+        height,
+        sums.iter().map(|v| *v as f32).collect(),
+        Gray32::new(),
+    )
+}
+
+//
+// Private functions
+//
+
+// Projection of the plane `pixels` (of size `width` x `height`) at `angle` (degrees)
+// on a detector of `bins` bins
+pub(crate) fn project_plane(
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    angle: f32,
+    bins: usize,
+    interpolation: InterpolationMode,
+) -> Vec<f64> {
+    let (cx, cy) = ((width / 2) as f64, (height / 2) as f64);
+    let center = (bins / 2) as f64;
+    let (sin, cos) = (angle as f64).to_radians().sin_cos();
+    let mut sums = vec![0.0f64; bins];
+    for y in 0..height {
+        let dy = (y as f64 - cy) * sin + center;
+        for x in 0..width {
+            let value = pixels[x + y * width] as f64;
+            if value != 0.0 {
+                splat(&mut sums, (x as f64 - cx) * cos + dy, value, interpolation);
+            }
+        }
+    }
+    sums
+}
+
+// Value of the detector at `t`, zero outside
+pub(crate) fn interpolate(row: &[f32], t: f64, interpolation: InterpolationMode) -> f64 {
+    let at = |i: i64| -> f64 {
+        if i >= 0 && (i as usize) < row.len() {
+            row[i as usize] as f64
+        } else {
+            0.0
+        }
+    };
+    match interpolation {
+        InterpolationMode::Nearest => at(t.round() as i64),
+        InterpolationMode::Bilinear => {
+            let t0 = t.floor();
+            let f = t - t0;
+            at(t0 as i64) * (1.0 - f) + at(t0 as i64 + 1) * f
+        }
+        InterpolationMode::Bicubic => {
+            let t0 = t.floor() as i64;
+            (t0 - 1..=t0 + 2).map(|i| at(i) * cubic(t - i as f64)).sum()
+        }
+    }
+}
+
+// Adds `value` to the bins around `t` with the weights of `interpolate()`
+pub(crate) fn splat(bins: &mut [f64], t: f64, value: f64, interpolation: InterpolationMode) {
+    let mut add = |i: i64, w: f64| {
+        if i >= 0 && (i as usize) < bins.len() {
+            bins[i as usize] += value * w;
+        }
+    };
+    match interpolation {
+        InterpolationMode::Nearest => add(t.round() as i64, 1.0),
+        InterpolationMode::Bilinear => {
+            let t0 = t.floor();
+            let f = t - t0;
+            add(t0 as i64, 1.0 - f);
+            add(t0 as i64 + 1, f);
+        }
+        InterpolationMode::Bicubic => {
+            let t0 = t.floor() as i64;
+            for i in t0 - 1..=t0 + 2 {
+                add(i, cubic(t - i as f64));
+            }
+        }
+    }
+}
+
+// Frequency response of the filter: FFT of the discrete Ram-Lak kernel
+// (Kak & Slaney) times the window
+fn filter_response(size: usize, filter: RampFilter) -> Vec<f64> {
+    let mut kernel = vec![Complex64::new(0.0, 0.0); size];
+    kernel[0] = Complex64::new(0.25, 0.0);
+    for i in (1..size / 2).step_by(2) {
+        let v = -1.0 / (PI * i as f64).powi(2);
+        kernel[i] = Complex64::new(v, 0.0);
+        kernel[size - i] = Complex64::new(v, 0.0);
+    }
+    fft(&mut kernel, false);
+    (0..size)
+        .map(|k| {
+            // Frequency in cycles per bin in [-0.5, 0.5[
+            let f = if k < size / 2 {
+                k as f64 / size as f64
+            } else {
+                k as f64 / size as f64 - 1.0
+            };
+            let window = match filter {
+                RampFilter::Ramp => 1.0,
+                RampFilter::SheppLogan => {
+                    if f == 0.0 {
+                        1.0
+                    } else {
+                        (PI * f).sin() / (PI * f)
+                    }
+                }
+                RampFilter::Cosine => (PI * f).cos(),
+                RampFilter::Hamming => 0.54 + 0.46 * (2.0 * PI * f).cos(),
+                RampFilter::Hann => 0.5 + 0.5 * (2.0 * PI * f).cos(),
+            };
+            kernel[k].re * window
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cryoem::sinogram::Sinogram;
+
+    #[test]
+    fn ramp_response() {
+        let r = filter_response(64, RampFilter::Ramp);
+        // Close to |f| with a small positive DC term
+        assert!(r[0] > 0.0 && r[0] < 0.01);
+        assert!((r[16] - 0.25).abs() < 0.01);
+        assert!((r[32] - 0.5).abs() < 0.01);
+        let h = filter_response(64, RampFilter::Hann);
+        assert!(h[32].abs() < 1e-9);
+    }
+
+    #[test]
+    fn round_trip_5x5() {
+        let test: Vec<f32> = (1..=25).map(|v| v as f32).collect();
+        let ip = FloatProcessor::new(5, 5, test.clone(), Gray32::new());
+        let angles: Vec<f32> = (0..180).map(|a| a as f32).collect();
+        let sinogram = Sinogram::new(&ip, &angles);
+        for filter in [
+            RampFilter::Ramp,
+            RampFilter::SheppLogan,
+            RampFilter::Cosine,
+            RampFilter::Hamming,
+            RampFilter::Hann,
+        ] {
+            for interpolation in [InterpolationMode::Nearest, InterpolationMode::Bilinear] {
+                let slice = FilteredBackProjection::new(filter, interpolation)
+                    .reconstruct(&sinogram, &angles, 5, 5);
+                let err = slice
+                    .data()
+                    .iter()
+                    .zip(test.iter())
+                    .map(|(a, b)| (a - b).powi(2))
+                    .sum::<f32>();
+                let norm = test.iter().map(|b| b * b).sum::<f32>();
+                // Relative RMS error, the smoothing windows blur the edges of the image
+                let tolerance = if filter == RampFilter::Ramp { 0.2 } else { 0.4 };
+                assert!(
+                    (err / norm).sqrt() < tolerance,
+                    "{:?} {:?}",
+                    filter,
+                    interpolation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn disk_reconstruction() {
+        let n = 33;
+        let pixels: Vec<f32> = (0..n * n)
+            .map(|i| {
+                let (x, y) = ((i % n) as f32 - 16.0, (i / n) as f32 - 16.0);
+                if x * x + y * y < 64.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let ip = FloatProcessor::new(n as u32, n as u32, pixels, Gray32::new());
+        let angles: Vec<f32> = (0..90).map(|a| 2.0 * a as f32).collect();
+        let sinogram = Sinogram::new(&ip, &angles);
+        let slice =
+            FilteredBackProjection::new(RampFilter::SheppLogan, InterpolationMode::Bilinear)
+                .reconstruct(&sinogram, &angles, n as u32, n as u32);
+        assert!((slice.data()[16 + 16 * n] - 1.0).abs() < 0.1);
+        assert!(slice.data()[2 + 16 * n].abs() < 0.1);
+    }
+
+    #[test]
+    fn tilt_series_slice_by_slice() {
+        let mut pixels = vec![0.0f32; 81];
+        pixels[40] = 1.0;
+        let ip = FloatProcessor::new(9, 9, pixels, Gray32::new());
+        let angles: Vec<f32> = (0..60).map(|a| a as f32 * 3.0).collect();
+        let sinogram = Sinogram::new(&ip, &angles);
+        // Three identical rows per projection
+        let bins = sinogram.width;
+        let projections: Vec<Vec<f32>> = sinogram
+            .data
+            .chunks(bins as usize)
+            .map(|row| row.repeat(3))
+            .collect();
+        let stack = ImageStack::new(bins, 3, projections, Gray32::new());
+        let fbp = FilteredBackProjection::new(RampFilter::Ramp, InterpolationMode::Bilinear);
+        let slices = fbp.reconstruct_stack(&stack, &angles, 9);
+        assert_eq!(slices.n_slices(), 3);
+        assert_eq!((slices.get_width(), slices.get_height()), (bins, 9));
+        let single = fbp.reconstruct(&sinogram, &angles, bins, 9);
+        assert!(slices.data().iter().all(|s| *s == single.data));
+    }
+}
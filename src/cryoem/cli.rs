@@ -0,0 +1,83 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Helpers shared by the command-line tools of the cryo-EM modules
+//! (`tomo`, `cmonlines` and `projmatch`).
+//!
+
+use std::env;
+use std::process;
+
+///
+/// Parses the value of the command-line option `option`.
+///
+pub fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("Invalid value '{}' for {}", value, option))
+}
+
+///
+/// Rounds `value` to three decimals, the precision of the CSV and STAR files written by the
+/// tools (without negative zero).
+///
+pub fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0 + 0.0
+}
+
+///
+/// Entry point of the tool `name`: prints the help without arguments or with `-h`/`--help`,
+/// otherwise parses the arguments and runs. On error, prints the message and exits with 1.
+///
+pub fn main<T>(
+    name: &str,
+    help: fn(),
+    parse_args: fn(&[String]) -> Result<T, String>,
+    run: fn(&T) -> Result<(), String>,
+) {
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 1 || args.iter().any(|a| a == "-h" || a == "--help") {
+        help();
+        return;
+    }
+    let result = parse_args(&args).and_then(|options| run(&options));
+    if let Err(msg) = result {
+        eprintln!("{}: {}", name, msg);
+        eprintln!("Try '{} --help' for more information.", name);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number::<u32>("-n", "12"), Ok(12));
+        assert_eq!(
+            parse_number::<f64>("--step", "two"),
+            Err(String::from("Invalid value 'two' for --step"))
+        );
+        assert_eq!(round(12.34567), 12.346);
+        assert!(round(-0.0001).is_sign_positive());
+    }
+}
@@ -0,0 +1,571 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Angular reconstitution by common lines.
+//!
+//! By the central section theorem, the 2D Fourier transforms of two projections share a
+//! central line. In real space, this is the line of their sinograms (Radon transforms)
+//! that are identical: the 1D projection of the volume along the direction common to both
+//! image planes. Once the common lines are found for every pair of projections, the
+//! pairs are weighted by the consistency of their common lines with the other projections
+//! (voting), then the orientations are estimated by the eigenvector method of
+//! Singer & Shkolnisky (2011) and refined by robust least squares.
+//!
+//! The orientations are defined up to a global rotation (the first projection gets the
+//! identity) and up to a reflection (the handedness of the volume cannot be determined
+//! from common lines).
+//!
+
+use crate::color_space::ColorSpace;
+use crate::cryoem::backprojection::{filter_sinogram, RampFilter};
+use crate::cryoem::euler::{angle_between, Convention, Euler};
+use crate::cryoem::sinogram::Sinogram;
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_stack::ImageStack;
+use crate::transformable::InterpolationMode;
+use crate::vecmath::matrix3::Matrix3;
+#[cfg(test)]
+use crate::volume_processor::VolumeProcessor;
+use nalgebra::{DMatrix, DVector, Matrix3 as Mat3, SymmetricEigen, Vector3 as Vec3};
+
+// Residual angle (degrees) halving the weight of a common line during the refinement
+const ROBUST_SCALE: f64 = 5.0;
+
+///
+/// Common line between the projections `i` and `j`: the line at `angle_i` (degrees, in the
+/// sinogram of `i`) matches the line at `angle_j` in the sinogram of `j`.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct CommonLine {
+    pub i: usize,
+    pub j: usize,
+    pub angle_i: f32,
+    pub angle_j: f32,
+    /// Normalized cross-correlation of the two lines
+    pub score: f64,
+}
+
+///
+/// Angular reconstitution of a stack of projections
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::cryoem::common_lines::CommonLines;
+/// use rim::cryoem::euler::Convention;
+/// # use rim::color_space::ColorSpace;
+/// # use rim::grayscale::Gray32;
+/// # use rim::image_stack::ImageStack;
+/// # let projections = ImageStack::new(64, 64, vec![vec![0.0f32; 64 * 64]; 10], Gray32::new());
+///
+/// let common_lines = CommonLines::new(2.0);
+/// let (angles, lines) = common_lines.reconstitute(&projections, Convention::Relion);
+/// for (i, e) in angles.iter().enumerate() {
+///     println!("{} {:.2} {:.2} {:.2}", i, e.psi, e.theta, e.phi);
+/// }
+/// ```
+pub struct CommonLines {
+    angular_step: f32,
+    iterations: usize,
+}
+
+impl CommonLines {
+    ///
+    /// Creates an angular reconstitution sampling the lines every `angular_step` degrees.
+    ///
+    pub fn new(angular_step: f32) -> Self {
+        CommonLines {
+            angular_step,
+            iterations: 50,
+        }
+    }
+
+    pub fn get_angular_step(&self) -> f32 {
+        self.angular_step
+    }
+
+    ///
+    /// Sets the number of sweeps of the least-squares refinement (default 50).
+    ///
+    pub fn set_iterations(&mut self, iterations: usize) {
+        self.iterations = iterations;
+    }
+
+    ///
+    /// Returns the ramp-filtered sinogram of `projection` from 0 to 360 degrees,
+    /// each line being centered and normalized.
+    ///
+    pub fn lines(&self, projection: &FloatProcessor) -> FloatProcessor {
+        let angles = Sinogram::angles_in_range(0.0, 360.0, self.angular_step);
+        let sinogram =
+            Sinogram::with_interpolation(projection, &angles, InterpolationMode::Bilinear);
+        let mut lines = filter_sinogram(&sinogram, RampFilter::SheppLogan);
+        let n = lines.width as usize;
+        for row in lines.data.chunks_mut(n) {
+            let mean = row.iter().map(|v| *v as f64).sum::<f64>() / n as f64;
+            let norm = row
+                .iter()
+                .map(|v| (*v as f64 - mean).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            for v in row.iter_mut() {
+                *v = if norm > 0.0 {
+                    ((*v as f64 - mean) / norm) as f32
+                } else {
+                    0.0
+                };
+            }
+        }
+        lines
+    }
+
+    ///
+    /// Finds the common line of every pair of projections of `stack`. The pairs are
+    /// distributed among the available threads.
+    ///
+    pub fn find(&self, stack: &ImageStack<f32, Gray32>) -> Vec<CommonLine> {
+        let (w, h) = (stack.get_width(), stack.get_height());
+        let lines: Vec<FloatProcessor> = stack
+            .data()
+            .iter()
+            .map(|pixels| self.lines(&FloatProcessor::new(w, h, pixels.clone(), Gray32::new())))
+            .collect();
+        let n = lines.len();
+        let pairs: Vec<(usize, usize)> = (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .collect();
+        if pairs.is_empty() {
+            return vec![];
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = pairs.len().div_ceil(threads);
+        let step = self.angular_step;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = pairs
+                .chunks(chunk)
+                .map(|pairs| {
+                    let lines = &lines;
+                    scope.spawn(move || {
+                        pairs
+                            .iter()
+                            .map(|(i, j)| best_pair(&lines[*i], &lines[*j], *i, *j, step))
+                            .collect::<Vec<CommonLine>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    ///
+    /// Estimates the orientations of `n` projections from their common lines.
+    ///
+    /// Returns the rotation matrices (RELION definition: `p = A r` maps the volume coordinates
+    /// to the projection coordinates), the first one being the identity.
+    ///
+    pub fn orientations(&self, lines: &[CommonLine], n: usize) -> Vec<Matrix3> {
+        if n == 0 {
+            return vec![];
+        }
+        // Image axes in 3D: the columns of R_i = A_i^T
+        let weights = vote(lines, n);
+        let mut rotations = spectral(lines, &weights, n);
+        refine(&mut rotations, lines, &weights, self.iterations);
+        let reference = rotations[0].transpose();
+        rotations
+            .iter()
+            .map(|r| {
+                // A_i' = A_i A_0^T
+                let a = (reference * r).transpose();
+                Matrix3::from_array([
+                    a[(0, 0)],
+                    a[(0, 1)],
+                    a[(0, 2)],
+                    a[(1, 0)],
+                    a[(1, 1)],
+                    a[(1, 2)],
+                    a[(2, 0)],
+                    a[(2, 1)],
+                    a[(2, 2)],
+                ])
+            })
+            .collect()
+    }
+
+    ///
+    /// Finds the common lines of the projections of `stack` and returns the estimated Euler
+    /// angles in the given convention with the common lines.
+    ///
+    pub fn reconstitute(
+        &self,
+        stack: &ImageStack<f32, Gray32>,
+        convention: Convention,
+    ) -> (Vec<Euler>, Vec<CommonLine>) {
+        let lines = self.find(stack);
+        let angles = self
+            .orientations(&lines, stack.n_slices() as usize)
+            .iter()
+            .map(|a| Euler::from_matrix(a, convention))
+            .collect();
+        (angles, lines)
+    }
+}
+
+///
+/// Returns the mean angular error (degrees) between the relative orientations `A_i A_j^T`
+/// of `estimated` and `reference` over all the pairs. As the handedness is undetermined,
+/// the error of the mirrored estimation is also computed and the smallest is returned.
+///
+pub fn relative_error(estimated: &[Matrix3], reference: &[Matrix3]) -> f64 {
+    let n = estimated.len().min(reference.len());
+    if n < 2 {
+        return 0.0;
+    }
+    let j = Matrix3::from_array([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0]);
+    let mirror: Vec<Matrix3> = estimated.iter().map(|a| j * a.build() * j).collect();
+    let error = |set: &[Matrix3]| {
+        let mut sum = 0.0;
+        for p in 0..n {
+            for q in p + 1..n {
+                sum += angle_between(
+                    &relative(&set[p], &set[q]),
+                    &relative(&reference[p], &reference[q]),
+                );
+            }
+        }
+        sum / (n * (n - 1) / 2) as f64
+    };
+    error(estimated).min(error(&mirror))
+}
+
+//
+// Private functions
+//
+
+// Best pair of lines: all the lines of `a` (0-360) against the lines of `b` from 0 to 180
+// (the line at angle + 180 is the reversed line)
+fn best_pair(a: &FloatProcessor, b: &FloatProcessor, i: usize, j: usize, step: f32) -> CommonLine {
+    let n = a.width as usize;
+    let half = (b.height as usize).div_ceil(2);
+    let mut best = CommonLine {
+        i,
+        j,
+        angle_i: 0.0,
+        angle_j: 0.0,
+        score: f64::MIN,
+    };
+    for (ka, la) in a.data.chunks(n).enumerate() {
+        for (kb, lb) in b.data.chunks(n).take(half).enumerate() {
+            let score: f64 = la
+                .iter()
+                .zip(lb.iter())
+                .map(|(u, v)| (*u * *v) as f64)
+                .sum();
+            if score > best.score {
+                best.score = score;
+                best.angle_i = ka as f32 * step;
+                best.angle_j = kb as f32 * step;
+            }
+        }
+    }
+    best
+}
+
+// A B^T
+fn relative(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut bt = b.build();
+    bt.transpose();
+    a.build() * bt.build()
+}
+
+fn direction(angle: f32) -> (f64, f64) {
+    let (s, c) = (angle as f64).to_radians().sin_cos();
+    (c, s)
+}
+
+// Voting (Singer et al., 2010): each third projection `k` gives the angle between the planes
+// of `i` and `j` from the spherical triangle of the common lines (i, j), (i, k) and (j, k).
+// The weight of a pair is the fraction of the votes agreeing with the most voted angle.
+fn vote(lines: &[CommonLine], n: usize) -> Vec<f64> {
+    if n < 3 {
+        return vec![1.0; lines.len()];
+    }
+    let mut angles = vec![f64::NAN; n * n];
+    for line in lines.iter() {
+        angles[line.i * n + line.j] = (line.angle_i as f64).to_radians();
+        angles[line.j * n + line.i] = (line.angle_j as f64).to_radians();
+    }
+    let bins = 180;
+    lines
+        .iter()
+        .map(|line| {
+            let (i, j) = (line.i, line.j);
+            let votes: Vec<f64> = (0..n)
+                .filter(|k| *k != i && *k != j)
+                .filter_map(|k| {
+                    let alpha = angles[i * n + k] - angles[i * n + j];
+                    let beta = angles[j * n + k] - angles[j * n + i];
+                    let gamma = angles[k * n + j] - angles[k * n + i];
+                    let sines = alpha.sin().abs() * beta.sin().abs();
+                    if sines < 1e-3 {
+                        return None;
+                    }
+                    let cosine = (gamma.cos() - alpha.cos() * beta.cos()) / sines;
+                    (cosine.abs() <= 1.0).then(|| cosine.acos().to_degrees())
+                })
+                .collect();
+            if votes.is_empty() {
+                return 0.0;
+            }
+            let mut histogram = vec![0.0f64; bins];
+            for v in votes.iter() {
+                for (b, h) in histogram.iter_mut().enumerate() {
+                    let d = (b as f64 + 0.5 - v) / 2.0;
+                    *h += (-0.5 * d * d).exp();
+                }
+            }
+            let peak = histogram
+                .iter()
+                .enumerate()
+                .fold(
+                    (0, f64::MIN),
+                    |best, (b, h)| if *h > best.1 { (b, *h) } else { best },
+                )
+                .0 as f64
+                + 0.5;
+            let agreeing = votes.iter().filter(|v| (*v - peak).abs() < 5.0).count();
+            agreeing as f64 / (n - 2) as f64
+        })
+        .collect()
+}
+
+// Eigenvector method: the 3 leading eigenvectors of the common line matrix S span the
+// first two columns of the rotations
+fn spectral(lines: &[CommonLine], weights: &[f64], n: usize) -> Vec<Mat3<f64>> {
+    let mut s = DMatrix::<f64>::zeros(2 * n, 2 * n);
+    for (line, weight) in lines.iter().zip(weights.iter()) {
+        let (xi, yi) = direction(line.angle_i);
+        let (xj, yj) = direction(line.angle_j);
+        let block = [[xi * xj, xi * yj], [yi * xj, yi * yj]].map(|row| row.map(|v| v * weight));
+        for (u, row) in block.iter().enumerate() {
+            for (v, value) in row.iter().enumerate() {
+                s[(2 * line.i + u, 2 * line.j + v)] = *value;
+                s[(2 * line.j + v, 2 * line.i + u)] = *value;
+            }
+        }
+    }
+    let eigen = SymmetricEigen::new(s);
+    let mut order: Vec<usize> = (0..2 * n).collect();
+    order.sort_by(|a, b| eigen.eigenvalues[*b].total_cmp(&eigen.eigenvalues[*a]));
+    let v = DMatrix::<f64>::from_fn(2 * n, 3, |r, c| eigen.eigenvectors[(r, order[c])]);
+
+    // W = V A with the rows of W of unit length and orthogonal by pairs: B = A A^T
+    let quadratic = |u: &[f64], w: &[f64]| {
+        [
+            u[0] * w[0],
+            u[0] * w[1] + u[1] * w[0],
+            u[0] * w[2] + u[2] * w[0],
+            u[1] * w[1],
+            u[1] * w[2] + u[2] * w[1],
+            u[2] * w[2],
+        ]
+    };
+    let mut m = DMatrix::<f64>::zeros(3 * n, 6);
+    let mut rhs = DVector::<f64>::zeros(3 * n);
+    for i in 0..n {
+        let u: Vec<f64> = (0..3).map(|c| v[(2 * i, c)]).collect();
+        let w: Vec<f64> = (0..3).map(|c| v[(2 * i + 1, c)]).collect();
+        for (k, (a, b, value)) in [(&u, &u, 1.0), (&w, &w, 1.0), (&u, &w, 0.0)]
+            .iter()
+            .enumerate()
+        {
+            for (c, q) in quadratic(a, b).iter().enumerate() {
+                m[(3 * i + k, c)] = *q;
+            }
+            rhs[3 * i + k] = *value;
+        }
+    }
+    let b = (m.transpose() * &m)
+        .lu()
+        .solve(&(m.transpose() * rhs))
+        .unwrap_or_else(|| DVector::from_vec(vec![1.0, 0.0, 0.0, 1.0, 0.0, 1.0]));
+    let b = Mat3::new(b[0], b[1], b[2], b[1], b[3], b[4], b[2], b[4], b[5]);
+    // A = B^(1/2)
+    let eigen = b.symmetric_eigen();
+    let root = eigen.eigenvalues.map(|l| l.max(1e-12).sqrt());
+    let a = eigen.eigenvectors * Mat3::from_diagonal(&root) * eigen.eigenvectors.transpose();
+    let w = v * a;
+    (0..n)
+        .map(|i| {
+            let r1 = Vec3::new(w[(2 * i, 0)], w[(2 * i, 1)], w[(2 * i, 2)]);
+            let r2 = Vec3::new(w[(2 * i + 1, 0)], w[(2 * i + 1, 1)], w[(2 * i + 1, 2)]);
+            nearest_rotation(&Mat3::from_columns(&[r1, r2, r1.cross(&r2)]))
+        })
+        .collect()
+}
+
+// Least squares: minimizes the sum of w |R_i c_ij - R_j c_ji|^2 one rotation at a time
+// (orthogonal Procrustes problem). The weights are reduced for the lines far from their
+// fitted position (Cauchy robust estimator).
+fn refine(rotations: &mut [Mat3<f64>], lines: &[CommonLine], weights: &[f64], iterations: usize) {
+    let n = rotations.len();
+    let vector = |angle: f32| {
+        let (x, y) = direction(angle);
+        Vec3::new(x, y, 0.0)
+    };
+    for _ in 0..iterations {
+        let robust: Vec<f64> = lines
+            .iter()
+            .zip(weights.iter())
+            .map(|(line, weight)| {
+                let a = rotations[line.i] * vector(line.angle_i);
+                let b = rotations[line.j] * vector(line.angle_j);
+                let residual = a.angle(&b).to_degrees() / ROBUST_SCALE;
+                weight / (1.0 + residual * residual)
+            })
+            .collect();
+        for i in 0..n {
+            let mut m = Mat3::<f64>::zeros();
+            for (line, weight) in lines.iter().zip(robust.iter()) {
+                let (own, other, k) = if line.i == i {
+                    (line.angle_i, line.angle_j, line.j)
+                } else if line.j == i {
+                    (line.angle_j, line.angle_i, line.i)
+                } else {
+                    continue;
+                };
+                m += rotations[k] * vector(other) * vector(own).transpose() * *weight;
+            }
+            if m.norm() > 0.0 {
+                rotations[i] = nearest_rotation(&m);
+            }
+        }
+    }
+}
+
+// Closest rotation matrix (polar decomposition)
+fn nearest_rotation(m: &Mat3<f64>) -> Mat3<f64> {
+    let svd = m.svd(true, true);
+    let (mut u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+    if (u * v_t).determinant() < 0.0 {
+        let c = -u.column(2);
+        u.set_column(2, &c);
+    }
+    u * v_t
+}
+
+// Asymmetric phantom of the tests: gaussian blobs of different sizes and densities
+#[cfg(test)]
+pub(crate) fn phantom() -> VolumeProcessor<f32> {
+    let n = 32;
+    let blobs = [
+        (16.0, 16.0, 16.0, 5.0, 1.0),
+        (9.0, 14.0, 18.0, 2.5, 2.0),
+        (21.0, 9.0, 12.0, 2.0, 1.5),
+        (18.0, 22.0, 22.0, 3.0, 1.2),
+        (12.0, 20.0, 9.0, 1.5, 3.0),
+    ];
+    let voxels = (0..n * n * n)
+        .map(|i| {
+            let (x, y, z) = ((i % n) as f64, ((i / n) % n) as f64, (i / (n * n)) as f64);
+            blobs
+                .iter()
+                .map(|(bx, by, bz, r, d)| {
+                    let d2 = (x - bx).powi(2) + (y - by).powi(2) + (z - bz).powi(2);
+                    d * (-d2 / (2.0 * r * r)).exp()
+                })
+                .sum::<f64>() as f32
+        })
+        .collect();
+    VolumeProcessor::new_volume(n as u32, n as u32, n as u32, voxels, Gray32::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cryoem::euler::read_angles;
+    use crate::cryoem::projection::{ProjectionMode, Projector};
+    use crate::io::text_reader::TextReader;
+    use crate::io::tiff_decoder::TiffDecoder;
+
+    #[test]
+    fn reconstitute_phantom_orientations() {
+        let volume = phantom();
+        let projector = Projector::new(ProjectionMode::RayDriven);
+        // Orientations spread over the sphere
+        let truth: Vec<Matrix3> = (0..16)
+            .map(|k| {
+                let z = 1.0 - (2.0 * k as f64 + 1.0) / 16.0;
+                let phi = k as f64 * 137.508;
+                let theta = z.acos().to_degrees();
+                Euler::new(k as f64 * 23.0, theta, phi).to_matrix(Convention::Relion)
+            })
+            .collect();
+        let slices = truth
+            .iter()
+            .map(|a| projector.project(&volume, a).data)
+            .collect();
+        let stack = ImageStack::new(32, 32, slices, Gray32::new());
+        let common_lines = CommonLines::new(2.0);
+        let lines = common_lines.find(&stack);
+        assert_eq!(lines.len(), 16 * 15 / 2);
+        let estimated = common_lines.orientations(&lines, 16);
+        assert!(angle_between(&estimated[0], &Matrix3::identity()) < 1e-6);
+
+        let err = relative_error(&estimated, &truth);
+        assert!(err < 3.0, "mean error {} degrees", err);
+    }
+
+    #[test]
+    fn sample_projections() {
+        // The angles of the sample are RELION angles with the y-axis pointing up
+        // (same as `cmonlines --ref-convention relion --flip-y`)
+        // One projection out of four, spread over the sphere
+        let (output, _) =
+            TiffDecoder::open("./samples/projections-t1-head-psi-theta-phi-50.tif").unwrap();
+        let all = output.into_float_stack().unwrap();
+        let slices: Vec<Vec<f32>> = all.data.into_iter().step_by(4).collect();
+        let n = slices.len();
+        let stack = ImageStack::new(all.width, all.height, slices, Gray32::new());
+        let table = TextReader::open_csv("./samples/psi-theta-phi-50.csv", None).unwrap();
+        let flip = Matrix3::from_array([1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, -1.0]);
+        let angles = read_angles(&table).unwrap();
+        let known: Vec<Matrix3> = angles
+            .iter()
+            .step_by(4)
+            .map(|e| flip.build() * e.to_matrix(Convention::Relion))
+            .collect();
+        let common_lines = CommonLines::new(2.0);
+        let estimated = common_lines.orientations(&common_lines.find(&stack), n);
+        let err = relative_error(&estimated, &known);
+        assert!(err < 3.0, "mean error {} degrees", err);
+        // The direction columns of the sample follow the Yxz convention
+        for (i, e) in angles.iter().enumerate() {
+            let v = e.direction(Convention::Yxz);
+            let x = table.get_column_as_floats(String::from("x")).unwrap()[i];
+            let z = table.get_column_as_floats(String::from("z")).unwrap()[i];
+            assert!((v.x - x).abs() < 2e-3 && (v.z - z).abs() < 2e-3, "{}", i);
+        }
+    }
+}
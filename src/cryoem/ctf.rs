@@ -0,0 +1,720 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Contrast Transfer Function (CTF) of the electron microscope.
+//!
+//! The CTF at the spatial frequency `s` (in 1/Å) of direction `alpha` is
+//! `CTF(s) = -E(s) sin(chi(s))` with
+//! `chi(s) = pi lambda df(alpha) s^2 - pi/2 Cs lambda^3 s^4 + phase_shift + atan(Q / sqrt(1 - Q^2))`,
+//! the envelope `E(s) = exp(-B s^2 / 4)` and the astigmatic defocus
+//! `df(alpha) = (dU + dV + (dU - dV) cos(2 (alpha - angle))) / 2`.
+//! A positive defocus is an underfocus, as in RELION and CTFFIND.
+//!
+//! The estimation follows CTFFIND: the amplitude spectra of overlapping tiles of the
+//! micrograph are averaged (periodogram), the smooth background is subtracted, and the
+//! parameters maximize the correlation between the Thon rings and `CTF^2` between two
+//! resolution limits.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::complex_image::ComplexImage;
+use crate::fft::frequency;
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_processor::ImageProcessor;
+use crate::io::star::*;
+use crate::pixel::PixelType;
+use std::f64::consts::PI;
+
+/// Columns of the STAR files (RELION)
+pub const STAR_COLUMNS: [&str; 9] = [
+    "rlnMicrographName",
+    "rlnDefocusU",
+    "rlnDefocusV",
+    "rlnDefocusAngle",
+    "rlnVoltage",
+    "rlnSphericalAberration",
+    "rlnAmplitudeContrast",
+    "rlnPhaseShift",
+    "rlnCtfBfactor",
+];
+
+///
+/// Parameters of the CTF
+///
+/// # Example
+///
+/// ```rust
+/// use rim::cryoem::ctf::Ctf;
+///
+/// let ctf = Ctf::new(15000.0, 14000.0, 30.0);
+/// // At the origin, the CTF is the (negative) amplitude contrast
+/// assert!((ctf.value(0.0, 0.0) + 0.1).abs() < 1e-9);
+/// let image = ctf.image(128, 128, 1.5);
+/// assert_eq!(image.data[64 + 64 * 128], -0.1);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ctf {
+    /// Defocus along the astigmatism direction in Å
+    pub defocus_u: f64,
+    /// Defocus perpendicular to the astigmatism direction in Å
+    pub defocus_v: f64,
+    /// Astigmatism angle in degrees (from the X axis)
+    pub astigmatism_angle: f64,
+    /// Spherical aberration in mm
+    pub cs: f64,
+    /// Acceleration voltage in kV
+    pub voltage: f64,
+    /// Fraction of amplitude contrast
+    pub amplitude_contrast: f64,
+    /// Additional phase shift (phase plate) in degrees
+    pub phase_shift: f64,
+    /// B-factor of the envelope in Å^2
+    pub b_factor: f64,
+}
+
+impl Default for Ctf {
+    fn default() -> Self {
+        Ctf {
+            defocus_u: 10000.0,
+            defocus_v: 10000.0,
+            astigmatism_angle: 0.0,
+            cs: 2.7,
+            voltage: 300.0,
+            amplitude_contrast: 0.1,
+            phase_shift: 0.0,
+            b_factor: 0.0,
+        }
+    }
+}
+
+impl Ctf {
+    ///
+    /// Creates the CTF of the defoci `defocus_u`, `defocus_v` (Å) and the astigmatism
+    /// angle (degrees) for a microscope at 300 kV with a Cs of 2.7 mm and 10% of amplitude contrast.
+    ///
+    pub fn new(defocus_u: f64, defocus_v: f64, astigmatism_angle: f64) -> Self {
+        Ctf {
+            defocus_u,
+            defocus_v,
+            astigmatism_angle,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the relativistic wavelength of the electrons in Å.
+    pub fn wavelength(&self) -> f64 {
+        let volts = self.voltage * 1000.0;
+        12.2643247 / (volts * (1.0 + volts * 0.978466e-6)).sqrt()
+    }
+
+    /// Returns the defocus in Å along the direction `alpha` (radians).
+    pub fn defocus(&self, alpha: f64) -> f64 {
+        let angle = self.astigmatism_angle.to_radians();
+        0.5 * (self.defocus_u
+            + self.defocus_v
+            + (self.defocus_u - self.defocus_v) * (2.0 * (alpha - angle)).cos())
+    }
+
+    /// Returns the value of the CTF at the spatial frequency `(sx, sy)` in 1/Å.
+    pub fn value(&self, sx: f64, sy: f64) -> f64 {
+        let s2 = sx * sx + sy * sy;
+        let envelope = if self.b_factor != 0.0 {
+            (-self.b_factor * s2 / 4.0).exp()
+        } else {
+            1.0
+        };
+        -envelope * self.phase(s2, sy.atan2(sx)).sin()
+    }
+
+    ///
+    /// Returns the image of the CTF of size `width` x `height` with the zero frequency at
+    /// the center `(width / 2, height / 2)`, for a pixel size `pixel_size` in Å.
+    ///
+    pub fn image(&self, width: u32, height: u32, pixel_size: f64) -> FloatProcessor {
+        let (w, h) = (width as i64, height as i64);
+        let mut pixels = Vec::<f32>::with_capacity((w * h) as usize);
+        for y in 0..h {
+            let sy = (y - h / 2) as f64 / (h as f64 * pixel_size);
+            for x in 0..w {
+                let sx = (x - w / 2) as f64 / (w as f64 * pixel_size);
+                pixels.push(self.value(sx, sy) as f32);
+            }
+        }
+        ImageProcessor::new(width, height, pixels, Gray32::new())
+    }
+
+    /// Returns the image `ip` multiplied by this CTF in Fourier space.
+    pub fn apply<T: PixelType, C: ColorSpace>(
+        &self,
+        ip: &ImageProcessor<T, C>,
+        pixel_size: f64,
+    ) -> FloatProcessor {
+        self.filter(ip, pixel_size, |ctf| ctf)
+    }
+
+    ///
+    /// Returns the image `ip` with the phases flipped where the CTF is negative.
+    ///
+    pub fn phase_flip<T: PixelType, C: ColorSpace>(
+        &self,
+        ip: &ImageProcessor<T, C>,
+        pixel_size: f64,
+    ) -> FloatProcessor {
+        self.filter(ip, pixel_size, |ctf| if ctf < 0.0 { -1.0 } else { 1.0 })
+    }
+
+    ///
+    /// Returns the image `ip` corrected by the Wiener filter `CTF / (CTF^2 + 1 / snr)`
+    /// for the signal-to-noise ratio `snr`.
+    ///
+    pub fn wiener_filter<T: PixelType, C: ColorSpace>(
+        &self,
+        ip: &ImageProcessor<T, C>,
+        pixel_size: f64,
+        snr: f64,
+    ) -> FloatProcessor {
+        self.filter(ip, pixel_size, |ctf| ctf / (ctf * ctf + 1.0 / snr))
+    }
+
+    //
+    // Private methods
+    //
+
+    fn phase(&self, s2: f64, alpha: f64) -> f64 {
+        let lambda = self.wavelength();
+        let q = self.amplitude_contrast;
+        PI * lambda * self.defocus(alpha) * s2 - 0.5 * PI * self.cs * 1e7 * lambda.powi(3) * s2 * s2
+            + self.phase_shift.to_radians()
+            + (q / (1.0 - q * q).sqrt()).atan()
+    }
+
+    // Multiplies the spectrum of `ip` by `f(CTF)`
+    fn filter<T: PixelType, C: ColorSpace, F: Fn(f64) -> f64>(
+        &self,
+        ip: &ImageProcessor<T, C>,
+        pixel_size: f64,
+        f: F,
+    ) -> FloatProcessor {
+        let mut spectrum = ComplexImage::fft(ip);
+        let (w, h) = (ip.width as usize, ip.height as usize);
+        for y in 0..h {
+            let sy = frequency(y, h) / pixel_size;
+            for x in 0..w {
+                let sx = frequency(x, w) / pixel_size;
+                spectrum.data[x + y * w] *= f(self.value(sx, sy));
+            }
+        }
+        spectrum.ifft()
+    }
+
+    // Same CTF with dU >= dV and the angle in ]-90, 90]
+    fn normalized(mut self) -> Self {
+        if self.defocus_u < self.defocus_v {
+            std::mem::swap(&mut self.defocus_u, &mut self.defocus_v);
+            self.astigmatism_angle += 90.0;
+        }
+        self.astigmatism_angle = -(-self.astigmatism_angle + 90.0).rem_euclid(180.0) + 90.0;
+        self
+    }
+}
+
+///
+/// Result of the CTF estimation
+///
+#[derive(Clone, Copy, Debug)]
+pub struct CtfFit {
+    pub ctf: Ctf,
+    /// Correlation between the Thon rings and `CTF^2` in the resolution range
+    pub score: f64,
+}
+
+///
+/// Estimation of the CTF of micrographs (CTFFIND-like)
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::ctf::{Ctf, CtfEstimator};
+/// use rim::grayscale::Gray32;
+/// use rim::image_processor::ImageProcessor;
+///
+/// // White noise seen through the microscope
+/// let mut seed = 7u32;
+/// let noise = (0..128 * 128)
+///     .map(|_| {
+///         seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+///         (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+///     })
+///     .collect();
+/// let ip = ImageProcessor::<f32, Gray32>::new(128, 128, noise, Gray32::new());
+/// let micrograph = Ctf::new(12000.0, 12000.0, 0.0).apply(&ip, 2.0);
+///
+/// let mut estimator = CtfEstimator::new(2.0);
+/// estimator.set_box_size(64);
+/// let fit = estimator.estimate(&micrograph).unwrap();
+/// assert!((fit.ctf.defocus_u - 12000.0).abs() < 500.0);
+/// ```
+pub struct CtfEstimator {
+    pixel_size: f64,
+    box_size: u32,
+    resolution: (f64, f64),
+    defocus: (f64, f64, f64),
+    template: Ctf,
+}
+
+impl CtfEstimator {
+    ///
+    /// Creates an estimator for micrographs of pixel size `pixel_size` in Å.
+    ///
+    /// The defaults are tiles of 256 pixels, a fit between 30 Å and 2.5 pixels, a defocus
+    /// search from 5000 to 50000 Å by steps of 500 Å, and the microscope of [Ctf::default()].
+    ///
+    pub fn new(pixel_size: f64) -> Self {
+        CtfEstimator {
+            pixel_size,
+            box_size: 256,
+            resolution: (30.0, 2.5 * pixel_size),
+            defocus: (5000.0, 50000.0, 500.0),
+            template: Ctf::default(),
+        }
+    }
+
+    pub fn set_box_size(&mut self, size: u32) {
+        self.box_size = size;
+    }
+
+    pub fn get_box_size(&self) -> u32 {
+        self.box_size
+    }
+
+    /// Sets the lowest and the highest resolutions (Å) of the fit.
+    pub fn set_resolution_range(&mut self, low: f64, high: f64) {
+        self.resolution = (low, high);
+    }
+
+    pub fn get_resolution_range(&self) -> (f64, f64) {
+        self.resolution
+    }
+
+    /// Sets the range and the step (Å) of the initial defocus search.
+    pub fn set_defocus_range(&mut self, min: f64, max: f64, step: f64) {
+        self.defocus = (min, max, step);
+    }
+
+    pub fn get_defocus_range(&self) -> (f64, f64, f64) {
+        self.defocus
+    }
+
+    ///
+    /// Sets the known parameters (voltage, Cs, amplitude contrast, phase shift and B-factor)
+    /// of the fitted CTFs. The defoci of `ctf` are ignored.
+    ///
+    pub fn set_microscope(&mut self, ctf: &Ctf) {
+        self.template = *ctf;
+    }
+
+    ///
+    /// Returns the averaged amplitude spectrum of the tiles (half-overlapping) of `ip`,
+    /// with the zero frequency at the center.
+    ///
+    pub fn periodogram<T: PixelType, C: ColorSpace>(
+        &self,
+        ip: &ImageProcessor<T, C>,
+    ) -> Result<FloatProcessor, String> {
+        let n = self.box_size;
+        if n < 16 || ip.width < n || ip.height < n {
+            return Err(format!(
+                "Image of {}x{} pixels too small for tiles of {} pixels",
+                ip.width, ip.height, n
+            ));
+        }
+        let step = (n / 2) as usize;
+        let size = n as usize;
+        let width = ip.width as usize;
+        let mut sums = vec![0.0f64; size * size];
+        let mut count = 0;
+        for y0 in (0..=ip.height as usize - size).step_by(step) {
+            for x0 in (0..=width - size).step_by(step) {
+                let mut tile = Vec::<f32>::with_capacity(size * size);
+                for y in y0..y0 + size {
+                    let row = &ip.data[y * width + x0..y * width + x0 + size];
+                    tile.extend(row.iter().map(|v| v.to_f32()));
+                }
+                let mean = tile.iter().sum::<f32>() / tile.len() as f32;
+                tile.iter_mut().for_each(|v| *v -= mean);
+                let tile = ImageProcessor::<f32, Gray32>::new(n, n, tile, Gray32::new());
+                let mut spectrum = ComplexImage::fft(&tile);
+                spectrum.shift();
+                for (sum, v) in sums.iter_mut().zip(spectrum.data.iter()) {
+                    *sum += v.norm_sqr();
+                }
+                count += 1;
+            }
+        }
+        let pixels = sums
+            .iter()
+            .map(|v| (v / count as f64).sqrt() as f32)
+            .collect();
+        Ok(ImageProcessor::new(n, n, pixels, Gray32::new()))
+    }
+
+    ///
+    /// Returns the CTF of the micrograph `ip`: exhaustive search of the defocus without
+    /// astigmatism, then local refinement of the two defoci and the astigmatism angle.
+    ///
+    pub fn estimate<T: PixelType, C: ColorSpace>(
+        &self,
+        ip: &ImageProcessor<T, C>,
+    ) -> Result<CtfFit, String> {
+        let rings = Rings::new(&self.periodogram(ip)?, self.pixel_size, self.resolution)?;
+        let (min, max, step) = self.defocus;
+        if step <= 0.0 || max < min {
+            return Err(format!(
+                "Invalid defocus range {}..{} step {}",
+                min, max, step
+            ));
+        }
+        let mut ctf = self.template;
+        let mut best = (f64::MIN, min);
+        let mut defocus = min;
+        while defocus <= max {
+            ctf.defocus_u = defocus;
+            ctf.defocus_v = defocus;
+            let score = rings.score(&ctf);
+            if score > best.0 {
+                best = (score, defocus);
+            }
+            defocus += step;
+        }
+        ctf.defocus_u = best.1;
+        ctf.defocus_v = best.1;
+        ctf.astigmatism_angle = 0.0;
+        let (ctf, score) = refine(&rings, ctf, step);
+        Ok(CtfFit {
+            ctf: ctf.normalized(),
+            score,
+        })
+    }
+}
+
+///
+/// Returns the STAR data block `micrographs` of the CTFs with the image names `names`
+/// (the indices starting at 1 if missing).
+///
+pub fn to_star(ctfs: &[Ctf], names: &[String]) -> DataSet {
+    let mut table = Table::new(String::new());
+    for head in STAR_COLUMNS.iter() {
+        table.add_column_head(head.to_string());
+    }
+    for (i, ctf) in ctfs.iter().enumerate() {
+        let mut row = Row::new(i);
+        let name = names.get(i).cloned().unwrap_or_else(|| (i + 1).to_string());
+        row.push(Field::Text(name));
+        for value in [
+            ctf.defocus_u,
+            ctf.defocus_v,
+            ctf.astigmatism_angle,
+            ctf.voltage,
+            ctf.cs,
+            ctf.amplitude_contrast,
+            ctf.phase_shift,
+            ctf.b_factor,
+        ] {
+            row.push(Field::Number((value * 1000.0).round() / 1000.0 + 0.0));
+        }
+        table.add_row(row);
+    }
+    let mut block = DataSet::new(String::from("micrographs"));
+    block.push(Container::Table(table));
+    block
+}
+
+///
+/// Reads the CTFs of the first table of `block` (columns `rlnDefocusU`, and optionally
+/// the other columns of [STAR_COLUMNS], the missing ones taking the values of [Ctf::default()]).
+///
+pub fn from_star(block: &DataSet) -> Result<Vec<Ctf>, String> {
+    let table = block
+        .items()
+        .iter()
+        .find_map(|item| item.to_table().ok())
+        .ok_or_else(|| format!("No table in data_{}", block.name()))?;
+    let column = |name: &str| table.column_index(name);
+    let defocus_u =
+        column("rlnDefocusU").ok_or_else(|| String::from("Missing column _rlnDefocusU"))?;
+    let columns: Vec<Option<usize>> = STAR_COLUMNS[2..].iter().map(|name| column(name)).collect();
+    let default = Ctf::default();
+    table
+        .rows()
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let value = |c: Option<usize>, default: f64| match c {
+                Some(c) => row
+                    .cells()
+                    .get(c)
+                    .and_then(|f| f.to_f64())
+                    .ok_or_else(|| format!("Invalid value in row {} column {}", i + 1, c + 1)),
+                None => Ok(default),
+            };
+            let u = value(Some(defocus_u), 0.0)?;
+            Ok(Ctf {
+                defocus_u: u,
+                defocus_v: value(columns[0], u)?,
+                astigmatism_angle: value(columns[1], 0.0)?,
+                voltage: value(columns[2], default.voltage)?,
+                cs: value(columns[3], default.cs)?,
+                amplitude_contrast: value(columns[4], default.amplitude_contrast)?,
+                phase_shift: value(columns[5], 0.0)?,
+                b_factor: value(columns[6], 0.0)?,
+            })
+        })
+        .collect()
+}
+
+//
+// Private structures and functions
+//
+
+// Background-subtracted periodogram in the resolution range
+struct Rings {
+    // (s^2, direction, normalized amplitude) of the pixels
+    samples: Vec<(f64, f64, f64)>,
+}
+
+impl Rings {
+    fn new(
+        periodogram: &FloatProcessor,
+        pixel_size: f64,
+        resolution: (f64, f64),
+    ) -> Result<Self, String> {
+        let n = periodogram.width as i64;
+        let c = n / 2;
+        let radius = |x: i64, y: i64| (((x - c).pow(2) + (y - c).pow(2)) as f64).sqrt();
+        // Radial profile and its smooth background
+        let bins = c as usize + 1;
+        let mut profile = vec![(0.0f64, 0usize); bins];
+        for y in 0..n {
+            for x in 0..n {
+                let r = radius(x, y).round() as usize;
+                if r < bins {
+                    profile[r].0 += periodogram.data[(x + y * n) as usize] as f64;
+                    profile[r].1 += 1;
+                }
+            }
+        }
+        let profile: Vec<f64> = profile.iter().map(|(s, k)| s / *k as f64).collect();
+        let background = smooth(&profile, (n / 32).max(2) as usize);
+        let residual = |x: i64, y: i64| -> Option<f64> {
+            let r = radius(x, y);
+            let i = r.floor() as usize;
+            if i + 1 >= bins {
+                return None;
+            }
+            let t = r - i as f64;
+            let bg = background[i] * (1.0 - t) + background[i + 1] * t;
+            Some(periodogram.data[(x + y * n) as usize] as f64 - bg)
+        };
+        let (s_min, s_max) = (1.0 / resolution.0, 1.0 / resolution.1);
+        let scale = 1.0 / (n as f64 * pixel_size);
+        let mut samples = Vec::new();
+        // Half-plane (the spectrum is centrosymmetric)
+        for y in c..n {
+            for x in 0..n {
+                let (sx, sy) = ((x - c) as f64 * scale, (y - c) as f64 * scale);
+                let s = (sx * sx + sy * sy).sqrt();
+                if s < s_min || s > s_max || (y == c && x < c) {
+                    continue;
+                }
+                if let Some(value) = residual(x, y) {
+                    samples.push((s * s, sy.atan2(sx), value));
+                }
+            }
+        }
+        if samples.len() < 16 {
+            return Err(format!(
+                "Resolution range {}..{} Å too narrow for the periodogram",
+                resolution.0, resolution.1
+            ));
+        }
+        Ok(Rings { samples })
+    }
+
+    // Correlation between the rings and CTF^2
+    fn score(&self, ctf: &Ctf) -> f64 {
+        let n = self.samples.len() as f64;
+        let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (s2, alpha, value) in self.samples.iter() {
+            let model = ctf.phase(*s2, *alpha).sin().powi(2);
+            sa += value;
+            sb += model;
+            saa += value * value;
+            sbb += model * model;
+            sab += value * model;
+        }
+        let cov = sab - sa * sb / n;
+        let var = (saa - sa * sa / n) * (sbb - sb * sb / n);
+        if var > 0.0 {
+            cov / var.sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
+// Moving average of half-width `half`
+fn smooth(profile: &[f64], half: usize) -> Vec<f64> {
+    (0..profile.len())
+        .map(|i| {
+            let window = &profile[i.saturating_sub(half)..(i + half + 1).min(profile.len())];
+            window.iter().sum::<f64>() / window.len() as f64
+        })
+        .collect()
+}
+
+// Compass search of the two defoci and the astigmatism angle, until the defocus steps
+// are 1% of the step of the exhaustive search
+fn refine(rings: &Rings, mut ctf: Ctf, step: f64) -> (Ctf, f64) {
+    let mut best = rings.score(&ctf);
+    let mut steps = [step / 2.0, step / 2.0, 45.0];
+    while steps[0] > step / 100.0 {
+        let mut improved = false;
+        for (p, delta) in steps.iter().enumerate() {
+            for sign in [-1.0, 1.0] {
+                let mut trial = ctf;
+                match p {
+                    0 => trial.defocus_u += sign * delta,
+                    1 => trial.defocus_v += sign * delta,
+                    _ => trial.astigmatism_angle += sign * delta,
+                }
+                let score = rings.score(&trial);
+                if score > best {
+                    best = score;
+                    ctf = trial;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            steps.iter_mut().for_each(|s| *s /= 2.0);
+        }
+    }
+    (ctf, best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(w: u32, h: u32) -> FloatProcessor {
+        let mut seed = 12345u32;
+        let pixels = (0..w * h)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+            })
+            .collect();
+        ImageProcessor::new(w, h, pixels, Gray32::new())
+    }
+
+    #[test]
+    fn zeros_of_the_ctf() {
+        let mut ctf = Ctf::new(20000.0, 20000.0, 0.0);
+        ctf.cs = 0.0;
+        ctf.amplitude_contrast = 0.0;
+        assert!((ctf.wavelength() - 0.01969).abs() < 1e-4);
+        // First zero at s^2 = 1 / (lambda df)
+        let s = (1.0 / (ctf.wavelength() * 20000.0)).sqrt();
+        assert!(ctf.value(s, 0.0).abs() < 1e-9);
+        assert!(ctf.value(0.0, s / 2f64.sqrt()) < -0.99);
+        // Astigmatism: defocus U along the angle
+        let ctf = Ctf::new(20000.0, 10000.0, 30.0);
+        assert!((ctf.defocus(30f64.to_radians()) - 20000.0).abs() < 1e-6);
+        assert!((ctf.defocus(120f64.to_radians()) - 10000.0).abs() < 1e-6);
+        let normalized = Ctf::new(10000.0, 20000.0, 120.0).normalized();
+        assert_eq!(normalized.astigmatism_angle, 30.0);
+        assert_eq!(normalized.defocus_u, 20000.0);
+    }
+
+    #[test]
+    fn corrections() {
+        let ip = noise(64, 64);
+        let ctf = Ctf::new(8000.0, 8000.0, 0.0);
+        let observed = ctf.apply(&ip, 2.0);
+        // Phase flipping twice gives back the observed image
+        let flipped = ctf.phase_flip(&ctf.phase_flip(&observed, 2.0), 2.0);
+        assert!(flipped
+            .data
+            .iter()
+            .zip(observed.data.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-4));
+        // The Wiener filter restores the image better than the observation
+        let restored = ctf.wiener_filter(&observed, 2.0, 100.0);
+        let error = |a: &FloatProcessor| -> f32 {
+            a.data
+                .iter()
+                .zip(ip.data.iter())
+                .map(|(a, b)| (a - b).powi(2))
+                .sum()
+        };
+        assert!(error(&restored) < 0.5 * error(&observed));
+    }
+
+    #[test]
+    fn estimate_astigmatic_ctf() {
+        let truth = Ctf::new(13000.0, 11500.0, 30.0);
+        let micrograph = truth.apply(&noise(384, 384), 2.0);
+        let mut estimator = CtfEstimator::new(2.0);
+        estimator.set_box_size(128);
+        estimator.set_resolution_range(30.0, 6.0);
+        let fit = estimator.estimate(&micrograph).unwrap();
+        assert!(fit.score > 0.5, "{:?}", fit);
+        assert!((fit.ctf.defocus_u - 13000.0).abs() < 100.0, "{:?}", fit);
+        assert!((fit.ctf.defocus_v - 11500.0).abs() < 100.0, "{:?}", fit);
+        assert!((fit.ctf.astigmatism_angle - 30.0).abs() < 3.0, "{:?}", fit);
+    }
+
+    #[test]
+    fn refine_with_fine_defocus_step() {
+        let truth = Ctf::new(13000.0, 11500.0, 30.0);
+        let micrograph = truth.apply(&noise(384, 384), 2.0);
+        let mut estimator = CtfEstimator::new(2.0);
+        estimator.set_box_size(128);
+        estimator.set_resolution_range(30.0, 6.0);
+        estimator.set_defocus_range(12000.0, 12500.0, 10.0);
+        let fit = estimator.estimate(&micrograph).unwrap();
+        assert!((fit.ctf.defocus_u - 13000.0).abs() < 100.0, "{:?}", fit);
+        assert!((fit.ctf.defocus_v - 11500.0).abs() < 100.0, "{:?}", fit);
+        assert!((fit.ctf.astigmatism_angle - 30.0).abs() < 3.0, "{:?}", fit);
+    }
+
+    #[test]
+    fn star_round_trip() {
+        let mut ctf = Ctf::new(13000.0, 11500.0, 30.0);
+        ctf.phase_shift = 90.0;
+        let block = to_star(&[ctf, Ctf::default()], &[String::from("a.mrc")]);
+        let text = crate::io::star_writer::StarWriter::to_string(&[block]);
+        let blocks = crate::io::star_reader::StarReader::parse(&text).unwrap();
+        let ctfs = from_star(&blocks[0]).unwrap();
+        assert_eq!(ctfs, vec![ctf, Ctf::default()]);
+    }
+}
@@ -0,0 +1,372 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Euler angle conventions used in cryo-EM.
+//!
+//! All the conversions go through the rotation matrix of RELION/Xmipp (`Euler_angles2matrix`)
+//! which maps the coordinates of the volume to the coordinates of the projection. Its third row
+//! is the viewing direction.
+//!
+
+use crate::results_table::ResultsTable;
+use crate::vecmath::matrix3::Matrix3;
+use crate::vecmath::matrix4::Matrix4;
+use crate::vecmath::quaternion::Quaternion;
+use crate::vecmath::vector3::Vector3;
+
+// Below, sin(theta) is considered as null (gimbal lock)
+const EPSILON: f64 = 16.0 * f32::EPSILON as f64;
+
+///
+/// Euler angle conventions
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Convention {
+    /// ZYZ: `phi` (rot), `theta` (tilt), `psi`.
+    Relion,
+    /// ZYZ, same angles as RELION.
+    Spider,
+    /// ZYZ, same angles as SPIDER.
+    Frealign,
+    /// ZYZ, same angles as FREALIGN.
+    CisTem,
+    /// ZXZ: `phi` is az, `theta` is alt and `psi` is phi of EMAN2.
+    /// Equivalent to RELION with `rot = az - 90` and `psi = phi + 90`.
+    Eman,
+    /// Y-up convention: `theta` rotates around Y, `phi` is the elevation towards Y and `psi`
+    /// the in-plane rotation. The viewing direction is
+    /// `(cos(phi) sin(theta), sin(phi), cos(phi) cos(theta))`, as in the `x`, `y`, `z` columns
+    /// of `samples/psi-theta-phi-50.csv`. The projections of
+    /// `samples/projections-t1-head-psi-theta-phi-50.tif` are not related to these angles
+    /// by this convention: they follow [Convention::Relion] with the y-axis pointing up
+    /// (`cmonlines --ref-convention relion --flip-y`).
+    Yxz,
+}
+
+///
+/// Euler angles in degrees
+///
+/// # Example
+///
+/// ```rust
+/// use rim::cryoem::euler::{Convention, Euler};
+///
+/// let angles = Euler::new(0.0, 90.0, 90.0);
+/// let v = angles.direction(Convention::Yxz);
+/// assert!((v.y - 1.0).abs() < 1e-9);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Euler {
+    pub psi: f64,
+    pub theta: f64,
+    pub phi: f64,
+}
+
+impl Euler {
+    pub fn new(psi: f64, theta: f64, phi: f64) -> Self {
+        Euler { psi, theta, phi }
+    }
+
+    ///
+    /// Returns the rotation matrix (RELION definition) of these angles.
+    ///
+    pub fn to_matrix(&self, convention: Convention) -> Matrix3 {
+        let (psi, theta, phi) = (
+            self.psi.to_radians(),
+            self.theta.to_radians(),
+            self.phi.to_radians(),
+        );
+        match convention {
+            Convention::Relion | Convention::Spider | Convention::Frealign | Convention::CisTem => {
+                Matrix3::from_euler_zyz(phi, theta, psi)
+            }
+            Convention::Eman => {
+                Matrix3::from_euler_zyz(phi - 90f64.to_radians(), theta, psi + 90f64.to_radians())
+            }
+            Convention::Yxz => {
+                // Active rotation Ry(theta) Rx(-phi) Rz(psi), transposed
+                let (st, ct) = theta.sin_cos();
+                let (sp, cp) = phi.sin_cos();
+                let (ss, cs) = psi.sin_cos();
+                let ry = Matrix3::from_array([ct, 0.0, st, 0.0, 1.0, 0.0, -st, 0.0, ct]);
+                let rx = Matrix3::from_array([1.0, 0.0, 0.0, 0.0, cp, sp, 0.0, -sp, cp]);
+                let rz = Matrix3::from_array([cs, -ss, 0.0, ss, cs, 0.0, 0.0, 0.0, 1.0]);
+                let mut m = ry * rx * rz;
+                *m.transpose()
+            }
+        }
+    }
+
+    /// Same as [to_matrix()](Euler::to_matrix) as a 4x4 matrix without translation,
+    /// stored column by column like [Matrix4].
+    pub fn to_matrix4(&self, convention: Convention) -> Matrix4 {
+        let a = *self.to_matrix(convention).values();
+        Matrix4::from_array([
+            a[0], a[3], a[6], 0.0, //
+            a[1], a[4], a[7], 0.0, //
+            a[2], a[5], a[8], 0.0, //
+            0.0, 0.0, 0.0, 1.0,
+        ])
+    }
+
+    ///
+    /// Returns the Euler angles of a rotation matrix (RELION definition).
+    ///
+    /// With a null tilt, the whole in-plane rotation is stored in `psi`.
+    ///
+    pub fn from_matrix(mat: &Matrix3, convention: Convention) -> Euler {
+        let a = mat.values();
+        match convention {
+            Convention::Relion | Convention::Spider | Convention::Frealign | Convention::CisTem => {
+                // From `Euler_matrix2angles` of RELION
+                let abs_sb = (a[2] * a[2] + a[5] * a[5]).sqrt();
+                let (alpha, beta, gamma) = if abs_sb > EPSILON {
+                    let gamma = a[5].atan2(-a[2]);
+                    let alpha = a[7].atan2(a[6]);
+                    let sign_sb = if gamma.sin().abs() < f32::EPSILON as f64 {
+                        (-a[2] / gamma.cos()).signum()
+                    } else if gamma.sin() > 0.0 {
+                        a[5].signum()
+                    } else {
+                        -a[5].signum()
+                    };
+                    (alpha, (sign_sb * abs_sb).atan2(a[8]), gamma)
+                } else if a[8] > 0.0 {
+                    (0.0, 0.0, (-a[3]).atan2(a[0]))
+                } else {
+                    (0.0, std::f64::consts::PI, a[3].atan2(-a[0]))
+                };
+                Euler::new(gamma.to_degrees(), beta.to_degrees(), alpha.to_degrees())
+            }
+            Convention::Eman => {
+                let e = Euler::from_matrix(mat, Convention::Relion);
+                Euler::new(normalize(e.psi - 90.0), e.theta, normalize(e.phi + 90.0))
+            }
+            Convention::Yxz => {
+                // Active rotation R is the transpose of `a`
+                let sin_phi = a[7].clamp(-1.0, 1.0);
+                let cos_phi = (a[6] * a[6] + a[8] * a[8]).sqrt();
+                let (theta, psi) = if cos_phi > EPSILON {
+                    (a[6].atan2(a[8]), a[1].atan2(a[4]))
+                } else {
+                    (0.0, (-a[3]).atan2(a[0]))
+                };
+                Euler::new(
+                    psi.to_degrees(),
+                    theta.to_degrees(),
+                    sin_phi.asin().to_degrees(),
+                )
+            }
+        }
+    }
+
+    ///
+    /// Converts these angles from one convention to another one.
+    ///
+    pub fn convert(&self, from: Convention, to: Convention) -> Euler {
+        Euler::from_matrix(&self.to_matrix(from), to)
+    }
+
+    /// Returns the unit quaternion of the rotation matrix.
+    pub fn to_quaternion(&self, convention: Convention) -> Quaternion {
+        Quaternion::from_matrix3(&self.to_matrix(convention))
+    }
+
+    pub fn from_quaternion(q: &Quaternion, convention: Convention) -> Euler {
+        let mut q = *q;
+        Euler::from_matrix(&q.normalize().to_matrix3(), convention)
+    }
+
+    ///
+    /// Returns the viewing direction (unit vector) of these angles.
+    ///
+    pub fn direction(&self, convention: Convention) -> Vector3 {
+        let a = self.to_matrix(convention);
+        let m = a.values();
+        Vector3::new(m[6], m[7], m[8])
+    }
+
+    ///
+    /// Returns angles with the viewing direction `v` (normalized) and no in-plane rotation
+    /// in the RELION convention.
+    ///
+    pub fn from_direction(v: &Vector3, convention: Convention) -> Euler {
+        let len = v.length();
+        let (x, y, z) = (v.x / len, v.y / len, v.z / len);
+        let relion = Euler::new(
+            0.0,
+            z.clamp(-1.0, 1.0).acos().to_degrees(),
+            y.atan2(x).to_degrees(),
+        );
+        relion.convert(Convention::Relion, convention)
+    }
+}
+
+///
+/// Reads the angles of the columns `psi`, `theta` and `phi` of a table
+/// (e.g. `samples/psi-theta-phi-50.csv` opened by [TextReader](crate::io::text_reader::TextReader)).
+///
+pub fn read_angles(table: &ResultsTable) -> Result<Vec<Euler>, String> {
+    let column = |name: &str| {
+        table
+            .get_column_as_floats(name.to_string())
+            .map_err(|e| format!("{}: {}", e, name))
+    };
+    let (psi, theta, phi) = (column("psi")?, column("theta")?, column("phi")?);
+    Ok((0..psi.len())
+        .map(|i| Euler::new(psi[i], theta[i], phi[i]))
+        .collect())
+}
+
+///
+/// Returns the angle in degrees of the rotation between the rotation matrices `a` and `b`.
+///
+pub fn angle_between(a: &Matrix3, b: &Matrix3) -> f64 {
+    let (a, b) = (a.values(), b.values());
+    // trace(A B^T) = 1 + 2 cos(angle)
+    let trace: f64 = (0..9).map(|k| a[k] * b[k]).sum();
+    ((trace - 1.0) / 2.0).clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+// Angle in ]-180, 180]
+fn normalize(angle: f64) -> f64 {
+    let a = angle % 360.0;
+    if a > 180.0 {
+        a - 360.0
+    } else if a <= -180.0 {
+        a + 360.0
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::io::text_reader::TextReader;
+
+    const ALL: [Convention; 6] = [
+        Convention::Relion,
+        Convention::Spider,
+        Convention::Frealign,
+        Convention::CisTem,
+        Convention::Eman,
+        Convention::Yxz,
+    ];
+
+    fn assert_same_matrix(a: &Matrix3, b: &Matrix3) {
+        for (u, v) in a.values().iter().zip(b.values().iter()) {
+            assert!((u - v).abs() < 1e-9, "{:?} {:?}", a.values(), b.values());
+        }
+    }
+
+    #[test]
+    fn csv_viewing_directions() {
+        let rt = TextReader::open_csv("./samples/psi-theta-phi-50.csv", None).unwrap();
+        let column = |name: &str| rt.get_column_as_floats(name.to_string()).unwrap();
+        let (psi, theta, phi) = (column("psi"), column("theta"), column("phi"));
+        let (x, y, z) = (column("x"), column("y"), column("z"));
+        assert_eq!(psi.len(), 50);
+        // The last row (south pole, y = -1) is stored with phi = 90 instead of -90
+        for i in 0..psi.len() - 1 {
+            let v = Euler::new(psi[i], theta[i], phi[i]).direction(Convention::Yxz);
+            // Three decimals in the file
+            assert!((v.x - x[i]).abs() < 1e-3, "row {}", i);
+            assert!((v.y - y[i]).abs() < 1e-3, "row {}", i);
+            assert!((v.z - z[i]).abs() < 1e-3, "row {}", i);
+        }
+    }
+
+    #[test]
+    fn read_angles_from_table() {
+        let rt = TextReader::open_csv("./samples/psi-theta-phi-50.csv", None).unwrap();
+        let angles = read_angles(&rt).unwrap();
+        assert_eq!(angles.len(), 50);
+        assert_eq!(angles[1], Euler::new(0.0, -47.508, 73.574));
+        let rt = TextReader::open_csv("./samples/test.csv", None).unwrap();
+        assert!(read_angles(&rt).is_err());
+    }
+
+    #[test]
+    fn relion_viewing_direction() {
+        let v = Euler::new(30.0, 90.0, 0.0).direction(Convention::Relion);
+        assert!((v.x - 1.0).abs() < 1e-12 && v.y.abs() < 1e-12 && v.z.abs() < 1e-12);
+    }
+
+    #[test]
+    fn round_trips() {
+        let angles = Euler::new(-35.0, 62.0, 121.0);
+        for convention in ALL {
+            let m = angles.to_matrix(convention);
+            let back = Euler::from_matrix(&m, convention);
+            assert_same_matrix(&back.to_matrix(convention), &m);
+            let q = angles.to_quaternion(convention);
+            assert_same_matrix(
+                &Euler::from_quaternion(&q, convention).to_matrix(convention),
+                &m,
+            );
+        }
+        // Gimbal lock
+        let m = Euler::new(20.0, 0.0, 15.0).to_matrix(Convention::Relion);
+        let back = Euler::from_matrix(&m, Convention::Relion);
+        assert!((back.psi - 35.0).abs() < 1e-9 && back.phi == 0.0);
+    }
+
+    #[test]
+    fn matrix4_is_column_major() {
+        let angles = Euler::new(-35.0, 62.0, 121.0);
+        let a = *angles.to_matrix(Convention::Relion).values();
+        let mut v = Vector3::new(0.3, -0.5, 0.8);
+        v.transform_mat4(angles.to_matrix4(Convention::Relion));
+        assert!((v.x - (0.3 * a[0] - 0.5 * a[1] + 0.8 * a[2])).abs() < 1e-12);
+        assert!((v.y - (0.3 * a[3] - 0.5 * a[4] + 0.8 * a[5])).abs() < 1e-12);
+        assert!((v.z - (0.3 * a[6] - 0.5 * a[7] + 0.8 * a[8])).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rotation_angle() {
+        let a = Euler::new(10.0, 30.0, 0.0).to_matrix(Convention::Relion);
+        let b = Euler::new(10.0, 75.0, 0.0).to_matrix(Convention::Relion);
+        assert!((angle_between(&a, &b) - 45.0).abs() < 1e-9);
+        assert!(angle_between(&a, &a) < 1e-6);
+    }
+
+    #[test]
+    fn eman_to_relion() {
+        let eman = Euler::new(10.0, 40.0, 30.0);
+        let relion = eman.convert(Convention::Eman, Convention::Relion);
+        assert!((relion.phi + 60.0).abs() < 1e-9);
+        assert!((relion.theta - 40.0).abs() < 1e-9);
+        assert!((relion.psi - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn from_direction() {
+        let v = Vector3::new(0.3, -0.5, 0.8);
+        for convention in ALL {
+            let d = Euler::from_direction(&v, convention).direction(convention);
+            let len = v.length();
+            assert!((d.x - v.x / len).abs() < 1e-9, "{:?}", convention);
+            assert!((d.y - v.y / len).abs() < 1e-9, "{:?}", convention);
+            assert!((d.z - v.z / len).abs() < 1e-9, "{:?}", convention);
+        }
+    }
+}
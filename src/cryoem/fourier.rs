@@ -0,0 +1,465 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Fourier-space projection and reconstruction based on the central slice theorem.
+//!
+//! The 2D Fourier transform of a projection is the central slice, orthogonal to the
+//! viewing direction, of the 3D Fourier transform of the volume. Both operations work
+//! on grids zero-padded to a power of two and interpolate (trilinear) in Fourier space.
+//! The geometry is the one of [projection](crate::cryoem::projection): cubic volumes
+//! centered on the voxel `(n/2, n/2, n/2)` and rotation matrices of RELION.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::cryoem::euler::{Convention, Euler};
+use crate::fft::{fft_3d, frequency};
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::pixel::PixelType;
+use crate::vecmath::matrix3::Matrix3;
+use crate::volume_processor::VolumeProcessor;
+use num::complex::Complex64;
+use std::f64::consts::PI;
+
+///
+/// Fourier-slice projector. The 3D FFT of the volume is computed once.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::fourier::FourierProjector;
+/// use rim::grayscale::Gray32;
+/// use rim::vecmath::matrix3::Matrix3;
+/// use rim::volume_processor::VolumeProcessor;
+///
+/// let vol = VolumeProcessor::new_volume(4, 4, 4, vec![1.0f32; 64], Gray32::new());
+/// let projector = FourierProjector::new(&vol, 2.0, false);
+/// let proj = projector.project(&Matrix3::identity());
+/// assert!((proj.data()[5] - 4.0).abs() < 1e-4);
+/// ```
+pub struct FourierProjector {
+    size: usize,
+    pad_size: usize,
+    spectrum: Vec<Complex64>,
+}
+
+impl FourierProjector {
+    ///
+    /// Computes the 3D FFT of the cubic `volume` zero-padded by the factor `padding`.
+    ///
+    /// With `gridding_correction`, the volume is divided beforehand by the Fourier transform
+    /// of the trilinear interpolation kernel (sinc²) to compensate the attenuation of the
+    /// high frequencies.
+    ///
+    pub fn new<T: PixelType>(
+        volume: &VolumeProcessor<T>,
+        padding: f64,
+        gridding_correction: bool,
+    ) -> Self {
+        let size = cube_size(volume.width, volume.height, volume.depth);
+        let pad_size = padded_size(size, padding);
+        let mut spectrum = vec![Complex64::new(0.0, 0.0); pad_size.pow(3)];
+        let mut index = 0;
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    let (dx, dy, dz) = centered(x, y, z, size);
+                    let mut v = volume.data[index].to_f32() as f64;
+                    index += 1;
+                    if gridding_correction {
+                        v /= sinc2(dx, pad_size) * sinc2(dy, pad_size) * sinc2(dz, pad_size);
+                    }
+                    spectrum[wrapped(dx, dy, dz, pad_size)] = Complex64::new(v, 0.0);
+                }
+            }
+        }
+        fft_3d(&mut spectrum, pad_size, pad_size, pad_size, false);
+        FourierProjector {
+            size,
+            pad_size,
+            spectrum,
+        }
+    }
+
+    /// Size of the zero-padded Fourier grid
+    pub fn get_padded_size(&self) -> usize {
+        self.pad_size
+    }
+
+    ///
+    /// Returns the projection of the orientation `rotation` extracted from the central slice.
+    ///
+    pub fn project(&self, rotation: &Matrix3) -> FloatProcessor {
+        let n = self.pad_size;
+        let a = rotation.values();
+        let mut slice = vec![Complex64::new(0.0, 0.0); n * n];
+        for ky in 0..n {
+            let fy = frequency(ky, n) * n as f64;
+            for kx in 0..n {
+                let fx = frequency(kx, n) * n as f64;
+                // k = A^T (fx, fy, 0)
+                let (u, v, w) = (
+                    a[0] * fx + a[3] * fy,
+                    a[1] * fx + a[4] * fy,
+                    a[2] * fx + a[5] * fy,
+                );
+                let mut value = Complex64::new(0.0, 0.0);
+                for (index, weight) in trilinear_weights(u, v, w, n) {
+                    value += self.spectrum[index] * weight;
+                }
+                slice[kx + ky * n] = value;
+            }
+        }
+        fft_3d(&mut slice, n, n, 1, true);
+        let mut pixels = Vec::<f32>::with_capacity(self.size * self.size);
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let (dx, dy, _) = centered(x, y, 0, self.size);
+                pixels.push(slice[wrapped(dx, dy, 0.0, n)].re as f32);
+            }
+        }
+        ImageProcessor::new(self.size as u32, self.size as u32, pixels, Gray32::new())
+    }
+
+    /// Returns the projection of the orientation given by Euler angles.
+    pub fn project_euler(&self, angles: &Euler, convention: Convention) -> FloatProcessor {
+        self.project(&angles.to_matrix(convention))
+    }
+}
+
+///
+/// Reconstruction by direct Fourier inversion.
+///
+/// The 2D FFTs of the projections are inserted (trilinear weights) in a 3D Fourier grid.
+/// The grid is normalized by the sum of the weights plus a Wiener-like regularization
+/// constant, then inverse-transformed.
+///
+pub struct FourierReconstructor {
+    size: usize,
+    pad_size: usize,
+    gridding_correction: bool,
+    regularization: f64,
+    data: Vec<Complex64>,
+    weights: Vec<f64>,
+}
+
+impl FourierReconstructor {
+    ///
+    /// Creates an empty reconstruction of a cube of side `size` with the zero-padding factor `padding`.
+    ///
+    pub fn new(size: u32, padding: f64) -> Self {
+        let size = size as usize;
+        let pad_size = padded_size(size, padding);
+        FourierReconstructor {
+            size,
+            pad_size,
+            gridding_correction: true,
+            regularization: 0.0,
+            data: vec![Complex64::new(0.0, 0.0); pad_size.pow(3)],
+            weights: vec![0.0; pad_size.pow(3)],
+        }
+    }
+
+    /// Enables the division of the reconstruction by sinc² (default `true`).
+    pub fn set_gridding_correction(&mut self, enabled: bool) {
+        self.gridding_correction = enabled;
+    }
+
+    ///
+    /// Constant added to the weights before normalization (default 0), like
+    /// the inverse of the SNR of a Wiener filter. It damps the poorly sampled frequencies.
+    ///
+    pub fn set_regularization(&mut self, lambda: f64) {
+        self.regularization = lambda;
+    }
+
+    /// Size of the zero-padded Fourier grid
+    pub fn get_padded_size(&self) -> usize {
+        self.pad_size
+    }
+
+    ///
+    /// Inserts a projection (square image of the reconstruction size) of orientation `rotation`.
+    ///
+    pub fn insert(&mut self, projection: &FloatProcessor, rotation: &Matrix3) {
+        assert!(
+            projection.width as usize == self.size && projection.height as usize == self.size,
+            "Projection size must be {}x{}",
+            self.size,
+            self.size
+        );
+        let n = self.pad_size;
+        let mut slice = vec![Complex64::new(0.0, 0.0); n * n];
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let (dx, dy, _) = centered(x, y, 0, self.size);
+                slice[wrapped(dx, dy, 0.0, n)] =
+                    Complex64::new(projection.data[x + y * self.size] as f64, 0.0);
+            }
+        }
+        fft_3d(&mut slice, n, n, 1, false);
+        let a = rotation.values();
+        for ky in 0..n {
+            let fy = frequency(ky, n) * n as f64;
+            for kx in 0..n {
+                let fx = frequency(kx, n) * n as f64;
+                let (u, v, w) = (
+                    a[0] * fx + a[3] * fy,
+                    a[1] * fx + a[4] * fy,
+                    a[2] * fx + a[5] * fy,
+                );
+                let value = slice[kx + ky * n];
+                for (index, weight) in trilinear_weights(u, v, w, n) {
+                    self.data[index] += value * weight;
+                    self.weights[index] += weight;
+                }
+            }
+        }
+    }
+
+    /// Inserts a projection of orientation given by Euler angles.
+    pub fn insert_euler(
+        &mut self,
+        projection: &FloatProcessor,
+        angles: &Euler,
+        convention: Convention,
+    ) {
+        self.insert(projection, &angles.to_matrix(convention));
+    }
+
+    ///
+    /// Inserts each slice of `stack` with the corresponding orientation of `angles`.
+    ///
+    pub fn insert_stack(
+        &mut self,
+        stack: &ImageStack<f32, Gray32>,
+        angles: &[Euler],
+        convention: Convention,
+    ) {
+        for (pixels, angle) in stack.data().iter().zip(angles.iter()) {
+            let projection = ImageProcessor::new(
+                stack.get_width(),
+                stack.get_height(),
+                pixels.clone(),
+                Gray32::new(),
+            );
+            self.insert_euler(&projection, angle, convention);
+        }
+    }
+
+    ///
+    /// Returns the reconstructed volume.
+    ///
+    pub fn reconstruct(&self) -> VolumeProcessor<f32> {
+        let n = self.pad_size;
+        let mut grid: Vec<Complex64> = self
+            .data
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(v, w)| {
+                if *w > 0.0 {
+                    v / (w + self.regularization)
+                } else {
+                    Complex64::new(0.0, 0.0)
+                }
+            })
+            .collect();
+        fft_3d(&mut grid, n, n, n, true);
+        let mut voxels = Vec::<f32>::with_capacity(self.size.pow(3));
+        for z in 0..self.size {
+            for y in 0..self.size {
+                for x in 0..self.size {
+                    let (dx, dy, dz) = centered(x, y, z, self.size);
+                    let mut v = grid[wrapped(dx, dy, dz, n)].re;
+                    if self.gridding_correction {
+                        v /= sinc2(dx, n) * sinc2(dy, n) * sinc2(dz, n);
+                    }
+                    voxels.push(v as f32);
+                }
+            }
+        }
+        let size = self.size as u32;
+        VolumeProcessor::new_volume(size, size, size, voxels, Gray32::new())
+    }
+}
+
+//
+// Private functions
+//
+
+fn cube_size(w: u32, h: u32, d: u32) -> usize {
+    assert!(w == h && h == d, "Volume must be cubic ({}x{}x{})", w, h, d);
+    w as usize
+}
+
+fn padded_size(size: usize, padding: f64) -> usize {
+    ((size as f64 * padding.max(1.0)).ceil() as usize).next_power_of_two()
+}
+
+// Coordinates relative to the center (n/2, n/2, n/2)
+fn centered(x: usize, y: usize, z: usize, n: usize) -> (f64, f64, f64) {
+    let c = (n / 2) as f64;
+    (x as f64 - c, y as f64 - c, z as f64 - c)
+}
+
+// Index of centered coordinates in a grid of size n with the origin in the first element
+fn wrapped(dx: f64, dy: f64, dz: f64, n: usize) -> usize {
+    let w = |v: f64| (v as i64).rem_euclid(n as i64) as usize;
+    w(dx) + w(dy) * n + w(dz) * n * n
+}
+
+// Fourier transform of the linear interpolation kernel
+fn sinc2(x: f64, n: usize) -> f64 {
+    let a = PI * x / n as f64;
+    if a == 0.0 {
+        1.0
+    } else {
+        (a.sin() / a).powi(2)
+    }
+}
+
+// Indices (periodic grid) and weights of the 8 neighbors of the frequency (u, v, w).
+// The neighbors at or beyond Nyquist (|k| >= n/2) are skipped instead of being
+// wrapped onto the opposite frequencies.
+fn trilinear_weights(u: f64, v: f64, w: f64, n: usize) -> Vec<(usize, f64)> {
+    let (u0, v0, w0) = (u.floor(), v.floor(), w.floor());
+    let (fu, fv, fw) = (u - u0, v - v0, w - w0);
+    let nyquist = (n / 2) as f64;
+    let inside = |k: f64| k.abs() < nyquist;
+    let mut out = Vec::<(usize, f64)>::with_capacity(8);
+    for (k, ww) in [(0.0, 1.0 - fw), (1.0, fw)] {
+        for (j, wv) in [(0.0, 1.0 - fv), (1.0, fv)] {
+            for (i, wu) in [(0.0, 1.0 - fu), (1.0, fu)] {
+                let weight = wu * wv * ww;
+                let (x, y, z) = (u0 + i, v0 + j, w0 + k);
+                if weight > 0.0 && inside(x) && inside(y) && inside(z) {
+                    out.push((wrapped(x, y, z, n), weight));
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cryoem::projection::{ProjectionMode, Projector};
+    use crate::vecmath::vector3::Vector3;
+
+    // Off-center gaussian blob
+    fn blob(n: u32) -> VolumeProcessor<f32> {
+        let mut voxels = Vec::<f32>::new();
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let r2 = (x as f32 - 7.0).powi(2)
+                        + (y as f32 - 9.0).powi(2)
+                        + (z as f32 - 8.0).powi(2);
+                    voxels.push((-r2 / 6.0).exp());
+                }
+            }
+        }
+        VolumeProcessor::new_volume(n, n, n, voxels, Gray32::new())
+    }
+
+    fn correlation(a: &[f32], b: &[f32]) -> f64 {
+        let mean = |v: &[f32]| v.iter().map(|x| *x as f64).sum::<f64>() / v.len() as f64;
+        let (ma, mb) = (mean(a), mean(b));
+        let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+        for (x, y) in a.iter().zip(b.iter()) {
+            let (x, y) = (*x as f64 - ma, *y as f64 - mb);
+            ab += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        ab / (aa * bb).sqrt()
+    }
+
+    #[test]
+    fn top_view_is_exact() {
+        let vol = blob(16);
+        let proj = FourierProjector::new(&vol, 2.0, false).project(&Matrix3::identity());
+        let real = Projector::new(ProjectionMode::VoxelDriven).project(&vol, &Matrix3::identity());
+        for (a, b) in proj.data().iter().zip(real.data().iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn oblique_view_matches_real_space() {
+        let vol = blob(16);
+        let angles = Euler::new(30.0, 60.0, -40.0);
+        let fourier =
+            FourierProjector::new(&vol, 2.0, true).project_euler(&angles, Convention::Relion);
+        let real = Projector::new(ProjectionMode::RayDriven).project_euler(
+            &vol,
+            &angles,
+            Convention::Relion,
+        );
+        assert!(correlation(fourier.data(), real.data()) > 0.99);
+    }
+
+    #[test]
+    fn direct_fourier_inversion() {
+        let vol = blob(16);
+        let projector = Projector::new(ProjectionMode::RayDriven);
+        let mut reconstructor = FourierReconstructor::new(16, 2.0);
+        // Directions on a Fibonacci sphere
+        let count = 300;
+        let golden = PI * (3.0 - 5f64.sqrt());
+        for i in 0..count {
+            let z = 1.0 - 2.0 * (i as f64 + 0.5) / count as f64;
+            let r = (1.0 - z * z).sqrt();
+            let t = golden * i as f64;
+            let direction = Vector3::new(r * t.cos(), r * t.sin(), z);
+            let angles = Euler::from_direction(&direction, Convention::Relion);
+            let proj = projector.project_euler(&vol, &angles, Convention::Relion);
+            reconstructor.insert_euler(&proj, &angles, Convention::Relion);
+        }
+        let rec = reconstructor.reconstruct();
+        assert!(correlation(rec.data(), vol.data()) > 0.95);
+        // Peak at the center of the blob
+        let peak = rec
+            .data()
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap()
+            .0;
+        assert_eq!(peak, 7 + 9 * 16 + 8 * 256);
+    }
+
+    #[test]
+    fn no_wrap_beyond_nyquist() {
+        // Between the last frequency below Nyquist (7) and Nyquist (8)
+        let weights = trilinear_weights(7.25, 0.0, 0.0, 16);
+        assert_eq!(weights, vec![(7, 0.75)]);
+        // Beyond Nyquist, nothing is folded back onto the negative frequencies
+        assert!(trilinear_weights(9.5, 0.0, 0.0, 16).is_empty());
+        assert!(trilinear_weights(0.0, -8.5, 3.0, 16).is_empty());
+        let weights = trilinear_weights(-7.5, 0.0, 0.0, 16);
+        assert_eq!(weights, vec![(9, 0.5)]);
+    }
+}
@@ -0,0 +1,551 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Fourier Ring Correlation (FRC) of images and Fourier Shell Correlation (FSC) of volumes.
+//!
+//! The correlation of the shell `S` between the spectra `F1` and `F2` is
+//! `FSC(S) = Re(sum F1 F2*) / sqrt(sum |F1|^2 sum |F2|^2)`, the sums running over the
+//! frequencies of `S`. The resolution is the first crossing of the curve with a threshold.
+//!
+//! The masks increase the correlation of the noise. The phase-randomized FSC (Chen et al.,
+//! 2013) estimates this effect: the phases of the unmasked maps are randomized beyond a
+//! resolution, then the FSC of the masked maps `FSC_t` is corrected by the FSC of the masked
+//! randomized maps `FSC_n` as `(FSC_t - FSC_n) / (1 - FSC_n)`.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::complex_image::ComplexImage;
+use crate::fft::frequency;
+use crate::grayscale::Gray32;
+use crate::image_processor::ImageProcessor;
+use crate::io::file_info::FileInfo;
+use crate::pixel::PixelType;
+use crate::results_table::{Cell, ResultsTable};
+use num::complex::Complex64;
+use rand::{Rng, SeedableRng, StdRng};
+use std::f64::consts::PI;
+
+///
+/// Thresholds of the resolution
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threshold {
+    /// Constant threshold
+    Fixed(f64),
+    /// Half-bit information curve (van Heel and Schatz, 2005)
+    HalfBit,
+    /// Three standard deviations of the correlation of the noise
+    ThreeSigma,
+}
+
+impl Threshold {
+    /// Gold-standard threshold of independent half-maps
+    pub const GOLD_STANDARD: Threshold = Threshold::Fixed(0.143);
+    pub const HALF: Threshold = Threshold::Fixed(0.5);
+
+    /// Returns the threshold of a shell of `count` pixels or voxels.
+    pub fn value(&self, count: usize) -> f64 {
+        // Number of independent frequencies (Friedel symmetry)
+        let n = (count as f64 / 2.0).max(1.0);
+        match self {
+            Threshold::Fixed(value) => *value,
+            Threshold::HalfBit => (0.2071 + 1.9102 / n.sqrt()) / (1.2071 + 0.9102 / n.sqrt()),
+            Threshold::ThreeSigma => 3.0 / n.sqrt(),
+        }
+    }
+}
+
+///
+/// FRC/FSC curve and resolution
+///
+pub struct FscResult {
+    /// Columns `shell`, `frequency` (1/Å), `resolution` (Å), `correlation`, `threshold`
+    /// (and `unmasked`, `masked`, `randomized` for the phase-randomized FSC)
+    pub table: ResultsTable,
+    /// Resolution in Å
+    pub resolution: f64,
+}
+
+///
+/// Fourier Ring/Shell Correlation
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::fsc::{FourierShellCorrelation, Threshold};
+/// use rim::grayscale::Gray32;
+/// use rim::image_processor::ImageProcessor;
+///
+/// let pixels: Vec<f32> = (0..32 * 32).map(|i| ((i * 7) % 11) as f32).collect();
+/// let a = ImageProcessor::<f32, Gray32>::new(32, 32, pixels, Gray32::new());
+/// let mut frc = FourierShellCorrelation::new(1.5);
+/// frc.set_threshold(Threshold::HalfBit);
+/// let result = frc.compute(&a, &a).unwrap();
+/// // Identical images are correlated up to Nyquist (2 pixels)
+/// assert_eq!(result.resolution, 3.0);
+/// assert_eq!(result.table.size(), 17);
+/// ```
+pub struct FourierShellCorrelation {
+    pixel_size: f64,
+    shell_width: f64,
+    threshold: Threshold,
+    mask: Option<Vec<f32>>,
+    randomization: Option<f64>,
+}
+
+impl FourierShellCorrelation {
+    /// Creates the FRC/FSC of images or volumes of pixel size `pixel_size` in Å.
+    pub fn new(pixel_size: f64) -> Self {
+        FourierShellCorrelation {
+            pixel_size,
+            shell_width: 1.0,
+            threshold: Threshold::GOLD_STANDARD,
+            mask: None,
+            randomization: None,
+        }
+    }
+
+    /// Creates the FRC/FSC with the pixel size `pixel_width` of the file `info`.
+    pub fn from_file_info(info: &FileInfo) -> Self {
+        FourierShellCorrelation::new(info.pixel_width)
+    }
+
+    pub fn get_pixel_size(&self) -> f64 {
+        self.pixel_size
+    }
+
+    /// Sets the width of the shells in pixels (1 by default).
+    pub fn set_shell_width(&mut self, width: f64) {
+        self.shell_width = width;
+    }
+
+    pub fn get_shell_width(&self) -> f64 {
+        self.shell_width
+    }
+
+    /// Sets the threshold of the resolution ([Threshold::GOLD_STANDARD] by default).
+    pub fn set_threshold(&mut self, threshold: Threshold) {
+        self.threshold = threshold;
+    }
+
+    pub fn get_threshold(&self) -> Threshold {
+        self.threshold
+    }
+
+    ///
+    /// Sets the (soft) mask multiplying the images or volumes before the correlation.
+    ///
+    pub fn set_mask<T: PixelType, C: ColorSpace>(&mut self, mask: &ImageProcessor<T, C>) {
+        self.mask = Some(mask.data.iter().map(|v| v.to_f32()).collect());
+    }
+
+    pub fn reset_mask(&mut self) {
+        self.mask = None;
+    }
+
+    ///
+    /// Sets the resolution (Å) beyond which the phases are randomized. By default, this is
+    /// the resolution where the unmasked FSC drops below 0.8.
+    ///
+    pub fn set_randomization_resolution(&mut self, resolution: Option<f64>) {
+        self.randomization = resolution;
+    }
+
+    ///
+    /// Returns the FRC (images) or the FSC (volumes) of `a` and `b`, masked if a mask is set.
+    ///
+    pub fn compute<T: PixelType, C: ColorSpace>(
+        &self,
+        a: &ImageProcessor<T, C>,
+        b: &ImageProcessor<T, C>,
+    ) -> Result<FscResult, String> {
+        let dims = self.check(a, b)?;
+        let shells = self.shells(
+            &self.spectrum(&to_f32(a), dims, true),
+            &self.spectrum(&to_f32(b), dims, true),
+            dims,
+        );
+        let correlations: Vec<f64> = shells.iter().map(|s| s.correlation()).collect();
+        Ok(self.result(&shells, dims, &correlations, &[]))
+    }
+
+    ///
+    /// Returns the phase-randomized FSC of `a` and `b` (a mask must be set). The column
+    /// `correlation` is the corrected FSC, used for the resolution.
+    ///
+    pub fn compute_corrected<T: PixelType, C: ColorSpace>(
+        &self,
+        a: &ImageProcessor<T, C>,
+        b: &ImageProcessor<T, C>,
+    ) -> Result<FscResult, String> {
+        if self.mask.is_none() {
+            return Err(String::from("The phase-randomized FSC requires a mask"));
+        }
+        let dims = self.check(a, b)?;
+        let (a, b) = (to_f32(a), to_f32(b));
+        let unmasked = self.shells(
+            &self.spectrum(&a, dims, false),
+            &self.spectrum(&b, dims, false),
+            dims,
+        );
+        let unmasked: Vec<f64> = unmasked.iter().map(|s| s.correlation()).collect();
+        let masked = self.shells(
+            &self.spectrum(&a, dims, true),
+            &self.spectrum(&b, dims, true),
+            dims,
+        );
+        // Randomization shell
+        let n = max_dim(dims) as f64;
+        let first = match self.randomization {
+            Some(resolution) => {
+                (n * self.pixel_size / resolution / self.shell_width).ceil() as usize
+            }
+            None => (1..unmasked.len())
+                .find(|i| unmasked[*i] < 0.8)
+                .unwrap_or(unmasked.len()),
+        };
+        let radius = first as f64 * self.shell_width / n;
+        let mut rng: StdRng = SeedableRng::from_seed(&[first, dims.0, dims.1, dims.2][..]);
+        let randomized = self.shells(
+            &self.spectrum(&randomize(&a, dims, radius, &mut rng), dims, true),
+            &self.spectrum(&randomize(&b, dims, radius, &mut rng), dims, true),
+            dims,
+        );
+        let masked_values: Vec<f64> = masked.iter().map(|s| s.correlation()).collect();
+        let randomized_values: Vec<f64> = randomized.iter().map(|s| s.correlation()).collect();
+        let corrected: Vec<f64> = (0..masked.len())
+            .map(|i| {
+                let (t, r) = (masked_values[i], randomized_values[i]);
+                if i < first || r >= 1.0 {
+                    t
+                } else {
+                    (t - r) / (1.0 - r)
+                }
+            })
+            .collect();
+        Ok(self.result(
+            &masked,
+            dims,
+            &corrected,
+            &[
+                ("unmasked", unmasked),
+                ("masked", masked_values),
+                ("randomized", randomized_values),
+            ],
+        ))
+    }
+
+    //
+    // Private methods
+    //
+
+    fn check<T: PixelType, C: ColorSpace>(
+        &self,
+        a: &ImageProcessor<T, C>,
+        b: &ImageProcessor<T, C>,
+    ) -> Result<(usize, usize, usize), String> {
+        let dims = (a.width as usize, a.height as usize, a.depth.max(1) as usize);
+        if (a.width, a.height, a.depth) != (b.width, b.height, b.depth) {
+            return Err(format!(
+                "Different sizes {}x{}x{} and {}x{}x{}",
+                a.width, a.height, a.depth, b.width, b.height, b.depth
+            ));
+        }
+        if let Some(mask) = &self.mask {
+            if mask.len() != dims.0 * dims.1 * dims.2 {
+                return Err(String::from("The mask and the images have different sizes"));
+            }
+        }
+        if self.shell_width <= 0.0 || self.pixel_size <= 0.0 {
+            return Err(String::from(
+                "The shell width and the pixel size must be positive",
+            ));
+        }
+        Ok(dims)
+    }
+
+    fn spectrum(
+        &self,
+        pixels: &[f32],
+        dims: (usize, usize, usize),
+        masked: bool,
+    ) -> Vec<Complex64> {
+        let pixels = match (&self.mask, masked) {
+            (Some(mask), true) => pixels.iter().zip(mask.iter()).map(|(v, m)| v * m).collect(),
+            _ => pixels.to_vec(),
+        };
+        let ip = ImageProcessor::new_volume(
+            dims.0 as u32,
+            dims.1 as u32,
+            dims.2 as u32,
+            pixels,
+            Gray32::new(),
+        );
+        ComplexImage::fft(&ip).data
+    }
+
+    fn shells(&self, a: &[Complex64], b: &[Complex64], dims: (usize, usize, usize)) -> Vec<Shell> {
+        let n = max_dim(dims) as f64;
+        let count = (n / 2.0 / self.shell_width).floor() as usize + 1;
+        let mut shells = vec![Shell::default(); count];
+        for (i, r) in radii(dims).enumerate() {
+            let shell = (r * n / self.shell_width).round() as usize;
+            if shell < count {
+                shells[shell].add(&a[i], &b[i]);
+            }
+        }
+        shells
+    }
+
+    fn result(
+        &self,
+        shells: &[Shell],
+        dims: (usize, usize, usize),
+        correlations: &[f64],
+        extra: &[(&str, Vec<f64>)],
+    ) -> FscResult {
+        let n = max_dim(dims) as f64;
+        let shell_frequency = |i: usize| i as f64 * self.shell_width / (n * self.pixel_size);
+        let thresholds: Vec<f64> = shells
+            .iter()
+            .map(|s| self.threshold.value(s.count))
+            .collect();
+        let mut table = ResultsTable::new(String::from("FSC"));
+        for (i, shell) in shells.iter().enumerate() {
+            table.add_row();
+            let f = shell_frequency(i);
+            let mut values = vec![
+                ("shell", i as f64),
+                ("frequency", f),
+                ("resolution", if i == 0 { f64::INFINITY } else { 1.0 / f }),
+                ("correlation", correlations[i]),
+                ("threshold", thresholds[i]),
+                ("count", shell.count as f64),
+            ];
+            values.extend(extra.iter().map(|(name, column)| (*name, column[i])));
+            for (column, value) in values {
+                table.add_value(&column.to_string(), Cell::Number(value));
+            }
+        }
+        // First crossing (linear interpolation between the shells)
+        let last = shell_frequency(shells.len() - 1);
+        let crossing = (1..shells.len())
+            .find(|i| correlations[*i] < thresholds[*i])
+            .map(|i| {
+                let (d0, d1) = (
+                    correlations[i - 1] - thresholds[i - 1],
+                    correlations[i] - thresholds[i],
+                );
+                let t = if d0 > d1 { d0 / (d0 - d1) } else { 0.0 };
+                shell_frequency(i - 1) + t * (shell_frequency(i) - shell_frequency(i - 1))
+            })
+            .unwrap_or(last);
+        FscResult {
+            table,
+            resolution: 1.0 / crossing.max(shell_frequency(1) * 1e-3),
+        }
+    }
+}
+
+//
+// Private structures and functions
+//
+
+#[derive(Clone, Copy, Default)]
+struct Shell {
+    cross: f64,
+    power_a: f64,
+    power_b: f64,
+    count: usize,
+}
+
+impl Shell {
+    fn add(&mut self, a: &Complex64, b: &Complex64) {
+        self.cross += (a * b.conj()).re;
+        self.power_a += a.norm_sqr();
+        self.power_b += b.norm_sqr();
+        self.count += 1;
+    }
+
+    fn correlation(&self) -> f64 {
+        let norm = (self.power_a * self.power_b).sqrt();
+        if norm > 0.0 {
+            self.cross / norm
+        } else {
+            0.0
+        }
+    }
+}
+
+fn to_f32<T: PixelType, C: ColorSpace>(ip: &ImageProcessor<T, C>) -> Vec<f32> {
+    ip.data.iter().map(|v| v.to_f32()).collect()
+}
+
+fn max_dim(dims: (usize, usize, usize)) -> usize {
+    dims.0.max(dims.1).max(dims.2)
+}
+
+// Frequencies (cycles per pixel) of the full spectrum in the FFT order
+fn radii(dims: (usize, usize, usize)) -> impl Iterator<Item = f64> {
+    let (w, h, d) = dims;
+    (0..w * h * d).map(move |i| {
+        let (x, y, z) = (i % w, (i / w) % h, i / (w * h));
+        (frequency(x, w).powi(2) + frequency(y, h).powi(2) + frequency(z, d).powi(2)).sqrt()
+    })
+}
+
+// Randomizes the phases of the frequencies (cycles per pixel) beyond `radius`
+fn randomize(
+    pixels: &[f32],
+    dims: (usize, usize, usize),
+    radius: f64,
+    rng: &mut StdRng,
+) -> Vec<f32> {
+    let (w, h, d) = dims;
+    let ip =
+        ImageProcessor::new_volume(w as u32, h as u32, d as u32, pixels.to_vec(), Gray32::new());
+    let mut half = ComplexImage::rfft(&ip);
+    let hw = half.width as usize;
+    for (i, value) in half.data.iter_mut().enumerate() {
+        let (x, y, z) = (i % hw, (i / hw) % h, i / (hw * h));
+        let r = ((x as f64 / w as f64).powi(2) + frequency(y, h).powi(2) + frequency(z, d).powi(2))
+            .sqrt();
+        if r >= radius {
+            *value *= Complex64::from_polar(1.0, rng.gen_range(0.0, 2.0 * PI));
+        }
+    }
+    half.irfft(w as u32).data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Smooth random signal plus independent noise
+    fn half_maps(n: u32, depth: u32, noise: f32) -> (Vec<f32>, Vec<f32>) {
+        let mut rng: StdRng = SeedableRng::from_seed(&[1usize, 2, 3][..]);
+        let size = (n * n * depth) as usize;
+        let white: Vec<f32> = (0..size).map(|_| rng.gen_range(-1.0, 1.0)).collect();
+        // Low-pass signal: average of the 3x3(x3) neighbourhood
+        let (w, d) = (n as i64, depth as i64);
+        let at = |x: i64, y: i64, z: i64| {
+            white[(x.rem_euclid(w) + y.rem_euclid(w) * w + z.rem_euclid(d) * w * w) as usize]
+        };
+        let mut signal = Vec::with_capacity(size);
+        for z in 0..d {
+            for y in 0..w {
+                for x in 0..w {
+                    let mut sum = 0.0;
+                    for (dx, dy) in [(0, 0), (1, 0), (-1, 0), (0, 1), (0, -1)] {
+                        sum += at(x + dx, y + dy, z);
+                    }
+                    signal.push(sum);
+                }
+            }
+        }
+        let mut noisy = || -> Vec<f32> {
+            signal
+                .iter()
+                .map(|s| s + noise * rng.gen_range(-1.0, 1.0))
+                .collect()
+        };
+        (noisy(), noisy())
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(Threshold::GOLD_STANDARD.value(10), 0.143);
+        // The half-bit curve tends to 0.1716
+        assert!((Threshold::HalfBit.value(1 << 30) - 0.1716).abs() < 1e-3);
+        assert!(Threshold::HalfBit.value(20) > 0.4);
+        assert!((Threshold::ThreeSigma.value(18) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn frc_of_noisy_images() {
+        let (a, b) = half_maps(64, 1, 2.0);
+        let a = ImageProcessor::<f32, Gray32>::new(64, 64, a, Gray32::new());
+        let b = ImageProcessor::<f32, Gray32>::new(64, 64, b, Gray32::new());
+        let mut frc = FourierShellCorrelation::new(2.0);
+        let result = frc.compute(&a, &b).unwrap();
+        let values = result
+            .table
+            .get_column_as_floats(String::from("correlation"))
+            .unwrap();
+        assert_eq!(values.len(), 33);
+        assert!(values[2] > 0.8 && values[30] < 0.3, "{:?}", values);
+        // Between the low frequencies and Nyquist
+        assert!(
+            result.resolution > 4.0 && result.resolution < 40.0,
+            "{}",
+            result.resolution
+        );
+        frc.set_shell_width(2.0);
+        frc.set_threshold(Threshold::HALF);
+        let coarse = frc.compute(&a, &b).unwrap();
+        assert_eq!(coarse.table.size(), 17);
+        assert!(coarse.resolution > result.resolution);
+    }
+
+    #[test]
+    fn phase_randomized_fsc() {
+        let n = 24;
+        let (a, b) = half_maps(n, n, 3.0);
+        // The sharp edges of the masked offset correlate the high frequencies
+        let offset = |v: Vec<f32>| -> Vec<f32> { v.iter().map(|v| v + 10.0).collect() };
+        let a = ImageProcessor::<f32, Gray32>::new_volume(n, n, n, offset(a), Gray32::new());
+        let b = ImageProcessor::<f32, Gray32>::new_volume(n, n, n, offset(b), Gray32::new());
+        // Sharp spherical mask
+        let c = (n / 2) as i64;
+        let mut mask = Vec::new();
+        for z in 0..n as i64 {
+            for y in 0..n as i64 {
+                for x in 0..n as i64 {
+                    let r2 = (x - c).pow(2) + (y - c).pow(2) + (z - c).pow(2);
+                    mask.push(if r2 < 64 { 1.0f32 } else { 0.0 });
+                }
+            }
+        }
+        let mut fsc = FourierShellCorrelation::new(1.0);
+        assert!(fsc.compute_corrected(&a, &b).is_err());
+        fsc.set_mask(&ImageProcessor::<f32, Gray32>::new_volume(
+            n,
+            n,
+            n,
+            mask,
+            Gray32::new(),
+        ));
+        let result = fsc.compute_corrected(&a, &b).unwrap();
+        let column = |name: &str| result.table.get_column_as_floats(name.to_string()).unwrap();
+        let (unmasked, masked) = (column("unmasked"), column("masked"));
+        let (corrected, randomized) = (column("correlation"), column("randomized"));
+        // Mean deviation from the unmasked FSC beyond the randomization
+        let deviation = |values: &[f64]| -> f64 {
+            (3..values.len())
+                .map(|i| (values[i] - unmasked[i]).abs())
+                .sum::<f64>()
+                / (values.len() - 3) as f64
+        };
+        assert!(deviation(&masked) > 0.2, "{:?}", masked);
+        assert!(deviation(&corrected) < 0.1, "{:?}", corrected);
+        assert!(randomized[1] > 0.9);
+    }
+}
@@ -0,0 +1,561 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Iterative reconstructions of parallel beam sinograms: ART, SIRT and SART.
+//!
+//! All the algorithms share the projector/back-projector pair of
+//! [backprojection](crate::cryoem::backprojection): the pixel-driven projection splats
+//! each pixel on the detector with linear weights and the back-projection is its adjoint.
+//! The geometry is the one of [FilteredBackProjection](crate::cryoem::backprojection::FilteredBackProjection):
+//! one row per angle (in degrees), detector centered on the bin `width/2`.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::cryoem::backprojection::{interpolate, splat};
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::results_table::{Cell, ResultsTable};
+use crate::roi::Roi;
+use crate::transformable::InterpolationMode;
+
+// Projector weights (linear interpolation on the detector)
+const INTERPOLATION: InterpolationMode = InterpolationMode::Bilinear;
+
+///
+/// Iterative algorithms
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Algebraic Reconstruction Technique (Kaczmarz): update after each ray
+    Art,
+    /// Simultaneous Iterative Reconstruction Technique: update after all the projections
+    Sirt,
+    /// Simultaneous Algebraic Reconstruction Technique: update after each projection
+    Sart,
+}
+
+///
+/// Iterative reconstruction of sinograms or tilt series
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::iterative::{Algorithm, IterativeReconstruction};
+/// use rim::cryoem::sinogram::Sinogram;
+/// use rim::float_processor::FloatProcessor;
+/// use rim::grayscale::Gray32;
+///
+/// let mut pixels = vec![0.0f32; 81];
+/// pixels[40] = 1.0;
+/// let ip = FloatProcessor::new(9, 9, pixels, Gray32::new());
+/// let angles: Vec<f32> = (0..60).map(|a| a as f32 * 3.0).collect();
+/// let sinogram = Sinogram::new(&ip, &angles);
+/// let mut sirt = IterativeReconstruction::new(Algorithm::Sirt);
+/// sirt.set_iterations(50);
+/// let (slice, residuals) = sirt.reconstruct(&sinogram, &angles, 9, 9);
+/// assert!(slice.data()[40] > 0.5);
+/// assert_eq!(residuals.size(), 50);
+/// ```
+pub struct IterativeReconstruction {
+    algorithm: Algorithm,
+    relaxation: f64,
+    iterations: usize,
+    positivity: bool,
+    mask: Option<Roi>,
+}
+
+impl IterativeReconstruction {
+    ///
+    /// Creates a reconstruction with 10 iterations, no positivity constraint, no mask
+    /// and a relaxation of 1.0 for SIRT, 0.5 for ART and SART.
+    ///
+    pub fn new(algorithm: Algorithm) -> Self {
+        IterativeReconstruction {
+            algorithm,
+            relaxation: match algorithm {
+                Algorithm::Sirt => 1.0,
+                Algorithm::Art | Algorithm::Sart => 0.5,
+            },
+            iterations: 10,
+            positivity: false,
+            mask: None,
+        }
+    }
+
+    pub fn get_algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn set_relaxation(&mut self, relaxation: f64) {
+        self.relaxation = relaxation;
+    }
+
+    pub fn get_relaxation(&self) -> f64 {
+        self.relaxation
+    }
+
+    pub fn set_iterations(&mut self, iterations: usize) {
+        self.iterations = iterations;
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        self.iterations
+    }
+
+    ///
+    /// Sets the negative values of the slice to zero after each update.
+    ///
+    pub fn set_positivity(&mut self, positivity: bool) {
+        self.positivity = positivity;
+    }
+
+    pub fn get_positivity(&self) -> bool {
+        self.positivity
+    }
+
+    ///
+    /// Restricts the reconstruction to the pixels of the `roi` (the other ones remain zero).
+    ///
+    pub fn set_mask(&mut self, roi: Roi) {
+        self.mask = Some(roi);
+    }
+
+    pub fn reset_mask(&mut self) {
+        self.mask = None;
+    }
+
+    ///
+    /// Reconstructs a slice of size `width` x `height` from `sinogram` acquired at `angles`.
+    ///
+    /// Returns the slice and a table with the columns `Iteration` and `Residual`, the
+    /// relative residual `|b - Ax| / |b|` at the end of each iteration.
+    /// Panics if the height of `sinogram` differs from the number of angles.
+    ///
+    pub fn reconstruct(
+        &self,
+        sinogram: &FloatProcessor,
+        angles: &[f32],
+        width: u32,
+        height: u32,
+    ) -> (FloatProcessor, ResultsTable) {
+        assert_eq!(
+            sinogram.height as usize,
+            angles.len(),
+            "The sinogram has {} rows for {} angles",
+            sinogram.height,
+            angles.len()
+        );
+        let system = self.system(sinogram.width as usize, angles, width, height);
+        let (slice, residuals) = self.solve(&system, &sinogram.data);
+        let norm = squared_norm(&sinogram.data).sqrt();
+        let residuals: Vec<f64> = residuals.iter().map(|r| relative(*r, norm)).collect();
+        (
+            ImageProcessor::new(width, height, slice, Gray32::new()),
+            residual_table(&residuals),
+        )
+    }
+
+    ///
+    /// Reconstructs a tilt series slice-by-slice: each slice of `tilt_series` is the projection
+    /// at the corresponding angle and the tilt axis is parallel to the y-axis.
+    ///
+    /// The row `y` of all the projections is the sinogram of the slice `y` of the output stack
+    /// (of size `width` x `thickness`, with `width` the width of the projections).
+    /// The residual table sums the residuals of all the slices.
+    /// Panics if the number of projections differs from the number of angles.
+    ///
+    pub fn reconstruct_stack(
+        &self,
+        tilt_series: &ImageStack<f32, Gray32>,
+        angles: &[f32],
+        thickness: u32,
+    ) -> (ImageStack<f32, Gray32>, ResultsTable) {
+        let (width, height) = (tilt_series.get_width(), tilt_series.get_height());
+        let n = width as usize;
+        assert_eq!(
+            tilt_series.n_slices() as usize,
+            angles.len(),
+            "The tilt series has {} projections for {} angles",
+            tilt_series.n_slices(),
+            angles.len()
+        );
+        let system = self.system(n, angles, width, thickness);
+        let mut slices = Vec::<Vec<f32>>::with_capacity(height as usize);
+        let mut sums = vec![0.0f64; self.iterations];
+        let mut norm = 0.0f64;
+        for y in 0..height as usize {
+            let sinogram: Vec<f32> = tilt_series
+                .data()
+                .iter()
+                .flat_map(|projection| projection[y * n..(y + 1) * n].iter().copied())
+                .collect();
+            let (slice, residuals) = self.solve(&system, &sinogram);
+            sums.iter_mut()
+                .zip(residuals.iter())
+                .for_each(|(s, r)| *s += r);
+            norm += squared_norm(&sinogram);
+            slices.push(slice);
+        }
+        let residuals: Vec<f64> = sums.iter().map(|r| relative(*r, norm.sqrt())).collect();
+        (
+            ImageStack::new(width, thickness, slices, Gray32::new()),
+            residual_table(&residuals),
+        )
+    }
+
+    // Private function: geometry of the reconstruction (ART needs the rows of A)
+    fn system(&self, bins: usize, angles: &[f32], width: u32, height: u32) -> System {
+        let rays = self.algorithm == Algorithm::Art;
+        System::new(bins, angles, width, height, &self.mask, rays)
+    }
+
+    //
+    // Private function: runs the iterations, returns the slice and the squared residual
+    // of each iteration.
+    //
+    fn solve(&self, system: &System, sinogram: &[f32]) -> (Vec<f32>, Vec<f64>) {
+        let b: Vec<f64> = sinogram.iter().map(|v| *v as f64).collect();
+        let mut x = vec![0.0f64; system.width * system.height];
+        let mut residuals = Vec::<f64>::with_capacity(self.iterations);
+        for _ in 0..self.iterations {
+            match self.algorithm {
+                Algorithm::Art => self.art(system, &b, &mut x),
+                Algorithm::Sirt => self.sirt(system, &b, &mut x),
+                Algorithm::Sart => self.sart(system, &b, &mut x),
+            }
+            let p = system.project(&x);
+            residuals.push(b.iter().zip(p.iter()).map(|(b, p)| (b - p).powi(2)).sum());
+        }
+        (x.iter().map(|v| *v as f32).collect(), residuals)
+    }
+
+    // Kaczmarz: projection of x on the hyperplane of each ray
+    fn art(&self, system: &System, b: &[f64], x: &mut [f64]) {
+        for (a, rays) in system.rays.iter().enumerate() {
+            for (i, ray) in rays.iter().enumerate() {
+                let norm: f64 = ray.iter().map(|(_, w)| w * w).sum();
+                if norm <= 0.0 {
+                    continue;
+                }
+                let dot: f64 = ray.iter().map(|(j, w)| w * x[*j]).sum();
+                let c = self.relaxation * (b[a * system.bins + i] - dot) / norm;
+                for (j, w) in ray.iter() {
+                    x[*j] += c * w;
+                    if self.positivity && x[*j] < 0.0 {
+                        x[*j] = 0.0;
+                    }
+                }
+            }
+        }
+    }
+
+    // x += λ C Aᵗ R (b - Ax), with R and C the inverse row and column sums of A
+    fn sirt(&self, system: &System, b: &[f64], x: &mut [f64]) {
+        let mut corrections = vec![0.0f64; x.len()];
+        let mut weights = vec![0.0f64; x.len()];
+        for a in 0..system.angles.len() {
+            let residual = system.normalized_residual(a, b, x);
+            system.back_project_angle(a, &residual, &mut corrections);
+            system.back_project_angle(a, &vec![1.0; system.bins], &mut weights);
+        }
+        self.update(system, x, &corrections, &weights);
+    }
+
+    // SIRT restricted to one projection at a time
+    fn sart(&self, system: &System, b: &[f64], x: &mut [f64]) {
+        for a in 0..system.angles.len() {
+            let mut corrections = vec![0.0f64; x.len()];
+            let mut weights = vec![0.0f64; x.len()];
+            let residual = system.normalized_residual(a, b, x);
+            system.back_project_angle(a, &residual, &mut corrections);
+            system.back_project_angle(a, &vec![1.0; system.bins], &mut weights);
+            self.update(system, x, &corrections, &weights);
+        }
+    }
+
+    fn update(&self, system: &System, x: &mut [f64], corrections: &[f64], weights: &[f64]) {
+        for j in 0..x.len() {
+            if system.inside[j] && weights[j] > 0.0 {
+                x[j] += self.relaxation * corrections[j] / weights[j];
+                if self.positivity && x[j] < 0.0 {
+                    x[j] = 0.0;
+                }
+            }
+        }
+    }
+}
+
+//
+// Private structures and functions
+//
+
+// Geometry of the linear system Ax = b
+struct System {
+    bins: usize,
+    width: usize,
+    height: usize,
+    // (sin, cos) of each angle
+    angles: Vec<(f64, f64)>,
+    // Pixels reconstructed (mask)
+    inside: Vec<bool>,
+    // Row sums of A for each angle
+    row_sums: Vec<Vec<f64>>,
+    // Sparse rows of A for each angle (only computed for ART)
+    rays: Vec<Vec<Vec<(usize, f64)>>>,
+}
+
+impl System {
+    fn new(
+        bins: usize,
+        angles: &[f32],
+        width: u32,
+        height: u32,
+        mask: &Option<Roi>,
+        rays: bool,
+    ) -> Self {
+        let (width, height) = (width as usize, height as usize);
+        let inside = (0..width * height)
+            .map(|i| match mask {
+                Some(roi) => roi.contains_pixel((i % width) as i32, (i / width) as i32),
+                None => true,
+            })
+            .collect();
+        let mut system = System {
+            bins,
+            width,
+            height,
+            angles: angles
+                .iter()
+                .map(|a| (*a as f64).to_radians().sin_cos())
+                .collect(),
+            inside,
+            row_sums: vec![],
+            rays: vec![],
+        };
+        let ones = vec![1.0f64; width * height];
+        system.row_sums = (0..angles.len())
+            .map(|a| system.project_angle(a, &ones))
+            .collect();
+        if rays {
+            system.rays = (0..angles.len()).map(|a| system.rays_angle(a)).collect();
+        }
+        system
+    }
+
+    // Detector coordinate of the pixel (x,y) for the angle `a`
+    fn detector(&self, a: usize, x: usize, y: usize) -> f64 {
+        let (sin, cos) = self.angles[a];
+        let (cx, cy) = ((self.width / 2) as f64, (self.height / 2) as f64);
+        (x as f64 - cx) * cos + (y as f64 - cy) * sin + (self.bins / 2) as f64
+    }
+
+    fn project_angle(&self, a: usize, x: &[f64]) -> Vec<f64> {
+        let mut bins = vec![0.0f64; self.bins];
+        for (j, v) in x.iter().enumerate() {
+            if self.inside[j] && *v != 0.0 {
+                let t = self.detector(a, j % self.width, j / self.width);
+                splat(&mut bins, t, *v, INTERPOLATION);
+            }
+        }
+        bins
+    }
+
+    fn project(&self, x: &[f64]) -> Vec<f64> {
+        (0..self.angles.len())
+            .flat_map(|a| self.project_angle(a, x))
+            .collect()
+    }
+
+    fn back_project_angle(&self, a: usize, row: &[f64], out: &mut [f64]) {
+        let row: Vec<f32> = row.iter().map(|v| *v as f32).collect();
+        for (j, v) in out.iter_mut().enumerate() {
+            if self.inside[j] {
+                let t = self.detector(a, j % self.width, j / self.width);
+                *v += interpolate(&row, t, INTERPOLATION);
+            }
+        }
+    }
+
+    // R (b - Ax) for the angle `a`
+    fn normalized_residual(&self, a: usize, b: &[f64], x: &[f64]) -> Vec<f64> {
+        let p = self.project_angle(a, x);
+        (0..self.bins)
+            .map(|i| {
+                let sum = self.row_sums[a][i];
+                if sum > 0.0 {
+                    (b[a * self.bins + i] - p[i]) / sum
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    // Sparse rows of A for the angle `a`: the pixels (and weights) hitting each bin
+    fn rays_angle(&self, a: usize) -> Vec<Vec<(usize, f64)>> {
+        let mut rays = vec![Vec::<(usize, f64)>::new(); self.bins];
+        for j in 0..self.width * self.height {
+            if !self.inside[j] {
+                continue;
+            }
+            let t = self.detector(a, j % self.width, j / self.width);
+            let t0 = t.floor();
+            let f = t - t0;
+            for (i, w) in [(t0 as i64, 1.0 - f), (t0 as i64 + 1, f)] {
+                if i >= 0 && (i as usize) < self.bins && w > 0.0 {
+                    rays[i as usize].push((j, w));
+                }
+            }
+        }
+        rays
+    }
+}
+
+fn squared_norm(data: &[f32]) -> f64 {
+    data.iter().map(|v| (*v as f64).powi(2)).sum()
+}
+
+fn relative(squared_residual: f64, norm: f64) -> f64 {
+    if norm > 0.0 {
+        squared_residual.sqrt() / norm
+    } else {
+        squared_residual.sqrt()
+    }
+}
+
+fn residual_table(residuals: &[f64]) -> ResultsTable {
+    let mut table = ResultsTable::new(String::from("Residuals"));
+    let (iteration, residual) = (String::from("Iteration"), String::from("Residual"));
+    for (i, r) in residuals.iter().enumerate() {
+        table.add_row();
+        table.add_value(&iteration, Cell::Number((i + 1) as f64));
+        table.add_value(&residual, Cell::Number(*r));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cryoem::sinogram::Sinogram;
+
+    // Disk of radius 8 centered in a 33x33 slice
+    fn disk() -> FloatProcessor {
+        let pixels = (0..33 * 33)
+            .map(|i| {
+                let (x, y) = ((i % 33) as f32 - 16.0, (i / 33) as f32 - 16.0);
+                if x * x + y * y <= 64.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        FloatProcessor::new(33, 33, pixels, Gray32::new())
+    }
+
+    fn rms_error(a: &FloatProcessor, b: &FloatProcessor) -> f64 {
+        let sum: f64 = a
+            .data
+            .iter()
+            .zip(b.data.iter())
+            .map(|(u, v)| ((u - v) as f64).powi(2))
+            .sum();
+        (sum / a.data.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn residuals_decrease() {
+        let slice = disk();
+        let angles: Vec<f32> = (0..45).map(|a| a as f32 * 4.0).collect();
+        let sinogram = Sinogram::new(&slice, &angles);
+        for algorithm in [Algorithm::Art, Algorithm::Sirt, Algorithm::Sart] {
+            let mut solver = IterativeReconstruction::new(algorithm);
+            solver.set_iterations(20);
+            solver.set_positivity(true);
+            let (result, table) = solver.reconstruct(&sinogram, &angles, 33, 33);
+            let residuals = table
+                .get_column_as_floats(String::from("Residual"))
+                .unwrap();
+            assert_eq!(residuals.len(), 20);
+            assert!(residuals[19] < residuals[0], "{:?}", algorithm);
+            assert!(residuals[19] < 0.1, "{:?} {}", algorithm, residuals[19]);
+            assert!(rms_error(&result, &slice) < 0.15, "{:?}", algorithm);
+            assert!(result.data.iter().all(|v| *v >= 0.0));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The sinogram has 45 rows for 44 angles")]
+    fn angles_must_match_sinogram() {
+        let angles: Vec<f32> = (0..45).map(|a| a as f32 * 4.0).collect();
+        let sinogram = Sinogram::new(&disk(), &angles);
+        IterativeReconstruction::new(Algorithm::Art).reconstruct(&sinogram, &angles[1..], 33, 33);
+    }
+
+    #[test]
+    fn limited_angle_with_mask() {
+        let slice = disk();
+        // Tilt series from -60 to +60 degrees
+        let angles: Vec<f32> = (-30..=30).map(|a| a as f32 * 2.0).collect();
+        let sinogram = Sinogram::new(&slice, &angles);
+        let mut solver = IterativeReconstruction::new(Algorithm::Sart);
+        solver.set_iterations(30);
+        solver.set_mask(Roi::oval(4.0, 4.0, 25.0, 25.0));
+        let (result, _) = solver.reconstruct(&sinogram, &angles, 33, 33);
+        assert_eq!(result.data[0], 0.0);
+        assert_eq!(result.data[33 * 33 - 1], 0.0);
+        assert!((result.data[16 + 16 * 33] - 1.0).abs() < 0.1);
+        assert!(rms_error(&result, &slice) < 0.15);
+    }
+
+    #[test]
+    fn tilt_series_slice_by_slice() {
+        let slice = disk();
+        let angles: Vec<f32> = (0..30).map(|a| a as f32 * 6.0).collect();
+        let sinogram = Sinogram::new(&slice, &angles);
+        // Two identical rows per projection
+        let bins = sinogram.width;
+        let projections: Vec<Vec<f32>> = sinogram
+            .data
+            .chunks(bins as usize)
+            .map(|row| row.iter().chain(row.iter()).copied().collect())
+            .collect();
+        let stack = ImageStack::new(bins, 2, projections, Gray32::new());
+        let mut solver = IterativeReconstruction::new(Algorithm::Sirt);
+        solver.set_iterations(30);
+        let (volume, table) = solver.reconstruct_stack(&stack, &angles, 33);
+        assert_eq!(volume.n_slices(), 2);
+        assert_eq!(volume.get_height(), 33);
+        assert_eq!(volume.data()[0], volume.data()[1]);
+        let (single, single_table) = solver.reconstruct(&sinogram, &angles, bins, 33);
+        assert_eq!(volume.data()[0], single.data);
+        let stack_residuals = table
+            .get_column_as_floats(String::from("Residual"))
+            .unwrap();
+        let single_residuals = single_table
+            .get_column_as_floats(String::from("Residual"))
+            .unwrap();
+        assert!((stack_residuals[29] - single_residuals[29]).abs() < 1e-9);
+    }
+}
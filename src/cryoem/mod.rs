@@ -1 +1,15 @@
+pub mod backprojection;
+pub mod cli;
+pub mod common_lines;
+pub mod ctf;
+pub mod euler;
+pub mod fourier;
+pub mod fsc;
+pub mod iterative;
+pub mod motion;
+pub mod projection;
+pub mod projection_matching;
+pub mod sampling;
 pub mod sinogram;
+pub mod symmetry;
+pub mod tomography;
@@ -0,0 +1,695 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Correction of the beam-induced motion of movie stacks.
+//!
+//! The shift `(dx, dy)` of a frame is the displacement of its content relative to the first
+//! frame: the aligned frame is `aligned(p) = frame(p + shift)`.
+//!
+//! The global shifts are refined iteratively: every frame is aligned to the sum of the other
+//! aligned frames by a cross-correlation weighted by the B-factor `exp(-B s^2 / 4)`. The
+//! local motion is measured the same way in patches of the globally aligned frames, then
+//! smoothed by the polynomial deformation model of MotionCor2: the coefficients of
+//! `{t, t^2, t^3} x {1, x, x^2, y, y^2, xy}` are fitted by least squares.
+//!
+//! The dose weighting (Grant and Grigorieff, 2015) attenuates each frequency of each frame
+//! according to the accumulated exposure and the critical exposure `a s^b + c`.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::fft::{fft_3d, frequency};
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::{Gray, Gray32};
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::pixel::PixelType;
+use crate::registration::{peak, taper};
+use crate::results_table::{Cell, ResultsTable};
+use crate::transformable::Transform;
+use nalgebra::{DMatrix, DVector};
+use num::complex::Complex64;
+use std::f64::consts::PI;
+
+///
+/// Exposure filter of the dose weighting
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DoseWeighting {
+    /// Exposure of each frame in e/Å^2
+    pub dose_per_frame: f64,
+    /// Exposure before the first frame in e/Å^2
+    pub pre_exposure: f64,
+    /// Acceleration voltage in kV (300 or 200)
+    pub voltage: f64,
+}
+
+impl DoseWeighting {
+    pub fn new(dose_per_frame: f64) -> Self {
+        DoseWeighting {
+            dose_per_frame,
+            pre_exposure: 0.0,
+            voltage: 300.0,
+        }
+    }
+
+    ///
+    /// Returns the critical exposure (e/Å^2) at the spatial frequency `s` (1/Å).
+    ///
+    pub fn critical_exposure(&self, s: f64) -> f64 {
+        let exposure = 0.245 * s.powf(-1.665) + 2.81;
+        // Values measured at 300 kV
+        if self.voltage < 250.0 {
+            exposure * 0.8
+        } else {
+            exposure
+        }
+    }
+
+    /// Returns the weight of the frequency `s` (1/Å) of the frame `frame` (from 0).
+    pub fn weight(&self, frame: usize, s: f64) -> f64 {
+        if s <= 0.0 {
+            return 1.0;
+        }
+        let exposure = self.pre_exposure + (frame + 1) as f64 * self.dose_per_frame;
+        (-exposure / (2.0 * self.critical_exposure(s))).exp()
+    }
+}
+
+///
+/// Local motion of the patches and its smooth model
+///
+#[derive(Clone, Debug)]
+pub struct LocalMotion {
+    width: u32,
+    height: u32,
+    /// Centers of the patches
+    pub centers: Vec<(f64, f64)>,
+    /// Measured shifts of each patch (relative to the global shifts) by frame
+    pub shifts: Vec<Vec<(f64, f64)>>,
+    coefficients: [Vec<f64>; 2],
+}
+
+impl LocalMotion {
+    ///
+    /// Returns the shift (relative to the global shift) of the model at the pixel `(x, y)`
+    /// of the frame `frame`.
+    ///
+    pub fn shift(&self, x: f64, y: f64, frame: usize) -> (f64, f64) {
+        let basis = basis(
+            2.0 * x / self.width as f64 - 1.0,
+            2.0 * y / self.height as f64 - 1.0,
+            frame as f64,
+        );
+        let eval = |c: &[f64]| basis.iter().zip(c.iter()).map(|(b, c)| b * c).sum::<f64>();
+        (eval(&self.coefficients[0]), eval(&self.coefficients[1]))
+    }
+
+    ///
+    /// Returns the table of the measured and modelled local shifts (columns `frame`,
+    /// `x`, `y`, `dx`, `dy`, `model_dx` and `model_dy`).
+    ///
+    pub fn table(&self) -> ResultsTable {
+        let mut table = ResultsTable::new(String::from("Local motion"));
+        for (center, shifts) in self.centers.iter().zip(self.shifts.iter()) {
+            for (t, (dx, dy)) in shifts.iter().enumerate() {
+                let (mx, my) = self.shift(center.0, center.1, t);
+                table.add_row();
+                for (column, value) in [
+                    ("frame", (t + 1) as f64),
+                    ("x", center.0),
+                    ("y", center.1),
+                    ("dx", *dx),
+                    ("dy", *dy),
+                    ("model_dx", mx),
+                    ("model_dy", my),
+                ] {
+                    table.add_value(&column.to_string(), Cell::Number(value));
+                }
+            }
+        }
+        table
+    }
+}
+
+///
+/// Result of the motion correction
+///
+pub struct MotionResult {
+    /// Sum of the aligned (and dose-weighted) frames
+    pub sum: FloatProcessor,
+    /// Global shifts of the frames
+    pub shifts: Vec<(f64, f64)>,
+    pub local: Option<LocalMotion>,
+}
+
+impl MotionResult {
+    /// Returns the table of the global shifts (columns `frame`, `dx` and `dy`).
+    pub fn table(&self) -> ResultsTable {
+        let mut table = ResultsTable::new(String::from("Shifts"));
+        for (i, (dx, dy)) in self.shifts.iter().enumerate() {
+            table.add_row();
+            for (column, value) in [("frame", (i + 1) as f64), ("dx", *dx), ("dy", *dy)] {
+                table.add_value(&column.to_string(), Cell::Number(value));
+            }
+        }
+        table
+    }
+}
+
+///
+/// Motion correction of movies
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::motion::{DoseWeighting, MotionCorrection};
+/// use rim::grayscale::Gray32;
+/// use rim::image_stack::ImageStack;
+///
+/// // Spot drifting by 1 pixel per frame along X
+/// let frame = |t: usize| -> Vec<f32> {
+///     (0..64 * 64)
+///         .map(|i| {
+///             let (x, y) = ((i % 64) as f32 - 30.0 - t as f32, (i / 64) as f32 - 30.0);
+///             (-(x * x + y * y) / 8.0).exp()
+///         })
+///         .collect()
+/// };
+/// let movie = ImageStack::new(64, 64, (0..4).map(frame).collect(), Gray32::new());
+/// let mut correction = MotionCorrection::new(1.0);
+/// correction.set_dose_weighting(Some(DoseWeighting::new(1.5)));
+/// let result = correction.correct(&movie).unwrap();
+/// assert!((result.shifts[3].0 - 3.0).abs() < 0.1);
+/// assert_eq!(result.table().size(), 4);
+/// ```
+pub struct MotionCorrection {
+    pixel_size: f64,
+    b_factor: f64,
+    iterations: usize,
+    tolerance: f64,
+    max_shift: f64,
+    patches: Option<(u32, u32)>,
+    dose: Option<DoseWeighting>,
+}
+
+impl MotionCorrection {
+    ///
+    /// Creates a motion correction for movies of pixel size `pixel_size` in Å, with a
+    /// B-factor of 150 Å^2, at most 10 iterations down to 0.05 pixel and shifts up to 40 pixels.
+    ///
+    pub fn new(pixel_size: f64) -> Self {
+        MotionCorrection {
+            pixel_size,
+            b_factor: 150.0,
+            iterations: 10,
+            tolerance: 0.05,
+            max_shift: 40.0,
+            patches: None,
+            dose: None,
+        }
+    }
+
+    /// Sets the B-factor (Å^2) of the cross-correlations.
+    pub fn set_b_factor(&mut self, b_factor: f64) {
+        self.b_factor = b_factor;
+    }
+
+    pub fn get_b_factor(&self) -> f64 {
+        self.b_factor
+    }
+
+    pub fn set_iterations(&mut self, iterations: usize) {
+        self.iterations = iterations;
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Sets the largest change (pixels) of the shifts stopping the iterations.
+    pub fn set_tolerance(&mut self, tolerance: f64) {
+        self.tolerance = tolerance;
+    }
+
+    pub fn get_tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    /// Sets the largest shift (pixels) between a frame and the sum.
+    pub fn set_max_shift(&mut self, max_shift: f64) {
+        self.max_shift = max_shift;
+    }
+
+    pub fn get_max_shift(&self) -> f64 {
+        self.max_shift
+    }
+
+    /// Enables the local motion with a grid of `columns` x `rows` patches.
+    pub fn set_patches(&mut self, patches: Option<(u32, u32)>) {
+        self.patches = patches;
+    }
+
+    pub fn get_patches(&self) -> Option<(u32, u32)> {
+        self.patches
+    }
+
+    pub fn set_dose_weighting(&mut self, dose: Option<DoseWeighting>) {
+        self.dose = dose;
+    }
+
+    pub fn get_dose_weighting(&self) -> Option<DoseWeighting> {
+        self.dose
+    }
+
+    ///
+    /// Returns the global shifts of the frames of `movie`.
+    ///
+    pub fn global_shifts<T: PixelType + Clone>(
+        &self,
+        movie: &ImageStack<T, Gray<T>>,
+    ) -> Result<Vec<(f64, f64)>, String> {
+        let (w, h) = check(movie)?;
+        let spectra: Vec<Vec<Complex64>> = movie
+            .data
+            .iter()
+            .map(|frame| spectrum(frame.iter().map(|v| v.to_f32() as f64), w, h, false))
+            .collect();
+        Ok(self.align(&spectra, w, h, self.max_shift))
+    }
+
+    ///
+    /// Returns the aligned sum, the global shifts and the local motion (if patches are set)
+    /// of `movie`.
+    ///
+    pub fn correct<T: PixelType + Clone>(
+        &self,
+        movie: &ImageStack<T, Gray<T>>,
+    ) -> Result<MotionResult, String> {
+        let (w, h) = check(movie)?;
+        let spectra: Vec<Vec<Complex64>> = movie
+            .data
+            .iter()
+            .map(|frame| spectrum(frame.iter().map(|v| v.to_f32() as f64), w, h, false))
+            .collect();
+        let shifts = self.align(&spectra, w, h, self.max_shift);
+        let mut aligned: Vec<Vec<Complex64>> = spectra
+            .iter()
+            .zip(shifts.iter())
+            .map(|(s, shift)| shifted(s, w, h, *shift))
+            .collect();
+        let local = match self.patches {
+            Some(grid) => {
+                let frames: Vec<FloatProcessor> =
+                    aligned.iter().map(|s| to_processor(s, w, h)).collect();
+                let local = self.local_motion(&frames, grid)?;
+                aligned = frames
+                    .iter()
+                    .enumerate()
+                    .map(|(t, frame)| {
+                        let pixels = (0..w * h).map(|i| {
+                            let (x, y) = ((i % w) as f64, (i / w) as f64);
+                            let (dx, dy) = local.shift(x, y, t);
+                            frame.get_interpolated_pixel(x + dx, y + dy)
+                        });
+                        spectrum(pixels, w, h, false)
+                    })
+                    .collect();
+                Some(local)
+            }
+            None => None,
+        };
+        Ok(MotionResult {
+            sum: self.sum(&aligned, w, h),
+            shifts,
+            local,
+        })
+    }
+
+    //
+    // Private methods
+    //
+
+    // Iterative alignment of the frames to the sum of the others
+    fn align(
+        &self,
+        spectra: &[Vec<Complex64>],
+        w: usize,
+        h: usize,
+        max_shift: f64,
+    ) -> Vec<(f64, f64)> {
+        let n = spectra.len();
+        let filter: Vec<f64> = frequencies(w, h, self.pixel_size)
+            .map(|s2| (-self.b_factor * s2 / 4.0).exp())
+            .collect();
+        let mut shifts = vec![(0.0, 0.0); n];
+        for _ in 0..self.iterations {
+            let aligned: Vec<Vec<Complex64>> = spectra
+                .iter()
+                .zip(shifts.iter())
+                .map(|(s, shift)| shifted(s, w, h, *shift))
+                .collect();
+            let mut sum = vec![Complex64::new(0.0, 0.0); w * h];
+            for frame in aligned.iter() {
+                sum.iter_mut().zip(frame.iter()).for_each(|(s, v)| *s += v);
+            }
+            let mut change = 0.0f64;
+            for i in 0..n {
+                let mut cc: Vec<Complex64> = (0..w * h)
+                    .map(|k| spectra[i][k] * (sum[k] - aligned[i][k]).conj() * filter[k])
+                    .collect();
+                fft_3d(&mut cc, w, h, 1, true);
+                let (dx, dy, _) = peak(&cc, w, h, (0.0, 0.0), max_shift);
+                change = change.max((dx - shifts[i].0).hypot(dy - shifts[i].1));
+                shifts[i] = (dx, dy);
+            }
+            if change < self.tolerance {
+                break;
+            }
+        }
+        // Relative to the first frame
+        let first = shifts[0];
+        shifts
+            .iter()
+            .map(|(x, y)| (x - first.0, y - first.1))
+            .collect()
+    }
+
+    fn local_motion(
+        &self,
+        frames: &[FloatProcessor],
+        grid: (u32, u32),
+    ) -> Result<LocalMotion, String> {
+        let (w, h) = (frames[0].width as usize, frames[0].height as usize);
+        let (columns, rows) = (grid.0.max(1) as usize, grid.1.max(1) as usize);
+        let (pw, ph) = (w / columns, h / rows);
+        let n = frames.len();
+        if pw < 16 || ph < 16 {
+            return Err(format!("Patches of {}x{} pixels are too small", pw, ph));
+        }
+        if columns * rows * (n - 1) < BASIS_SIZE {
+            return Err(format!(
+                "{} patches and {} frames are not enough for the deformation model",
+                columns * rows,
+                n
+            ));
+        }
+        let (tx, ty) = (taper(pw), taper(ph));
+        let mut centers = Vec::new();
+        let mut measured = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x0, y0) = (column * pw, row * ph);
+                let spectra: Vec<Vec<Complex64>> = frames
+                    .iter()
+                    .map(|frame| {
+                        let pixels = (0..pw * ph).map(|i| {
+                            let (x, y) = (x0 + i % pw, y0 + i / pw);
+                            frame.data[x + y * w] as f64
+                        });
+                        let mean = pixels.clone().sum::<f64>() / (pw * ph) as f64;
+                        let tapered = pixels
+                            .enumerate()
+                            .map(|(i, v)| (v - mean) * tx[i % pw] * ty[i / pw]);
+                        spectrum(tapered, pw, ph, true)
+                    })
+                    .collect();
+                let max_shift = self.max_shift.min((pw.min(ph) / 4) as f64);
+                centers.push(((x0 + pw / 2) as f64, (y0 + ph / 2) as f64));
+                measured.push(self.align(&spectra, pw, ph, max_shift));
+            }
+        }
+        // Least squares fit of the model
+        let rows_count = centers.len() * n;
+        let mut a = DMatrix::<f64>::zeros(rows_count, BASIS_SIZE);
+        let mut b = [
+            DVector::<f64>::zeros(rows_count),
+            DVector::<f64>::zeros(rows_count),
+        ];
+        for (p, (center, shifts)) in centers.iter().zip(measured.iter()).enumerate() {
+            for (t, shift) in shifts.iter().enumerate() {
+                let r = p * n + t;
+                let values = basis(
+                    2.0 * center.0 / w as f64 - 1.0,
+                    2.0 * center.1 / h as f64 - 1.0,
+                    t as f64,
+                );
+                for (c, v) in values.iter().enumerate() {
+                    a[(r, c)] = *v;
+                }
+                b[0][r] = shift.0;
+                b[1][r] = shift.1;
+            }
+        }
+        let svd = a.svd(true, true);
+        let mut coefficients = [Vec::new(), Vec::new()];
+        for axis in 0..2 {
+            coefficients[axis] = svd.solve(&b[axis], 1e-9)?.iter().copied().collect();
+        }
+        Ok(LocalMotion {
+            width: w as u32,
+            height: h as u32,
+            centers,
+            shifts: measured,
+            coefficients,
+        })
+    }
+
+    // Sum of the aligned spectra, dose-weighted with the restoration of the noise power
+    fn sum(&self, aligned: &[Vec<Complex64>], w: usize, h: usize) -> FloatProcessor {
+        let mut sum = vec![Complex64::new(0.0, 0.0); w * h];
+        match &self.dose {
+            Some(dose) => {
+                let s: Vec<f64> = frequencies(w, h, self.pixel_size)
+                    .map(|s2| s2.sqrt())
+                    .collect();
+                let mut norms = vec![0.0f64; w * h];
+                for (t, frame) in aligned.iter().enumerate() {
+                    for k in 0..w * h {
+                        let weight = dose.weight(t, s[k]);
+                        sum[k] += frame[k] * weight;
+                        norms[k] += weight * weight;
+                    }
+                }
+                let scale = (aligned.len() as f64).sqrt();
+                for (v, norm) in sum.iter_mut().zip(norms.iter()) {
+                    *v *= scale / norm.sqrt();
+                }
+            }
+            None => {
+                for frame in aligned.iter() {
+                    sum.iter_mut().zip(frame.iter()).for_each(|(s, v)| *s += v);
+                }
+            }
+        }
+        to_processor(&sum, w, h)
+    }
+}
+
+//
+// Private functions
+//
+
+const BASIS_SIZE: usize = 18;
+
+// {t, t^2, t^3} x {1, x, x^2, y, y^2, xy}
+fn basis(x: f64, y: f64, t: f64) -> [f64; BASIS_SIZE] {
+    let spatial = [1.0, x, x * x, y, y * y, x * y];
+    let mut values = [0.0; BASIS_SIZE];
+    for (i, p) in [t, t * t, t * t * t].iter().enumerate() {
+        for (j, s) in spatial.iter().enumerate() {
+            values[i * 6 + j] = p * s;
+        }
+    }
+    values
+}
+
+fn check<T: PixelType + Clone>(movie: &ImageStack<T, Gray<T>>) -> Result<(usize, usize), String> {
+    if movie.data.len() < 2 {
+        return Err(String::from("A movie needs at least 2 frames"));
+    }
+    Ok((movie.width as usize, movie.height as usize))
+}
+
+fn spectrum<I: Iterator<Item = f64>>(
+    pixels: I,
+    w: usize,
+    h: usize,
+    zero_mean: bool,
+) -> Vec<Complex64> {
+    let mut data: Vec<Complex64> = pixels.map(|v| Complex64::new(v, 0.0)).collect();
+    if !zero_mean {
+        let mean = data.iter().map(|v| v.re).sum::<f64>() / (w * h) as f64;
+        data.iter_mut().for_each(|v| v.re -= mean);
+    }
+    fft_3d(&mut data, w, h, 1, false);
+    data
+}
+
+fn to_processor(spectrum: &[Complex64], w: usize, h: usize) -> FloatProcessor {
+    let mut data = spectrum.to_vec();
+    fft_3d(&mut data, w, h, 1, true);
+    let pixels = data.iter().map(|v| v.re as f32).collect();
+    ImageProcessor::new(w as u32, h as u32, pixels, Gray32::new())
+}
+
+// Squared spatial frequencies (1/Å^2) in the FFT order
+fn frequencies(w: usize, h: usize, pixel_size: f64) -> impl Iterator<Item = f64> {
+    (0..w * h).map(move |i| {
+        let sx = frequency(i % w, w) / pixel_size;
+        let sy = frequency(i / w, h) / pixel_size;
+        sx * sx + sy * sy
+    })
+}
+
+// Spectrum of the frame aligned by `shift`: aligned(p) = frame(p + shift)
+fn shifted(spectrum: &[Complex64], w: usize, h: usize, shift: (f64, f64)) -> Vec<Complex64> {
+    spectrum
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let phase = 2.0 * PI * (frequency(i % w, w) * shift.0 + frequency(i / w, h) * shift.1);
+            v * Complex64::from_polar(1.0, phase)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, StdRng};
+
+    const SIZE: u32 = 128;
+
+    // Random spots seen through a deformation, plus noise
+    fn movie<F: Fn(f64, f64, usize) -> (f64, f64)>(
+        frames: usize,
+        motion: F,
+        noise: f32,
+    ) -> ImageStack<f32, Gray32> {
+        let mut rng: StdRng = SeedableRng::from_seed(&[4usize, 2][..]);
+        let spots: Vec<(f64, f64)> = (0..150)
+            .map(|_| {
+                (
+                    rng.gen_range(0.0, SIZE as f64),
+                    rng.gen_range(0.0, SIZE as f64),
+                )
+            })
+            .collect();
+        let slices = (0..frames)
+            .map(|t| {
+                (0..SIZE * SIZE)
+                    .map(|i| {
+                        let (x, y) = ((i % SIZE) as f64, (i / SIZE) as f64);
+                        let (dx, dy) = motion(x, y, t);
+                        let value: f64 = spots
+                            .iter()
+                            .map(|(sx, sy)| {
+                                (-((x - dx - sx).powi(2) + (y - dy - sy).powi(2)) / 4.0).exp()
+                            })
+                            .sum();
+                        value as f32 + noise * rng.gen_range(-1.0, 1.0)
+                    })
+                    .collect()
+            })
+            .collect();
+        ImageStack::new(SIZE, SIZE, slices, Gray32::new())
+    }
+
+    #[test]
+    fn exposure_filter() {
+        let dose = DoseWeighting::new(2.0);
+        assert!((dose.critical_exposure(0.1) - 14.1).abs() < 0.1);
+        // Exposure of 2 critical exposures after the 7th frame
+        let s = ((14.0 / 2.0 - 2.81) / 0.245f64).powf(-1.0 / 1.665);
+        assert!((dose.weight(6, s) - (-1.0f64).exp()).abs() < 1e-6);
+        assert_eq!(dose.weight(6, 0.0), 1.0);
+        assert!(dose.weight(0, 0.3) > dose.weight(10, 0.3));
+    }
+
+    #[test]
+    fn global_drift() {
+        let drift = |t: usize| (0.8 * t as f64, -0.5 * t as f64 + 0.05 * (t * t) as f64);
+        let stack = movie(8, |_, _, t| drift(t), 0.5);
+        let mut correction = MotionCorrection::new(1.0);
+        correction.set_b_factor(20.0);
+        let result = correction.correct(&stack).unwrap();
+        for (t, (dx, dy)) in result.shifts.iter().enumerate() {
+            let (x, y) = drift(t);
+            assert!(
+                (dx - x).abs() < 0.15 && (dy - y).abs() < 0.15,
+                "{} {:?}",
+                t,
+                result.shifts
+            );
+        }
+        let table = result.table();
+        assert_eq!(table.size(), 8);
+        assert_eq!(
+            table.get_column_as_floats(String::from("dy")).unwrap()[3],
+            result.shifts[3].1
+        );
+        // The aligned sum is sharper than the raw sum
+        let variance = |pixels: &[f32]| -> f32 {
+            let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+            pixels.iter().map(|v| (v - mean).powi(2)).sum()
+        };
+        let raw: Vec<f32> = (0..stack.data[0].len())
+            .map(|i| stack.data.iter().map(|f| f[i]).sum::<f32>())
+            .collect();
+        assert!(variance(&result.sum.data) > 1.5 * variance(&raw));
+    }
+
+    #[test]
+    fn local_motion() {
+        // Stretching along X in addition to the drift
+        let c = SIZE as f64 / 2.0;
+        let motion = move |x: f64, _: f64, t: usize| {
+            (
+                0.5 * t as f64 + 0.04 * t as f64 * (x - c) / c * 10.0,
+                0.3 * t as f64,
+            )
+        };
+        let stack = movie(8, motion, 0.2);
+        let mut correction = MotionCorrection::new(1.0);
+        correction.set_b_factor(20.0);
+        correction.set_patches(Some((3, 3)));
+        let result = correction.correct(&stack).unwrap();
+        let local = result.local.as_ref().unwrap();
+        assert_eq!(local.centers.len(), 9);
+        assert_eq!(local.table().size(), 72);
+        for t in 0..8 {
+            for (x, y) in [(30.0, 40.0), (64.0, 64.0), (100.0, 90.0)] {
+                let (gx, gy) = result.shifts[t];
+                let (lx, ly) = local.shift(x, y, t);
+                let (ex, ey) = motion(x + gx + lx, y + gy + ly, t);
+                assert!(
+                    (gx + lx - ex).abs() < 0.4 && (gy + ly - ey).abs() < 0.4,
+                    "{} {} {} {} {}",
+                    t,
+                    x,
+                    gx + lx,
+                    ex,
+                    gy + ly
+                );
+            }
+        }
+    }
+}
@@ -16,5 +16,286 @@
 //
 //  You should have received a copy of the GNU General Public License
 //  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
- 
- 
+
+//!
+//! Real-space forward projection of volumes.
+//!
+//! The projection of the orientation `A` (rotation matrix of RELION, see
+//! [euler](crate::cryoem::euler)) is the integral along Z of the volume rotated by `A`:
+//! the pixel `p = (x, y)` of the projection sums the voxels `r` such that `A r = (x, y, z)`.
+//! The volume and the projection are centered on the voxel `(w/2, h/2, d/2)` and the pixel `(w/2, h/2)`.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::cryoem::euler::{read_angles, Convention, Euler};
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::pixel::PixelType;
+use crate::results_table::ResultsTable;
+use crate::vecmath::matrix3::Matrix3;
+use crate::volume_processor::VolumeProcessor;
+
+///
+/// Projection algorithms
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionMode {
+    /// Each pixel integrates the volume (trilinear interpolation) along its ray, one sample per voxel.
+    RayDriven,
+    /// Each voxel is splatted (bilinear weights) on the projection plane. The mass is preserved.
+    VoxelDriven,
+}
+
+///
+/// Forward projector of volumes
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::euler::{Convention, Euler};
+/// use rim::cryoem::projection::{ProjectionMode, Projector};
+/// use rim::grayscale::Gray32;
+/// use rim::volume_processor::VolumeProcessor;
+///
+/// let vol = VolumeProcessor::new_volume(4, 4, 4, vec![1.0f32; 64], Gray32::new());
+/// let projector = Projector::new(ProjectionMode::VoxelDriven);
+/// let proj = projector.project_euler(&vol, &Euler::new(0.0, 0.0, 0.0), Convention::Relion);
+/// assert_eq!(proj.data()[5], 4.0);
+/// ```
+pub struct Projector {
+    mode: ProjectionMode,
+}
+
+impl Projector {
+    pub fn new(mode: ProjectionMode) -> Self {
+        Projector { mode }
+    }
+
+    pub fn mode(&self) -> ProjectionMode {
+        self.mode
+    }
+
+    ///
+    /// Returns the projection (same width and height as the volume) of the orientation `rotation`.
+    ///
+    pub fn project<T: PixelType>(
+        &self,
+        volume: &VolumeProcessor<T>,
+        rotation: &Matrix3,
+    ) -> FloatProcessor {
+        let pixels = match self.mode {
+            ProjectionMode::RayDriven => ray_driven(volume, rotation),
+            ProjectionMode::VoxelDriven => voxel_driven(volume, rotation),
+        };
+        ImageProcessor::new(volume.width, volume.height, pixels, Gray32::new())
+    }
+
+    /// Returns the projection of the orientation given by Euler angles.
+    pub fn project_euler<T: PixelType>(
+        &self,
+        volume: &VolumeProcessor<T>,
+        angles: &Euler,
+        convention: Convention,
+    ) -> FloatProcessor {
+        self.project(volume, &angles.to_matrix(convention))
+    }
+
+    ///
+    /// Returns the stack of the projections of the orientations of the
+    /// columns `psi`, `theta` and `phi` of `table`.
+    ///
+    pub fn project_table<T: PixelType>(
+        &self,
+        volume: &VolumeProcessor<T>,
+        table: &ResultsTable,
+        convention: Convention,
+    ) -> Result<ImageStack<f32, Gray32>, String> {
+        let slices = read_angles(table)?
+            .iter()
+            .map(|angles| self.project_euler(volume, angles, convention).data)
+            .collect();
+        Ok(ImageStack::new(
+            volume.width,
+            volume.height,
+            slices,
+            Gray32::new(),
+        ))
+    }
+}
+
+//
+// Private functions
+//
+
+fn center<T: PixelType>(volume: &VolumeProcessor<T>) -> (f64, f64, f64) {
+    (
+        (volume.width / 2) as f64,
+        (volume.height / 2) as f64,
+        (volume.depth / 2) as f64,
+    )
+}
+
+fn ray_driven<T: PixelType>(volume: &VolumeProcessor<T>, rotation: &Matrix3) -> Vec<f32> {
+    let a = rotation.values();
+    let (cx, cy, cz) = center(volume);
+    let (w, h, d) = (
+        volume.width as f64,
+        volume.height as f64,
+        volume.depth as f64,
+    );
+    // Half length of the rays
+    let half = ((w * w + h * h + d * d).sqrt() / 2.0).ceil() as i64;
+    let mut pixels = Vec::<f32>::with_capacity((volume.width * volume.height) as usize);
+    for y in 0..volume.height {
+        let dy = y as f64 - cy;
+        for x in 0..volume.width {
+            let dx = x as f64 - cx;
+            // r = A^T (dx, dy, z) + c
+            let bx = a[0] * dx + a[3] * dy + cx;
+            let by = a[1] * dx + a[4] * dy + cy;
+            let bz = a[2] * dx + a[5] * dy + cz;
+            let mut sum = 0.0;
+            for z in -half..=half {
+                let z = z as f64;
+                let (rx, ry, rz) = (bx + a[6] * z, by + a[7] * z, bz + a[8] * z);
+                if rx > -1.0 && rx < w && ry > -1.0 && ry < h && rz > -1.0 && rz < d {
+                    sum += volume.trilinear(rx, ry, rz, 0.0);
+                }
+            }
+            pixels.push(sum as f32);
+        }
+    }
+    pixels
+}
+
+fn voxel_driven<T: PixelType>(volume: &VolumeProcessor<T>, rotation: &Matrix3) -> Vec<f32> {
+    let a = rotation.values();
+    let (cx, cy, cz) = center(volume);
+    let (w, h) = (volume.width as i64, volume.height as i64);
+    let mut sums = vec![0.0f64; (w * h) as usize];
+    let mut splat = |x: i64, y: i64, value: f64| {
+        if x >= 0 && x < w && y >= 0 && y < h {
+            sums[(x + y * w) as usize] += value;
+        }
+    };
+    let mut index = 0;
+    for z in 0..volume.depth {
+        let dz = z as f64 - cz;
+        for y in 0..volume.height {
+            let dy = y as f64 - cy;
+            for x in 0..volume.width {
+                let value = volume.data[index].to_f32() as f64;
+                index += 1;
+                if value == 0.0 {
+                    continue;
+                }
+                let dx = x as f64 - cx;
+                let u = a[0] * dx + a[1] * dy + a[2] * dz + cx;
+                let v = a[3] * dx + a[4] * dy + a[5] * dz + cy;
+                let (u0, v0) = (u.floor(), v.floor());
+                let (fu, fv) = (u - u0, v - v0);
+                let (iu, iv) = (u0 as i64, v0 as i64);
+                splat(iu, iv, value * (1.0 - fu) * (1.0 - fv));
+                splat(iu + 1, iv, value * fu * (1.0 - fv));
+                splat(iu, iv + 1, value * (1.0 - fu) * fv);
+                splat(iu + 1, iv + 1, value * fu * fv);
+            }
+        }
+    }
+    sums.iter().map(|v| *v as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::io::text_reader::TextReader;
+
+    // Small asymmetric object
+    fn phantom() -> VolumeProcessor<f32> {
+        let n = 8u32;
+        let mut voxels = vec![0.0f32; (n * n * n) as usize];
+        let mut put = |x: u32, y: u32, z: u32, v: f32| voxels[(x + y * n + z * n * n) as usize] = v;
+        put(4, 4, 4, 1.0);
+        put(5, 4, 4, 2.0);
+        put(4, 6, 3, 3.0);
+        VolumeProcessor::new_volume(n, n, n, voxels, Gray32::new())
+    }
+
+    #[test]
+    fn top_view_sums_along_z() {
+        let vol = phantom();
+        for mode in [ProjectionMode::RayDriven, ProjectionMode::VoxelDriven] {
+            let proj = Projector::new(mode).project(&vol, &Matrix3::identity());
+            assert_eq!(proj.data()[4 + 4 * 8], 1.0, "{:?}", mode);
+            assert_eq!(proj.data()[5 + 4 * 8], 2.0, "{:?}", mode);
+            assert_eq!(proj.data()[4 + 6 * 8], 3.0, "{:?}", mode);
+            assert_eq!(proj.data().iter().sum::<f32>(), 6.0, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn side_view_along_x() {
+        let vol = phantom();
+        // Tilt of 90 degrees: the projection axis is X
+        let angles = Euler::new(0.0, 90.0, 0.0);
+        for mode in [ProjectionMode::RayDriven, ProjectionMode::VoxelDriven] {
+            let proj = Projector::new(mode).project_euler(&vol, &angles, Convention::Relion);
+            // A maps (dx, dy, dz) to (-dz, dy, dx)
+            assert!((proj.data()[4 + 4 * 8] - 3.0).abs() < 1e-6, "{:?}", mode);
+            assert!((proj.data()[5 + 6 * 8] - 3.0).abs() < 1e-6, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn modes_agree_on_oblique_views() {
+        let n = 16u32;
+        let mut voxels = Vec::<f32>::new();
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let r2 = [x, y, z]
+                        .iter()
+                        .map(|v| (*v as f32 - 8.0).powi(2))
+                        .sum::<f32>();
+                    voxels.push((-r2 / 8.0).exp());
+                }
+            }
+        }
+        let vol = VolumeProcessor::new_volume(n, n, n, voxels, Gray32::new());
+        let angles = Euler::new(20.0, 35.0, 50.0);
+        let ray = Projector::new(ProjectionMode::RayDriven).project_euler(
+            &vol,
+            &angles,
+            Convention::Relion,
+        );
+        let voxel = Projector::new(ProjectionMode::VoxelDriven).project_euler(
+            &vol,
+            &angles,
+            Convention::Relion,
+        );
+        let total = voxel.data().iter().sum::<f32>();
+        let diff: f32 = ray
+            .data()
+            .iter()
+            .zip(voxel.data())
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        assert!(diff / total < 0.1, "{}", diff / total);
+    }
+
+    #[test]
+    fn project_csv_orientations() {
+        let rt = TextReader::open_csv("./samples/psi-theta-phi-50.csv", None).unwrap();
+        let stack = Projector::new(ProjectionMode::VoxelDriven)
+            .project_table(&phantom(), &rt, Convention::Yxz)
+            .unwrap();
+        assert_eq!(stack.n_slices(), 50);
+        for slice in stack.data().iter() {
+            assert!((slice.iter().sum::<f32>() - 6.0).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,608 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Projection matching: assignment of the orientations and shifts of particle images by
+//! comparison with projections of a reference volume.
+//!
+//! The references are the projections without in-plane rotation of the directions of an
+//! even angular grid (see [sampling](crate::cryoem::sampling)). Each image is rotated by
+//! every in-plane angle `psi`, then its cross-correlation with every reference gives the
+//! score of all the translations at once (FFT). The images and the references are
+//! normalized (zero mean and unit norm in the circular mask), thus the score is the
+//! normalized cross-correlation (NCC).
+//!
+//! With the RELION definition of the angles, the image of the orientation `(psi, theta, phi)`
+//! and the shift `(x, y)` is `image(p) = reference(P^T (p - shift))`, `P` being the
+//! in-plane rotation of `psi`.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::cryoem::cli::round;
+use crate::cryoem::euler::{Convention, Euler};
+use crate::cryoem::projection::{ProjectionMode, Projector};
+use crate::cryoem::sampling::{even_directions, in_plane_angles};
+use crate::cryoem::symmetry::PointGroup;
+use crate::fft::fft_3d;
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_stack::ImageStack;
+use crate::io::star::*;
+use crate::pixel::PixelType;
+use crate::registration::peak;
+use crate::transformable::Transform;
+use crate::volume_processor::VolumeProcessor;
+use num::complex::Complex64;
+
+/// Columns of the STAR files: angles, origin (opposite of the shift) and score
+pub const STAR_COLUMNS: [&str; 7] = [
+    "rlnImageName",
+    "rlnAngleRot",
+    "rlnAngleTilt",
+    "rlnAnglePsi",
+    "rlnOriginX",
+    "rlnOriginY",
+    "rlnCorrelation",
+];
+
+///
+/// Orientation (RELION angles) and shift (pixels) assigned to an image
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Assignment {
+    pub angles: Euler,
+    pub shift_x: f64,
+    pub shift_y: f64,
+    /// Normalized cross-correlation with the reference
+    pub score: f64,
+}
+
+///
+/// Reference projections of a volume
+///
+pub struct References {
+    angles: Vec<Euler>,
+    projections: ImageStack<f32, Gray32>,
+    spectra: Vec<Vec<Complex64>>,
+}
+
+impl References {
+    /// Returns the viewing directions of the references (RELION angles, `psi` = 0).
+    pub fn angles(&self) -> &[Euler] {
+        &self.angles
+    }
+
+    pub fn projections(&self) -> &ImageStack<f32, Gray32> {
+        &self.projections
+    }
+
+    pub fn len(&self) -> usize {
+        self.angles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.angles.is_empty()
+    }
+}
+
+///
+/// Projection matching
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::color_space::ColorSpace;
+/// use rim::cryoem::projection_matching::ProjectionMatching;
+/// use rim::grayscale::Gray32;
+/// use rim::image_stack::ImageStack;
+/// use rim::volume_processor::VolumeProcessor;
+///
+/// let volume = VolumeProcessor::new_volume(64, 64, 64, vec![0.0f32; 64 * 64 * 64], Gray32::new());
+/// let particles = ImageStack::new(64, 64, vec![vec![0.0f32; 64 * 64]; 100], Gray32::new());
+///
+/// let matching = ProjectionMatching::new(10.0);
+/// let references = matching.references(&volume);
+/// let assignments = matching.align_stack(&particles, &references);
+/// // Local refinement with a finer sampling
+/// let mut fine = ProjectionMatching::new(2.0);
+/// fine.set_max_shift(2.0);
+/// let refined = fine.refine_stack(&volume, &particles, &assignments, 10.0);
+/// ```
+pub struct ProjectionMatching {
+    angular_step: f64,
+    psi_step: f64,
+    max_shift: f64,
+    mode: ProjectionMode,
+    symmetry: PointGroup,
+}
+
+impl ProjectionMatching {
+    ///
+    /// Creates a projection matching sampling the directions and the in-plane angles every
+    /// `angular_step` degrees. The shifts are searched up to 10 pixels.
+    ///
+    pub fn new(angular_step: f64) -> Self {
+        ProjectionMatching {
+            angular_step,
+            psi_step: angular_step,
+            max_shift: 10.0,
+            mode: ProjectionMode::RayDriven,
+            symmetry: PointGroup::C(1),
+        }
+    }
+
+    pub fn get_angular_step(&self) -> f64 {
+        self.angular_step
+    }
+
+    /// Sets the step of the in-plane angles (by default, the angular step).
+    pub fn set_psi_step(&mut self, step: f64) {
+        self.psi_step = step;
+    }
+
+    pub fn get_psi_step(&self) -> f64 {
+        self.psi_step
+    }
+
+    /// Sets the maximum shift (pixels) from the center, or from the previous shift in local refinement.
+    pub fn set_max_shift(&mut self, shift: f64) {
+        self.max_shift = shift;
+    }
+
+    pub fn get_max_shift(&self) -> f64 {
+        self.max_shift
+    }
+
+    /// Sets the projection algorithm of the references (ray-driven by default).
+    pub fn set_projection_mode(&mut self, mode: ProjectionMode) {
+        self.mode = mode;
+    }
+
+    ///
+    /// Sets the point group of the volume (C1 by default). The references are restricted to
+    /// its asymmetric unit.
+    ///
+    pub fn set_symmetry(&mut self, group: PointGroup) {
+        self.symmetry = group;
+    }
+
+    pub fn get_symmetry(&self) -> PointGroup {
+        self.symmetry
+    }
+
+    ///
+    /// Computes the reference projections of `volume` on the angular grid.
+    ///
+    pub fn references<T: PixelType>(&self, volume: &VolumeProcessor<T>) -> References {
+        let directions = even_directions(self.angular_step);
+        self.project(volume, self.symmetry.asymmetric_unit(&directions))
+    }
+
+    ///
+    /// Returns the best orientation and shift of `image` among all the references.
+    ///
+    pub fn align(&self, image: &FloatProcessor, references: &References) -> Assignment {
+        let candidates: Vec<usize> = (0..references.len()).collect();
+        let psis = in_plane_angles(self.psi_step);
+        self.search(image, references, &candidates, &psis, (0.0, 0.0))
+    }
+
+    ///
+    /// Aligns every image of `stack`. The images are distributed among the available threads.
+    ///
+    pub fn align_stack(
+        &self,
+        stack: &ImageStack<f32, Gray32>,
+        references: &References,
+    ) -> Vec<Assignment> {
+        parallel(stack, |_, image| self.align(image, references))
+    }
+
+    ///
+    /// Local refinement: searches the directions and the in-plane angles within `range`
+    /// degrees of the `previous` assignment, and the shifts within the maximum shift of the
+    /// previous shift. The references are projected on the fly.
+    ///
+    pub fn refine<T: PixelType>(
+        &self,
+        volume: &VolumeProcessor<T>,
+        image: &FloatProcessor,
+        previous: &Assignment,
+        range: f64,
+    ) -> Assignment {
+        let previous_direction = Euler::new(0.0, previous.angles.theta, previous.angles.phi);
+        let axis = direction(&previous_direction);
+        let cos_range = range.to_radians().cos();
+        let mut directions = vec![previous_direction];
+        directions.extend(even_directions(self.angular_step).into_iter().filter(|e| {
+            let d = direction(e);
+            d.iter().zip(axis.iter()).map(|(a, b)| a * b).sum::<f64>() >= cos_range
+        }));
+        let references = self.project(volume, directions);
+        let candidates: Vec<usize> = (0..references.len()).collect();
+        let n = (range / self.psi_step).floor() as i64;
+        let psis: Vec<f64> = (-n..=n)
+            .map(|k| previous.angles.psi + k as f64 * self.psi_step)
+            .collect();
+        let best = self.search(
+            image,
+            &references,
+            &candidates,
+            &psis,
+            (previous.shift_x, previous.shift_y),
+        );
+        if best.score >= previous.score || previous.score.is_nan() {
+            best
+        } else {
+            *previous
+        }
+    }
+
+    ///
+    /// Local refinement of every image of `stack` (see [refine()](ProjectionMatching::refine)).
+    ///
+    pub fn refine_stack<T: PixelType + Sync>(
+        &self,
+        volume: &VolumeProcessor<T>,
+        stack: &ImageStack<f32, Gray32>,
+        previous: &[Assignment],
+        range: f64,
+    ) -> Vec<Assignment> {
+        parallel(stack, |i, image| {
+            let mut start = previous[i];
+            // The score of the previous assignment may come from another program
+            start.score = f64::NAN;
+            self.refine(volume, image, &start, range)
+        })
+    }
+
+    //
+    // Private methods
+    //
+
+    fn project<T: PixelType>(&self, volume: &VolumeProcessor<T>, angles: Vec<Euler>) -> References {
+        let projector = Projector::new(self.mode);
+        let (w, h) = (volume.width as usize, volume.height as usize);
+        let slices: Vec<Vec<f32>> = angles
+            .iter()
+            .map(|e| projector.project_euler(volume, e, Convention::Relion).data)
+            .collect();
+        let spectra = slices.iter().map(|s| spectrum(s, w, h)).collect();
+        References {
+            angles,
+            projections: ImageStack::new(volume.width, volume.height, slices, Gray32::new()),
+            spectra,
+        }
+    }
+
+    fn search(
+        &self,
+        image: &FloatProcessor,
+        references: &References,
+        candidates: &[usize],
+        psis: &[f64],
+        shift: (f64, f64),
+    ) -> Assignment {
+        let (w, h) = (image.width as usize, image.height as usize);
+        let (nx, ny) = (w.next_power_of_two(), h.next_power_of_two());
+        let (cx, cy) = ((w / 2) as f64, (h / 2) as f64);
+        let mut best = Assignment {
+            angles: Euler::new(0.0, 0.0, 0.0),
+            shift_x: 0.0,
+            shift_y: 0.0,
+            score: f64::MIN,
+        };
+        for psi in psis.iter() {
+            let p = in_plane(*psi);
+            // I'(q) = image(P q)
+            let rotated: Vec<f32> = (0..w * h)
+                .map(|i| {
+                    let (qx, qy) = ((i % w) as f64 - cx, (i / w) as f64 - cy);
+                    let (x, y) = (p[0] * qx + p[1] * qy, p[2] * qx + p[3] * qy);
+                    image.get_interpolated_pixel(x + cx, y + cy) as f32
+                })
+                .collect();
+            let rotated = spectrum(&rotated, w, h);
+            // Expected shift of I': s = P^T t
+            let s0 = (
+                p[0] * shift.0 + p[2] * shift.1,
+                p[1] * shift.0 + p[3] * shift.1,
+            );
+            for k in candidates.iter() {
+                let mut cc: Vec<Complex64> = rotated
+                    .iter()
+                    .zip(references.spectra[*k].iter())
+                    .map(|(a, b)| a * b.conj())
+                    .collect();
+                fft_3d(&mut cc, nx, ny, 1, true);
+                let (sx, sy, score) = peak(&cc, nx, ny, s0, self.max_shift);
+                if score > best.score {
+                    let e = references.angles[*k];
+                    best = Assignment {
+                        angles: Euler::new(normalize(*psi), e.theta, e.phi),
+                        shift_x: p[0] * sx + p[1] * sy,
+                        shift_y: p[2] * sx + p[3] * sy,
+                        score,
+                    };
+                }
+            }
+        }
+        best
+    }
+}
+
+///
+/// Returns the STAR data block `particles` of the assignments. The origins are the
+/// opposites of the shifts (RELION convention).
+///
+pub fn to_star(assignments: &[Assignment], image_names: &[String]) -> DataSet {
+    let mut table = Table::new(String::new());
+    for head in STAR_COLUMNS.iter() {
+        table.add_column_head(head.to_string());
+    }
+    for (i, a) in assignments.iter().enumerate() {
+        let mut row = Row::new(i);
+        let name = image_names
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| (i + 1).to_string());
+        row.push(Field::Text(name));
+        for value in [
+            a.angles.phi,
+            a.angles.theta,
+            a.angles.psi,
+            -a.shift_x,
+            -a.shift_y,
+            a.score,
+        ] {
+            row.push(Field::Number(round(value)));
+        }
+        table.add_row(row);
+    }
+    let mut block = DataSet::new(String::from("particles"));
+    block.push(Container::Table(table));
+    block
+}
+
+///
+/// Reads the assignments of the first table of `block` (columns `rlnAngleRot`,
+/// `rlnAngleTilt`, `rlnAnglePsi`, and optionally `rlnOriginX`, `rlnOriginY`, `rlnCorrelation`).
+///
+pub fn from_star(block: &DataSet) -> Result<Vec<Assignment>, String> {
+    let table = block
+        .items()
+        .iter()
+        .find_map(|item| item.to_table().ok())
+        .ok_or_else(|| format!("No table in data_{}", block.name()))?;
+    let column = |name: &str| table.column_index(name);
+    let required = |name: &str| column(name).ok_or_else(|| format!("Missing column _{}", name));
+    let (rot, tilt, psi) = (
+        required("rlnAngleRot")?,
+        required("rlnAngleTilt")?,
+        required("rlnAnglePsi")?,
+    );
+    let (x, y, score) = (
+        column("rlnOriginX"),
+        column("rlnOriginY"),
+        column("rlnCorrelation"),
+    );
+    table
+        .rows()
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let value = |c: Option<usize>, default: f64| match c {
+                Some(c) => row
+                    .cells()
+                    .get(c)
+                    .and_then(|f| f.to_f64())
+                    .ok_or_else(|| format!("Invalid value in row {} column {}", i + 1, c + 1)),
+                None => Ok(default),
+            };
+            Ok(Assignment {
+                angles: Euler::new(
+                    value(Some(psi), 0.0)?,
+                    value(Some(tilt), 0.0)?,
+                    value(Some(rot), 0.0)?,
+                ),
+                shift_x: -value(x, 0.0)?,
+                shift_y: -value(y, 0.0)?,
+                score: value(score, f64::NAN)?,
+            })
+        })
+        .collect()
+}
+
+//
+// Private functions
+//
+
+// Angle in [0, 360[
+fn normalize(angle: f64) -> f64 {
+    angle.rem_euclid(360.0)
+}
+
+fn direction(e: &Euler) -> [f64; 3] {
+    let v = e.direction(Convention::Relion);
+    [v.x, v.y, v.z]
+}
+
+// 2x2 block (row-major) of the in-plane rotation of `psi` (RELION)
+fn in_plane(psi: f64) -> [f64; 4] {
+    let a = Euler::new(psi, 0.0, 0.0).to_matrix(Convention::Relion);
+    let m = a.values();
+    [m[0], m[1], m[3], m[4]]
+}
+
+// Runs `f` on every image of `stack` in parallel
+fn parallel<F>(stack: &ImageStack<f32, Gray32>, f: F) -> Vec<Assignment>
+where
+    F: Fn(usize, &FloatProcessor) -> Assignment + Sync,
+{
+    let (w, h) = (stack.get_width(), stack.get_height());
+    let n = stack.n_slices() as usize;
+    if n == 0 {
+        return vec![];
+    }
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let indices: Vec<usize> = (0..n).collect();
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = indices
+            .chunks(n.div_ceil(threads))
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|i| {
+                            let image =
+                                FloatProcessor::new(w, h, stack.data()[*i].clone(), Gray32::new());
+                            f(*i, &image)
+                        })
+                        .collect::<Vec<Assignment>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+// Spectrum of the image normalized in the circular mask (zero mean, unit norm), zero-padded
+// to powers of two
+fn spectrum(pixels: &[f32], w: usize, h: usize) -> Vec<Complex64> {
+    let (nx, ny) = (w.next_power_of_two(), h.next_power_of_two());
+    let (cx, cy) = ((w / 2) as f64, (h / 2) as f64);
+    let radius2 = (w.min(h) as f64 / 2.0).powi(2);
+    let inside = |i: usize| {
+        let (x, y) = ((i % w) as f64 - cx, (i / w) as f64 - cy);
+        x * x + y * y < radius2
+    };
+    let (sum, count) = pixels
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| inside(*i))
+        .fold((0.0, 0usize), |(s, n), (_, v)| (s + *v as f64, n + 1));
+    let mean = sum / count.max(1) as f64;
+    let mut data = vec![Complex64::new(0.0, 0.0); nx * ny];
+    let mut norm = 0.0;
+    for (i, v) in pixels.iter().enumerate() {
+        if inside(i) {
+            let value = *v as f64 - mean;
+            data[i % w + (i / w) * nx] = Complex64::new(value, 0.0);
+            norm += value * value;
+        }
+    }
+    if norm > 0.0 {
+        let scale = 1.0 / norm.sqrt();
+        data.iter_mut().for_each(|v| *v *= scale);
+    }
+    fft_3d(&mut data, nx, ny, 1, false);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cryoem::common_lines::phantom;
+    use crate::cryoem::euler::angle_between;
+
+    // Projection of `angles` moved by an integer shift
+    fn particle(volume: &VolumeProcessor<f32>, angles: &Euler, dx: i64, dy: i64) -> FloatProcessor {
+        let proj = Projector::new(ProjectionMode::RayDriven).project_euler(
+            volume,
+            angles,
+            Convention::Relion,
+        );
+        let (w, h) = (proj.width as i64, proj.height as i64);
+        let pixels = (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w - dx, i / w - dy);
+                if x >= 0 && x < w && y >= 0 && y < h {
+                    proj.data[(x + y * w) as usize]
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        FloatProcessor::new(proj.width, proj.height, pixels, Gray32::new())
+    }
+
+    fn angle_to(a: &Euler, b: &Euler) -> f64 {
+        angle_between(
+            &a.to_matrix(Convention::Relion),
+            &b.to_matrix(Convention::Relion),
+        )
+    }
+
+    #[test]
+    fn global_search_finds_orientation_and_shift() {
+        let volume = phantom();
+        let matching = ProjectionMatching::new(15.0);
+        let references = matching.references(&volume);
+        // Orientation of the grid, in-plane angle multiple of the step
+        let e = references.angles()[57];
+        let truth = Euler::new(135.0, e.theta, e.phi);
+        let image = particle(&volume, &truth, 3, -2);
+        let a = matching.align(&image, &references);
+        assert!(angle_to(&a.angles, &truth) < 1.0, "{:?}", a);
+        assert!(
+            (a.shift_x - 3.0).abs() < 0.5 && (a.shift_y + 2.0).abs() < 0.5,
+            "{:?}",
+            a
+        );
+        assert!(a.score > 0.9, "{:?}", a);
+    }
+
+    #[test]
+    fn local_refinement_improves_assignment() {
+        let volume = phantom();
+        let truth = Euler::new(33.0, 71.0, 148.0);
+        let image = particle(&volume, &truth, -1, 2);
+        let coarse = Assignment {
+            angles: Euler::new(40.0, 65.0, 140.0),
+            shift_x: 0.0,
+            shift_y: 0.0,
+            score: f64::NAN,
+        };
+        let mut fine = ProjectionMatching::new(3.0);
+        fine.set_max_shift(3.0);
+        let a = fine.refine(&volume, &image, &coarse, 12.0);
+        assert!(angle_to(&a.angles, &truth) < 4.0, "{:?}", a);
+        assert!(
+            (a.shift_x + 1.0).abs() < 0.5 && (a.shift_y - 2.0).abs() < 0.5,
+            "{:?}",
+            a
+        );
+    }
+
+    #[test]
+    fn star_round_trip() {
+        let a = Assignment {
+            angles: Euler::new(10.0, 20.0, 30.0),
+            shift_x: 1.5,
+            shift_y: -2.0,
+            score: 0.75,
+        };
+        let block = to_star(&[a, a], &[]);
+        assert_eq!(from_star(&block).unwrap(), vec![a, a]);
+    }
+}
@@ -0,0 +1,181 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Angular sampling of the orientations.
+//!
+//! The viewing directions are spread on the sphere along a generalized spiral
+//! (Fibonacci lattice): the point `k` of `n` has the height `z = 1 - (2k + 1) / n` and the
+//! longitude `k` times the golden angle. Each point covers the same area.
+//!
+//! The orientation grids combine these directions, restricted to the asymmetric unit of a
+//! point group (see [symmetry](crate::cryoem::symmetry)), with in-plane angles.
+//!
+
+use crate::cryoem::euler::{Convention, Euler};
+use crate::cryoem::symmetry::PointGroup;
+use crate::results_table::{Cell, ResultsTable};
+use std::f64::consts::PI;
+
+///
+/// Returns `n` viewing directions evenly spread on the sphere, as RELION Euler angles
+/// (`phi` rot, `theta` tilt) without in-plane rotation.
+///
+pub fn spiral(n: usize) -> Vec<Euler> {
+    let golden = PI * (3.0 - 5f64.sqrt());
+    (0..n)
+        .map(|k| {
+            let z = 1.0 - (2 * k + 1) as f64 / n as f64;
+            let phi = (k as f64 * golden).to_degrees() % 360.0;
+            Euler::new(0.0, z.acos().to_degrees(), phi)
+        })
+        .collect()
+}
+
+///
+/// Returns viewing directions spread on the sphere with a spacing of about `step` degrees.
+///
+pub fn even_directions(step: f64) -> Vec<Euler> {
+    let area = step.to_radians().powi(2);
+    spiral(((4.0 * PI / area).round() as usize).max(1))
+}
+
+///
+/// Returns the in-plane angles from 0 (included) to 360 degrees (excluded) every `step` degrees.
+///
+pub fn in_plane_angles(step: f64) -> Vec<f64> {
+    let n = (360.0 / step).round().max(1.0) as usize;
+    (0..n).map(|k| k as f64 * 360.0 / n as f64).collect()
+}
+
+///
+/// Returns the orientations (RELION angles) of the directions spaced by `step` degrees in
+/// the asymmetric unit of `group`, each one with the in-plane angles every `psi_step` degrees.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::cryoem::sampling::orientations;
+/// use rim::cryoem::symmetry::PointGroup;
+///
+/// let grid = orientations(15.0, 30.0, &PointGroup::D(2));
+/// assert_eq!(grid.len() % 12, 0);
+/// ```
+pub fn orientations(step: f64, psi_step: f64, group: &PointGroup) -> Vec<Euler> {
+    let psis = in_plane_angles(psi_step);
+    group
+        .asymmetric_unit(&even_directions(step))
+        .iter()
+        .flat_map(|e| psis.iter().map(move |psi| Euler::new(*psi, e.theta, e.phi)))
+        .collect()
+}
+
+///
+/// Returns the table of the orientations `angles` (RELION angles) expressed in `convention`,
+/// with the columns of `samples/psi-theta-phi-50.csv`: index, `psi`, `theta`, `phi` and the
+/// viewing direction `x`, `y`, `z`.
+///
+pub fn to_table(angles: &[Euler], convention: Convention) -> ResultsTable {
+    let mut table = ResultsTable::new(String::from("Orientations"));
+    let round = |v: f64| (v * 1000.0).round() / 1000.0 + 0.0;
+    for (i, e) in angles.iter().enumerate() {
+        let converted = e.convert(Convention::Relion, convention);
+        let v = e.direction(Convention::Relion);
+        table.add_row();
+        for (column, value) in [
+            ("i ", (i + 1) as f64),
+            ("psi", converted.psi),
+            ("theta", converted.theta),
+            ("phi", converted.phi),
+            ("x", v.x),
+            ("y", v.y),
+            ("z", v.z),
+        ] {
+            table.add_value(&column.to_string(), Cell::Number(round(value)));
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cryoem::euler::read_angles;
+    use crate::io::text_reader::TextReader;
+
+    fn unit(e: &Euler) -> [f64; 3] {
+        let v = e.direction(Convention::Relion);
+        [v.x, v.y, v.z]
+    }
+
+    #[test]
+    fn spiral_spacing() {
+        let directions = even_directions(10.0);
+        assert_eq!(directions.len(), 413);
+        // Angular distance to the nearest neighbour close to the step
+        let vectors: Vec<[f64; 3]> = directions.iter().map(unit).collect();
+        let mean = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                vectors
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, b)| (a[0] * b[0] + a[1] * b[1] + a[2] * b[2]).min(1.0).acos())
+                    .fold(f64::MAX, f64::min)
+                    .to_degrees()
+            })
+            .sum::<f64>()
+            / vectors.len() as f64;
+        assert!(mean > 8.0 && mean < 12.0, "{}", mean);
+    }
+
+    #[test]
+    fn grid_in_asymmetric_unit() {
+        let all = orientations(10.0, 30.0, &PointGroup::C(1));
+        assert_eq!(all.len(), 413 * 12);
+        for group in [PointGroup::D(2), PointGroup::O] {
+            let grid = orientations(10.0, 30.0, &group);
+            let expected = all.len() as f64 / group.order() as f64;
+            assert!(
+                (grid.len() as f64 - expected).abs() < 0.15 * expected,
+                "{}",
+                group
+            );
+            assert!(grid.iter().all(|e| group.in_asymmetric_unit(&unit(e))));
+        }
+    }
+
+    #[test]
+    fn table_with_sample_columns() {
+        let sample = TextReader::open_csv("./samples/psi-theta-phi-50.csv", None).unwrap();
+        let angles = orientations(30.0, 90.0, &PointGroup::C(4));
+        let table = to_table(&angles, Convention::Yxz);
+        assert_eq!(table.get_headings(), sample.get_headings());
+        let read = read_angles(&table).unwrap();
+        for (a, b) in angles.iter().zip(read.iter()) {
+            let (u, v) = (
+                unit(a),
+                unit(&b.convert(Convention::Yxz, Convention::Relion)),
+            );
+            assert!((0..3).all(|k| (u[k] - v[k]).abs() < 1e-4));
+        }
+    }
+}
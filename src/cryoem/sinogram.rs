@@ -20,59 +20,129 @@
 // Authors: Romane Cathelin, Paul Sastourne
 
 use crate::color_space::ColorSpace;
+use crate::cryoem::backprojection::project_plane;
 use crate::float_processor::FloatProcessor;
-use crate::grayscale::Gray;
-use crate::image_traits::Access;
-use std::f64::consts::PI;
+use crate::grayscale::{Gray, Gray32};
+use crate::image_stack::ImageStack;
+use crate::pixel::PixelType;
+use crate::transformable::InterpolationMode;
+use crate::volume_processor::VolumeProcessor;
 
+///
+/// Radon transform of images (parallel beam projections).
+///
+/// A sinogram is a FloatProcessor with one row per angle (in degrees) and one column per
+/// detector bin. The detector width is the image diagonal so that no projected pixel falls
+/// outside the detector. It is centered on the bin `width/2`, the image on the pixel `(w/2, h/2)`.
+///
 pub struct Sinogram {}
 
 impl Sinogram {
     ///
+    /// Computes the sinogram of `ip` at the angles of `angle_list` with a bilinear interpolation.
     ///
+    pub fn new(ip: &FloatProcessor, angle_list: &[f32]) -> FloatProcessor {
+        Sinogram::with_interpolation(ip, angle_list, InterpolationMode::Bilinear)
+    }
+
     ///
+    /// Computes the sinogram of `ip` for the angles from `start` (included) to `end` (excluded)
+    /// by increments of `step`.
     ///
-    pub fn new(ip: &FloatProcessor, angle_list: &Vec<f32>) -> FloatProcessor {
-        let num = angle_list.len();
-        let mut r: Vec<f32> = Vec::<f32>::new();
-        for i in 0..num {
-            let th_deg: f32 = angle_list[i];
-            r.append(&mut Self::radon_transform(&ip, th_deg));
-        }
-        //return
-        FloatProcessor::new(ip.get_width(), ip.get_height(), r, Gray::<f32>::new())
+    pub fn new_in_range(ip: &FloatProcessor, start: f32, end: f32, step: f32) -> FloatProcessor {
+        Sinogram::new(ip, &Sinogram::angles_in_range(start, end, step))
     }
+
     ///
+    /// Computes the sinogram of `ip` with the given interpolation on the detector.
+    /// Each pixel is splatted on the bins with the interpolation weights.
     ///
+    pub fn with_interpolation(
+        ip: &FloatProcessor,
+        angle_list: &[f32],
+        interpolation: InterpolationMode,
+    ) -> FloatProcessor {
+        let bins = Sinogram::detector_width(ip.width, ip.height);
+        let data = Sinogram::radon_transform(
+            &ip.data,
+            ip.width as usize,
+            ip.height as usize,
+            angle_list,
+            bins as usize,
+            interpolation,
+        );
+        FloatProcessor::new(bins, angle_list.len() as u32, data, Gray::<f32>::new())
+    }
+
     ///
-    pub fn new_in_range(ip: &FloatProcessor, start: f32, end: f32, step: f32) -> FloatProcessor {
-        let num: usize = ((end - start) / step) as usize;
-        let angles: Vec<f32> = (0..num).map(|i| i as f32 * step).collect();
-        Sinogram::new(ip, &angles)
+    /// Computes one sinogram per slice of `volume`. The rotation axis is the z-axis.
+    ///
+    pub fn from_volume<T: PixelType>(
+        volume: &VolumeProcessor<T>,
+        angle_list: &[f32],
+        interpolation: InterpolationMode,
+    ) -> ImageStack<f32, Gray32> {
+        let (w, h) = (volume.width as usize, volume.height as usize);
+        let bins = Sinogram::detector_width(volume.width, volume.height);
+        let sinograms = volume
+            .data
+            .chunks(w * h)
+            .map(|slice| {
+                let plane: Vec<f32> = slice.iter().map(|v| v.to_f32()).collect();
+                Sinogram::radon_transform(&plane, w, h, angle_list, bins as usize, interpolation)
+            })
+            .collect();
+        ImageStack::new(bins, angle_list.len() as u32, sinograms, Gray32::new())
     }
 
-    // Private Function to perform the radon transform on the attenuation matrix
-    fn radon_transform(ip: &FloatProcessor, angle: f32) -> Vec<f32> {
-        let mut r: Vec<f32> = vec![0.0; ip.get_width() as usize];
+    ///
+    /// Returns the number of detector bins of an image of size `width` x `height`: its diagonal.
+    ///
+    pub fn detector_width(width: u32, height: u32) -> u32 {
+        ((width as f64).hypot(height as f64).ceil() as u32).max(1)
+    }
 
-        let cx: f32 = (ip.get_width() as f32 / 2.0).floor();
-        let cy: f32 = (ip.get_height() as f32 / 2.0).floor();
-        for x in 0..ip.get_width() {
-            for y in 0..ip.get_height() {
-                // convert the angle to radians
-                let th_rad: f32 = angle * std::f32::consts::PI / 180.0;
-                // calculate perpendicular distance from line given angle theta
-                let t: i64 = (((x as f32) - cx) * th_rad.cos() + ((y as f32) - cy) * th_rad.sin())
-                    .round() as i64;
-                // adding values along line
-                // Nearest or bilinear????
-                let index = (t + cx as i64) as usize;
-                if index < ip.get_width() as usize {
-                    r[index] += ip.get_pixel_at(x as u32, y as u32).unwrap(); // as f32;
-                }
-            }
+    ///
+    /// Returns the angles from `start` (included) to `end` (excluded) by increments of `step`.
+    /// The list is empty if `step` is not strictly positive.
+    ///
+    pub fn angles_in_range(start: f32, end: f32, step: f32) -> Vec<f32> {
+        if step.is_nan() || step <= 0.0 {
+            return vec![];
         }
+        let num = ((end - start) / step).ceil().max(0.0) as usize;
+        (0..num).map(|i| start + i as f32 * step).collect()
+    }
 
+    // Private Function to perform the radon transform of a plane. The angles are
+    // split among the available threads.
+    fn radon_transform(
+        pixels: &[f32],
+        width: usize,
+        height: usize,
+        angles: &[f32],
+        bins: usize,
+        interpolation: InterpolationMode,
+    ) -> Vec<f32> {
+        let mut r = vec![0.0f32; bins * angles.len()];
+        if angles.is_empty() {
+            return r;
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = angles.len().div_ceil(threads);
+        std::thread::scope(|scope| {
+            for (rows, angles) in r.chunks_mut(chunk * bins).zip(angles.chunks(chunk)) {
+                scope.spawn(move || {
+                    for (row, angle) in rows.chunks_mut(bins).zip(angles.iter()) {
+                        let sums =
+                            project_plane(pixels, width, height, *angle, bins, interpolation);
+                        for (v, s) in row.iter_mut().zip(sums.iter()) {
+                            *v = *s as f32;
+                        }
+                    }
+                });
+            }
+        });
         r // return the radon transform matrix
     }
 }
@@ -82,10 +152,6 @@ mod tests {
 
     use super::*;
 
-    use crate::image_processor::*;
-    use crate::image_traits::Access;
-    use crate::operator::Operator;
-
     #[test]
     pub fn sinogram_with_5x5_ip() {
         let test = vec![
@@ -94,8 +160,56 @@ mod tests {
         ];
         let ip = FloatProcessor::new(5, 5, test, Gray::<f32>::new());
         let op = Sinogram::new_in_range(&ip, 0.0, 1.0, 1.0);
+        // Detector of 8 bins (diagonal) centered on the bin 4
+        assert_eq!(op.get_width(), 8);
+        assert_eq!(op.get_height(), 1);
         let pixels = op.data(); // Vec<f32>
-        let answer = vec![55.0, 60.0, 65.0, 70.0, 75.0];
+        let answer = vec![0.0, 0.0, 55.0, 60.0, 65.0, 70.0, 75.0, 0.0];
         assert!(pixels.iter().zip(answer).all(|(a, b)| *a == b));
     }
+
+    #[test]
+    pub fn sinogram_angle_range_and_interpolation() {
+        let angles = Sinogram::angles_in_range(10.0, 100.0, 30.0);
+        assert_eq!(angles, vec![10.0, 40.0, 70.0]);
+        assert!(Sinogram::angles_in_range(0.0, 180.0, 0.0).is_empty());
+        assert!(Sinogram::angles_in_range(0.0, 180.0, -1.0).is_empty());
+
+        let mut pixels = vec![0.0f32; 49];
+        pixels[3 + 2 * 7] = 1.0;
+        let ip = FloatProcessor::new(7, 7, pixels, Gray::<f32>::new());
+        // Pixel (3,2) projected at 90 degrees on t = -1 + 5
+        let angles: Vec<f32> = (0..12).map(|i| i as f32 * 15.0).collect();
+        for mode in [InterpolationMode::Nearest, InterpolationMode::Bilinear] {
+            let op = Sinogram::with_interpolation(&ip, &angles, mode);
+            assert_eq!(op.get_width(), 10);
+            assert_eq!(op.get_height(), 12);
+            assert!((op.data()[6 * 10 + 4] - 1.0).abs() < 1e-6);
+            // Mass conservation for every angle
+            for row in op.data().chunks(10) {
+                assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+            }
+        }
+        let bilinear = Sinogram::with_interpolation(&ip, &angles, InterpolationMode::Bilinear);
+        // 45 degrees: t = -sqrt(2)/2 + 5, shared between the bins 4 and 5 (weight of 5: 1 - sqrt(2)/2)
+        let f = 1.0 - std::f32::consts::FRAC_1_SQRT_2;
+        assert!((bilinear.data()[3 * 10 + 5] - f).abs() < 1e-5);
+    }
+
+    #[test]
+    pub fn sinogram_of_volume() {
+        let mut voxels = vec![0.0f32; 5 * 5 * 3];
+        for (z, v) in voxels.chunks_mut(25).enumerate() {
+            v[12] = (z + 1) as f32;
+        }
+        let volume = VolumeProcessor::new_volume(5, 5, 3, voxels, Gray::<f32>::new());
+        let angles = Sinogram::angles_in_range(0.0, 180.0, 45.0);
+        let stack = Sinogram::from_volume(&volume, &angles, InterpolationMode::Bilinear);
+        assert_eq!(stack.n_slices(), 3);
+        assert_eq!(stack.get_width(), 8);
+        assert_eq!(stack.get_height(), 4);
+        for (z, sinogram) in stack.data().iter().enumerate() {
+            assert!(sinogram.chunks(8).all(|row| row[4] == (z + 1) as f32));
+        }
+    }
 }
@@ -0,0 +1,359 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Point group symmetries.
+//!
+//! The symmetry axes follow the conventions of RELION:
+//! * `Cn`: n-fold axis along Z.
+//! * `Dn`: n-fold axis along Z, 2-fold axis along X.
+//! * `T`: 2-fold axes along X, Y and Z, 3-fold axis along (1, 1, 1).
+//! * `O`: 4-fold axes along X, Y and Z, 3-fold axis along (1, 1, 1).
+//! * `I` (I2): 2-fold axes along X, Y and Z, 5-fold axes in the YZ plane.
+//!
+//! The operators are rotation matrices `S` such that the volume is invariant: `v(S r) = v(r)`.
+//! The orientations `A` and `A S` (RELION matrices) give the same projection.
+//!
+
+use crate::cryoem::euler::{Convention, Euler};
+use crate::vecmath::matrix3::Matrix3;
+use std::fmt;
+use std::str::FromStr;
+
+///
+/// Point groups of the symmetric particles
+///
+/// # Example
+///
+/// ```rust
+/// use rim::cryoem::symmetry::PointGroup;
+///
+/// let group: PointGroup = "D7".parse().unwrap();
+/// assert_eq!(group.order(), 14);
+/// assert_eq!(group.operators().len(), 14);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointGroup {
+    C(u32),
+    D(u32),
+    T,
+    O,
+    I,
+}
+
+impl PointGroup {
+    /// Returns the number of operators of the group.
+    pub fn order(&self) -> usize {
+        match self {
+            PointGroup::C(n) => *n as usize,
+            PointGroup::D(n) => 2 * *n as usize,
+            PointGroup::T => 12,
+            PointGroup::O => 24,
+            PointGroup::I => 60,
+        }
+    }
+
+    ///
+    /// Returns the rotation matrices of the group, the identity first.
+    ///
+    pub fn operators(&self) -> Vec<Matrix3> {
+        let z = [0.0, 0.0, 1.0];
+        let generators = match self {
+            PointGroup::C(n) => vec![rotation(z, 360.0 / *n as f64)],
+            PointGroup::D(n) => vec![
+                rotation(z, 360.0 / *n as f64),
+                rotation([1.0, 0.0, 0.0], 180.0),
+            ],
+            PointGroup::T => vec![
+                rotation(z, 180.0),
+                rotation([1.0, 0.0, 0.0], 180.0),
+                rotation([1.0, 1.0, 1.0], 120.0),
+            ],
+            PointGroup::O => vec![rotation(z, 90.0), rotation([1.0, 1.0, 1.0], 120.0)],
+            PointGroup::I => {
+                let golden = (1.0 + 5f64.sqrt()) / 2.0;
+                vec![
+                    rotation(z, 180.0),
+                    rotation([1.0, 0.0, 0.0], 180.0),
+                    rotation([0.0, 1.0, golden], 72.0),
+                ]
+            }
+        };
+        closure(&generators)
+    }
+
+    ///
+    /// Returns the orientations equivalent to `angles` (RELION convention) by symmetry,
+    /// `angles` first.
+    ///
+    pub fn expand(&self, angles: &Euler) -> Vec<Euler> {
+        let a = angles.to_matrix(Convention::Relion);
+        self.operators()
+            .into_iter()
+            .map(|s| Euler::from_matrix(&(a.build() * s), Convention::Relion))
+            .collect()
+    }
+
+    ///
+    /// Returns `true` if the viewing direction `v` (unit vector) belongs to the asymmetric unit.
+    ///
+    /// The asymmetric unit is the set of the directions closer to a reference direction than
+    /// to any of its symmetry mates (Dirichlet domain). For `Cn`, it is the wedge of
+    /// longitudes `[0, 360/n]` with both poles. For `Dn`, it is the part of the same wedge
+    /// with `z >= 0` (north pole included, south pole excluded).
+    ///
+    pub fn in_asymmetric_unit(&self, v: &[f64; 3]) -> bool {
+        self.contains(&self.operators(), v)
+    }
+
+    ///
+    /// Returns the viewing directions of `directions` (RELION angles) within the asymmetric unit.
+    ///
+    pub fn asymmetric_unit(&self, directions: &[Euler]) -> Vec<Euler> {
+        let operators = self.operators();
+        directions
+            .iter()
+            .filter(|e| {
+                let v = e.direction(Convention::Relion);
+                self.contains(&operators, &[v.x, v.y, v.z])
+            })
+            .copied()
+            .collect()
+    }
+
+    fn contains(&self, operators: &[Matrix3], v: &[f64; 3]) -> bool {
+        if operators.len() == 1 {
+            return true;
+        }
+        // The ties on the borders are broken by a second reference direction
+        let (p, q) = self.reference_directions();
+        let dot = |a: &[f64; 3], b: &[f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let (own, own_q) = (dot(v, &p), dot(v, &q));
+        operators.iter().skip(1).all(|s| {
+            let other = dot(v, &apply(s, &p));
+            if (own - other).abs() > 1e-9 {
+                own > other
+            } else {
+                // Tie on both directions: `v` is on the axis of `s`, its own symmetry mate
+                own_q > dot(v, &apply(s, &q)) - 1e-9
+            }
+        })
+    }
+
+    // Direction inside the asymmetric unit, on no symmetry axis, and a nearby direction
+    fn reference_directions(&self) -> ([f64; 3], [f64; 3]) {
+        let direction = |theta: f64, phi: f64| {
+            let (st, ct) = theta.to_radians().sin_cos();
+            let (sp, cp) = phi.to_radians().sin_cos();
+            [st * cp, st * sp, ct]
+        };
+        let (theta, phi) = match self {
+            PointGroup::C(n) => (90.0, 180.0 / *n as f64),
+            PointGroup::D(n) => (45.0, 180.0 / *n as f64),
+            PointGroup::T | PointGroup::O | PointGroup::I => (20.0, 35.0),
+        };
+        (direction(theta, phi), direction(theta + 1.0, phi + 2.0))
+    }
+}
+
+impl FromStr for PointGroup {
+    type Err = String;
+
+    ///
+    /// Parses the symbol of a point group: `C1`, `C4`, `D2`, `T`, `O`, `I` (case insensitive).
+    ///
+    fn from_str(symbol: &str) -> Result<Self, Self::Err> {
+        let symbol = symbol.trim().to_uppercase();
+        let order = |s: &str| {
+            s.parse::<u32>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("Invalid order in the point group {}", symbol))
+        };
+        match symbol.as_str() {
+            "T" => Ok(PointGroup::T),
+            "O" => Ok(PointGroup::O),
+            "I" | "I2" => Ok(PointGroup::I),
+            s if s.starts_with('C') => Ok(PointGroup::C(order(&s[1..])?)),
+            s if s.starts_with('D') => Ok(PointGroup::D(order(&s[1..])?)),
+            _ => Err(format!("Unknown point group {}", symbol)),
+        }
+    }
+}
+
+impl fmt::Display for PointGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PointGroup::C(n) => write!(f, "C{}", n),
+            PointGroup::D(n) => write!(f, "D{}", n),
+            PointGroup::T => write!(f, "T"),
+            PointGroup::O => write!(f, "O"),
+            PointGroup::I => write!(f, "I"),
+        }
+    }
+}
+
+//
+// Private functions
+//
+
+// Rotation of `angle` degrees around `axis` (Rodrigues formula)
+fn rotation(axis: [f64; 3], angle: f64) -> Matrix3 {
+    let norm = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+    let (x, y, z) = (axis[0] / norm, axis[1] / norm, axis[2] / norm);
+    let (s, c) = angle.to_radians().sin_cos();
+    let t = 1.0 - c;
+    Matrix3::from_array([
+        t * x * x + c,
+        t * x * y - s * z,
+        t * x * z + s * y,
+        t * x * y + s * z,
+        t * y * y + c,
+        t * y * z - s * x,
+        t * x * z - s * y,
+        t * y * z + s * x,
+        t * z * z + c,
+    ])
+}
+
+fn apply(m: &Matrix3, v: &[f64; 3]) -> [f64; 3] {
+    let a = m.values();
+    [
+        a[0] * v[0] + a[1] * v[1] + a[2] * v[2],
+        a[3] * v[0] + a[4] * v[1] + a[5] * v[2],
+        a[6] * v[0] + a[7] * v[1] + a[8] * v[2],
+    ]
+}
+
+// All the products of the generators
+fn closure(generators: &[Matrix3]) -> Vec<Matrix3> {
+    let same = |a: &Matrix3, b: &Matrix3| {
+        a.values()
+            .iter()
+            .zip(b.values().iter())
+            .all(|(u, v)| (u - v).abs() < 1e-6)
+    };
+    let mut group = vec![Matrix3::identity()];
+    let mut next = 0;
+    while next < group.len() {
+        for g in generators.iter() {
+            let product = group[next] * *g;
+            if !group.iter().any(|m| same(m, &product)) {
+                group.push(product);
+            }
+        }
+        next += 1;
+    }
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cryoem::sampling::spiral;
+
+    fn groups() -> Vec<PointGroup> {
+        vec![
+            PointGroup::C(1),
+            PointGroup::C(5),
+            PointGroup::D(2),
+            PointGroup::D(7),
+            PointGroup::T,
+            PointGroup::O,
+            PointGroup::I,
+        ]
+    }
+
+    #[test]
+    fn orders_and_symbols() {
+        for group in groups() {
+            assert_eq!(group.operators().len(), group.order(), "{}", group);
+            assert_eq!(group.to_string().parse::<PointGroup>(), Ok(group));
+        }
+        assert!("X3".parse::<PointGroup>().is_err());
+        assert!("C0".parse::<PointGroup>().is_err());
+    }
+
+    #[test]
+    fn one_symmetry_mate_in_asymmetric_unit() {
+        for group in groups() {
+            for e in spiral(101).iter() {
+                let v = e.direction(Convention::Relion);
+                let v = [v.x, v.y, v.z];
+                let operators = group.operators();
+                let count = operators
+                    .iter()
+                    .filter(|s| group.contains(&operators, &apply(s, &v)))
+                    .count();
+                assert_eq!(count, 1, "{} {:?}", group, v);
+            }
+        }
+    }
+
+    #[test]
+    fn cyclic_and_dihedral_units() {
+        let (north, south) = ([0.0, 0.0, 1.0], [0.0, 0.0, -1.0]);
+        let c5 = PointGroup::C(5);
+        assert!(c5.in_asymmetric_unit(&north) && c5.in_asymmetric_unit(&south));
+        let d7 = PointGroup::D(7);
+        assert!(d7.in_asymmetric_unit(&north) && !d7.in_asymmetric_unit(&south));
+        for e in spiral(401).iter() {
+            let v = e.direction(Convention::Relion);
+            let phi = v.y.atan2(v.x).to_degrees().rem_euclid(360.0);
+            // Away from the borders
+            let border = [0.0, 360.0 / 7.0, 360.0]
+                .iter()
+                .any(|b| (phi - b).abs() < 1e-6);
+            if !border && v.z.abs() > 1e-6 {
+                let expected = phi < 360.0 / 7.0 && v.z > 0.0;
+                let inside = d7.in_asymmetric_unit(&[v.x, v.y, v.z]);
+                assert_eq!(inside, expected, "{} {} {}", v.x, v.y, v.z);
+            }
+        }
+    }
+
+    #[test]
+    fn expanded_orientations_differ() {
+        let angles = Euler::new(10.0, 30.0, 50.0);
+        let expanded = PointGroup::O.expand(&angles);
+        assert_eq!(expanded.len(), 24);
+        let a = angles.to_matrix(Convention::Relion);
+        assert!(expanded[0]
+            .to_matrix(Convention::Relion)
+            .values()
+            .iter()
+            .zip(a.values().iter())
+            .all(|(u, v)| (u - v).abs() < 1e-9));
+        let directions: Vec<[f64; 3]> = expanded
+            .iter()
+            .map(|e| {
+                let v = e.direction(Convention::Relion);
+                [v.x, v.y, v.z]
+            })
+            .collect();
+        for i in 0..24 {
+            for j in i + 1..24 {
+                let d: f64 = (0..3)
+                    .map(|k| (directions[i][k] - directions[j][k]).abs())
+                    .sum();
+                assert!(d > 1e-6);
+            }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Tools for the tilt series of electron tomography: tilt angle files, binning, alignment
+//! of the projections by cross-correlation and conversion of the slices reconstructed
+//! row by row into a volume.
+//!
+//! The tilt axis is parallel to the y-axis of the projections.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::fft::fft_3d;
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::Gray32;
+use crate::image_stack::ImageStack;
+use crate::io::text_reader::TextReader;
+use crate::registration::peak;
+use crate::transformable::Transform;
+use num::complex::Complex64;
+use std::fs;
+
+///
+/// Reads the tilt angles (in degrees) of a tilt series.
+///
+/// A `.csv` file is read with [TextReader::open_csv()]: the angles are taken from the column
+/// `angle` or `tilt` (case insensitive) or from the first column. Any other file is read as
+/// an IMOD `.rawtlt` file: one angle per line.
+///
+pub fn read_tilt_angles(filename: &str) -> Result<Vec<f32>, String> {
+    let angles: Vec<f32> = if filename.to_lowercase().ends_with(".csv") {
+        let table = TextReader::open_csv(filename, None)
+            .map_err(|e| format!("Unable to read {}: {}", filename, e))?;
+        let headings = table.get_headings();
+        let heading = headings
+            .iter()
+            .find(|h| matches!(h.trim().to_lowercase().as_str(), "angle" | "tilt"))
+            .or(headings.first())
+            .ok_or_else(|| format!("No column in {}", filename))?;
+        table
+            .get_column_as_floats(heading.clone())
+            .map_err(|e| format!("Invalid column {} in {}: {}", heading, filename, e))?
+            .iter()
+            .map(|a| *a as f32)
+            .collect()
+    } else {
+        let text = fs::read_to_string(filename)
+            .map_err(|e| format!("Unable to read {}: {}", filename, e))?;
+        text.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                line.split_whitespace()
+                    .next()
+                    .unwrap_or("")
+                    .parse::<f32>()
+                    .map_err(|_| {
+                        format!("Invalid angle '{}' at line {} of {}", line, i + 1, filename)
+                    })
+            })
+            .collect::<Result<_, _>>()?
+    };
+    if angles.is_empty() {
+        return Err(format!("No tilt angle in {}", filename));
+    }
+    Ok(angles)
+}
+
+///
+/// Averages the pixels of each slice of `stack` by blocks of `factor` x `factor`.
+/// The incomplete blocks at the right and bottom edges are dropped.
+///
+pub fn bin_stack(stack: &ImageStack<f32, Gray32>, factor: u32) -> ImageStack<f32, Gray32> {
+    let factor = factor.max(1);
+    let (w, h) = (stack.get_width(), stack.get_height());
+    let (bw, bh) = ((w / factor).max(1), (h / factor).max(1));
+    let f = factor as usize;
+    let slices = stack
+        .data()
+        .iter()
+        .map(|pixels| {
+            (0..(bw * bh) as usize)
+                .map(|i| {
+                    let (x0, y0) = ((i % bw as usize) * f, (i / bw as usize) * f);
+                    let mut sum = 0.0f64;
+                    let mut count = 0usize;
+                    for y in y0..(y0 + f).min(h as usize) {
+                        for x in x0..(x0 + f).min(w as usize) {
+                            sum += pixels[x + y * w as usize] as f64;
+                            count += 1;
+                        }
+                    }
+                    (sum / count as f64) as f32
+                })
+                .collect()
+        })
+        .collect();
+    ImageStack::new(bw, bh, slices, Gray32::new())
+}
+
+///
+/// Aligns the projections by cross-correlation between neighbouring tilts, starting from
+/// the projection of lowest tilt.
+///
+/// Returns the translation (dx, dy) to apply to each projection (see [apply_shifts()]).
+///
+pub fn align_tilt_series(stack: &ImageStack<f32, Gray32>, angles: &[f32]) -> Vec<(f64, f64)> {
+    let n = stack.n_slices() as usize;
+    let (w, h) = (stack.get_width() as usize, stack.get_height() as usize);
+    let mut shifts = vec![(0.0f64, 0.0f64); n];
+    if n == 0 {
+        return shifts;
+    }
+    // Order of the projections by tilt angle
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| angles[*a].total_cmp(&angles[*b]));
+    let start = (0..n)
+        .min_by(|a, b| angles[order[*a]].abs().total_cmp(&angles[order[*b]].abs()))
+        .unwrap_or(0);
+    let data = stack.data();
+    let mut propagate = |i: usize, j: usize| {
+        let (sx, sy) = cross_correlation_shift(&data[order[j]], &data[order[i]], w, h);
+        let (cx, cy) = shifts[order[j]];
+        shifts[order[i]] = (cx - sx, cy - sy);
+    };
+    for i in start + 1..n {
+        propagate(i, i - 1);
+    }
+    for i in (0..start).rev() {
+        propagate(i, i + 1);
+    }
+    shifts
+}
+
+///
+/// Translates each slice of `stack` by the corresponding (dx, dy) with a bilinear
+/// interpolation (the pixels moved in are set to zero).
+///
+pub fn apply_shifts(
+    stack: &ImageStack<f32, Gray32>,
+    shifts: &[(f64, f64)],
+) -> ImageStack<f32, Gray32> {
+    let (w, h) = (stack.get_width(), stack.get_height());
+    let slices = stack
+        .data()
+        .iter()
+        .zip(shifts.iter())
+        .map(|(pixels, (dx, dy))| {
+            let mut ip = FloatProcessor::new(w, h, pixels.clone(), Gray32::new());
+            ip.translate(*dx, *dy);
+            ip.data
+        })
+        .collect();
+    ImageStack::new(w, h, slices, Gray32::new())
+}
+
+///
+/// Reorders the slices reconstructed row by row (one slice of size `width` x `thickness` per
+/// row of the projections) into a volume of `thickness` slices of size `width` x `rows`.
+///
+pub fn to_volume(slices: &ImageStack<f32, Gray32>) -> ImageStack<f32, Gray32> {
+    let (w, thickness) = (slices.get_width() as usize, slices.get_height() as usize);
+    let rows = slices.n_slices() as usize;
+    let volume = (0..thickness)
+        .map(|z| {
+            slices
+                .data()
+                .iter()
+                .flat_map(|slice| slice[z * w..(z + 1) * w].iter().copied())
+                .collect()
+        })
+        .collect();
+    ImageStack::new(w as u32, rows as u32, volume, Gray32::new())
+}
+
+//
+// Private functions
+//
+
+// Translation `s` (subpixel) such as `b(x) = a(x - s)`, at the peak of the cross-correlation
+fn cross_correlation_shift(a: &[f32], b: &[f32], w: usize, h: usize) -> (f64, f64) {
+    let (nx, ny) = (w.next_power_of_two(), h.next_power_of_two());
+    let spectrum = |pixels: &[f32]| {
+        let mean = pixels.iter().map(|v| *v as f64).sum::<f64>() / pixels.len() as f64;
+        let mut data = vec![Complex64::new(0.0, 0.0); nx * ny];
+        for y in 0..h {
+            for x in 0..w {
+                data[x + y * nx] = Complex64::new(pixels[x + y * w] as f64 - mean, 0.0);
+            }
+        }
+        fft_3d(&mut data, nx, ny, 1, false);
+        data
+    };
+    let fa = spectrum(a);
+    let mut cc = spectrum(b);
+    for (c, a) in cc.iter_mut().zip(fa.iter()) {
+        *c *= a.conj();
+    }
+    fft_3d(&mut cc, nx, ny, 1, true);
+    let (sx, sy, _) = peak(&cc, nx, ny, (0.0, 0.0), f64::INFINITY);
+    (sx, sy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Gaussian blob centered at (x0, y0) in a 32x32 image
+    fn blob(x0: f64, y0: f64) -> Vec<f32> {
+        (0..32 * 32)
+            .map(|i| {
+                let (x, y) = ((i % 32) as f64 - x0, (i / 32) as f64 - y0);
+                (-(x * x + y * y) / 8.0).exp() as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn align_shifted_projections() {
+        let stack = ImageStack::new(
+            32,
+            32,
+            vec![blob(13.0, 16.0), blob(16.0, 16.0), blob(18.5, 14.0)],
+            Gray32::new(),
+        );
+        let shifts = align_tilt_series(&stack, &[-30.0, 0.0, 30.0]);
+        let expected = [(3.0, 0.0), (0.0, 0.0), (-2.5, 2.0)];
+        for (s, e) in shifts.iter().zip(expected.iter()) {
+            assert!(
+                (s.0 - e.0).abs() < 0.2 && (s.1 - e.1).abs() < 0.2,
+                "{:?}",
+                shifts
+            );
+        }
+        let aligned = apply_shifts(&stack, &shifts);
+        let reference = &aligned.data()[1];
+        let index = 16 + 16 * 32;
+        assert!(aligned
+            .data()
+            .iter()
+            .all(|s| (s[index] - reference[index]).abs() < 0.05));
+    }
+
+    #[test]
+    fn binning_and_volume() {
+        let pixels: Vec<f32> = (0..16).map(|i| i as f32).collect();
+        let stack = ImageStack::new(4, 4, vec![pixels], Gray32::new());
+        let binned = bin_stack(&stack, 2);
+        assert_eq!((binned.get_width(), binned.get_height()), (2, 2));
+        assert_eq!(binned.data()[0], vec![2.5, 4.5, 10.5, 12.5]);
+
+        // 2 rows reconstructed as slices of 3x2 (width x thickness)
+        let slices = ImageStack::new(
+            3,
+            2,
+            vec![
+                vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0],
+                vec![6.0, 7.0, 8.0, 9.0, 10.0, 11.0],
+            ],
+            Gray32::new(),
+        );
+        let volume = to_volume(&slices);
+        assert_eq!(volume.n_slices(), 2);
+        assert_eq!((volume.get_width(), volume.get_height()), (3, 2));
+        assert_eq!(volume.data()[0], vec![0.0, 1.0, 2.0, 6.0, 7.0, 8.0]);
+        assert_eq!(volume.data()[1], vec![3.0, 4.0, 5.0, 9.0, 10.0, 11.0]);
+    }
+
+    #[test]
+    fn read_csv_and_rawtlt() {
+        let dir = std::env::temp_dir();
+        let csv = dir.join("rim_tilt_angles.csv");
+        fs::write(&csv, "index,Angle\n1,-60\n2,0\n3,60\n").unwrap();
+        assert_eq!(
+            read_tilt_angles(csv.to_str().unwrap()).unwrap(),
+            vec![-60.0, 0.0, 60.0]
+        );
+        let rawtlt = dir.join("rim_tilt_angles.rawtlt");
+        fs::write(&rawtlt, "-60.0\n 0.0\n\n60.0\n").unwrap();
+        assert_eq!(
+            read_tilt_angles(rawtlt.to_str().unwrap()).unwrap(),
+            vec![-60.0, 0.0, 60.0]
+        );
+        // Ragged rows
+        fs::write(&csv, "a,b,c\n1,2\n3,4,5,6\n").unwrap();
+        assert!(read_tilt_angles(csv.to_str().unwrap()).is_err());
+        fs::write(&rawtlt, "-60.0\nabc\n").unwrap();
+        assert!(read_tilt_angles(rawtlt.to_str().unwrap()).is_err());
+        assert!(read_tilt_angles("./samples/missing.rawtlt").is_err());
+    }
+}
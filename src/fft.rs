@@ -0,0 +1,482 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Fast Fourier Transforms of complex and real arrays of any size.
+//!
+//! The lengths which are powers of two use a radix-2 algorithm, the other ones the
+//! Bluestein algorithm (chirp z-transform computed with a radix-2 convolution).
+//! The forward transforms are not normalized, the inverse transforms are divided by the number of elements.
+//!
+//! The real-to-complex transforms return the half-spectrum: only the `nx / 2 + 1` first
+//! frequencies along X are stored, the other ones being given by the Hermitian symmetry
+//! `F(-k) = conj(F(k))`.
+//!
+
+use num::complex::Complex64;
+use std::f64::consts::PI;
+
+///
+/// Precomputed FFT of a given length, to transform many arrays of the same length.
+///
+/// # Example
+///
+/// ```rust
+/// use num::complex::Complex64;
+/// use rim::fft::FftPlan;
+///
+/// let plan = FftPlan::new(6);
+/// let mut data = vec![Complex64::new(1.0, 0.0); 6];
+/// plan.process(&mut data, false);
+/// assert!((data[0].re - 6.0).abs() < 1e-12 && data[1].norm() < 1e-12);
+/// ```
+pub struct FftPlan {
+    n: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Radix2 {
+        // exp(-2 i pi k / n) for k < n / 2
+        twiddles: Vec<Complex64>,
+    },
+    Bluestein {
+        // exp(-i pi k^2 / n) for k < n
+        chirp: Vec<Complex64>,
+        // Spectrum of the conjugate chirp wrapped on `inner.n` elements
+        kernel: Vec<Complex64>,
+        inner: Box<FftPlan>,
+    },
+}
+
+impl FftPlan {
+    pub fn new(n: usize) -> Self {
+        if n.is_power_of_two() || n < 2 {
+            let twiddles = (0..n / 2)
+                .map(|k| Complex64::from_polar(1.0, -2.0 * PI * k as f64 / n as f64))
+                .collect();
+            return FftPlan {
+                n,
+                kind: Kind::Radix2 { twiddles },
+            };
+        }
+        let m = (2 * n - 1).next_power_of_two();
+        let inner = FftPlan::new(m);
+        // k^2 modulo 2n keeps the angles accurate for large k
+        let chirp: Vec<Complex64> = (0..n)
+            .map(|k| {
+                let k2 = (k as u128 * k as u128 % (2 * n as u128)) as f64;
+                Complex64::from_polar(1.0, -PI * k2 / n as f64)
+            })
+            .collect();
+        let mut kernel = vec![Complex64::new(0.0, 0.0); m];
+        kernel[0] = chirp[0].conj();
+        for k in 1..n {
+            kernel[k] = chirp[k].conj();
+            kernel[m - k] = chirp[k].conj();
+        }
+        inner.process(&mut kernel, false);
+        FftPlan {
+            n,
+            kind: Kind::Bluestein {
+                chirp,
+                kernel,
+                inner: Box::new(inner),
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    ///
+    /// In-place FFT of `data` (of the length of the plan).
+    ///
+    pub fn process(&self, data: &mut [Complex64], inverse: bool) {
+        assert_eq!(data.len(), self.n, "FFT length");
+        match &self.kind {
+            Kind::Radix2 { twiddles } => radix2(data, twiddles, inverse),
+            Kind::Bluestein {
+                chirp,
+                kernel,
+                inner,
+            } => {
+                // The inverse transform is the conjugate of the forward transform of the conjugate
+                let m = inner.n;
+                let mut work = vec![Complex64::new(0.0, 0.0); m];
+                for (k, (w, v)) in work.iter_mut().zip(data.iter()).enumerate() {
+                    let v = if inverse { v.conj() } else { *v };
+                    *w = v * chirp[k];
+                }
+                inner.process(&mut work, false);
+                work.iter_mut()
+                    .zip(kernel.iter())
+                    .for_each(|(w, k)| *w *= k);
+                inner.process(&mut work, true);
+                let scale = if inverse { 1.0 / self.n as f64 } else { 1.0 };
+                for (k, v) in data.iter_mut().enumerate() {
+                    let x = work[k] * chirp[k];
+                    *v = if inverse { x.conj() * scale } else { x };
+                }
+            }
+        }
+    }
+}
+
+///
+/// In-place FFT of `data` (any length).
+///
+pub fn fft(data: &mut [Complex64], inverse: bool) {
+    FftPlan::new(data.len()).process(data, inverse);
+}
+
+///
+/// In-place FFT of a 3D array of size `nx` x `ny` x `nz` (X varies fastest).
+/// The axes of size 1 are skipped, thus `nz = 1` is a 2D transform.
+///
+pub fn fft_3d(data: &mut [Complex64], nx: usize, ny: usize, nz: usize, inverse: bool) {
+    assert_eq!(data.len(), nx * ny * nz);
+    transform_axes(data, [nx, ny, nz], [true, true, true], inverse);
+}
+
+///
+/// Returns the half-spectrum (`n / 2 + 1` frequencies) of the real array `input`.
+///
+pub fn rfft(input: &[f64]) -> Vec<Complex64> {
+    let n = input.len();
+    if n < 2 || n % 2 == 1 {
+        let mut data: Vec<Complex64> = input.iter().map(|v| Complex64::new(*v, 0.0)).collect();
+        fft(&mut data, false);
+        data.truncate(n / 2 + 1);
+        return data;
+    }
+    // Even length: the real array is packed in a complex array of half length
+    let half = n / 2;
+    let mut z: Vec<Complex64> = input
+        .chunks(2)
+        .map(|p| Complex64::new(p[0], p[1]))
+        .collect();
+    fft(&mut z, false);
+    (0..=half)
+        .map(|k| {
+            let a = z[k % half];
+            let b = z[(half - k) % half].conj();
+            let even = (a + b) * 0.5;
+            let odd = (a - b) * Complex64::new(0.0, -0.5);
+            even + odd * Complex64::from_polar(1.0, -2.0 * PI * k as f64 / n as f64)
+        })
+        .collect()
+}
+
+///
+/// Returns the real array of length `n` of the half-spectrum `spectrum` (inverse of [rfft()]).
+///
+pub fn irfft(spectrum: &[Complex64], n: usize) -> Vec<f64> {
+    assert_eq!(spectrum.len(), n / 2 + 1, "Half-spectrum length");
+    let mut data: Vec<Complex64> = (0..n)
+        .map(|k| {
+            if k <= n / 2 {
+                spectrum[k]
+            } else {
+                spectrum[n - k].conj()
+            }
+        })
+        .collect();
+    fft(&mut data, true);
+    data.iter().map(|v| v.re).collect()
+}
+
+///
+/// Returns the half-spectrum of size `(nx / 2 + 1)` x `ny` x `nz` of the real 3D array `input`.
+///
+pub fn rfft_3d(input: &[f64], nx: usize, ny: usize, nz: usize) -> Vec<Complex64> {
+    assert_eq!(input.len(), nx * ny * nz);
+    let mut data: Vec<Complex64> = input.chunks(nx.max(1)).flat_map(rfft).collect();
+    transform_axes(&mut data, [nx / 2 + 1, ny, nz], [false, true, true], false);
+    data
+}
+
+///
+/// Returns the real 3D array of size `nx` x `ny` x `nz` of the half-spectrum `spectrum`
+/// (inverse of [rfft_3d()]).
+///
+pub fn irfft_3d(spectrum: &[Complex64], nx: usize, ny: usize, nz: usize) -> Vec<f64> {
+    let hx = nx / 2 + 1;
+    assert_eq!(spectrum.len(), hx * ny * nz, "Half-spectrum size");
+    let mut data = spectrum.to_vec();
+    transform_axes(&mut data, [hx, ny, nz], [false, true, true], true);
+    data.chunks(hx).flat_map(|row| irfft(row, nx)).collect()
+}
+
+///
+/// Moves the zero frequency of a 3D array from the first element to the center
+/// `(nx / 2, ny / 2, nz / 2)`.
+///
+pub fn fftshift<T: Copy>(data: &[T], nx: usize, ny: usize, nz: usize) -> Vec<T> {
+    roll(data, [nx, ny, nz], [nx / 2, ny / 2, nz / 2])
+}
+
+///
+/// Moves the zero frequency from the center back to the first element (inverse of [fftshift()]).
+///
+pub fn ifftshift<T: Copy>(data: &[T], nx: usize, ny: usize, nz: usize) -> Vec<T> {
+    roll(data, [nx, ny, nz], [nx - nx / 2, ny - ny / 2, nz - nz / 2])
+}
+
+///
+/// Frequency (cycles per pixel) of the element `i` of a transform of length `n`:
+/// `0, 1/n, ..., 0.5` then the negative frequencies `..., -1/n`.
+///
+/// The Nyquist frequency of an even length is positive (`frequency(n / 2, n) == 0.5`).
+///
+pub fn frequency(i: usize, n: usize) -> f64 {
+    let i = if i <= n / 2 {
+        i as f64
+    } else {
+        i as f64 - n as f64
+    };
+    i / n as f64
+}
+
+//
+// Private functions
+//
+
+fn radix2(data: &mut [Complex64], twiddles: &[Complex64], inverse: bool) {
+    let n = data.len();
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let half = len / 2;
+        let step = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                let w = twiddles[k * step];
+                let w = if inverse { w.conj() } else { w };
+                let u = data[start + k];
+                let v = data[start + k + half] * w;
+                data[start + k] = u + v;
+                data[start + k + half] = u - v;
+            }
+        }
+        len <<= 1;
+    }
+    if inverse {
+        let scale = 1.0 / n as f64;
+        data.iter_mut().for_each(|v| *v *= scale);
+    }
+}
+
+// FFT along the selected axes of a 3D array of size `dims`
+fn transform_axes(data: &mut [Complex64], dims: [usize; 3], axes: [bool; 3], inverse: bool) {
+    let strides = [1, dims[0], dims[0] * dims[1]];
+    for axis in 0..3 {
+        let (n, stride) = (dims[axis], strides[axis]);
+        if !axes[axis] || n < 2 {
+            continue;
+        }
+        let plan = FftPlan::new(n);
+        let mut line = vec![Complex64::new(0.0, 0.0); n];
+        for start in 0..data.len() {
+            // First element of each line along this axis
+            if (start / stride) % n != 0 {
+                continue;
+            }
+            for (i, v) in line.iter_mut().enumerate() {
+                *v = data[start + i * stride];
+            }
+            plan.process(&mut line, inverse);
+            for (i, v) in line.iter().enumerate() {
+                data[start + i * stride] = *v;
+            }
+        }
+    }
+}
+
+// Circular shift of `offsets` elements along each axis
+fn roll<T: Copy>(data: &[T], dims: [usize; 3], offsets: [usize; 3]) -> Vec<T> {
+    let [nx, ny, nz] = dims;
+    assert_eq!(data.len(), nx * ny * nz);
+    let mut out = data.to_vec();
+    for z in 0..nz {
+        let tz = (z + offsets[2]) % nz;
+        for y in 0..ny {
+            let ty = (y + offsets[1]) % ny;
+            for x in 0..nx {
+                let tx = (x + offsets[0]) % nx;
+                out[tx + ty * nx + tz * nx * ny] = data[x + y * nx + z * nx * ny];
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn dirac_and_round_trip() {
+        let mut data = vec![Complex64::new(0.0, 0.0); 8];
+        data[0] = Complex64::new(1.0, 0.0);
+        fft(&mut data, false);
+        assert!(data
+            .iter()
+            .all(|v| (v - Complex64::new(1.0, 0.0)).norm() < 1e-12));
+        fft(&mut data, true);
+        assert!((data[0].re - 1.0).abs() < 1e-12 && data[1].norm() < 1e-12);
+    }
+
+    #[test]
+    fn matches_dft() {
+        let input: Vec<Complex64> = (0..16)
+            .map(|i| Complex64::new((i * i % 7) as f64, (i % 3) as f64))
+            .collect();
+        let mut data = input.clone();
+        fft(&mut data, false);
+        for (k, value) in data.iter().enumerate() {
+            let dft: Complex64 = input
+                .iter()
+                .enumerate()
+                .map(|(n, v)| v * Complex64::from_polar(1.0, -2.0 * PI * (k * n) as f64 / 16.0))
+                .sum();
+            assert!((dft - value).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn round_trip_3d() {
+        let input: Vec<Complex64> = (0..4 * 8 * 2)
+            .map(|i| Complex64::new((i % 5) as f64, 0.0))
+            .collect();
+        let mut data = input.clone();
+        fft_3d(&mut data, 4, 8, 2, false);
+        // DC term
+        let sum: f64 = input.iter().map(|v| v.re).sum();
+        assert!((data[0].re - sum).abs() < 1e-9);
+        fft_3d(&mut data, 4, 8, 2, true);
+        assert!(data
+            .iter()
+            .zip(input.iter())
+            .all(|(a, b)| (a - b).norm() < 1e-9));
+    }
+
+    #[test]
+    fn bluestein_matches_dft() {
+        for n in [3, 12, 17, 100] {
+            let input: Vec<Complex64> = (0..n)
+                .map(|i| Complex64::new((i * i % 7) as f64, (i % 3) as f64))
+                .collect();
+            let mut data = input.clone();
+            fft(&mut data, false);
+            for (k, v) in data.iter().enumerate() {
+                let dft: Complex64 = input
+                    .iter()
+                    .enumerate()
+                    .map(|(j, x)| {
+                        x * Complex64::from_polar(1.0, -2.0 * PI * (k * j) as f64 / n as f64)
+                    })
+                    .sum();
+                assert!((dft - v).norm() < 1e-8, "n = {}", n);
+            }
+            fft(&mut data, true);
+            assert!(data
+                .iter()
+                .zip(input.iter())
+                .all(|(a, b)| (a - b).norm() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn real_transforms() {
+        for n in [1, 8, 9, 10] {
+            let input: Vec<f64> = (0..n).map(|i| ((i * 5) % 7) as f64 - 2.5).collect();
+            let mut full: Vec<Complex64> = input.iter().map(|v| Complex64::new(*v, 0.0)).collect();
+            fft(&mut full, false);
+            let half = rfft(&input);
+            assert_eq!(half.len(), n / 2 + 1);
+            assert!(half
+                .iter()
+                .zip(full.iter())
+                .all(|(a, b)| (a - b).norm() < 1e-9));
+            let back = irfft(&half, n);
+            assert!(back
+                .iter()
+                .zip(input.iter())
+                .all(|(a, b)| (a - b).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn real_transforms_3d() {
+        let (nx, ny, nz) = (5, 6, 3);
+        let input: Vec<f64> = (0..nx * ny * nz).map(|i| ((i * 7) % 11) as f64).collect();
+        let half = rfft_3d(&input, nx, ny, nz);
+        let mut full: Vec<Complex64> = input.iter().map(|v| Complex64::new(*v, 0.0)).collect();
+        fft_3d(&mut full, nx, ny, nz, false);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx / 2 + 1 {
+                    let (a, b) = (half[x + (y + z * ny) * 3], full[x + (y + z * ny) * nx]);
+                    assert!((a - b).norm() < 1e-9);
+                }
+            }
+        }
+        let back = irfft_3d(&half, nx, ny, nz);
+        assert!(back
+            .iter()
+            .zip(input.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-9));
+    }
+
+    #[test]
+    fn shift_and_unshift() {
+        let data: Vec<usize> = (0..5 * 4).collect();
+        let shifted = fftshift(&data, 5, 4, 1);
+        // The origin moves to the center (2, 2)
+        assert_eq!(shifted[2 + 2 * 5], 0);
+        assert_eq!(ifftshift(&shifted, 5, 4, 1), data);
+    }
+
+    #[test]
+    fn frequencies() {
+        let even: Vec<f64> = (0..4).map(|i| frequency(i, 4)).collect();
+        assert_eq!(even, vec![0.0, 0.25, 0.5, -0.25]);
+        let odd: Vec<f64> = (0..5).map(|i| frequency(i, 5) * 5.0).collect();
+        assert_eq!(odd, vec![0.0, 1.0, 2.0, -2.0, -1.0]);
+    }
+}
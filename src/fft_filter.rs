@@ -0,0 +1,567 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Frequency-domain filters of images, stacks and volumes.
+//!
+//! The frequencies are in cycles per pixel (Nyquist at 0.5) and the volumes are filtered in 3D.
+//! The cutoffs are periods in pixels or resolutions in Å. The soft filters have a gain of 0.5
+//! at their cutoff.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::fft::{frequency, irfft_3d, rfft_3d};
+use crate::grayscale::Gray;
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::pixel::PixelType;
+use std::f64::consts::{LN_2, PI};
+
+///
+/// Cutoff of a frequency filter
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cutoff {
+    /// Period in pixels
+    Pixels(f64),
+    /// Resolution in Å
+    Angstrom(f64),
+}
+
+impl Cutoff {
+    /// Returns the frequency in cycles per pixel for the pixel size `pixel_size` (Å).
+    pub fn frequency(&self, pixel_size: f64) -> f64 {
+        match self {
+            Cutoff::Pixels(period) => 1.0 / period,
+            Cutoff::Angstrom(resolution) => pixel_size / resolution,
+        }
+    }
+}
+
+///
+/// Profiles of the filters
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterShape {
+    /// Sharp cutoff
+    Ideal,
+    /// Butterworth filter of the given order
+    Butterworth(u32),
+    /// Gaussian profile
+    Gaussian,
+    /// Cosine edge of the given width in Fourier pixels (of the largest dimension)
+    RaisedCosine(f64),
+}
+
+///
+/// Pass bands of the filters
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PassBand {
+    LowPass(Cutoff),
+    HighPass(Cutoff),
+    /// Pass band between the large and the small cutoffs
+    BandPass(Cutoff, Cutoff),
+}
+
+///
+/// Frequency filter
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::fft_filter::{Cutoff, FilterShape, FourierFilter, FrequencyFilter};
+/// use rim::grayscale::Gray32;
+/// use rim::image_processor::ImageProcessor;
+///
+/// // Map of 1.2 Å/px sharpened with a B-factor of -100 Å^2 and low-pass filtered at 4 Å
+/// let mut map = ImageProcessor::<f32, Gray32>::new_volume(8, 8, 8, vec![1.0; 512], Gray32::new());
+/// map.apply_b_factor(-100.0, 1.2);
+/// let mut filter = FrequencyFilter::low_pass(Cutoff::Angstrom(4.0), FilterShape::RaisedCosine(2.0));
+/// filter.set_pixel_size(1.2);
+/// map.filter_frequencies(&filter);
+/// assert!((map.data[0] - 1.0).abs() < 1e-5);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrequencyFilter {
+    band: PassBand,
+    shape: FilterShape,
+    pixel_size: f64,
+}
+
+impl FrequencyFilter {
+    pub fn new(band: PassBand, shape: FilterShape) -> Self {
+        FrequencyFilter {
+            band,
+            shape,
+            pixel_size: 1.0,
+        }
+    }
+
+    pub fn low_pass(cutoff: Cutoff, shape: FilterShape) -> Self {
+        FrequencyFilter::new(PassBand::LowPass(cutoff), shape)
+    }
+
+    pub fn high_pass(cutoff: Cutoff, shape: FilterShape) -> Self {
+        FrequencyFilter::new(PassBand::HighPass(cutoff), shape)
+    }
+
+    /// Filter keeping the structures between `large` and `small`.
+    pub fn band_pass(large: Cutoff, small: Cutoff, shape: FilterShape) -> Self {
+        FrequencyFilter::new(PassBand::BandPass(large, small), shape)
+    }
+
+    /// Sets the pixel size in Å of the cutoffs in Å (1 by default).
+    pub fn set_pixel_size(&mut self, pixel_size: f64) {
+        self.pixel_size = pixel_size;
+    }
+
+    pub fn get_pixel_size(&self) -> f64 {
+        self.pixel_size
+    }
+
+    pub fn band(&self) -> PassBand {
+        self.band
+    }
+
+    pub fn shape(&self) -> FilterShape {
+        self.shape
+    }
+
+    ///
+    /// Returns the gain at the frequency `frequency` (cycles per pixel), `size` being the
+    /// largest dimension of the filtered image.
+    ///
+    pub fn gain(&self, frequency: f64, size: usize) -> f64 {
+        let low = |cutoff: &Cutoff| {
+            self.low_pass_gain(frequency, cutoff.frequency(self.pixel_size), size)
+        };
+        match &self.band {
+            PassBand::LowPass(cutoff) => low(cutoff),
+            PassBand::HighPass(cutoff) => 1.0 - low(cutoff),
+            PassBand::BandPass(large, small) => (1.0 - low(large)) * low(small),
+        }
+    }
+
+    fn low_pass_gain(&self, f: f64, cutoff: f64, size: usize) -> f64 {
+        match self.shape {
+            FilterShape::Ideal => {
+                if f <= cutoff {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            FilterShape::Butterworth(order) => 1.0 / (1.0 + (f / cutoff).powi(2 * order as i32)),
+            FilterShape::Gaussian => (-LN_2 * (f / cutoff).powi(2)).exp(),
+            FilterShape::RaisedCosine(width) => {
+                let width = width / size as f64;
+                let start = cutoff - width / 2.0;
+                if f <= start {
+                    1.0
+                } else if f >= cutoff + width / 2.0 {
+                    0.0
+                } else {
+                    0.5 * (1.0 + (PI * (f - start) / width).cos())
+                }
+            }
+        }
+    }
+}
+
+///
+/// Stripes removed by the ImageJ bandpass filter
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stripes {
+    None,
+    Horizontal,
+    Vertical,
+}
+
+///
+/// Options of the ImageJ "FFT Bandpass" filter
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bandpass {
+    /// Structures larger than this size (pixels) are removed
+    pub filter_large: f64,
+    /// Structures smaller than this size (pixels) are removed
+    pub filter_small: f64,
+    pub stripes: Stripes,
+    /// Tolerance of direction of the stripes in %
+    pub tolerance: f64,
+    /// Rescales the result to the range of the original image
+    pub autoscale: bool,
+    /// Saturates 1% of the pixels when rescaling
+    pub saturate: bool,
+}
+
+impl Default for Bandpass {
+    fn default() -> Self {
+        Bandpass {
+            filter_large: 40.0,
+            filter_small: 3.0,
+            stripes: Stripes::None,
+            tolerance: 5.0,
+            autoscale: true,
+            saturate: true,
+        }
+    }
+}
+
+///
+/// Fourier filters of gray-level images, stacks and volumes.
+///
+/// Only the pixels of the active ROI are modified.
+///
+pub trait FourierFilter<T: PixelType> {
+    /// Multiplies the spectrum by the gain of `filter` (in 3D for the volumes).
+    fn filter_frequencies(&mut self, filter: &FrequencyFilter);
+
+    ///
+    /// ImageJ "FFT Bandpass": Gaussian band-pass with optional stripe suppression. The image
+    /// is padded with mirrored copies to a power of 2 and the mean is preserved. The planes
+    /// of a volume are filtered independently.
+    ///
+    fn bandpass(&mut self, options: &Bandpass);
+
+    ///
+    /// Multiplies the spectrum by `exp(-B s^2 / 4)`, `s` being the frequency in 1/Å for the
+    /// pixel size `pixel_size`. A negative B-factor sharpens, a positive one blurs.
+    ///
+    fn apply_b_factor(&mut self, b_factor: f64, pixel_size: f64);
+}
+
+impl<T: PixelType> FourierFilter<T> for ImageProcessor<T, Gray<T>> {
+    fn filter_frequencies(&mut self, filter: &FrequencyFilter) {
+        let dims = dims(self);
+        let size = dims.0.max(dims.1).max(dims.2);
+        let filtered = filter_pixels(&pixels(self), dims, |f| filter.gain(f, size));
+        store(self, &filtered);
+    }
+
+    fn bandpass(&mut self, options: &Bandpass) {
+        let (w, h, d) = dims(self);
+        let pixels = pixels(self);
+        let filtered: Vec<f32> = pixels
+            .chunks(w * h)
+            .take(d)
+            .flat_map(|plane| bandpass_plane(plane, w, h, options))
+            .collect();
+        store(self, &filtered);
+    }
+
+    fn apply_b_factor(&mut self, b_factor: f64, pixel_size: f64) {
+        let filtered = filter_pixels(&pixels(self), dims(self), |f| {
+            (-b_factor * (f / pixel_size).powi(2) / 4.0).exp()
+        });
+        store(self, &filtered);
+    }
+}
+
+impl<T: PixelType + Clone> FourierFilter<T> for ImageStack<T, Gray<T>> {
+    fn filter_frequencies(&mut self, filter: &FrequencyFilter) {
+        self.filter_slices(|ip| ip.filter_frequencies(filter));
+    }
+
+    fn bandpass(&mut self, options: &Bandpass) {
+        self.filter_slices(|ip| ip.bandpass(options));
+    }
+
+    fn apply_b_factor(&mut self, b_factor: f64, pixel_size: f64) {
+        self.filter_slices(|ip| ip.apply_b_factor(b_factor, pixel_size));
+    }
+}
+
+//
+// Private functions
+//
+
+fn dims<T: PixelType>(ip: &ImageProcessor<T, Gray<T>>) -> (usize, usize, usize) {
+    (
+        ip.width as usize,
+        ip.height as usize,
+        ip.depth.max(1) as usize,
+    )
+}
+
+fn pixels<T: PixelType>(ip: &ImageProcessor<T, Gray<T>>) -> Vec<f32> {
+    ip.data.iter().map(|v| v.to_f32()).collect()
+}
+
+// Copies the pixels of the ROI of each plane
+fn store<T: PixelType>(ip: &mut ImageProcessor<T, Gray<T>>, filtered: &[f32]) {
+    let plane = (ip.width * ip.height) as usize;
+    let indices = ip.metadata.roi_indices();
+    for offset in (0..ip.data.len()).step_by(plane) {
+        for i in indices.iter() {
+            ip.data[offset + i] = T::round_pixel(filtered[offset + i]);
+        }
+    }
+    ip.metadata.set_dirty();
+}
+
+impl<T: PixelType + Clone> ImageStack<T, Gray<T>> {
+    fn filter_slices<F: Fn(&mut ImageProcessor<T, Gray<T>>)>(&mut self, f: F) {
+        let (w, h) = (self.width, self.height);
+        for slice in self.data.iter_mut() {
+            let mut ip = ImageProcessor::new(w, h, std::mem::take(slice), Gray::<T>::new());
+            f(&mut ip);
+            *slice = ip.data;
+        }
+    }
+}
+
+// Multiplies the spectrum by `gain(frequency)`
+fn filter_pixels<F: Fn(f64) -> f64>(
+    pixels: &[f32],
+    dims: (usize, usize, usize),
+    gain: F,
+) -> Vec<f32> {
+    filter_spectrum(pixels, dims, |fx, fy, fz| {
+        gain((fx * fx + fy * fy + fz * fz).sqrt())
+    })
+}
+
+// Multiplies the spectrum by `gain(fx, fy, fz)`
+fn filter_spectrum<F: Fn(f64, f64, f64) -> f64>(
+    pixels: &[f32],
+    dims: (usize, usize, usize),
+    gain: F,
+) -> Vec<f32> {
+    let (w, h, d) = dims;
+    let input: Vec<f64> = pixels.iter().map(|v| *v as f64).collect();
+    let mut spectrum = rfft_3d(&input, w, h, d);
+    let hw = w / 2 + 1;
+    for (i, value) in spectrum.iter_mut().enumerate() {
+        let (x, y, z) = (i % hw, (i / hw) % h, i / (hw * h));
+        *value *= gain(x as f64 / w as f64, frequency(y, h), frequency(z, d));
+    }
+    irfft_3d(&spectrum, w, h, d)
+        .iter()
+        .map(|v| *v as f32)
+        .collect()
+}
+
+// Mirrored index in [0, n[
+fn mirror(i: i64, n: i64) -> usize {
+    let m = i.rem_euclid(2 * n);
+    (if m < n { m } else { 2 * n - 1 - m }) as usize
+}
+
+// ImageJ FFTFilter.filterLargeSmall on a plane padded with mirrored copies
+fn bandpass_plane(pixels: &[f32], w: usize, h: usize, options: &Bandpass) -> Vec<f32> {
+    let mut size = 2;
+    while (size as f64) < 1.5 * w.max(h) as f64 {
+        size *= 2;
+    }
+    let (x0, y0) = (((size - w) / 2) as i64, ((size - h) / 2) as i64);
+    let mut padded = Vec::<f32>::with_capacity(size * size);
+    for y in 0..size as i64 {
+        let row = mirror(y - y0, h as i64) * w;
+        for x in 0..size as i64 {
+            padded.push(pixels[row + mirror(x - x0, w as i64)]);
+        }
+    }
+    let n = size as f64;
+    let scale_large = (2.0 * options.filter_large / n).powi(2);
+    let scale_small = (2.0 * options.filter_small / n).powi(2);
+    let scale_stripes = ((100.0 - options.tolerance) / 100.0).powi(2);
+    let filtered = filter_spectrum(&padded, (size, size, 1), |fx, fy, _| {
+        // Fourier indices
+        let (col, row) = (fx * n, fy * n);
+        let r2 = col * col + row * row;
+        if r2 == 0.0 {
+            return 1.0;
+        }
+        let large = if options.filter_large > 0.0 {
+            1.0 - (-r2 * scale_large).exp()
+        } else {
+            1.0
+        };
+        let stripes = match options.stripes {
+            Stripes::None => 1.0,
+            Stripes::Horizontal => 1.0 - (-col * col * scale_stripes).exp(),
+            Stripes::Vertical => 1.0 - (-row * row * scale_stripes).exp(),
+        };
+        large * (-r2 * scale_small).exp() * stripes
+    });
+    let mut result = Vec::<f32>::with_capacity(w * h);
+    for y in 0..h {
+        let start = (y + y0 as usize) * size + x0 as usize;
+        result.extend_from_slice(&filtered[start..start + w]);
+    }
+    if options.autoscale {
+        rescale(&mut result, pixels, options.saturate);
+    }
+    result
+}
+
+// Maps the range of `values` (0.5% saturated at each end) to the range of `original`
+fn rescale(values: &mut [f32], original: &[f32], saturate: bool) {
+    let range = |v: &[f32]| {
+        v.iter()
+            .fold((f32::MAX, f32::MIN), |(a, b), v| (a.min(*v), b.max(*v)))
+    };
+    let (min, max) = range(original);
+    let (low, high) = if saturate {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let last = sorted.len() - 1;
+        let at = |q: f64| sorted[(q * last as f64).round() as usize];
+        (at(0.005), at(0.995))
+    } else {
+        range(values)
+    };
+    if high > low {
+        let scale = (max - min) / (high - low);
+        for v in values.iter_mut() {
+            *v = min + (v.clamp(low, high) - low) * scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grayscale::Gray32;
+
+    // Cosines of periods 4 and 32 pixels along X
+    fn cosines() -> ImageProcessor<f32, Gray32> {
+        let pixels = (0..64 * 64)
+            .map(|i| {
+                let x = (i % 64) as f32 * 2.0 * std::f32::consts::PI;
+                (x / 4.0).cos() + (x / 32.0).cos()
+            })
+            .collect();
+        ImageProcessor::new(64, 64, pixels, Gray32::new())
+    }
+
+    fn amplitude(ip: &ImageProcessor<f32, Gray32>, period: f32) -> f32 {
+        let row = &ip.data[0..64];
+        let x = |i: usize| i as f32 * 2.0 * std::f32::consts::PI / period;
+        row.iter()
+            .enumerate()
+            .map(|(i, v)| v * x(i).cos())
+            .sum::<f32>()
+            / 32.0
+    }
+
+    #[test]
+    fn gains() {
+        let shapes = [
+            FilterShape::Butterworth(2),
+            FilterShape::Gaussian,
+            FilterShape::RaisedCosine(4.0),
+        ];
+        for shape in shapes {
+            let filter = FrequencyFilter::low_pass(Cutoff::Pixels(8.0), shape);
+            assert!((filter.gain(0.125, 64) - 0.5).abs() < 1e-9, "{:?}", shape);
+            assert_eq!(filter.gain(0.0, 64), 1.0);
+            let high = FrequencyFilter::high_pass(Cutoff::Pixels(8.0), shape);
+            assert!((high.gain(0.3, 64) + filter.gain(0.3, 64) - 1.0).abs() < 1e-9);
+        }
+        let mut ideal = FrequencyFilter::low_pass(Cutoff::Angstrom(10.0), FilterShape::Ideal);
+        ideal.set_pixel_size(2.0);
+        assert_eq!(ideal.gain(0.2, 64), 1.0);
+        assert_eq!(ideal.gain(0.21, 64), 0.0);
+    }
+
+    #[test]
+    fn low_high_and_band_pass() {
+        let shape = FilterShape::Butterworth(4);
+        let mut low = cosines();
+        low.filter_frequencies(&FrequencyFilter::low_pass(Cutoff::Pixels(8.0), shape));
+        assert!(amplitude(&low, 4.0).abs() < 0.01 && (amplitude(&low, 32.0) - 1.0).abs() < 0.01);
+        let mut high = cosines();
+        high.filter_frequencies(&FrequencyFilter::high_pass(Cutoff::Pixels(8.0), shape));
+        assert!((amplitude(&high, 4.0) - 1.0).abs() < 0.01 && amplitude(&high, 32.0).abs() < 0.01);
+        let mut band = cosines();
+        let filter = FrequencyFilter::band_pass(Cutoff::Pixels(16.0), Cutoff::Pixels(2.5), shape);
+        band.filter_frequencies(&filter);
+        assert!((amplitude(&band, 4.0) - 1.0).abs() < 0.05 && amplitude(&band, 32.0).abs() < 0.05);
+        // Each slice of a stack is filtered like an image
+        let mut stack = ImageStack::new(64, 64, vec![cosines().data; 2], Gray32::new());
+        stack.filter_frequencies(&filter);
+        assert_eq!(stack.data[1], band.data);
+    }
+
+    #[test]
+    fn b_factor_of_volumes() {
+        let pixels = (0..16 * 16 * 16).map(|i| ((i * 7) % 13) as f32).collect();
+        let original = ImageProcessor::<f32, Gray32>::new_volume(16, 16, 16, pixels, Gray32::new());
+        let mut map = ImageProcessor::<f32, Gray32>::new_volume(
+            16,
+            16,
+            16,
+            original.data.clone(),
+            Gray32::new(),
+        );
+        map.apply_b_factor(50.0, 1.0);
+        let variance = |ip: &ImageProcessor<f32, Gray32>| -> f32 {
+            let mean = ip.data.iter().sum::<f32>() / ip.data.len() as f32;
+            ip.data.iter().map(|v| (v - mean).powi(2)).sum()
+        };
+        assert!(variance(&map) < 0.5 * variance(&original));
+        map.apply_b_factor(-50.0, 1.0);
+        assert!(map
+            .data
+            .iter()
+            .zip(original.data.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-2));
+    }
+
+    #[test]
+    fn bandpass_suppresses_stripes() {
+        // Horizontal stripes and a spot
+        let mut pixels: Vec<f32> = (0..64 * 48)
+            .map(|i| 100.0 + 20.0 * ((i / 64) % 2) as f32)
+            .collect();
+        pixels[20 + 24 * 64] += 200.0;
+        let mut ip = ImageProcessor::<f32, Gray32>::new(64, 48, pixels, Gray32::new());
+        let mean =
+            |ip: &ImageProcessor<f32, Gray32>| ip.data.iter().sum::<f32>() / ip.data.len() as f32;
+        let before = mean(&ip);
+        let options = Bandpass {
+            filter_small: 0.0,
+            stripes: Stripes::Horizontal,
+            autoscale: false,
+            ..Default::default()
+        };
+        ip.bandpass(&options);
+        assert!((mean(&ip) - before).abs() < 0.5);
+        // The stripes are gone far from the spot
+        assert!((ip.data[2 + 4 * 64] - ip.data[2 + 5 * 64]).abs() < 1.0);
+        assert!(ip.data[20 + 24 * 64] > ip.data[40 + 24 * 64] + 100.0);
+        // Autoscale: same range as the original
+        let mut bytes =
+            ImageProcessor::<u8, Gray<u8>>::new(64, 48, vec![0u8; 64 * 48], Gray::<u8>::new());
+        bytes.data[10 + 10 * 64] = 255;
+        bytes.data[50 + 30 * 64] = 100;
+        bytes.bandpass(&Bandpass {
+            saturate: false,
+            ..Default::default()
+        });
+        let max = bytes.data.iter().max().unwrap();
+        let min = bytes.data.iter().min().unwrap();
+        assert_eq!((*min, *max), (0, 255));
+    }
+}
@@ -1,42 +1,495 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
 
-pub trait Filter {
-    /// Blurs the image by convolving with a Gaussian function.
-    fn blur_gaussian​(f64 sigma) {}
-    
-    /// Performs a convolution operation using the specified kernel.
-    fn convolve​(float[] kernel, i32 kernel_width, i32 kernel_height) {}
+use crate::grayscale::Gray;
+use crate::image_processor::ImageProcessor;
+use crate::pixel::PixelType;
 
-    /// Convolves the image or ROI with the specified 3x3 i32eger convolution kernel.
-    fn convolve3x3​(i32[] kernel) {}
+/// Accuracy of the Gaussian kernel: the kernel is truncated where
+/// its value falls below this fraction of the central value.
+const GAUSSIAN_ACCURACY: f64 = 0.0002;
 
-    /// Dilates the image or ROI using a 3x3 minimum filter. 
-    fn dilate() {}
-    
-    /// Erodes the image or ROI using a 3x3 maximum filter.
-    fn erode() {}
-    
-    /// A 3x3 filter operation, where the argument (BLUR_MORE, FIND_EDGES, MEDIAN_FILTER, MIN or MAX) determines the filter type.
-    fn filter​(i32 type) {}
+///
+/// Edge handling used when a kernel reaches outside the image.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeMode {
+    /// Out-of-image pixels take the value of the nearest edge pixel (ImageJ behaviour).
+    Clamp,
+    /// Out-of-image pixels are mirrored across the image border.
+    Mirror,
+    /// Out-of-image pixels are considered as zero.
+    Zero,
+}
 
-    /// Finds edges in the image or ROI using a Sobel operator.
-    fn find_edges() {}
+///
+/// 3x3 filter types available in [filter()](Filter::filter).
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterType {
+    /// 3x3 unweighted smoothing.
+    BlurMore,
+    /// Sobel edge detector.
+    FindEdges,
+    /// 3x3 median.
+    Median,
+    /// 3x3 minimum.
+    Min,
+    /// 3x3 maximum.
+    Max,
+}
 
-    fn get_neighborhood​(i32 x, i32 y, f64[][] arr) {};
+///
+/// Neighbourhood operations on gray-level processors.
+///
+/// When the processor is a volume (depth > 1), each plane is filtered independently.
+/// Only the pixels of the active ROI are modified; the neighbors outside the ROI are still read.
+///
+pub trait Filter<T: PixelType> {
+    /// Blurs the image by convolving with a Gaussian function of standard deviation `sigma` (in pixels).
+    fn blur_gaussian(&mut self, sigma: f64, edge: EdgeMode);
+
+    /// Performs a convolution operation using the specified kernel.
+    ///
+    /// # Arguments
+    ///
+    /// * `kernel` - Kernel values stored row by row (`kernel_width` x `kernel_height`).
+    /// * `kernel_width` - Kernel width, must be odd.
+    /// * `kernel_height` - Kernel height, must be odd.
+    /// * `edge` - Edge handling.
+    ///
+    /// Like ImageJ, the kernel is normalized by the sum of its values if this sum is not zero.
+    fn convolve(&mut self, kernel: &[f32], kernel_width: u32, kernel_height: u32, edge: EdgeMode);
 
-    /// Inverts the image or ROI.
-    fn invert() {}
+    /// Convolves the image with the specified 3x3 integer convolution kernel.
+    ///
+    /// The result is divided by the sum of the kernel values (if not zero) and rounded.
+    fn convolve3x3(&mut self, kernel: &[i32; 9]);
 
-    /// Inverts the values in this image's LUT (indexed color model).
-    fn invert_Lut() {}
+    /// A 3x3 filter operation, where the argument (BlurMore, FindEdges, Median, Min or Max) determines the filter type.
+    ///
+    /// Like ImageJ, the edge pixels are computed by replicating the nearest edge pixel.
+    fn filter(&mut self, kind: FilterType);
+
+    /// Dilates the image using a 3x3 minimum filter.
+    fn dilate(&mut self) {
+        self.filter(FilterType::Min);
+    }
+
+    /// Erodes the image using a 3x3 maximum filter.
+    fn erode(&mut self) {
+        self.filter(FilterType::Max);
+    }
+
+    /// Finds edges in the image using a Sobel operator.
+    fn find_edges(&mut self) {
+        self.filter(FilterType::FindEdges);
+    }
 
     /// A 3x3 median filter.
-    fn median_Filter() {}
- 
-    fn sharpen() {}
-    /// Sharpens the image or ROI using a 3x3 convolution kernel.
+    fn median_filter(&mut self) {
+        self.filter(FilterType::Median);
+    }
+
+    /// Sharpens the image using a 3x3 convolution kernel.
+    fn sharpen(&mut self) {
+        self.convolve3x3(&[-1, -1, -1, -1, 12, -1, -1, -1, -1]);
+    }
 
     /// Replaces each pixel with the 3x3 neighborhood mean.
-    fn smooth() {}
+    fn smooth(&mut self) {
+        self.filter(FilterType::BlurMore);
+    }
+}
+
+impl<T: PixelType> Filter<T> for ImageProcessor<T, Gray<T>> {
+    fn blur_gaussian(&mut self, sigma: f64, edge: EdgeMode) {
+        if sigma <= 0.0 {
+            return;
+        }
+        let kernel = gaussian_kernel(sigma);
+        let w = self.get_width() as usize;
+        let h = self.get_height() as usize;
+        for plane in 0..self.depth as usize {
+            let offset = plane * w * h;
+            let mut pixels: Vec<f32> = self.data[offset..offset + w * h]
+                .iter()
+                .map(|v| v.to_f32())
+                .collect();
+            // Rows...
+            let mut line = vec![0.0f32; w];
+            for y in 0..h {
+                convolve_line(&pixels[y * w..(y + 1) * w], &mut line, &kernel, edge);
+                pixels[y * w..(y + 1) * w].copy_from_slice(&line);
+            }
+            // ... then columns
+            let mut column = vec![0.0f32; h];
+            let mut line = vec![0.0f32; h];
+            for x in 0..w {
+                for y in 0..h {
+                    column[y] = pixels[x + y * w];
+                }
+                convolve_line(&column, &mut line, &kernel, edge);
+                for y in 0..h {
+                    pixels[x + y * w] = line[y];
+                }
+            }
+            for i in self.metadata.roi_indices() {
+                self.data[offset + i] = T::round_pixel(pixels[i]);
+            }
+        }
+        self.metadata.set_dirty();
+    }
+
+    fn convolve(&mut self, kernel: &[f32], kernel_width: u32, kernel_height: u32, edge: EdgeMode) {
+        assert!(
+            kernel_width % 2 == 1 && kernel_height % 2 == 1,
+            "Kernel width and height must be odd"
+        );
+        assert_eq!(
+            kernel.len(),
+            (kernel_width * kernel_height) as usize,
+            "Kernel length does not match its dimensions"
+        );
+        let sum: f32 = kernel.iter().sum();
+        let scale = if sum != 0.0 { 1.0 / sum } else { 1.0 };
+        let w = self.get_width() as i64;
+        let h = self.get_height() as i64;
+        let kw = kernel_width as i64;
+        let kh = kernel_height as i64;
+        let (uc, vc) = (kw / 2, kh / 2);
+        for plane in 0..self.depth as usize {
+            let offset = plane * (w * h) as usize;
+            let pixels: Vec<f32> = self.data[offset..offset + (w * h) as usize]
+                .iter()
+                .map(|v| v.to_f32())
+                .collect();
+            for y in 0..h {
+                for x in 0..w {
+                    if !self.metadata.contains(x as u32, y as u32) {
+                        continue;
+                    }
+                    let mut acc = 0.0f32;
+                    for v in -vc..=vc {
+                        let yy = match edge_index(y + v, h, edge) {
+                            Some(yy) => yy,
+                            None => continue,
+                        };
+                        for u in -uc..=uc {
+                            if let Some(xx) = edge_index(x + u, w, edge) {
+                                acc += kernel[((v + vc) * kw + u + uc) as usize]
+                                    * pixels[(xx + yy * w) as usize];
+                            }
+                        }
+                    }
+                    self.data[offset + (x + y * w) as usize] = T::round_pixel(acc * scale);
+                }
+            }
+        }
+        self.metadata.set_dirty();
+    }
+
+    fn convolve3x3(&mut self, kernel: &[i32; 9]) {
+        let sum: i32 = kernel.iter().sum();
+        let scale = if sum != 0 { sum as f32 } else { 1.0 };
+        self.filter3x3(|p| {
+            let acc: f32 = p.iter().zip(kernel).map(|(v, k)| v * *k as f32).sum();
+            T::round_pixel(acc / scale)
+        });
+    }
+
+    fn filter(&mut self, kind: FilterType) {
+        match kind {
+            FilterType::BlurMore => self.filter3x3(|p| T::round_pixel(p.iter().sum::<f32>() / 9.0)),
+            FilterType::FindEdges => self.filter3x3(|p| {
+                let sum1 = p[0] + 2.0 * p[1] + p[2] - p[6] - 2.0 * p[7] - p[8];
+                let sum2 = p[0] + 2.0 * p[3] + p[6] - p[2] - 2.0 * p[5] - p[8];
+                // Truncated like ImageJ
+                T::clamp_pixel((sum1 * sum1 + sum2 * sum2).sqrt())
+            }),
+            FilterType::Median => self.filter3x3(|p| {
+                let mut sorted = *p;
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                T::round_pixel(sorted[4])
+            }),
+            FilterType::Min => {
+                self.filter3x3(|p| T::round_pixel(p.iter().fold(f32::INFINITY, |a, b| a.min(*b))))
+            }
+            FilterType::Max => self.filter3x3(|p| {
+                T::round_pixel(p.iter().fold(f32::NEG_INFINITY, |a, b| a.max(*b)))
+            }),
+        }
+    }
+}
+
+impl<T: PixelType> ImageProcessor<T, Gray<T>> {
+    //
+    // Private function: applies `f` to the 3x3 neighborhood of each pixel.
+    // The neighbors are stored row by row (p1..p9 in ImageJ) and
+    // the out-of-image neighbors are replaced by the nearest edge pixel.
+    //
+    fn filter3x3<F>(&mut self, f: F)
+    where
+        F: Fn(&[f32; 9]) -> T,
+    {
+        let w = self.get_width() as i64;
+        let h = self.get_height() as i64;
+        for plane in 0..self.depth as usize {
+            let offset = plane * (w * h) as usize;
+            let pixels: Vec<f32> = self.data[offset..offset + (w * h) as usize]
+                .iter()
+                .map(|v| v.to_f32())
+                .collect();
+            let mut p = [0.0f32; 9];
+            for y in 0..h {
+                for x in 0..w {
+                    if !self.metadata.contains(x as u32, y as u32) {
+                        continue;
+                    }
+                    for (k, item) in p.iter_mut().enumerate() {
+                        let xx = (x + k as i64 % 3 - 1).clamp(0, w - 1);
+                        let yy = (y + k as i64 / 3 - 1).clamp(0, h - 1);
+                        *item = pixels[(xx + yy * w) as usize];
+                    }
+                    self.data[offset + (x + y * w) as usize] = f(&p);
+                }
+            }
+        }
+        self.metadata.set_dirty();
+    }
+}
+
+///
+/// Returns the normalized 1D Gaussian kernel of standard deviation `sigma`.
+///
+/// The kernel is centered: its length is always odd.
+///
+pub fn gaussian_kernel(sigma: f64) -> Vec<f32> {
+    let radius = (sigma * (-2.0 * GAUSSIAN_ACCURACY.ln()).sqrt()).ceil() as i64 + 1;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-0.5 * (i * i) as f64 / (sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter().map(|k| (k / sum) as f32).collect()
 }
 
+//
+// Private function: 1D convolution of `input` by a centered `kernel`.
+//
+fn convolve_line(input: &[f32], output: &mut [f32], kernel: &[f32], edge: EdgeMode) {
+    let n = input.len() as i64;
+    let radius = (kernel.len() / 2) as i64;
+    for (i, out) in output.iter_mut().enumerate() {
+        let mut acc = 0.0f32;
+        for (k, coef) in kernel.iter().enumerate() {
+            if let Some(j) = edge_index(i as i64 + k as i64 - radius, n, edge) {
+                acc += coef * input[j as usize];
+            }
+        }
+        *out = acc;
+    }
+}
+
+///
+/// Maps a coordinate `i` to a valid index in `0..n` according to the edge mode.
+///
+/// Returns `None` if the pixel must be considered as zero.
+///
+pub(crate) fn edge_index(i: i64, n: i64, edge: EdgeMode) -> Option<i64> {
+    if i >= 0 && i < n {
+        return Some(i);
+    }
+    match edge {
+        EdgeMode::Zero => None,
+        EdgeMode::Clamp => Some(i.clamp(0, n - 1)),
+        EdgeMode::Mirror => {
+            if n == 1 {
+                return Some(0);
+            }
+            let period = 2 * (n - 1);
+            let j = i.rem_euclid(period);
+            Some(if j < n { j } else { period - j })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::color_space::ColorSpace;
+    use crate::float_processor::FloatProcessor;
+    use crate::grayscale::Gray8;
+    use crate::roi::Roi;
+
+    #[test]
+    fn gaussian_kernel_is_normalized() {
+        let kernel = gaussian_kernel(2.0);
+        assert_eq!(kernel.len() % 2, 1);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn blur_gaussian_keeps_constant_image() {
+        let mut ip = ImageProcessor::<u8, Gray8>::new(8, 6, vec![100u8; 48], Gray8::new());
+        ip.blur_gaussian(1.5, EdgeMode::Clamp);
+        assert!(ip.data().iter().all(|v| *v == 100));
+    }
+
+    #[test]
+    fn blur_gaussian_spreads_a_dirac() {
+        let mut pixels = vec![0.0f32; 81];
+        pixels[40] = 1.0;
+        let mut ip = FloatProcessor::new(9, 9, pixels, Gray::<f32>::new());
+        ip.blur_gaussian(1.0, EdgeMode::Zero);
+        let sum: f32 = ip.data().iter().sum();
+        assert!((sum - 1.0).abs() < 1e-4);
+        assert!(ip.data()[40] < 1.0 && ip.data()[41] > 0.0);
+        assert!((ip.data()[39] - ip.data()[41]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn convolve_identity_kernel() {
+        let pixels: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let mut ip = FloatProcessor::new(4, 3, pixels.clone(), Gray::<f32>::new());
+        ip.convolve(&[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0], 3, 3, EdgeMode::Zero);
+        assert_eq!(*ip.data(), pixels);
+    }
 
+    #[test]
+    fn convolve_edge_modes() {
+        // Horizontal shift kernel: output(x) = input(x - 1)
+        let kernel = [1.0, 0.0, 0.0];
+        let mut ip = FloatProcessor::new(3, 1, vec![1.0, 2.0, 3.0], Gray::<f32>::new());
+        ip.convolve(&kernel, 3, 1, EdgeMode::Zero);
+        assert_eq!(*ip.data(), vec![0.0, 1.0, 2.0]);
+        let mut ip = FloatProcessor::new(3, 1, vec![1.0, 2.0, 3.0], Gray::<f32>::new());
+        ip.convolve(&kernel, 3, 1, EdgeMode::Clamp);
+        assert_eq!(*ip.data(), vec![1.0, 1.0, 2.0]);
+        let mut ip = FloatProcessor::new(3, 1, vec![1.0, 2.0, 3.0], Gray::<f32>::new());
+        ip.convolve(&kernel, 3, 1, EdgeMode::Mirror);
+        assert_eq!(*ip.data(), vec![2.0, 1.0, 2.0]);
+    }
+
+    // 4x4 test image and expected ImageJ results (Process > Filters)
+    fn ramp_u8() -> ImageProcessor<u8, Gray8> {
+        ImageProcessor::<u8, Gray8>::new(
+            4,
+            4,
+            vec![10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 120, 130, 140, 150, 200],
+            Gray8::new(),
+        )
+    }
+
+    #[test]
+    fn smooth_u8_like_imagej() {
+        let mut ip = ramp_u8();
+        ip.smooth();
+        // Corner (0,0): 10,10,20,10,10,20,50,50,60 => 240/9 = 26.7
+        assert_eq!(ip.data()[0], 27);
+        // Inner (1,1): 10,20,30,50,60,70,90,100,110 => 540/9
+        assert_eq!(ip.data()[5], 60);
+        // Corner (3,3): 110,120,120,150,200,200,150,200,200 => 1450/9 = 161.1
+        assert_eq!(ip.data()[15], 161);
+    }
+
+    #[test]
+    fn rank3x3_u16() {
+        let pixels: Vec<u16> = vec![5, 1000, 3, 7, 60000, 2, 9, 8, 4];
+        let mut ip = ImageProcessor::<u16, Gray<u16>>::new(3, 3, pixels.clone(), Gray::<u16>::new());
+        ip.median_filter();
+        assert_eq!(ip.data()[4], 7);
+        let mut ip = ImageProcessor::<u16, Gray<u16>>::new(3, 3, pixels.clone(), Gray::<u16>::new());
+        ip.filter(FilterType::Max);
+        assert!(ip.data().iter().all(|v| *v == 60000));
+        let mut ip = ImageProcessor::<u16, Gray<u16>>::new(3, 3, pixels, Gray::<u16>::new());
+        ip.dilate();
+        assert_eq!(ip.data()[4], 2);
+        // Corner (0,0) sees 5,5,1000,5,5,1000,7,7,60000
+        assert_eq!(ip.data()[0], 5);
+    }
+
+    #[test]
+    fn erode_binary_u8() {
+        // Black objects on a white background (ImageJ): erode (3x3 maximum) removes
+        // the isolated black pixel, and a white pixel on black grows to 3x3
+        let mut pixels = vec![255u8; 25];
+        pixels[12] = 0;
+        let mut ip = ImageProcessor::<u8, Gray8>::new(5, 5, pixels, Gray8::new());
+        ip.erode();
+        assert!(ip.data().iter().all(|v| *v == 255));
+        let mut pixels = vec![0u8; 25];
+        pixels[12] = 255;
+        let mut ip = ImageProcessor::<u8, Gray8>::new(5, 5, pixels, Gray8::new());
+        ip.erode();
+        assert_eq!(ip.data().iter().filter(|v| **v == 255).count(), 9);
+    }
+
+    #[test]
+    fn find_edges_f32_and_u8() {
+        let pixels: Vec<f32> = vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0];
+        let mut ip = FloatProcessor::new(4, 3, pixels, Gray::<f32>::new());
+        ip.find_edges();
+        // Vertical step: sum2 = -4 at columns 1 and 2
+        assert_eq!(*ip.data(), vec![0.0, 4.0, 4.0, 0.0, 0.0, 4.0, 4.0, 0.0, 0.0, 4.0, 4.0, 0.0]);
+        let mut ip = ramp_u8();
+        ip.find_edges();
+        // Inner (1,1): sum1 = -320, sum2 = -80 => 329.8 => 255
+        assert_eq!(ip.data()[5], 255);
+    }
+
+    #[test]
+    fn sharpen_u8_like_imagej() {
+        let mut ip = ramp_u8();
+        ip.sharpen();
+        // Inner (1,1): (12*60 - 480)/4 = 60
+        assert_eq!(ip.data()[5], 60);
+        // Inner (2,2): (12*110 - (60+70+80+100+120+140+150+200))/4 = 100
+        assert_eq!(ip.data()[10], 100);
+    }
+
+    #[test]
+    fn convolve_clamps_u8_pixels() {
+        let mut ip = ImageProcessor::<u8, Gray8>::new(3, 3, vec![200u8; 9], Gray8::new());
+        // Kernel normalized by its sum (2.0)
+        ip.convolve(&[0.0, -1.0, 0.0, -1.0, 6.0, -1.0, 0.0, -1.0, 0.0], 3, 3, EdgeMode::Zero);
+        assert_eq!(ip.data()[4], 200);
+        assert_eq!(ip.data()[0], 255);
+        // Sum is zero: no normalization
+        let mut ip = ImageProcessor::<u8, Gray8>::new(3, 1, vec![10u8, 0, 10], Gray8::new());
+        ip.convolve(&[1.0, -2.0, 1.0], 3, 1, EdgeMode::Zero);
+        assert_eq!(*ip.data(), vec![0u8, 20, 0]);
+    }
+
+    #[test]
+    fn filter_restricted_to_roi() {
+        let mut ip = ramp_u8();
+        let before = ip.data().clone();
+        ip.set_roi(Roi::rectangle(1.0, 1.0, 2.0, 2.0));
+        ip.median_filter();
+        ip.blur_gaussian(2.0, EdgeMode::Clamp);
+        let w = ip.get_width();
+        for (i, v) in ip.data().iter().enumerate() {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            if !(1..3).contains(&x) || !(1..3).contains(&y) {
+                assert_eq!(*v, before[i]);
+            }
+        }
+    }
+}
@@ -38,25 +38,25 @@ impl Statistics<f32> for FloatProcessor {
         if self.metadata.stats.is_dirty() {
             let mut sum: f64 = 0.0;
             let mut sum2: f64 = 0.0;
-            let mut hist = vec![0u32; 255];
-            let start = (self.metadata.roi.y() * self.width + self.metadata.roi.x()) as usize;
-            let mut mi: f64 = self.data[start] as f64;
-            let mut mx = mi;
+            let mut hist = vec![0u32; 256];
+            let mut mi = f64::MAX;
+            let mut mx = f64::MIN;
             let mut count: u32 = 0;
-            for y in self.metadata.roi.y()..(self.metadata.roi.y() + self.metadata.roi.height()) {
-                let mut i = y * self.width + self.metadata.roi.x();
-                for x in self.metadata.roi.x()..(self.metadata.roi.x() + self.metadata.roi.width())
-                {
-                    let v: f64 = self.getf(i as usize) as f64;
-                    let index: usize = self.get(i as usize) as usize;
-                    sum += v as f64;
-                    sum2 += (v * v) as f64;
-                    i += 1;
-                    hist[(((v as f32) / f32::MAX) * (255 as f32)) as usize] += 1;
-                    mi = if v < mi { v } else { mi };
-                    mx = if v > mx { v } else { mx };
-                    count += 1;
-                }
+            // Pixels of the active ROI (whole image without ROI)
+            let indices = self.metadata.roi_indices();
+            for i in indices.iter() {
+                let v: f64 = self.getf(*i) as f64;
+                sum += v;
+                sum2 += v * v;
+                mi = if v < mi { v } else { mi };
+                mx = if v > mx { v } else { mx };
+                count += 1;
+            }
+            // 256 bins between min and max like ImageJ
+            let scale = if mx > mi { 256.0 / (mx - mi) } else { 0.0 };
+            for i in indices.iter() {
+                let index = ((self.getf(*i) as f64 - mi) * scale) as usize;
+                hist[index.min(255)] += 1;
             }
             let mut std_dev = (count as f64 * sum2 - sum * sum) / count as f64;
             std_dev = if std_dev > 0.0 {
@@ -122,6 +122,24 @@ impl<T: PixelType + std::clone::Clone> Access<T> for ImageProcessor<T, Gray<T>>
     }
 }
 
+impl<T: PixelType> ImageProcessor<T, Gray<T>> {
+    //
+    // Private function: applies `f(index_in_plane, value)` to the pixels of the
+    // active ROI (the whole image without ROI) of every plane.
+    //
+    fn map_roi<F: Fn(usize, T) -> T>(&mut self, f: F) {
+        let size = self.get_size();
+        let indices = self.metadata.roi_indices();
+        for plane in 0..self.depth.max(1) as usize {
+            let offset = plane * size;
+            for i in indices.iter() {
+                self.data[offset + i] = f(*i, self.data[offset + i].to_value());
+            }
+        }
+        self.metadata.set_dirty();
+    }
+}
+
 //
 // Operators Implementation
 //
@@ -129,42 +147,24 @@ impl<T: PixelType> Operator<T> for ImageProcessor<T, Gray<T>> {
     type Output = T;
 
     fn macro_op(&mut self, f: fn(T, f32, f32, f32, u32, u32, f32, f32) -> T) {
-        self.data = self
-            .data
-            .iter()
-            .enumerate()
-            .map(|(i, v)| {
-                // Compute v,x,y,z,w,h,a,d
-                let x = (i as u32 % self.get_width()) as f32;
-                let y = (i as f32 / self.get_width() as f32).floor();
-                let z = 0f32;
-                let a = y.atan2(x); // In radians
-                let cx = (self.get_width() as f32 / 2.0).floor();
-                let cy = (self.get_height() as f32 / 2.0).floor();
-                let d = ((x - cx) * (x - cx) + (y - cy) * (y - cy)).sqrt();
-                // Call f(..)
-                f(
-                    v.to_value(),
-                    x,
-                    y,
-                    z,
-                    self.get_width(),
-                    self.get_height(),
-                    a,
-                    d,
-                )
-            })
-            .collect();
+        let w = self.get_width();
+        let h = self.get_height();
+        let cx = (w as f32 / 2.0).floor();
+        let cy = (h as f32 / 2.0).floor();
+        self.map_roi(|i, v| {
+            // Compute v,x,y,z,w,h,a,d
+            let x = (i as u32 % w) as f32;
+            let y = (i as f32 / w as f32).floor();
+            let z = 0f32;
+            let a = y.atan2(x); // In radians
+            let d = ((x - cx) * (x - cx) + (y - cy) * (y - cy)).sqrt();
+            // Call f(..)
+            f(v, x, y, z, w, h, a, d)
+        });
     }
     fn macro_scalar(&mut self, scalar: T, f: fn(T, f32) -> T) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| {
-                // Call f(..)
-                f(v.to_value(), scalar.to_f32())
-            })
-            .collect();
+        let s = scalar.to_f32();
+        self.map_roi(|_, v| f(v, s));
     }
 
     fn add(&mut self, scalar: T) {
@@ -196,31 +196,23 @@ impl<T: PixelType> Operator<T> for ImageProcessor<T, Gray<T>> {
     }
 
     fn ceil(&mut self, scalar: T) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| {
-                if v.to_f32() > scalar.to_f32() {
-                    scalar.to_value()
-                } else {
-                    v.to_value()
-                }
-            })
-            .collect();
+        self.map_roi(|_, v| {
+            if v.to_f32() > scalar.to_f32() {
+                scalar.to_value()
+            } else {
+                v
+            }
+        });
     }
 
     fn floor(&mut self, scalar: T) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| {
-                if v.to_f32() < scalar.to_f32() {
-                    scalar.to_value()
-                } else {
-                    v.to_value()
-                }
-            })
-            .collect();
+        self.map_roi(|_, v| {
+            if v.to_f32() < scalar.to_f32() {
+                scalar.to_value()
+            } else {
+                v
+            }
+        });
     }
 
     fn and(&mut self, scalar: T) {
@@ -247,43 +239,23 @@ impl<T: PixelType> Operator<T> for ImageProcessor<T, Gray<T>> {
     }
 
     fn abs(&mut self) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| <T as PixelType>::clamp_pixel(v.to_f32().abs()))
-            .collect();
+        self.map_roi(|_, v| <T as PixelType>::clamp_pixel(v.to_f32().abs()));
     }
 
     fn exp(&mut self) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| <T as PixelType>::clamp_pixel(v.to_f32().exp()))
-            .collect();
+        self.map_roi(|_, v| <T as PixelType>::clamp_pixel(v.to_f32().exp()));
     }
 
     fn sqrt(&mut self) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| <T as PixelType>::clamp_pixel(v.to_f32().sqrt()))
-            .collect();
+        self.map_roi(|_, v| <T as PixelType>::clamp_pixel(v.to_f32().sqrt()));
     }
 
     fn ln(&mut self) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| <T as PixelType>::clamp_pixel(v.to_f32().log(2.0)))
-            .collect();
+        self.map_roi(|_, v| <T as PixelType>::clamp_pixel(v.to_f32().log(2.0)));
     }
 
     fn log(&mut self) {
-        self.data = self
-            .data
-            .iter()
-            .map(|v| <T as PixelType>::clamp_pixel(v.to_f32().log(10.0)))
-            .collect();
+        self.map_roi(|_, v| <T as PixelType>::clamp_pixel(v.to_f32().log(10.0)));
     }
 
     /// Performs gamma correction of the image or ROI.
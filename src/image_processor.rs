@@ -20,6 +20,7 @@
 use crate::color_space::ColorSpace;
 use crate::meta_data::MetaData;
 use crate::pixel::PixelType;
+use crate::roi::Roi;
 
 ///
 /// ImageProcessor
@@ -83,6 +84,22 @@ impl<T: PixelType, C: ColorSpace> ImageProcessor<T, C> {
         const BITS_PER_BYTE: usize = 8;
         BITS_PER_BYTE * std::mem::size_of::<T>()
     }
+    // ROI
+    /// Sets the active ROI restricting the operators, filters and statistics.
+    pub fn set_roi(&mut self, roi: Roi) {
+        self.metadata.set_roi(roi);
+    }
+    /// Removes the active ROI.
+    pub fn reset_roi(&mut self) {
+        self.metadata.reset_roi();
+    }
+    pub fn get_roi(&self) -> Option<&Roi> {
+        self.metadata.get_roi()
+    }
+    /// Returns the mask of the active ROI, `None` for a rectangle or without ROI.
+    pub fn get_mask(&self) -> Option<&Vec<u8>> {
+        self.metadata.get_mask()
+    }
 }
 
 /*
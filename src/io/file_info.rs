@@ -152,6 +152,23 @@ impl FileInfo {
     pub const ZIP_ARCHIVE: u32 = 7;
     pub const PGM: u32 = 8;
     pub const IMAGEIO: u32 = 9;
+    /** ImageJ selection (.roi). The ROI type is stored in `file_type`. */
+    pub const ROI: u32 = 10;
+    /** MRC2014 (cryo-EM images, stacks and volumes). */
+    pub const MRC: u32 = 11;
+
+    // ROI types of the ImageJ .roi format
+    pub const ROI_POLYGON: u32 = 0;
+    pub const ROI_RECT: u32 = 1;
+    pub const ROI_OVAL: u32 = 2;
+    pub const ROI_LINE: u32 = 3;
+    pub const ROI_FREELINE: u32 = 4;
+    pub const ROI_POLYLINE: u32 = 5;
+    pub const ROI_NO_ROI: u32 = 6;
+    pub const ROI_FREEHAND: u32 = 7;
+    pub const ROI_TRACED: u32 = 8;
+    pub const ROI_ANGLE: u32 = 9;
+    pub const ROI_POINT: u32 = 10;
 
     // Compression modes
     pub const COMPRESSION_UNKNOWN: u32 = 0;
@@ -26,6 +26,7 @@ use std::fs::metadata;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::Read;
+use std::path::Path;
 
 use crate::color_space::ColorSpace;
 use crate::gray_processor::ip_gray;
@@ -49,6 +50,48 @@ pub enum OutputProcessor {
     Unknown(String),
 }
 
+impl OutputProcessor {
+    ///
+    /// Converts the image or stack to a stack of floats (an image gives a single slice).
+    /// Returns the message of an `Unknown` output as error.
+    ///
+    pub fn into_float_stack(self) -> Result<ImageStack<f32, Gray32>, String> {
+        fn convert<T: PixelType>(
+            width: u32,
+            height: u32,
+            slices: &[Vec<T>],
+        ) -> ImageStack<f32, Gray32> {
+            let slices = slices
+                .iter()
+                .map(|s| s.iter().map(|v| v.to_f32()).collect())
+                .collect();
+            ImageStack::new(width, height, slices, Gray32::new())
+        }
+        match self {
+            OutputProcessor::FloatStack(stack) => Ok(stack),
+            OutputProcessor::ByteStack(s) => Ok(convert(s.width, s.height, &s.data)),
+            OutputProcessor::ShortStack(s) => Ok(convert(s.width, s.height, &s.data)),
+            OutputProcessor::UIntStack(s) => Ok(convert(s.width, s.height, &s.data)),
+            OutputProcessor::ByteProcessor(ip) => Ok(convert(ip.width, ip.height, &[ip.data])),
+            OutputProcessor::ShortProcessor(ip) => Ok(convert(ip.width, ip.height, &[ip.data])),
+            OutputProcessor::UIntProcessor(ip) => Ok(convert(ip.width, ip.height, &[ip.data])),
+            OutputProcessor::FloatProcessor(ip) => Ok(convert(ip.width, ip.height, &[ip.data])),
+            OutputProcessor::Unknown(msg) => Err(msg),
+        }
+    }
+}
+
+///
+/// Returns the extension of `filename` in lower case (empty if there is none),
+/// to select the decoder or the encoder of a file.
+///
+pub fn extension(filename: &str) -> String {
+    Path::new(filename)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
 ///
 /// FileOpener is a utility class to load raw binary file without header.
 ///
@@ -171,6 +214,10 @@ impl FileOpener {
                 let stck = FileOpener::read_u16_stack(w, h, &buffer);
                 OutputProcessor::ShortStack(stck)
             }
+            FileInfo::GRAY32_FLOAT => {
+                let stck = FileOpener::read_float_stack(w, h, &buffer);
+                OutputProcessor::FloatStack(stck)
+            }
             _ => OutputProcessor::Unknown(String::from("Unknown File Format")),
         }
     }
@@ -253,7 +300,7 @@ impl FileOpener {
     // * `slice` - The number of images in the stack
     // * `filename` - The name of the raw file
     //
-    fn read_u16_stack(height: u32, width: u32, buffer: &Vec<u8>) -> ImageStack<u16, Gray16> {
+    fn read_u16_stack(width: u32, height: u32, buffer: &Vec<u8>) -> ImageStack<u16, Gray16> {
         let nslices = buffer.len() / (width * height * 2) as usize;
         let mut new_buffer: Vec<Vec<u16>> = vec![];
         let data = FileOpener::separate_slices(buffer, nslices);
@@ -290,7 +337,7 @@ impl FileOpener {
     // * `slice` - The number of images in the stack
     // * `filename` - The name of the raw file
     //
-    fn read_float_stack(height: u32, width: u32, buffer: &Vec<u8>) -> ImageStack<f32, Gray32> {
+    fn read_float_stack(width: u32, height: u32, buffer: &Vec<u8>) -> ImageStack<f32, Gray32> {
         let nslices = buffer.len() / (width * height * 4) as usize;
         let mut new_buffer: Vec<Vec<f32>> = vec![];
         let data = FileOpener::separate_slices(buffer, nslices);
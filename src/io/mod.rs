@@ -2,7 +2,11 @@ pub mod file_info;
 
 pub mod image_reader;
 pub mod image_writer;
+pub mod mrc_decoder;
+pub mod mrc_encoder;
 pub mod raw_reader;
+pub mod roi_decoder;
+pub mod roi_encoder;
 ///
 /// Example of tabular data in STAR format
 ///
@@ -54,4 +58,8 @@ pub mod raw_reader;
 pub mod star;
 pub mod star_parser;
 pub mod star_reader;
+pub mod star_writer;
 pub mod text_reader;
+pub mod text_writer;
+pub mod tiff_decoder;
+pub mod tiff_encoder;
@@ -0,0 +1,255 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Reader of the MRC2014 format used in cryo-EM and cryo-ET for volumes, image stacks
+//! and tilt series.
+//!
+//! The file starts with a header of 1024 bytes, followed by an optional extended header
+//! of `NSYMBT` bytes and by the data (x fastest). The byte order is given by the machine
+//! stamp: `0x44 0x44` for little-endian, `0x11 0x11` for big-endian.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::grayscale::{Gray16, Gray32, Gray8};
+use crate::image_stack::ImageStack;
+use crate::io::file_info::FileInfo;
+use crate::io::image_reader::OutputProcessor;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Offsets of the fields in the header
+pub(crate) const NX: usize = 0;
+pub(crate) const NY: usize = 4;
+pub(crate) const NZ: usize = 8;
+pub(crate) const MODE: usize = 12;
+pub(crate) const MX: usize = 28;
+pub(crate) const CELLA: usize = 40;
+pub(crate) const MAPC: usize = 64;
+pub(crate) const DMIN: usize = 76;
+pub(crate) const ISPG: usize = 88;
+pub(crate) const NSYMBT: usize = 92;
+pub(crate) const NVERSION: usize = 108;
+pub(crate) const MAP: usize = 208;
+pub(crate) const MACHST: usize = 212;
+pub(crate) const RMS: usize = 216;
+pub(crate) const NLABL: usize = 220;
+pub(crate) const LABELS: usize = 224;
+pub(crate) const HEADER_SIZE: usize = 1024;
+
+// Data types
+pub(crate) const MODE_INT8: i32 = 0;
+pub(crate) const MODE_INT16: i32 = 1;
+pub(crate) const MODE_FLOAT32: i32 = 2;
+pub(crate) const MODE_UINT16: i32 = 6;
+
+///
+/// Decoder of MRC files
+///
+/// The modes 0 (8-bit, read as unsigned like ImageJ and IMOD), 1 (signed 16-bit, converted
+/// to 32-bit), 2 (32-bit float) and 6 (unsigned 16-bit) are supported.
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::io::image_reader::OutputProcessor;
+/// use rim::io::mrc_decoder::MrcDecoder;
+///
+/// let (stack, info) = MrcDecoder::open("./tilt_series.mrc").unwrap();
+/// println!("{}x{}x{} at {} Å/px", info.width, info.height, info.n_images, info.pixel_width);
+/// if let OutputProcessor::FloatStack(stack) = stack {
+///     println!("{} slices", stack.n_slices());
+/// }
+/// ```
+pub struct MrcDecoder {}
+
+impl MrcDecoder {
+    ///
+    /// Opens a MRC file as an image stack (one slice per section).
+    ///
+    /// The returned FileInfo contains the dimensions, the type of the stack, the byte order
+    /// and the pixel size in Å (`pixel_width`, `pixel_height` and `pixel_depth`).
+    ///
+    pub fn open(filename: &str) -> io::Result<(OutputProcessor, FileInfo)> {
+        let bytes = fs::read(filename)?;
+        let (stack, mut info) = MrcDecoder::decode(&bytes)?;
+        let path = Path::new(filename);
+        if let Some(name) = path.file_name() {
+            info.file_name = name.to_string_lossy().to_string();
+        }
+        if let Some(dir) = path.parent() {
+            info.directory = dir.to_string_lossy().to_string();
+        }
+        Ok((stack, info))
+    }
+
+    ///
+    /// Reads the header of the MRC file.
+    ///
+    pub fn read_info(bytes: &[u8]) -> io::Result<FileInfo> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(invalid("Truncated MRC header"));
+        }
+        let little = match bytes[MACHST] {
+            0x11 => false,
+            0x44 | 0x41 => true,
+            // Old files without stamp: plausible dimensions give the byte order
+            _ => Bytes(bytes, true).i32(MODE)?.unsigned_abs() < 16,
+        };
+        let data = Bytes(bytes, little);
+        let (nx, ny, nz) = (data.i32(NX)?, data.i32(NY)?, data.i32(NZ)?);
+        if nx <= 0 || ny <= 0 || nz <= 0 {
+            return Err(invalid("Invalid MRC dimensions"));
+        }
+        let mut info = FileInfo::new();
+        info.file_format = FileInfo::MRC;
+        info.file_type = match data.i32(MODE)? {
+            MODE_INT8 => FileInfo::GRAY8,
+            MODE_INT16 => FileInfo::GRAY16_SIGNED,
+            MODE_FLOAT32 => FileInfo::GRAY32_FLOAT,
+            MODE_UINT16 => FileInfo::GRAY16_UNSIGNED,
+            mode => return Err(invalid(&format!("Unsupported MRC mode {}", mode))),
+        };
+        info.width = nx as u32;
+        info.height = ny as u32;
+        info.n_images = nz as u32;
+        info.intel_byte_order = little;
+        info.offset = (HEADER_SIZE as i64 + data.i32(NSYMBT)?.max(0) as i64) as u32;
+        // Pixel size (Å) = cell dimension / sampling
+        let sizes: Vec<f64> = (0..3)
+            .map(|i| {
+                let cell = data.f32(CELLA + 4 * i)? as f64;
+                let m = data.i32(MX + 4 * i)?;
+                Ok(if m > 0 && cell > 0.0 {
+                    cell / m as f64
+                } else {
+                    1.0
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        info.pixel_width = sizes[0];
+        info.pixel_height = sizes[1];
+        info.pixel_depth = sizes[2];
+        info.unit = String::from("Å");
+        Ok(info)
+    }
+
+    ///
+    /// Decodes the content of a MRC file.
+    ///
+    pub fn decode(bytes: &[u8]) -> io::Result<(OutputProcessor, FileInfo)> {
+        let info = MrcDecoder::read_info(bytes)?;
+        let (w, h) = (info.width, info.height);
+        let dimensions = || invalid("Invalid MRC dimensions");
+        let size = (w as usize)
+            .checked_mul(h as usize)
+            .ok_or_else(dimensions)?;
+        let count = size
+            .checked_mul(info.n_images as usize)
+            .ok_or_else(dimensions)?;
+        let start = info.offset as usize;
+        let end = count
+            .checked_mul(info.get_bytes_per_pixel() as usize)
+            .and_then(|length| length.checked_add(start))
+            .ok_or_else(dimensions)?;
+        let raw = bytes
+            .get(start..end)
+            .ok_or_else(|| invalid("Truncated MRC data"))?;
+        let data = Bytes(raw, info.intel_byte_order);
+        let stack = match info.file_type {
+            FileInfo::GRAY8 => OutputProcessor::ByteStack(ImageStack::new(
+                w,
+                h,
+                raw.chunks(size).map(|s| s.to_vec()).collect(),
+                Gray8::new(),
+            )),
+            FileInfo::GRAY16_UNSIGNED => {
+                let values = (0..count)
+                    .map(|i| data.u16(2 * i))
+                    .collect::<io::Result<Vec<u16>>>()?;
+                OutputProcessor::ShortStack(ImageStack::new(
+                    w,
+                    h,
+                    values.chunks(size).map(|s| s.to_vec()).collect(),
+                    Gray16::new(),
+                ))
+            }
+            _ => {
+                let values = (0..count)
+                    .map(|i| match info.file_type {
+                        FileInfo::GRAY16_SIGNED => Ok(data.u16(2 * i)? as i16 as f32),
+                        _ => data.f32(4 * i),
+                    })
+                    .collect::<io::Result<Vec<f32>>>()?;
+                OutputProcessor::FloatStack(ImageStack::new(
+                    w,
+                    h,
+                    values.chunks(size).map(|s| s.to_vec()).collect(),
+                    Gray32::new(),
+                ))
+            }
+        };
+        Ok((stack, info))
+    }
+}
+
+//
+// Private functions
+//
+
+pub(crate) fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Reader with bounds checking and byte order (`true` for little-endian)
+struct Bytes<'a>(&'a [u8], bool);
+
+impl<'a> Bytes<'a> {
+    fn get<const N: usize>(&self, offset: usize) -> io::Result<[u8; N]> {
+        self.0
+            .get(offset..offset + N)
+            .map(|s| s.try_into().unwrap())
+            .ok_or_else(|| invalid("Truncated MRC file"))
+    }
+    fn u16(&self, offset: usize) -> io::Result<u16> {
+        let b = self.get(offset)?;
+        Ok(if self.1 {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+    fn i32(&self, offset: usize) -> io::Result<i32> {
+        let b = self.get(offset)?;
+        Ok(if self.1 {
+            i32::from_le_bytes(b)
+        } else {
+            i32::from_be_bytes(b)
+        })
+    }
+    fn f32(&self, offset: usize) -> io::Result<f32> {
+        let b = self.get(offset)?;
+        Ok(if self.1 {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        })
+    }
+}
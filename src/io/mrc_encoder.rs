@@ -0,0 +1,260 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Writer of the MRC2014 format (little-endian, no extended header).
+//!
+
+use crate::io::image_reader::OutputProcessor;
+use crate::io::mrc_decoder::*;
+use crate::pixel::PixelType;
+use std::fs;
+use std::io;
+
+///
+/// Encoder of MRC files
+///
+/// 8-bit images are saved in mode 0, 16-bit images in mode 6 and 32-bit images in mode 2.
+/// Stacks and volumes are saved with one section per slice.
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::io::image_reader::OutputProcessor;
+/// use rim::io::mrc_decoder::MrcDecoder;
+/// use rim::io::mrc_encoder::MrcEncoder;
+///
+/// let (stack, info) = MrcDecoder::open("./tilt_series.mrc").unwrap();
+/// MrcEncoder::save(&stack, info.pixel_width, "./copy.mrc").unwrap();
+/// ```
+pub struct MrcEncoder {}
+
+impl MrcEncoder {
+    /// Saves `image` with the pixel size `pixel_size` (in Å) in a MRC file.
+    pub fn save(image: &OutputProcessor, pixel_size: f64, filename: &str) -> io::Result<()> {
+        fs::write(filename, MrcEncoder::encode(image, pixel_size)?)
+    }
+
+    ///
+    /// Encodes `image` in the MRC format.
+    ///
+    pub fn encode(image: &OutputProcessor, pixel_size: f64) -> io::Result<Vec<u8>> {
+        match image {
+            OutputProcessor::ByteProcessor(ip) => {
+                encode_planes(ip.width, ip.height, &[&ip.data[..]], MODE_INT8, pixel_size)
+            }
+            OutputProcessor::ShortProcessor(ip) => encode_planes(
+                ip.width,
+                ip.height,
+                &[&ip.data[..]],
+                MODE_UINT16,
+                pixel_size,
+            ),
+            OutputProcessor::FloatProcessor(ip) => encode_planes(
+                ip.width,
+                ip.height,
+                &[&ip.data[..]],
+                MODE_FLOAT32,
+                pixel_size,
+            ),
+            OutputProcessor::ByteStack(stack) => encode_planes(
+                stack.width,
+                stack.height,
+                &slices(&stack.data),
+                MODE_INT8,
+                pixel_size,
+            ),
+            OutputProcessor::ShortStack(stack) => encode_planes(
+                stack.width,
+                stack.height,
+                &slices(&stack.data),
+                MODE_UINT16,
+                pixel_size,
+            ),
+            OutputProcessor::FloatStack(stack) => encode_planes(
+                stack.width,
+                stack.height,
+                &slices(&stack.data),
+                MODE_FLOAT32,
+                pixel_size,
+            ),
+            _ => Err(invalid("Unsupported image type for MRC")),
+        }
+    }
+}
+
+//
+// Private functions
+//
+
+fn slices<T>(data: &[Vec<T>]) -> Vec<&[T]> {
+    data.iter().map(|s| &s[..]).collect()
+}
+
+// Bytes (little-endian) of a pixel
+trait LittleEndian {
+    fn bytes(&self, out: &mut Vec<u8>);
+}
+
+impl LittleEndian for u8 {
+    fn bytes(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl LittleEndian for u16 {
+    fn bytes(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+}
+
+impl LittleEndian for f32 {
+    fn bytes(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+}
+
+fn encode_planes<T: PixelType + LittleEndian>(
+    width: u32,
+    height: u32,
+    planes: &[&[T]],
+    mode: i32,
+    pixel_size: f64,
+) -> io::Result<Vec<u8>> {
+    let size = (width * height) as usize;
+    // Volumes are split in sections
+    let sections: Vec<&[T]> = planes.iter().flat_map(|p| p.chunks(size)).collect();
+    if sections.is_empty() || sections.iter().any(|s| s.len() != size) {
+        return Err(invalid("Inconsistent image dimensions"));
+    }
+    let (nx, ny, nz) = (width as i32, height as i32, sections.len() as i32);
+
+    // Statistics
+    let (mut min, mut max, mut sum, mut sum2) = (f64::MAX, f64::MIN, 0.0f64, 0.0f64);
+    for v in sections.iter().flat_map(|s| s.iter()) {
+        let v = v.to_f32() as f64;
+        min = min.min(v);
+        max = max.max(v);
+        sum += v;
+        sum2 += v * v;
+    }
+    let n = (size * sections.len()) as f64;
+    let mean = sum / n;
+    let rms = (sum2 / n - mean * mean).max(0.0).sqrt();
+
+    let mut out = vec![0u8; HEADER_SIZE];
+    let mut put = |offset: usize, bytes: [u8; 4]| out[offset..offset + 4].copy_from_slice(&bytes);
+    for (i, v) in [nx, ny, nz, mode].iter().enumerate() {
+        put(NX + 4 * i, v.to_le_bytes());
+    }
+    for (i, v) in [nx, ny, nz].iter().enumerate() {
+        put(MX + 4 * i, v.to_le_bytes());
+        put(
+            CELLA + 4 * i,
+            ((*v as f64 * pixel_size) as f32).to_le_bytes(),
+        );
+        put(CELLA + 12 + 4 * i, 90.0f32.to_le_bytes());
+        put(MAPC + 4 * i, (i as i32 + 1).to_le_bytes());
+    }
+    for (i, v) in [min, max, mean].iter().enumerate() {
+        put(DMIN + 4 * i, (*v as f32).to_le_bytes());
+    }
+    // Image stack (0) or volume (1)
+    put(ISPG, (if nz > 1 { 1i32 } else { 0 }).to_le_bytes());
+    put(NVERSION, 20140i32.to_le_bytes());
+    put(MAP, *b"MAP ");
+    put(MACHST, [0x44, 0x44, 0x00, 0x00]);
+    put(RMS, (rms as f32).to_le_bytes());
+    put(NLABL, 1i32.to_le_bytes());
+    let label = b"RIM - Rust Image";
+    out[LABELS..LABELS + label.len()].copy_from_slice(label);
+
+    out.reserve(size * sections.len() * std::mem::size_of::<T>());
+    for v in sections.iter().flat_map(|s| s.iter()) {
+        v.bytes(&mut out);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_space::ColorSpace;
+    use crate::grayscale::{Gray16, Gray32};
+    use crate::image_stack::ImageStack;
+    use crate::io::file_info::FileInfo;
+
+    #[test]
+    fn float_stack_round_trip() {
+        let data: Vec<Vec<f32>> = (0..3)
+            .map(|z| (0..12).map(|i| (i * z) as f32 - 0.5).collect())
+            .collect();
+        let stack = OutputProcessor::FloatStack(ImageStack::new(4, 3, data.clone(), Gray32::new()));
+        let bytes = MrcEncoder::encode(&stack, 2.5).unwrap();
+        assert_eq!(bytes.len(), HEADER_SIZE + 3 * 12 * 4);
+        let (decoded, info) = MrcDecoder::decode(&bytes).unwrap();
+        assert_eq!((info.width, info.height, info.n_images), (4, 3, 3));
+        assert_eq!(info.file_type, FileInfo::GRAY32_FLOAT);
+        assert!((info.pixel_width - 2.5).abs() < 1e-6);
+        match decoded {
+            OutputProcessor::FloatStack(s) => assert_eq!(s.data, data),
+            _ => panic!("Wrong type"),
+        }
+    }
+
+    #[test]
+    fn short_stack_and_big_endian() {
+        let data = vec![vec![0u16, 1, 2, 65535]];
+        let stack = OutputProcessor::ShortStack(ImageStack::new(2, 2, data.clone(), Gray16::new()));
+        let mut bytes = MrcEncoder::encode(&stack, 1.0).unwrap();
+        match MrcDecoder::decode(&bytes).unwrap().0 {
+            OutputProcessor::ShortStack(s) => assert_eq!(s.data, data),
+            _ => panic!("Wrong type"),
+        }
+        // Same file written by a big-endian machine
+        for offset in [NX, NY, NZ, MODE] {
+            bytes[offset..offset + 4].reverse();
+        }
+        for i in 0..4 {
+            bytes[HEADER_SIZE + 2 * i..HEADER_SIZE + 2 * i + 2].reverse();
+        }
+        bytes[MACHST] = 0x11;
+        let (decoded, info) = MrcDecoder::decode(&bytes).unwrap();
+        assert!(!info.intel_byte_order);
+        match decoded {
+            OutputProcessor::ShortStack(s) => assert_eq!(s.data, data),
+            _ => panic!("Wrong type"),
+        }
+        assert!(MrcDecoder::decode(&bytes[..100]).is_err());
+    }
+
+    #[test]
+    fn malformed_header() {
+        let stack =
+            OutputProcessor::FloatStack(ImageStack::new(1, 1, vec![vec![0.0]], Gray32::new()));
+        let mut bytes = MrcEncoder::encode(&stack, 1.0).unwrap();
+        // 70000 x 70000 x 70000 pixels of 4 bytes
+        for offset in [NX, NY, NZ] {
+            bytes[offset..offset + 4].copy_from_slice(&70000i32.to_le_bytes());
+        }
+        assert!(MrcDecoder::decode(&bytes).is_err());
+        bytes[NZ..NZ + 4].copy_from_slice(&1i32.to_le_bytes());
+        assert!(MrcDecoder::decode(&bytes).is_err());
+    }
+}
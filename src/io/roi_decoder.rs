@@ -0,0 +1,336 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Reader of the ImageJ binary selection format (.roi) and of the RoiSet.zip archives
+//! exported by the ROI Manager of ImageJ/Fiji.
+//!
+//! All the values are big-endian. The file starts with a header of 64 bytes followed by
+//! the coordinates (for polygon-based ROIs) and an optional second header containing
+//! the name of the ROI.
+//!
+
+use crate::io::file_info::FileInfo;
+use crate::roi::Roi;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+// Offsets of the fields in the header
+pub(crate) const VERSION_OFFSET: usize = 4;
+pub(crate) const TYPE: usize = 6;
+pub(crate) const TOP: usize = 8;
+pub(crate) const LEFT: usize = 10;
+pub(crate) const BOTTOM: usize = 12;
+pub(crate) const RIGHT: usize = 14;
+pub(crate) const N_COORDINATES: usize = 16;
+pub(crate) const X1: usize = 18;
+pub(crate) const Y1: usize = 22;
+pub(crate) const X2: usize = 26;
+pub(crate) const Y2: usize = 30;
+pub(crate) const SHAPE_ROI_SIZE: usize = 36;
+pub(crate) const OPTIONS: usize = 50;
+pub(crate) const HEADER2_OFFSET: usize = 60;
+pub(crate) const COORDINATES: usize = 64;
+// Offsets in the second header
+pub(crate) const NAME_OFFSET: usize = 16;
+pub(crate) const NAME_LENGTH: usize = 20;
+pub(crate) const HEADER2_SIZE: usize = 64;
+
+pub(crate) const VERSION: u16 = 228;
+pub(crate) const SUB_PIXEL_RESOLUTION: u16 = 128;
+
+///
+/// Decoder of ImageJ ROI files
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::io::roi_decoder::RoiDecoder;
+///
+/// let (roi, info) = RoiDecoder::open("./cell.roi").unwrap();
+/// println!("{} of type {}", info.file_name, info.file_type);
+/// ```
+pub struct RoiDecoder {}
+
+impl RoiDecoder {
+    ///
+    /// Opens a `.roi` file.
+    ///
+    /// The returned FileInfo has the format [FileInfo::ROI], the ROI type (`FileInfo::ROI_*`)
+    /// in `file_type` and the name of the ROI in `file_name`.
+    ///
+    pub fn open(filename: &str) -> io::Result<(Roi, FileInfo)> {
+        let bytes = fs::read(filename)?;
+        let (roi, mut info) = RoiDecoder::decode(&bytes)?;
+        let path = Path::new(filename);
+        if info.file_name.is_empty() {
+            info.file_name = name_from_path(filename);
+        }
+        if let Some(dir) = path.parent() {
+            info.directory = dir.to_string_lossy().to_string();
+        }
+        Ok((roi, info))
+    }
+
+    ///
+    /// Opens a zip archive of `.roi` files (RoiSet.zip) saved by the ROI Manager.
+    ///
+    pub fn open_roi_set(filename: &str) -> io::Result<Vec<(Roi, FileInfo)>> {
+        let mut archive = zip::ZipArchive::new(fs::File::open(filename)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut rois = Vec::<(Roi, FileInfo)>::new();
+        for i in 0..archive.len() {
+            let mut entry = archive
+                .by_index(i)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if !entry.name().ends_with(".roi") {
+                continue;
+            }
+            // The declared size of a corrupt archive is not trusted
+            let mut bytes = Vec::<u8>::new();
+            entry.read_to_end(&mut bytes)?;
+            let (roi, mut info) = RoiDecoder::decode(&bytes)?;
+            if info.file_name.is_empty() {
+                info.file_name = name_from_path(entry.name());
+            }
+            info.directory = filename.to_string();
+            rois.push((roi, info));
+        }
+        Ok(rois)
+    }
+
+    ///
+    /// Decodes the content of a `.roi` file.
+    ///
+    pub fn decode(bytes: &[u8]) -> io::Result<(Roi, FileInfo)> {
+        if bytes.len() < COORDINATES || &bytes[0..4] != b"Iout" {
+            return Err(invalid("Not an ImageJ ROI file"));
+        }
+        let data = Bytes(bytes);
+        let version = data.u16(VERSION_OFFSET)?;
+        let roi_type = bytes[TYPE] as u32;
+        let top = data.i16(TOP)? as f64;
+        let left = data.i16(LEFT)? as f64;
+        let bottom = data.i16(BOTTOM)? as f64;
+        let right = data.i16(RIGHT)? as f64;
+        let n = data.u16(N_COORDINATES)? as usize;
+        let options = data.u16(OPTIONS)?;
+        let sub_pixel = version >= 222 && (options & SUB_PIXEL_RESOLUTION) != 0;
+
+        if data.i32(SHAPE_ROI_SIZE)? > 0 {
+            return Err(invalid("Composite (shape) ROIs are not supported"));
+        }
+
+        let roi = match roi_type {
+            FileInfo::ROI_RECT | FileInfo::ROI_OVAL => {
+                let (x, y, w, h) = if sub_pixel && version >= 223 {
+                    (
+                        data.f32(X1)? as f64,
+                        data.f32(Y1)? as f64,
+                        data.f32(X2)? as f64,
+                        data.f32(Y2)? as f64,
+                    )
+                } else {
+                    (left, top, right - left, bottom - top)
+                };
+                if roi_type == FileInfo::ROI_RECT {
+                    Roi::rectangle(x, y, w, h)
+                } else {
+                    Roi::oval(x, y, w, h)
+                }
+            }
+            FileInfo::ROI_LINE => Roi::Line {
+                x1: data.f32(X1)? as f64,
+                y1: data.f32(Y1)? as f64,
+                x2: data.f32(X2)? as f64,
+                y2: data.f32(Y2)? as f64,
+            },
+            FileInfo::ROI_POLYGON
+            | FileInfo::ROI_FREEHAND
+            | FileInfo::ROI_TRACED
+            | FileInfo::ROI_POLYLINE
+            | FileInfo::ROI_FREELINE
+            | FileInfo::ROI_ANGLE
+            | FileInfo::ROI_POINT => {
+                let mut points = Vec::<(f64, f64)>::with_capacity(n);
+                if sub_pixel {
+                    // Absolute float coordinates after the integer ones
+                    let base = COORDINATES + 4 * n;
+                    for i in 0..n {
+                        points.push((
+                            data.f32(base + 4 * i)? as f64,
+                            data.f32(base + 4 * (n + i))? as f64,
+                        ));
+                    }
+                } else {
+                    // Coordinates relative to the top-left corner of the bounding box
+                    for i in 0..n {
+                        points.push((
+                            left + data.i16(COORDINATES + 2 * i)? as f64,
+                            top + data.i16(COORDINATES + 2 * (n + i))? as f64,
+                        ));
+                    }
+                }
+                match roi_type {
+                    FileInfo::ROI_POLYGON | FileInfo::ROI_TRACED => Roi::Polygon(points),
+                    FileInfo::ROI_FREEHAND => Roi::Freehand(points),
+                    FileInfo::ROI_POINT => Roi::Point(points),
+                    _ => Roi::Polyline(points),
+                }
+            }
+            _ => return Err(invalid(&format!("Unsupported ROI type {}", roi_type))),
+        };
+
+        let mut info = FileInfo::new();
+        info.file_format = FileInfo::ROI;
+        info.file_type = roi_type;
+        info.intel_byte_order = false;
+        info.file_name = String::new();
+        let bounds = roi.bounds();
+        info.width = bounds.width.max(0) as u32;
+        info.height = bounds.height.max(0) as u32;
+        // Name stored in the second header
+        let header2 = index(data.i32(HEADER2_OFFSET)?)?;
+        let in_file = header2
+            .checked_add(HEADER2_SIZE)
+            .is_some_and(|end| end <= bytes.len());
+        if version >= 218 && header2 > 0 && in_file {
+            let offset = index(data.i32(header2 + NAME_OFFSET)?)?;
+            let length = index(data.i32(header2 + NAME_LENGTH)?)?;
+            if offset > 0 && length > 0 {
+                let chars = (0..length)
+                    .map(|i| data.u16(offset.saturating_add(2 * i)))
+                    .collect::<io::Result<Vec<u16>>>()?;
+                info.file_name = String::from_utf16_lossy(&chars);
+            }
+        }
+        Ok((roi, info))
+    }
+}
+
+//
+// Private functions
+//
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Offset or length read from the file
+fn index(value: i32) -> io::Result<usize> {
+    usize::try_from(value).map_err(|_| invalid("Invalid offset in ROI file"))
+}
+
+fn name_from_path(filename: &str) -> String {
+    Path::new(filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// Big-endian reader with bounds checking
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn get<const N: usize>(&self, offset: usize) -> io::Result<[u8; N]> {
+        offset
+            .checked_add(N)
+            .and_then(|end| self.0.get(offset..end))
+            .map(|s| s.try_into().unwrap())
+            .ok_or_else(|| invalid("Truncated ROI file"))
+    }
+    fn u16(&self, offset: usize) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.get(offset)?))
+    }
+    fn i16(&self, offset: usize) -> io::Result<i16> {
+        Ok(i16::from_be_bytes(self.get(offset)?))
+    }
+    fn i32(&self, offset: usize) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.get(offset)?))
+    }
+    fn f32(&self, offset: usize) -> io::Result<f32> {
+        Ok(f32::from_be_bytes(self.get(offset)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn decode_rectangle_header() {
+        // Rectangle (5,10) 20x30 as written by ImageJ
+        let mut bytes = vec![0u8; 64];
+        bytes[0..4].copy_from_slice(b"Iout");
+        bytes[4..6].copy_from_slice(&227u16.to_be_bytes());
+        bytes[TYPE] = 1;
+        bytes[TOP..TOP + 2].copy_from_slice(&10i16.to_be_bytes());
+        bytes[LEFT..LEFT + 2].copy_from_slice(&5i16.to_be_bytes());
+        bytes[BOTTOM..BOTTOM + 2].copy_from_slice(&40i16.to_be_bytes());
+        bytes[RIGHT..RIGHT + 2].copy_from_slice(&25i16.to_be_bytes());
+        let (roi, info) = RoiDecoder::decode(&bytes).unwrap();
+        assert_eq!(roi, Roi::rectangle(5.0, 10.0, 20.0, 30.0));
+        assert_eq!(info.file_format, FileInfo::ROI);
+        assert_eq!(info.file_type, FileInfo::ROI_RECT);
+        assert_eq!(info.width, 20);
+    }
+
+    #[test]
+    fn decode_integer_polygon() {
+        let mut bytes = vec![0u8; 64 + 12];
+        bytes[0..4].copy_from_slice(b"Iout");
+        bytes[4..6].copy_from_slice(&227u16.to_be_bytes());
+        bytes[TYPE] = 0;
+        bytes[TOP..TOP + 2].copy_from_slice(&2i16.to_be_bytes());
+        bytes[LEFT..LEFT + 2].copy_from_slice(&3i16.to_be_bytes());
+        bytes[N_COORDINATES..N_COORDINATES + 2].copy_from_slice(&3u16.to_be_bytes());
+        for (i, v) in [0i16, 4, 0, 0, 0, 4].iter().enumerate() {
+            bytes[64 + 2 * i..66 + 2 * i].copy_from_slice(&v.to_be_bytes());
+        }
+        let (roi, _) = RoiDecoder::decode(&bytes).unwrap();
+        assert_eq!(roi, Roi::Polygon(vec![(3.0, 2.0), (7.0, 2.0), (3.0, 6.0)]));
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert!(RoiDecoder::decode(b"not a roi").is_err());
+        let mut bytes = vec![0u8; 64];
+        bytes[0..4].copy_from_slice(b"Iout");
+        bytes[TYPE] = 42;
+        assert!(RoiDecoder::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn reject_corrupt_offsets() {
+        let mut bytes = vec![0u8; 128];
+        bytes[0..4].copy_from_slice(b"Iout");
+        bytes[4..6].copy_from_slice(&VERSION.to_be_bytes());
+        bytes[TYPE] = 1;
+        bytes[HEADER2_OFFSET..HEADER2_OFFSET + 4].copy_from_slice(&(-8i32).to_be_bytes());
+        assert!(RoiDecoder::decode(&bytes).is_err());
+        // Second header at 64 with corrupt name offsets
+        bytes[HEADER2_OFFSET..HEADER2_OFFSET + 4].copy_from_slice(&64i32.to_be_bytes());
+        for (offset, length) in [(-4i32, 3i32), (i32::MAX, 3), (100, -1)] {
+            bytes[64 + NAME_OFFSET..64 + NAME_OFFSET + 4].copy_from_slice(&offset.to_be_bytes());
+            bytes[64 + NAME_LENGTH..64 + NAME_LENGTH + 4].copy_from_slice(&length.to_be_bytes());
+            assert!(RoiDecoder::decode(&bytes).is_err());
+        }
+    }
+}
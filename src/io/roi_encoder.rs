@@ -0,0 +1,267 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Writer of the ImageJ binary selection format (.roi) and of RoiSet.zip archives
+//! readable by the ROI Manager of ImageJ/Fiji.
+//!
+
+use crate::io::file_info::FileInfo;
+use crate::io::roi_decoder::*;
+use crate::roi::Roi;
+use std::fs;
+use std::io::{self, Write};
+
+///
+/// Encoder of ImageJ ROI files
+///
+/// Non-integer coordinates are saved with the subpixel resolution option.
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::io::roi_encoder::RoiEncoder;
+/// use rim::roi::Roi;
+///
+/// let roi = Roi::oval(10.0, 10.0, 20.0, 20.0);
+/// RoiEncoder::save(&roi, "cell", "./cell.roi").unwrap();
+/// ```
+pub struct RoiEncoder {}
+
+impl RoiEncoder {
+    /// Saves `roi` named `name` in a `.roi` file.
+    pub fn save(roi: &Roi, name: &str, filename: &str) -> io::Result<()> {
+        fs::write(filename, RoiEncoder::encode(roi, name)?)
+    }
+
+    ///
+    /// Saves a list of named ROIs in a zip archive (RoiSet.zip), one `<name>.roi` entry per ROI.
+    ///
+    pub fn save_roi_set(rois: &[(String, Roi)], filename: &str) -> io::Result<()> {
+        let mut zip = zip::ZipWriter::new(fs::File::create(filename)?);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, roi) in rois.iter() {
+            zip.start_file(format!("{}.roi", name), options)
+                .map_err(io::Error::other)?;
+            zip.write_all(&RoiEncoder::encode(roi, name)?)?;
+        }
+        zip.finish().map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    ///
+    /// Encodes `roi` named `name` in the ImageJ `.roi` format.
+    ///
+    pub fn encode(roi: &Roi, name: &str) -> io::Result<Vec<u8>> {
+        let bounds = roi.bounds();
+        let (roi_type, points): (u32, &[(f64, f64)]) = match roi {
+            Roi::Rectangle { .. } => (FileInfo::ROI_RECT, &[]),
+            Roi::Oval { .. } => (FileInfo::ROI_OVAL, &[]),
+            Roi::Line { .. } => (FileInfo::ROI_LINE, &[]),
+            Roi::Polygon(points) => (FileInfo::ROI_POLYGON, points),
+            Roi::Freehand(points) => (FileInfo::ROI_FREEHAND, points),
+            Roi::Polyline(points) => (FileInfo::ROI_POLYLINE, points),
+            Roi::Point(points) => (FileInfo::ROI_POINT, points),
+            Roi::Composite(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Composite ROIs are not supported",
+                ))
+            }
+        };
+        let n = points.len();
+        if n > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Too many vertices",
+            ));
+        }
+        let sub_pixel = match roi {
+            Roi::Rectangle {
+                x,
+                y,
+                width,
+                height,
+            }
+            | Roi::Oval {
+                x,
+                y,
+                width,
+                height,
+            } => [x, y, width, height].iter().any(|v| v.fract() != 0.0),
+            _ => points
+                .iter()
+                .any(|(x, y)| x.fract() != 0.0 || y.fract() != 0.0),
+        };
+        let coordinates_size = if sub_pixel { 12 * n } else { 4 * n };
+        let header2 = COORDINATES + coordinates_size;
+
+        let mut out = vec![0u8; header2 + HEADER2_SIZE];
+        out[0..4].copy_from_slice(b"Iout");
+        put(&mut out, VERSION_OFFSET, &VERSION.to_be_bytes());
+        out[TYPE] = roi_type as u8;
+        put(&mut out, TOP, &(bounds.y as i16).to_be_bytes());
+        put(&mut out, LEFT, &(bounds.x as i16).to_be_bytes());
+        put(
+            &mut out,
+            BOTTOM,
+            &((bounds.y + bounds.height) as i16).to_be_bytes(),
+        );
+        put(
+            &mut out,
+            RIGHT,
+            &((bounds.x + bounds.width) as i16).to_be_bytes(),
+        );
+        put(&mut out, N_COORDINATES, &(n as u16).to_be_bytes());
+        if sub_pixel {
+            put(&mut out, OPTIONS, &SUB_PIXEL_RESOLUTION.to_be_bytes());
+        }
+        match roi {
+            Roi::Line { x1, y1, x2, y2 } => {
+                put_f32(&mut out, X1, *x1);
+                put_f32(&mut out, Y1, *y1);
+                put_f32(&mut out, X2, *x2);
+                put_f32(&mut out, Y2, *y2);
+            }
+            Roi::Rectangle {
+                x,
+                y,
+                width,
+                height,
+            }
+            | Roi::Oval {
+                x,
+                y,
+                width,
+                height,
+            } if sub_pixel => {
+                put_f32(&mut out, X1, *x);
+                put_f32(&mut out, Y1, *y);
+                put_f32(&mut out, X2, *width);
+                put_f32(&mut out, Y2, *height);
+            }
+            _ => (),
+        }
+        // Integer coordinates relative to the bounding box, then absolute float coordinates
+        for (i, (x, y)) in points.iter().enumerate() {
+            let ix = (x.floor() as i32 - bounds.x) as i16;
+            let iy = (y.floor() as i32 - bounds.y) as i16;
+            put(&mut out, COORDINATES + 2 * i, &ix.to_be_bytes());
+            put(&mut out, COORDINATES + 2 * (n + i), &iy.to_be_bytes());
+            if sub_pixel {
+                put_f32(&mut out, COORDINATES + 4 * n + 4 * i, *x);
+                put_f32(&mut out, COORDINATES + 4 * n + 4 * (n + i), *y);
+            }
+        }
+        // Second header with the name
+        put(&mut out, HEADER2_OFFSET, &(header2 as i32).to_be_bytes());
+        let chars: Vec<u16> = name.encode_utf16().collect();
+        if !chars.is_empty() {
+            let name_offset = header2 + HEADER2_SIZE;
+            put(
+                &mut out,
+                header2 + NAME_OFFSET,
+                &(name_offset as i32).to_be_bytes(),
+            );
+            put(
+                &mut out,
+                header2 + NAME_LENGTH,
+                &(chars.len() as i32).to_be_bytes(),
+            );
+            for c in chars.iter() {
+                out.extend_from_slice(&c.to_be_bytes());
+            }
+        }
+        Ok(out)
+    }
+}
+
+//
+// Private functions
+//
+fn put(out: &mut [u8], offset: usize, bytes: &[u8]) {
+    out[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn put_f32(out: &mut [u8], offset: usize, v: f64) {
+    put(out, offset, &(v as f32).to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::io::roi_decoder::RoiDecoder;
+
+    fn round_trip(roi: Roi, roi_type: u32) {
+        let bytes = RoiEncoder::encode(&roi, "cell-1").unwrap();
+        let (decoded, info) = RoiDecoder::decode(&bytes).unwrap();
+        assert_eq!(decoded, roi);
+        assert_eq!(info.file_type, roi_type);
+        assert_eq!(info.file_name, "cell-1");
+    }
+
+    #[test]
+    fn round_trip_all_types() {
+        round_trip(Roi::rectangle(1.0, 2.0, 30.0, 40.0), FileInfo::ROI_RECT);
+        round_trip(Roi::oval(1.5, 2.0, 30.25, 40.0), FileInfo::ROI_OVAL);
+        round_trip(
+            Roi::Line {
+                x1: 1.0,
+                y1: 2.5,
+                x2: 10.0,
+                y2: 20.0,
+            },
+            FileInfo::ROI_LINE,
+        );
+        let points = vec![(3.0, 2.0), (7.0, 2.0), (3.0, 6.0)];
+        round_trip(Roi::Polygon(points.clone()), FileInfo::ROI_POLYGON);
+        round_trip(Roi::Freehand(points.clone()), FileInfo::ROI_FREEHAND);
+        round_trip(Roi::Polyline(points.clone()), FileInfo::ROI_POLYLINE);
+        round_trip(Roi::Point(points), FileInfo::ROI_POINT);
+        // Subpixel coordinates
+        round_trip(
+            Roi::Polygon(vec![(3.25, 2.5), (7.75, 2.0), (3.0, 6.125)]),
+            FileInfo::ROI_POLYGON,
+        );
+    }
+
+    #[test]
+    fn composite_not_supported() {
+        let roi = Roi::Composite(vec![Roi::rectangle(0.0, 0.0, 2.0, 2.0)]);
+        assert!(RoiEncoder::encode(&roi, "").is_err());
+    }
+
+    #[test]
+    fn roi_set_zip() {
+        let filename = std::env::temp_dir().join("rim_roi_set_test.zip");
+        let filename = filename.to_str().unwrap();
+        let rois = vec![
+            ("a".to_string(), Roi::rectangle(0.0, 0.0, 4.0, 4.0)),
+            ("b".to_string(), Roi::Point(vec![(1.0, 1.0)])),
+        ];
+        RoiEncoder::save_roi_set(&rois, filename).unwrap();
+        let decoded = RoiDecoder::open_roi_set(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].1.file_name, "a");
+        assert_eq!(decoded[1].0, rois[1].1);
+    }
+}
@@ -83,6 +83,16 @@ impl DataSet {
     pub fn item(&self, name: String) -> &Container {
         &self.objects.iter().find(|&x| x.name() == name).unwrap()
     }
+
+    /// Returns the `id` of **this** `DataSet`
+    pub fn name(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the children (`Category` or `Table`) of **this** `DataSet`
+    pub fn items(&self) -> &[Container] {
+        &self.objects
+    }
 }
 
 ///
@@ -145,6 +155,9 @@ impl Category {
     pub fn push(&mut self, attr: Attribute) {
         self.attrs.push(attr);
     }
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attrs
+    }
 }
 
 impl fmt::Display for Category {
@@ -190,6 +203,20 @@ impl Table {
     pub fn add_row(&mut self, row: Row) {
         self.rows.push(row);
     }
+    pub fn name(&self) -> &str {
+        &self.key
+    }
+    pub fn headers(&self) -> &[String] {
+        &self.header
+    }
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// Returns the index of the column `name` (without prefix)
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| h == name)
+    }
 
     pub fn get_column(&self, index: usize) -> Vec<&Field> {
         let mut col = Vec::<&Field>::new();
@@ -224,6 +251,9 @@ impl Row {
     pub fn push(&mut self, item: Field) {
         self.cells.push(item);
     }
+    pub fn cells(&self) -> &[Field] {
+        &self.cells
+    }
 }
 
 ///
@@ -235,6 +265,31 @@ pub enum Field {
     Text(String),
 }
 
+impl Field {
+    /// Returns the value of a number field, `None` for a text
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            Field::Number(x) => Some(*x),
+            Field::Text(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    // Numbers in their shortest form, texts quoted if they contain spaces,
+    // multi-line texts between semicolons
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Number(x) => write!(f, "{}", x),
+            Field::Text(t) if t.contains('\n') => write!(f, "\n;{}\n;\n", t),
+            Field::Text(t) if t.is_empty() || t.contains(char::is_whitespace) => {
+                write!(f, "'{}'", t)
+            }
+            Field::Text(t) => write!(f, "{}", t),
+        }
+    }
+}
+
 ///
 /// This class allows the storage of key/value data.
 ///
@@ -248,4 +303,10 @@ impl Attribute {
     pub fn new(key: String, value: Field) -> Self {
         Attribute { key, value }
     }
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    pub fn value(&self) -> &Field {
+        &self.value
+    }
 }
@@ -16,44 +16,275 @@
 //
 //  You should have received a copy of the GNU General Public License
 //  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
- 
- 
- /*
-use crate::io::star_parser::parse_star;
-use std::fs;
 
-pub struct StarIO {}
+//!
+//! Reader of STAR files (RELION metadata, mmCIF).
+//!
+//! Each data block `data_<id>` is read as a `DataSet`. A `loop_` is read as a `Table` named
+//! by the prefix of its headers (`_rlnAngleRot` gives an empty name, `_atom_site.id` gives
+//! `atom_site`). The other items `_<category>.<key> <value>` are gathered in `Category`s.
+//! Values are read as numbers when possible. Quoted strings and multi-line text fields
+//! (between lines starting by `;`) are supported, the comments (`#`) are skipped.
+//!
 
-impl StarIO {
-    ///
-    /// Load distant mmCIF file from RCSB web site
-    ///
-    pub fn load_mmcif(pdb_id: &str) {
-        let path = "https://files.rcsb.org/view";
-        let url = format!("${}/${}.cif", path, pdb_id.to_uppercase());
-        println!("{}", url);
+use crate::io::star::*;
+use std::fs;
+use std::io;
 
-        // Get data from RCSB PDB site
-        // TODO
+///
+/// STAR reader
+///
+/// # Example
+///
+/// ```rust
+/// use rim::io::star::*;
+/// use rim::io::star_reader::StarReader;
+///
+/// let text = "data_particles\nloop_\n_rlnAngleRot #1\n_rlnImageName #2\n12.5 1@stack.mrcs\n";
+/// let blocks = StarReader::parse(text).unwrap();
+/// let table = blocks[0].item_at(0).to_table().unwrap();
+/// assert_eq!(table.headers(), ["rlnAngleRot", "rlnImageName"]);
+/// assert_eq!(table.rows()[0].cells()[0].to_f64(), Some(12.5));
+/// ```
+pub struct StarReader {}
 
-        // Parse text and return
-        // parse_star(txt)
-    }
+// Words of a STAR file
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+}
+
+impl StarReader {
     ///
-    /// Load local mmCIF file from disk
+    /// Reads the data blocks of the STAR file `filename`.
     ///
-    pub fn load_file_mmcif(filename: &str) {
-        Self::load_file_star(filename)
+    pub fn open(filename: &str) -> io::Result<Vec<DataSet>> {
+        let text = fs::read_to_string(filename)?;
+        StarReader::parse(&text).map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))
     }
+
     ///
-    /// Load local STAR file from disk
+    /// Parses the data blocks of a STAR text.
     ///
-    pub fn load_file_star(filename: &str) {
-        // Get data
-        let txt = fs::read_to_string(filename).expect("Unable to read file");
+    pub fn parse(text: &str) -> Result<Vec<DataSet>, String> {
+        let mut blocks = Vec::<DataSet>::new();
+        // Current loop (table and row being filled) or category
+        let mut table: Option<Table> = None;
+        let mut row: Option<Row> = None;
+        let mut category: Option<Category> = None;
+        let mut tokens = tokenize(text)?.into_iter().peekable();
+
+        while let Some(token) = tokens.next() {
+            let word = match &token {
+                Token::Word(w) => Some(w.as_str()),
+                Token::Quoted(_) => None,
+            };
+            let keyword =
+                word.filter(|w| w.starts_with("data_") || *w == "loop_" || w.starts_with('_'));
+            match keyword {
+                Some(w) if w.starts_with("data_") || w == "loop_" => {
+                    close(&mut blocks, &mut table, &mut row, &mut category)?;
+                    if w == "loop_" {
+                        table = Some(Table::new(String::new()));
+                    } else {
+                        blocks.push(DataSet::new(w[5..].to_string()));
+                    }
+                }
+                Some(w) => {
+                    let (name, key) = split_tag(w);
+                    let filling = table.as_ref().map(|t| t.rows().is_empty() && row.is_none());
+                    if filling == Some(true) {
+                        let t = table.as_mut().unwrap();
+                        if t.headers().is_empty() {
+                            t.set_head_name(name);
+                        }
+                        t.add_column_head(key);
+                        continue;
+                    }
+                    if table.is_some() {
+                        close(&mut blocks, &mut table, &mut row, &mut category)?;
+                    }
+                    let value = tokens
+                        .next()
+                        .ok_or_else(|| format!("Missing value of {}", w))?;
+                    if category.as_ref().map(|c| c.key != name).unwrap_or(true) {
+                        close(&mut blocks, &mut table, &mut row, &mut category)?;
+                        category = Some(Category::new(name));
+                    }
+                    category
+                        .as_mut()
+                        .unwrap()
+                        .push(Attribute::new(key, to_field(value)));
+                }
+                None => {
+                    let t = table
+                        .as_mut()
+                        .ok_or_else(|| format!("Unexpected value {:?} outside of a loop", token))?;
+                    let cells = row.get_or_insert_with(|| Row::new(t.rows().len()));
+                    cells.push(to_field(token));
+                    if cells.cells().len() == t.headers().len() {
+                        t.add_row(row.take().unwrap());
+                    }
+                }
+            }
+        }
+        close(&mut blocks, &mut table, &mut row, &mut category)?;
+        Ok(blocks)
+    }
+}
+
+//
+// Private functions
+//
+
+// Adds the current table or category to the last data block
+fn close(
+    blocks: &mut [DataSet],
+    table: &mut Option<Table>,
+    row: &mut Option<Row>,
+    category: &mut Option<Category>,
+) -> Result<(), String> {
+    if row.is_some() {
+        return Err(String::from("Incomplete row at the end of a loop"));
+    }
+    let item = match (table.take(), category.take()) {
+        (Some(t), _) => Container::Table(t),
+        (None, Some(c)) => Container::Category(c),
+        (None, None) => return Ok(()),
+    };
+    blocks
+        .last_mut()
+        .ok_or_else(|| String::from("Data found before the first data block"))?
+        .push(item);
+    Ok(())
+}
+
+// `_name.key` or `_key`
+fn split_tag(tag: &str) -> (String, String) {
+    match tag[1..].split_once('.') {
+        Some((name, key)) => (name.to_string(), key.to_string()),
+        None => (String::new(), tag[1..].to_string()),
+    }
+}
+
+fn to_field(token: Token) -> Field {
+    match token {
+        Token::Quoted(t) => Field::Text(t),
+        Token::Word(w) => match w.parse::<f64>() {
+            Ok(x) if !w.eq_ignore_ascii_case("nan") && !w.to_lowercase().contains("inf") => {
+                Field::Number(x)
+            }
+            _ => Field::Text(w),
+        },
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::<Token>::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        // Multi-line text field
+        if let Some(first) = line.strip_prefix(';') {
+            let mut field = vec![first];
+            loop {
+                match lines.next() {
+                    Some(l) if l.starts_with(';') => break,
+                    Some(l) => field.push(l),
+                    None => return Err(String::from("Unterminated text field (;)")),
+                }
+            }
+            tokens.push(Token::Quoted(field.join("\n")));
+            continue;
+        }
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == '#' {
+                break;
+            } else if c == '\'' || c == '"' {
+                // The closing quote is followed by a space or the end of line
+                let start = i + 1;
+                let mut end = start;
+                while end < chars.len()
+                    && !(chars[end] == c && chars.get(end + 1).is_none_or(|n| n.is_whitespace()))
+                {
+                    end += 1;
+                }
+                if end == chars.len() {
+                    return Err(format!("Unterminated string in line '{}'", line));
+                }
+                tokens.push(Token::Quoted(chars[start..end].iter().collect()));
+                i = end + 1;
+            } else {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() {
+                    i += 1;
+                }
+                tokens.push(Token::Word(chars[start..i].iter().collect()));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::star_writer::StarWriter;
+
+    #[test]
+    fn read_categories() {
+        let blocks = StarReader::open("./samples/config.star").unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].name(), "params");
+        match blocks[0].item_at(0) {
+            Container::Category(c) => {
+                assert_eq!(c.key, "input");
+                assert_eq!(c.attributes()[0].key(), "filename");
+                assert_eq!(
+                    c.attributes()[0].value().to_string(),
+                    "chessboard_u8_8x8.bin"
+                );
+                assert_eq!(c.attributes()[1].value().to_f64(), Some(8.0));
+            }
+            _ => panic!("Category expected"),
+        }
+    }
+
+    #[test]
+    fn read_loop_with_text_fields() {
+        let blocks = StarReader::open("./samples/music.star").unwrap();
+        let table = blocks[0].item_at(0).to_table().unwrap();
+        assert_eq!(table.name(), "data");
+        assert_eq!(table.headers().len(), 6);
+        assert_eq!(table.rows().len(), 3);
+        let cells = table.rows()[1].cells();
+        assert_eq!(cells[2].to_string(), "'Deep Purple'");
+        assert!(matches!(&cells[4], Field::Text(t) if t.starts_with("Smoke on the water\n")));
+        assert_eq!(cells[5].to_f64(), Some(1972.0));
+    }
 
-        // Parse text and return
-        parse_star(txt)
+    #[test]
+    fn write_and_read() {
+        let blocks = StarReader::open("./samples/music.star").unwrap();
+        let copy = StarReader::parse(&StarWriter::to_string(&blocks)).unwrap();
+        let (a, b) = (
+            blocks[0].item_at(0).to_table().unwrap(),
+            copy[0].item_at(0).to_table().unwrap(),
+        );
+        assert_eq!(a.headers(), b.headers());
+        for (r, s) in a.rows().iter().zip(b.rows().iter()) {
+            let texts = |row: &Row| {
+                row.cells()
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(texts(r), texts(s));
+        }
     }
 }
-*/
@@ -0,0 +1,108 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Writer of STAR files (RELION metadata).
+//!
+//! Each `DataSet` is written as a data block `data_<id>`. The column headers of a `Table`
+//! and the keys of a `Category` are prefixed by `_<name>.`, or by `_` alone if the name
+//! is empty (RELION style, e.g. `_rlnAngleRot`).
+//!
+
+use crate::io::star::*;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+
+///
+/// STAR writer
+///
+/// # Example
+///
+/// ```rust
+/// use rim::io::star::*;
+/// use rim::io::star_writer::StarWriter;
+///
+/// let mut particles = DataSet::new("particles".to_string());
+/// let mut table = Table::new(String::new());
+/// table.add_column_head("rlnAngleRot".to_string());
+/// table.add_column_head("rlnImageName".to_string());
+/// let mut row = Row::new(0);
+/// row.push(Field::Number(12.5));
+/// row.push(Field::Text("1@stack.mrcs".to_string()));
+/// table.add_row(row);
+/// particles.push(Container::Table(table));
+/// let text = StarWriter::to_string(&[particles]);
+/// assert!(text.contains("_rlnAngleRot #1"));
+/// ```
+pub struct StarWriter {}
+
+impl StarWriter {
+    /// Saves the data blocks `blocks` in a STAR file.
+    pub fn save(blocks: &[DataSet], filename: &str) -> io::Result<()> {
+        fs::write(filename, StarWriter::to_string(blocks))
+    }
+
+    ///
+    /// Returns the STAR text of the data blocks `blocks`.
+    ///
+    pub fn to_string(blocks: &[DataSet]) -> String {
+        let mut out = String::new();
+        for block in blocks.iter() {
+            let _ = writeln!(out, "\ndata_{}\n", block.name());
+            for item in block.items().iter() {
+                match item {
+                    Container::Category(category) => {
+                        for attr in category.attributes().iter() {
+                            let _ = writeln!(
+                                out,
+                                "{}{} {}",
+                                prefix(&category.key),
+                                attr.key(),
+                                attr.value()
+                            );
+                        }
+                        out.push('\n');
+                    }
+                    Container::Table(table) => {
+                        out.push_str("loop_\n");
+                        for (i, head) in table.headers().iter().enumerate() {
+                            let _ = writeln!(out, "{}{} #{}", prefix(table.name()), head, i + 1);
+                        }
+                        for row in table.rows().iter() {
+                            let fields: Vec<String> =
+                                row.cells().iter().map(|f| f.to_string()).collect();
+                            let _ = writeln!(out, "{}", fields.join(" "));
+                        }
+                        out.push('\n');
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn prefix(name: &str) -> String {
+    if name.is_empty() {
+        String::from("_")
+    } else {
+        format!("_{}.", name)
+    }
+}
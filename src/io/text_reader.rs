@@ -36,6 +36,8 @@ impl TextReader {
     /// * `filename` File name
     /// * `separator` (tab, comma, semi-column, etc.). Default value is the comma `,`.
     ///
+    /// Returns an `InvalidData` error if a row and the headings have a different number of fields.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -59,6 +61,16 @@ impl TextReader {
             if !head && words.len() >= 1 {
                 headings = words.clone();
                 head = true;
+            } else if words.len() != headings.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Line {} has {} fields instead of {}",
+                        i + 1,
+                        words.len(),
+                        headings.len()
+                    ),
+                ));
             } else {
                 // Add a new row
                 table.add_row();
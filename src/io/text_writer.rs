@@ -0,0 +1,98 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::results_table::{Cell, ResultsTable};
+use std::{fs, io};
+
+///
+/// Writer of text files (CSV)
+///
+pub struct TextWriter {}
+
+impl TextWriter {
+    /// Save a ResultsTable as a CSV file
+    ///
+    /// # Arguments
+    ///
+    /// * `table` The table to save
+    /// * `filename` File name
+    /// * `separator` (tab, comma, semi-column, etc.). Default value is the comma `,`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rim::io::text_writer::TextWriter;
+    /// use rim::results_table::{Cell, ResultsTable};
+    ///
+    /// let mut rt = ResultsTable::new(String::from("Results"));
+    /// rt.add_row();
+    /// rt.add_value(&String::from("A"), Cell::Number(1.0));
+    /// TextWriter::save_csv(&rt, "./results.csv", None).unwrap();
+    /// ```
+    pub fn save_csv(
+        table: &ResultsTable,
+        filename: &str,
+        separator: Option<char>,
+    ) -> io::Result<()> {
+        fs::write(filename, TextWriter::to_csv(table, separator))
+    }
+
+    /// Returns the CSV text of a ResultsTable: the headings then one line per row.
+    pub fn to_csv(table: &ResultsTable, separator: Option<char>) -> String {
+        let sep = separator.unwrap_or(',').to_string();
+        let mut text = table.get_headings().join(&sep);
+        text.push('\n');
+        for i in 0..table.size() {
+            let row: Vec<String> = table
+                .get_row_at(i)
+                .iter()
+                .map(|cell| match cell {
+                    Cell::Number(x) => x.to_string(),
+                    Cell::Text(t) => t.clone(),
+                    Cell::None => String::new(),
+                })
+                .collect();
+            text.push_str(&row.join(&sep));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::io::text_reader::TextReader;
+
+    #[test]
+    fn save_and_open_csv() {
+        let rt = TextReader::open_csv("./samples/test.csv", None).unwrap();
+        let filename = std::env::temp_dir().join("rim_text_writer.csv");
+        let filename = filename.to_str().unwrap();
+        TextWriter::save_csv(&rt, filename, None).unwrap();
+        let copy = TextReader::open_csv(filename, None).unwrap();
+        assert_eq!(copy.size(), 5);
+        assert_eq!(copy.get_headings(), rt.get_headings());
+        assert_eq!(
+            copy.get_column_as_floats(String::from("C")).unwrap(),
+            vec![3.0, 6.0, 9.0, 12.0, 15.0]
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Reader of uncompressed gray-level TIFF files (one image per IFD) and of the stacks
+//! saved by ImageJ.
+//!
+//! ImageJ may write a single IFD for a whole stack: the number of images is then given
+//! by `images=<n>` in the image description and the slices are contiguous.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::grayscale::{Gray16, Gray32, Gray8};
+use crate::image_stack::ImageStack;
+use crate::io::file_info::FileInfo;
+use crate::io::image_reader::OutputProcessor;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Tags
+pub(crate) const NEW_SUBFILE_TYPE: u16 = 254;
+pub(crate) const IMAGE_WIDTH: u16 = 256;
+pub(crate) const IMAGE_LENGTH: u16 = 257;
+pub(crate) const BITS_PER_SAMPLE: u16 = 258;
+pub(crate) const COMPRESSION: u16 = 259;
+pub(crate) const PHOTO_INTERP: u16 = 262;
+pub(crate) const IMAGE_DESCRIPTION: u16 = 270;
+pub(crate) const STRIP_OFFSETS: u16 = 273;
+pub(crate) const SAMPLES_PER_PIXEL: u16 = 277;
+pub(crate) const ROWS_PER_STRIP: u16 = 278;
+pub(crate) const STRIP_BYTE_COUNT: u16 = 279;
+pub(crate) const X_RESOLUTION: u16 = 282;
+pub(crate) const SAMPLE_FORMAT: u16 = 339;
+
+// Field types
+pub(crate) const ASCII: u16 = 2;
+pub(crate) const SHORT: u16 = 3;
+pub(crate) const LONG: u16 = 4;
+pub(crate) const RATIONAL: u16 = 5;
+
+// Sample formats
+pub(crate) const FORMAT_INT: u16 = 2;
+pub(crate) const FORMAT_FLOAT: u16 = 3;
+
+///
+/// Decoder of TIFF files
+///
+/// 8-bit, 16-bit (unsigned) and 32-bit (float) images are read as `ByteStack`, `ShortStack`
+/// and `FloatStack`; signed 16-bit and 32-bit integer images are converted to `FloatStack`.
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::io::image_reader::OutputProcessor;
+/// use rim::io::tiff_decoder::TiffDecoder;
+///
+/// let (stack, info) = TiffDecoder::open("./samples/projections-t1-head-psi-theta-phi-50.tif").unwrap();
+/// if let OutputProcessor::ShortStack(stack) = stack {
+///     println!("{}x{}x{}", stack.get_width(), stack.get_height(), stack.n_slices());
+/// }
+/// ```
+pub struct TiffDecoder {}
+
+impl TiffDecoder {
+    ///
+    /// Opens a TIFF file as an image stack (one slice per image).
+    ///
+    /// The returned FileInfo contains the dimensions and type of the stack, the byte order,
+    /// the description and the pixel size (inverse of the X resolution) with its unit.
+    ///
+    pub fn open(filename: &str) -> io::Result<(OutputProcessor, FileInfo)> {
+        let bytes = fs::read(filename)?;
+        let (stack, mut info) = TiffDecoder::decode(&bytes)?;
+        let path = Path::new(filename);
+        if let Some(name) = path.file_name() {
+            info.file_name = name.to_string_lossy().to_string();
+        }
+        if let Some(dir) = path.parent() {
+            info.directory = dir.to_string_lossy().to_string();
+        }
+        Ok((stack, info))
+    }
+
+    ///
+    /// Decodes the content of a TIFF file.
+    ///
+    pub fn decode(bytes: &[u8]) -> io::Result<(OutputProcessor, FileInfo)> {
+        let little = match bytes.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return Err(invalid("Not a TIFF file")),
+        };
+        let data = Bytes(bytes, little);
+        if data.u16(2)? != 42 {
+            return Err(invalid("Not a TIFF file"));
+        }
+        let mut ifds = Vec::<Ifd>::new();
+        let mut offset = data.u32(4)? as usize;
+        while offset != 0 {
+            if ifds.len() > 100_000 {
+                return Err(invalid("Too many IFDs"));
+            }
+            let (ifd, next) = Ifd::read(&data, offset)?;
+            ifds.push(ifd);
+            offset = next;
+        }
+        let first = ifds
+            .first()
+            .ok_or_else(|| invalid("No image in TIFF file"))?;
+        if first.compression != 1 {
+            return Err(invalid("Compressed TIFF files are not supported"));
+        }
+        if first.samples_per_pixel != 1 {
+            return Err(invalid("Only gray-level TIFF files are supported"));
+        }
+        // Images of the stack: same size and type as the first one (thumbnails are skipped)
+        let images: Vec<&Ifd> = ifds
+            .iter()
+            .filter(|ifd| {
+                ifd.width == first.width
+                    && ifd.height == first.height
+                    && ifd.bits == first.bits
+                    && ifd.format == first.format
+            })
+            .collect();
+
+        let mut info = FileInfo::new();
+        info.file_format = FileInfo::TIFF;
+        info.width = first.width;
+        info.height = first.height;
+        info.intel_byte_order = little;
+        info.description = first.description.clone();
+        info.file_type = match (first.bits, first.format) {
+            (8, _) => FileInfo::GRAY8,
+            (16, FORMAT_INT) => FileInfo::GRAY16_SIGNED,
+            (16, _) => FileInfo::GRAY16_UNSIGNED,
+            (32, FORMAT_FLOAT) => FileInfo::GRAY32_FLOAT,
+            (32, _) => FileInfo::GRAY32_INT,
+            (bits, _) => return Err(invalid(&format!("Unsupported bit depth {}", bits))),
+        };
+        if first.x_resolution > 0.0 {
+            info.pixel_width = 1.0 / first.x_resolution;
+            info.pixel_height = info.pixel_width;
+            info.unit =
+                imagej_property(&first.description, "unit").unwrap_or_else(|| String::from("inch"));
+        }
+        if let Some(spacing) = imagej_property(&first.description, "spacing") {
+            info.pixel_depth = spacing.parse().unwrap_or(info.pixel_depth);
+        }
+
+        // Contiguous slices of the ImageJ stacks with a single IFD
+        if first.width == 0 || first.height == 0 {
+            return Err(invalid("Invalid TIFF dimensions"));
+        }
+        let dimensions = || invalid("Invalid TIFF dimensions");
+        let size = (first.width as usize)
+            .checked_mul(first.height as usize)
+            .and_then(|n| n.checked_mul(info.get_bytes_per_pixel() as usize))
+            .ok_or_else(dimensions)?;
+        let n_images = imagej_property(&first.description, "images")
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(1);
+        let planes: Vec<Vec<u8>> = if images.len() == 1 && n_images > 1 {
+            let start = *first.strip_offsets.first().unwrap_or(&0) as usize;
+            let end = size
+                .checked_mul(n_images)
+                .and_then(|length| length.checked_add(start))
+                .ok_or_else(dimensions)?;
+            let raw = bytes
+                .get(start..end)
+                .ok_or_else(|| invalid("Truncated TIFF data"))?;
+            raw.chunks(size).map(|s| s.to_vec()).collect()
+        } else {
+            images
+                .iter()
+                .map(|ifd| ifd.pixels(bytes, size))
+                .collect::<io::Result<_>>()?
+        };
+        info.n_images = planes.len() as u32;
+
+        let (w, h) = (info.width, info.height);
+        let stack = match info.file_type {
+            FileInfo::GRAY8 => {
+                OutputProcessor::ByteStack(ImageStack::new(w, h, planes, Gray8::new()))
+            }
+            FileInfo::GRAY16_UNSIGNED => OutputProcessor::ShortStack(ImageStack::new(
+                w,
+                h,
+                planes
+                    .iter()
+                    .map(|p| p.chunks(2).map(|b| data.short(b)).collect())
+                    .collect(),
+                Gray16::new(),
+            )),
+            _ => OutputProcessor::FloatStack(ImageStack::new(
+                w,
+                h,
+                planes
+                    .iter()
+                    .map(|p| match info.file_type {
+                        FileInfo::GRAY16_SIGNED => {
+                            p.chunks(2).map(|b| data.short(b) as i16 as f32).collect()
+                        }
+                        FileInfo::GRAY32_INT => {
+                            p.chunks(4).map(|b| data.long(b) as i32 as f32).collect()
+                        }
+                        _ => p.chunks(4).map(|b| f32::from_bits(data.long(b))).collect(),
+                    })
+                    .collect(),
+                Gray32::new(),
+            )),
+        };
+        Ok((stack, info))
+    }
+}
+
+//
+// Private structures and functions
+//
+
+// Image File Directory
+struct Ifd {
+    width: u32,
+    height: u32,
+    bits: u32,
+    format: u16,
+    compression: u32,
+    samples_per_pixel: u32,
+    strip_offsets: Vec<u32>,
+    strip_byte_counts: Vec<u32>,
+    description: String,
+    x_resolution: f64,
+}
+
+impl Ifd {
+    // Reads the IFD at `offset`, returns it with the offset of the next one
+    fn read(data: &Bytes, offset: usize) -> io::Result<(Ifd, usize)> {
+        let count = data.u16(offset)? as usize;
+        let mut ifd = Ifd {
+            width: 0,
+            height: 0,
+            bits: 1,
+            format: 1,
+            compression: 1,
+            samples_per_pixel: 1,
+            strip_offsets: vec![],
+            strip_byte_counts: vec![],
+            description: String::new(),
+            x_resolution: 0.0,
+        };
+        for i in 0..count {
+            let entry = offset + 2 + 12 * i;
+            let tag = data.u16(entry)?;
+            let kind = data.u16(entry + 2)?;
+            let n = data.u32(entry + 4)? as usize;
+            let size = match kind {
+                SHORT => 2,
+                LONG => 4,
+                RATIONAL => 8,
+                _ => 1,
+            };
+            // Values larger than 4 bytes are stored at the given offset
+            let at = if n * size > 4 {
+                data.u32(entry + 8)? as usize
+            } else {
+                entry + 8
+            };
+            let value = |k: usize| -> io::Result<u32> {
+                match kind {
+                    SHORT => Ok(data.u16(at + 2 * k)? as u32),
+                    _ => data.u32(at + 4 * k),
+                }
+            };
+            match tag {
+                IMAGE_WIDTH => ifd.width = value(0)?,
+                IMAGE_LENGTH => ifd.height = value(0)?,
+                BITS_PER_SAMPLE => ifd.bits = value(0)?,
+                COMPRESSION => ifd.compression = value(0)?,
+                SAMPLES_PER_PIXEL => ifd.samples_per_pixel = value(0)?,
+                SAMPLE_FORMAT => ifd.format = value(0)? as u16,
+                STRIP_OFFSETS => {
+                    ifd.strip_offsets = (0..n).map(value).collect::<io::Result<_>>()?
+                }
+                STRIP_BYTE_COUNT => {
+                    ifd.strip_byte_counts = (0..n).map(value).collect::<io::Result<_>>()?
+                }
+                IMAGE_DESCRIPTION if kind == ASCII => {
+                    let text = data
+                        .0
+                        .get(at..at + n)
+                        .ok_or_else(|| invalid("Truncated TIFF file"))?;
+                    ifd.description = String::from_utf8_lossy(text)
+                        .trim_end_matches('\0')
+                        .to_string();
+                }
+                X_RESOLUTION if kind == RATIONAL => {
+                    let (num, den) = (data.u32(at)?, data.u32(at + 4)?);
+                    if den != 0 {
+                        ifd.x_resolution = num as f64 / den as f64;
+                    }
+                }
+                _ => (),
+            }
+        }
+        let next = data.u32(offset + 2 + 12 * count)? as usize;
+        Ok((ifd, next))
+    }
+
+    // Concatenates the strips of the image (of `size` bytes)
+    fn pixels(&self, bytes: &[u8], size: usize) -> io::Result<Vec<u8>> {
+        let mut pixels = Vec::<u8>::with_capacity(size);
+        for (i, offset) in self.strip_offsets.iter().enumerate() {
+            let length = self
+                .strip_byte_counts
+                .get(i)
+                .map_or(size - pixels.len(), |n| *n as usize)
+                .min(size - pixels.len());
+            let start = *offset as usize;
+            pixels.extend_from_slice(
+                start
+                    .checked_add(length)
+                    .and_then(|end| bytes.get(start..end))
+                    .ok_or_else(|| invalid("Truncated TIFF data"))?,
+            );
+        }
+        if pixels.len() != size {
+            return Err(invalid("Truncated TIFF data"));
+        }
+        Ok(pixels)
+    }
+}
+
+// Value of `key=value` in an ImageJ description
+fn imagej_property(description: &str, key: &str) -> Option<String> {
+    description.lines().find_map(|line| {
+        line.split_once('=')
+            .filter(|(k, _)| k.trim() == key)
+            .map(|(_, v)| v.trim().to_string())
+    })
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Reader with bounds checking and byte order (`true` for little-endian)
+struct Bytes<'a>(&'a [u8], bool);
+
+impl<'a> Bytes<'a> {
+    fn get<const N: usize>(&self, offset: usize) -> io::Result<[u8; N]> {
+        self.0
+            .get(offset..offset + N)
+            .map(|s| s.try_into().unwrap())
+            .ok_or_else(|| invalid("Truncated TIFF file"))
+    }
+    fn u16(&self, offset: usize) -> io::Result<u16> {
+        Ok(self.short(&self.get::<2>(offset)?))
+    }
+    fn u32(&self, offset: usize) -> io::Result<u32> {
+        Ok(self.long(&self.get::<4>(offset)?))
+    }
+    fn short(&self, b: &[u8]) -> u16 {
+        if self.1 {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        }
+    }
+    fn long(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.1 {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_imagej_projections() {
+        let (stack, info) =
+            TiffDecoder::open("./samples/projections-t1-head-psi-theta-phi-50.tif").unwrap();
+        assert_eq!((info.width, info.height, info.n_images), (128, 128, 50));
+        assert_eq!(info.file_type, FileInfo::GRAY16_UNSIGNED);
+        assert!(!info.intel_byte_order);
+        match stack {
+            OutputProcessor::ShortStack(s) => {
+                assert_eq!(s.n_slices(), 50);
+                assert!(s.data.iter().all(|p| p.len() == 128 * 128));
+                assert!(s.data[0].iter().any(|v| *v > 0));
+            }
+            _ => panic!("Wrong type"),
+        }
+    }
+
+    // Little-endian TIFF with one IFD of (tag, type, value) entries and an ImageJ description
+    fn tiff(entries: &[(u16, u16, u32)], description: &str) -> Vec<u8> {
+        let mut bytes = b"II".to_vec();
+        bytes.extend_from_slice(&42u16.to_le_bytes());
+        bytes.extend_from_slice(&8u32.to_le_bytes());
+        let n = entries.len() + 1;
+        bytes.extend_from_slice(&(n as u16).to_le_bytes());
+        let text = 8 + 2 + 12 * n as u32 + 4;
+        let mut entries = entries.to_vec();
+        entries.push((IMAGE_DESCRIPTION, ASCII, text));
+        for (tag, kind, value) in entries.iter() {
+            bytes.extend_from_slice(&tag.to_le_bytes());
+            bytes.extend_from_slice(&kind.to_le_bytes());
+            let count = if *tag == IMAGE_DESCRIPTION {
+                description.len() as u32 + 1
+            } else {
+                1
+            };
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(description.as_bytes());
+        bytes.extend_from_slice(&[0; 65]);
+        bytes
+    }
+
+    #[test]
+    fn malformed_header() {
+        let huge = [
+            (IMAGE_WIDTH, LONG, 70000),
+            (IMAGE_LENGTH, LONG, 70000),
+            (BITS_PER_SAMPLE, SHORT, 8),
+        ];
+        assert!(TiffDecoder::decode(&tiff(&huge, "ImageJ=1.53\nimages=70000\n")).is_err());
+        assert!(TiffDecoder::decode(&tiff(&huge, "")).is_err());
+        // Missing height
+        let flat = [(IMAGE_WIDTH, LONG, 8), (BITS_PER_SAMPLE, SHORT, 8)];
+        assert!(TiffDecoder::decode(&tiff(&flat, "ImageJ=1.53\nimages=3\n")).is_err());
+        // Valid 8x8 image of the padding bytes
+        let image = [
+            (IMAGE_WIDTH, LONG, 8),
+            (IMAGE_LENGTH, LONG, 8),
+            (BITS_PER_SAMPLE, SHORT, 8),
+            (STRIP_OFFSETS, LONG, 8),
+        ];
+        let (_, info) = TiffDecoder::decode(&tiff(&image, "")).unwrap();
+        assert_eq!((info.width, info.height, info.n_images), (8, 8, 1));
+    }
+}
@@ -0,0 +1,189 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Writer of uncompressed gray-level TIFF files readable by ImageJ (big-endian,
+//! one IFD per slice).
+//!
+
+use crate::io::image_reader::OutputProcessor;
+use crate::io::tiff_decoder::*;
+use std::fs;
+use std::io;
+
+// Size of an IFD with 12 entries
+const IFD_SIZE: usize = 2 + 12 * 12 + 4;
+
+///
+/// Encoder of TIFF files
+///
+/// Stacks and volumes are saved with one image per slice and the ImageJ description
+/// `images=<n>`.
+///
+/// # Example
+///
+/// ```no_run
+/// use rim::io::tiff_decoder::TiffDecoder;
+/// use rim::io::tiff_encoder::TiffEncoder;
+///
+/// let (stack, _) = TiffDecoder::open("./samples/projections-t1-head-psi-theta-phi-50.tif").unwrap();
+/// TiffEncoder::save(&stack, "./copy.tif").unwrap();
+/// ```
+pub struct TiffEncoder {}
+
+impl TiffEncoder {
+    /// Saves `image` in a TIFF file.
+    pub fn save(image: &OutputProcessor, filename: &str) -> io::Result<()> {
+        fs::write(filename, TiffEncoder::encode(image)?)
+    }
+
+    ///
+    /// Encodes `image` in the TIFF format.
+    ///
+    pub fn encode(image: &OutputProcessor) -> io::Result<Vec<u8>> {
+        let be16 = |v: &u16| v.to_be_bytes().to_vec();
+        let be32 = |v: &f32| v.to_be_bytes().to_vec();
+        let (width, height, bits, pixels): (u32, u32, u16, Vec<u8>) = match image {
+            OutputProcessor::ByteProcessor(ip) => (ip.width, ip.height, 8, ip.data.clone()),
+            OutputProcessor::ShortProcessor(ip) => (
+                ip.width,
+                ip.height,
+                16,
+                ip.data.iter().flat_map(be16).collect(),
+            ),
+            OutputProcessor::FloatProcessor(ip) => (
+                ip.width,
+                ip.height,
+                32,
+                ip.data.iter().flat_map(be32).collect(),
+            ),
+            OutputProcessor::ByteStack(s) => (s.width, s.height, 8, s.data.concat()),
+            OutputProcessor::ShortStack(s) => (
+                s.width,
+                s.height,
+                16,
+                s.data.iter().flatten().flat_map(be16).collect(),
+            ),
+            OutputProcessor::FloatStack(s) => (
+                s.width,
+                s.height,
+                32,
+                s.data.iter().flatten().flat_map(be32).collect(),
+            ),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Unsupported image type for TIFF",
+                ))
+            }
+        };
+        let size = (width * height) as usize * bits as usize / 8;
+        if size == 0 || pixels.len() % size != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Inconsistent image dimensions",
+            ));
+        }
+        let n = pixels.len() / size;
+
+        // Header, description, pixels then IFDs
+        let mut description = format!("ImageJ=1.53t\nimages={}\nslices={}\n", n, n).into_bytes();
+        description.push(0);
+        let description_offset = 8;
+        let pixels_offset = description_offset + description.len();
+        let ifds_offset = pixels_offset + pixels.len();
+        let mut out = Vec::<u8>::with_capacity(ifds_offset + n * IFD_SIZE);
+        out.extend(b"MM\0\x2a");
+        out.extend((ifds_offset as u32).to_be_bytes());
+        out.extend(&description);
+        out.extend(&pixels);
+        for i in 0..n {
+            let entries: [(u16, u16, u32, u32); 12] = [
+                (NEW_SUBFILE_TYPE, LONG, 1, 0),
+                (IMAGE_WIDTH, LONG, 1, width),
+                (IMAGE_LENGTH, LONG, 1, height),
+                (BITS_PER_SAMPLE, SHORT, 1, bits as u32),
+                (COMPRESSION, SHORT, 1, 1),
+                // Black is zero
+                (PHOTO_INTERP, SHORT, 1, 1),
+                (
+                    IMAGE_DESCRIPTION,
+                    ASCII,
+                    description.len() as u32,
+                    description_offset as u32,
+                ),
+                (STRIP_OFFSETS, LONG, 1, (pixels_offset + i * size) as u32),
+                (SAMPLES_PER_PIXEL, SHORT, 1, 1),
+                (ROWS_PER_STRIP, LONG, 1, height),
+                (STRIP_BYTE_COUNT, LONG, 1, size as u32),
+                (
+                    SAMPLE_FORMAT,
+                    SHORT,
+                    1,
+                    if bits == 32 { FORMAT_FLOAT as u32 } else { 1 },
+                ),
+            ];
+            out.extend((entries.len() as u16).to_be_bytes());
+            for (tag, kind, count, value) in entries.iter() {
+                out.extend(tag.to_be_bytes());
+                out.extend(kind.to_be_bytes());
+                out.extend(count.to_be_bytes());
+                // Short values are left-justified
+                if *kind == SHORT {
+                    out.extend((*value as u16).to_be_bytes());
+                    out.extend([0u8, 0u8]);
+                } else {
+                    out.extend(value.to_be_bytes());
+                }
+            }
+            let next = if i + 1 < n {
+                ifds_offset + (i + 1) * IFD_SIZE
+            } else {
+                0
+            };
+            out.extend((next as u32).to_be_bytes());
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_space::ColorSpace;
+    use crate::grayscale::Gray32;
+    use crate::image_stack::ImageStack;
+    use crate::io::file_info::FileInfo;
+
+    #[test]
+    fn float_stack_round_trip() {
+        let data: Vec<Vec<f32>> = (0..4)
+            .map(|z| (0..15).map(|i| (i as f32 - 7.0) * z as f32).collect())
+            .collect();
+        let stack = OutputProcessor::FloatStack(ImageStack::new(5, 3, data.clone(), Gray32::new()));
+        let bytes = TiffEncoder::encode(&stack).unwrap();
+        let (decoded, info) = TiffDecoder::decode(&bytes).unwrap();
+        assert_eq!((info.width, info.height, info.n_images), (5, 3, 4));
+        assert_eq!(info.file_type, FileInfo::GRAY32_FLOAT);
+        match decoded {
+            OutputProcessor::FloatStack(s) => assert_eq!(s.data, data),
+            _ => panic!("Wrong type"),
+        }
+    }
+}
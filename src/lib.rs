@@ -4,6 +4,7 @@ pub mod grayscale;
 pub mod image_processor;
 pub mod io;
 pub mod meta_data;
+pub mod roi;
 pub mod pixel;
 
 // ImageProcessor compatible with ImageJ
@@ -18,11 +19,23 @@ pub mod short_processor;
 pub mod results_table;
 
 // traits
+pub mod filter;
+pub mod local_threshold;
 pub mod operator;
+pub mod rank_filters;
 pub mod statistics;
+pub mod threshold;
+pub mod transformable;
+pub mod volume_processor;
 
 // Cryoem
 pub mod cryoem;
 
+// Fourier
+pub mod complex_image;
+pub mod fft;
+pub mod fft_filter;
+pub mod registration;
+
 // Vecmath
 pub mod vecmath;
@@ -0,0 +1,294 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::color_space::ColorSpace;
+use crate::grayscale::Gray8;
+use crate::image_processor::ImageProcessor;
+use crate::rank_filters::{line_radii, rank_plane, RankType};
+use crate::threshold::{get_threshold, Method};
+
+///
+/// Methods of the ImageJ "Auto Local Threshold" plugin (G. Landini) with their parameters.
+///
+/// The default values of the plugin are given by [LocalMethod::default_of()].
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LocalMethod {
+    /// Local contrast threshold (default 15). Below it, the pixels belong to the class of the mid-grey.
+    Bernsen { contrast: f64 },
+    /// Pixel closest to the local max (object) or to the local min (background).
+    Contrast,
+    /// Threshold is `mean - c`.
+    Mean { c: f64 },
+    /// Threshold is `median - c`.
+    Median { c: f64 },
+    /// Threshold is `(max + min) / 2 - c`.
+    MidGrey { c: f64 },
+    /// Threshold is `mean + k * std_dev - c` (defaults k = 0.2, c = 0).
+    Niblack { k: f64, c: f64 },
+    /// Otsu threshold of the local histogram.
+    Otsu,
+    /// Sauvola variant for low contrast images (defaults k = 0.25, r = 0.5, on normalized values).
+    Phansalkar { k: f64, r: f64 },
+    /// Threshold is `mean * (1 + k * (std_dev / r - 1))` (defaults k = 0.5, r = 128).
+    Sauvola { k: f64, r: f64 },
+}
+
+impl LocalMethod {
+    /// Returns `method` with the default parameters of the ImageJ plugin.
+    pub fn default_of(method: LocalMethod) -> LocalMethod {
+        match method {
+            LocalMethod::Bernsen { .. } => LocalMethod::Bernsen { contrast: 15.0 },
+            LocalMethod::Mean { .. } => LocalMethod::Mean { c: 0.0 },
+            LocalMethod::Median { .. } => LocalMethod::Median { c: 0.0 },
+            LocalMethod::MidGrey { .. } => LocalMethod::MidGrey { c: 0.0 },
+            LocalMethod::Niblack { .. } => LocalMethod::Niblack { k: 0.2, c: 0.0 },
+            LocalMethod::Phansalkar { .. } => LocalMethod::Phansalkar { k: 0.25, r: 0.5 },
+            LocalMethod::Sauvola { .. } => LocalMethod::Sauvola { k: 0.5, r: 128.0 },
+            other => other,
+        }
+    }
+}
+
+///
+/// Local (adaptive) thresholding of 8-bit images.
+///
+/// A threshold is computed for each pixel from the pixels of a circular window of
+/// the given radius (same kernel as [RankFilters](crate::rank_filters::RankFilters)).
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::grayscale::Gray8;
+/// use rim::image_processor::ImageProcessor;
+/// use rim::local_threshold::{LocalMethod, LocalThreshold};
+///
+/// let ip = ImageProcessor::<u8, Gray8>::new(3, 1, vec![10, 200, 10], Gray8::new());
+/// let binary = ip.local_threshold(LocalMethod::Mean { c: 0.0 }, 1.0, true);
+/// assert_eq!(*binary.data(), vec![0, 255, 0]);
+/// ```
+pub trait LocalThreshold {
+    ///
+    /// Returns a binary image where the objects are set to 255.
+    ///
+    /// With `dark_background`, the objects are the pixels brighter than the local threshold,
+    /// otherwise the darker ones.
+    ///
+    fn local_threshold(
+        &self,
+        method: LocalMethod,
+        radius: f64,
+        dark_background: bool,
+    ) -> ImageProcessor<u8, Gray8>;
+}
+
+impl LocalThreshold for ImageProcessor<u8, Gray8> {
+    fn local_threshold(
+        &self,
+        method: LocalMethod,
+        radius: f64,
+        dark_background: bool,
+    ) -> ImageProcessor<u8, Gray8> {
+        let w = self.get_width() as usize;
+        let h = self.get_height() as usize;
+        let size = w * h;
+        let mut out = Vec::<u8>::with_capacity(self.data.len());
+        for plane in 0..self.depth as usize {
+            let pixels: Vec<f32> = self.data[plane * size..(plane + 1) * size]
+                .iter()
+                .map(|v| *v as f32)
+                .collect();
+            let rank = |kind: RankType| rank_plane(&pixels, w, h, radius, kind, None);
+            let bright: Vec<bool> = match method {
+                LocalMethod::Bernsen { contrast } => {
+                    let (min, max) = (rank(RankType::Min), rank(RankType::Max));
+                    (0..size)
+                        .map(|i| {
+                            let mid_gray = (min[i] + max[i]) as f64 / 2.0;
+                            if ((max[i] - min[i]) as f64) < contrast {
+                                mid_gray >= 128.0
+                            } else {
+                                pixels[i] as f64 >= mid_gray
+                            }
+                        })
+                        .collect()
+                }
+                LocalMethod::Contrast => {
+                    let (min, max) = (rank(RankType::Min), rank(RankType::Max));
+                    (0..size)
+                        .map(|i| (max[i] - pixels[i]).abs() <= (pixels[i] - min[i]).abs())
+                        .collect()
+                }
+                LocalMethod::Mean { c } => {
+                    let mean = rank(RankType::Mean);
+                    (0..size)
+                        .map(|i| pixels[i] as f64 > mean[i] as f64 - c)
+                        .collect()
+                }
+                LocalMethod::Median { c } => {
+                    let median = rank(RankType::Median);
+                    (0..size)
+                        .map(|i| pixels[i] as f64 > median[i] as f64 - c)
+                        .collect()
+                }
+                LocalMethod::MidGrey { c } => {
+                    let (min, max) = (rank(RankType::Min), rank(RankType::Max));
+                    (0..size)
+                        .map(|i| pixels[i] as f64 > (min[i] + max[i]) as f64 / 2.0 - c)
+                        .collect()
+                }
+                LocalMethod::Niblack { k, c } => {
+                    let (mean, var) = (rank(RankType::Mean), rank(RankType::Variance));
+                    (0..size)
+                        .map(|i| {
+                            let t = mean[i] as f64 + k * (var[i] as f64).sqrt() - c;
+                            pixels[i] as f64 > t
+                        })
+                        .collect()
+                }
+                LocalMethod::Otsu => local_otsu(&pixels, w, h, radius),
+                LocalMethod::Phansalkar { k, r } => {
+                    // Computed on the image normalized between 0 and 1
+                    let (p, q) = (2.0, 10.0);
+                    let normalized: Vec<f32> = pixels.iter().map(|v| v / 255.0).collect();
+                    let mean = rank_plane(&normalized, w, h, radius, RankType::Mean, None);
+                    let var = rank_plane(&normalized, w, h, radius, RankType::Variance, None);
+                    (0..size)
+                        .map(|i| {
+                            let (m, sd) = (mean[i] as f64, (var[i] as f64).sqrt());
+                            let t = m * (1.0 + p * (-q * m).exp() + k * (sd / r - 1.0));
+                            normalized[i] as f64 > t
+                        })
+                        .collect()
+                }
+                LocalMethod::Sauvola { k, r } => {
+                    let (mean, var) = (rank(RankType::Mean), rank(RankType::Variance));
+                    (0..size)
+                        .map(|i| {
+                            let sd = (var[i] as f64).sqrt();
+                            pixels[i] as f64 > mean[i] as f64 * (1.0 + k * (sd / r - 1.0))
+                        })
+                        .collect()
+                }
+            };
+            out.extend(
+                bright
+                    .iter()
+                    .map(|b| if *b == dark_background { 255 } else { 0 }),
+            );
+        }
+        ImageProcessor::new_volume(self.width, self.height, self.depth, out, Gray8::new())
+    }
+}
+
+//
+// Private function: Otsu threshold of the histogram of the circular window of each pixel
+//
+fn local_otsu(pixels: &[f32], w: usize, h: usize, radius: f64) -> Vec<bool> {
+    let lines = line_radii(radius);
+    let at = |x: i64, y: i64| -> usize {
+        let xx = x.clamp(0, w as i64 - 1) as usize;
+        let yy = y.clamp(0, h as i64 - 1) as usize;
+        pixels[xx + yy * w] as usize
+    };
+    let mut hist = vec![0u32; 256];
+    let mut bright = Vec::<bool>::with_capacity(w * h);
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            hist.iter_mut().for_each(|v| *v = 0);
+            for (dy, dx) in lines.iter() {
+                for u in -dx..=*dx {
+                    hist[at(x + u, y + dy)] += 1;
+                }
+            }
+            let threshold = get_threshold(Method::Otsu, &mut hist);
+            bright.push(at(x, y) as i32 > threshold);
+        }
+    }
+    bright
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // Two discs on a background with a horizontal illumination gradient
+    fn uneven() -> ImageProcessor<u8, Gray8> {
+        let (w, h) = (40, 20);
+        let mut pixels = vec![0u8; w * h];
+        for y in 0..h {
+            for x in 0..w {
+                let background = 20.0 + 4.0 * x as f64;
+                let d1 = ((x as f64 - 8.0).powi(2) + (y as f64 - 10.0).powi(2)).sqrt();
+                let d2 = ((x as f64 - 32.0).powi(2) + (y as f64 - 10.0).powi(2)).sqrt();
+                let v = if d1 < 4.0 || d2 < 4.0 {
+                    background + 60.0
+                } else {
+                    background
+                };
+                pixels[x + y * w] = v.min(255.0) as u8;
+            }
+        }
+        ImageProcessor::new(w as u32, h as u32, pixels, Gray8::new())
+    }
+
+    #[test]
+    fn centers_of_discs_are_objects() {
+        let ip = uneven();
+        let methods = [
+            LocalMethod::default_of(LocalMethod::Bernsen { contrast: 0.0 }),
+            LocalMethod::Contrast,
+            LocalMethod::Mean { c: 0.0 },
+            LocalMethod::Median { c: 0.0 },
+            LocalMethod::MidGrey { c: 0.0 },
+            LocalMethod::default_of(LocalMethod::Niblack { k: 0.0, c: 0.0 }),
+            LocalMethod::Otsu,
+            LocalMethod::default_of(LocalMethod::Sauvola { k: 0.0, r: 0.0 }),
+            LocalMethod::default_of(LocalMethod::Phansalkar { k: 0.0, r: 0.0 }),
+        ];
+        for method in methods {
+            let binary = ip.local_threshold(method, 8.0, true);
+            // Centers of the discs
+            assert_eq!(binary.data()[8 + 10 * 40], 255, "{:?}", method);
+            assert_eq!(binary.data()[32 + 10 * 40], 255, "{:?}", method);
+            // Background next to the brightest disc
+            match method {
+                // Designed for dark objects on a bright background
+                LocalMethod::Sauvola { .. } | LocalMethod::Phansalkar { .. } => (),
+                _ => assert_eq!(binary.data()[26 + 10 * 40], 0, "{:?}", method),
+            }
+        }
+    }
+
+    #[test]
+    fn dark_objects() {
+        let ip = ImageProcessor::<u8, Gray8>::new(5, 1, vec![200, 200, 10, 200, 200], Gray8::new());
+        let binary = ip.local_threshold(LocalMethod::MidGrey { c: 0.0 }, 2.0, false);
+        assert_eq!(*binary.data(), vec![0, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn bernsen_low_contrast_uses_mid_grey() {
+        let ip = ImageProcessor::<u8, Gray8>::new(3, 1, vec![140, 145, 150], Gray8::new());
+        let binary = ip.local_threshold(LocalMethod::Bernsen { contrast: 15.0 }, 1.0, true);
+        assert_eq!(*binary.data(), vec![255, 255, 255]);
+    }
+}
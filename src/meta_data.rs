@@ -20,35 +20,8 @@
 #![allow(non_camel_case_types)]
 #![allow(unused)]
 
-pub struct Roi {
-    x: u32,
-    y: u32,
-    width: u32,
-    height: u32,
-}
-
-impl Roi {
-    // Accessors
-    pub fn x(&self) -> u32 {
-        self.x
-    }
-    pub fn y(&self) -> u32 {
-        self.y
-    }
-    pub fn width(&self) -> u32 {
-        self.width
-    }
-    pub fn height(&self) -> u32 {
-        self.height
-    }
-    // Compatibility for ImageJ
-    pub fn get_width(&self) -> u32 {
-        self.width
-    }
-    pub fn get_height(&self) -> u32 {
-        self.height
-    }
-}
+use crate::roi::{Rectangle, Roi, MASK_INSIDE};
+use crate::transformable::InterpolationMode;
 
 ///
 /// Storage of statistics
@@ -80,20 +53,25 @@ impl Statistics {
 ///
 ///
 pub struct MetaData {
-    pub roi: Roi,
+    pub roi: Option<Roi>,
     pub stats: Statistics,
+    // Image size used to clip the ROI
+    width: u32,
+    height: u32,
+    // Bounding box of the ROI clipped to the image
+    roi_rect: Rectangle,
+    // Mask (same size as `roi_rect`) for non-rectangular ROIs
+    mask: Option<Vec<u8>>,
+    // Settings of the geometric transforms
+    interpolation: InterpolationMode,
+    background: f64,
 }
 
 impl MetaData {
     pub fn new(w: u32, h: u32) -> Self {
         let n_buckets: usize = 256;
         MetaData {
-            roi: Roi {
-                x: 0,
-                y: 0,
-                width: w,
-                height: h,
-            },
+            roi: None,
             stats: Statistics {
                 is_dirty: true,
                 min: 0.0,
@@ -103,9 +81,113 @@ impl MetaData {
                 n_buckets,
                 histogram: vec![0u32; n_buckets],
             },
+            width: w,
+            height: h,
+            roi_rect: Rectangle::new(0, 0, w as i32, h as i32),
+            mask: None,
+            interpolation: InterpolationMode::Bilinear,
+            background: 0.0,
         }
     }
 
+    ///
+    /// Sets the active ROI. The subsequent operators, filters and statistics are
+    /// restricted to the pixels of this ROI.
+    ///
+    pub fn set_roi(&mut self, roi: Roi) {
+        let image = Rectangle::new(0, 0, self.width as i32, self.height as i32);
+        self.roi_rect = roi.bounds().intersection(&image);
+        // A rectangle on the pixel grid covers its whole bounding box
+        let on_grid = |values: [f64; 4]| values.iter().all(|v| v.fract() == 0.0);
+        self.mask = match roi {
+            Roi::Rectangle {
+                x,
+                y,
+                width,
+                height,
+            } if on_grid([x, y, width, height]) => None,
+            // Only the part of the ROI inside the image is rasterized
+            _ => Some(roi.mask_within(&self.roi_rect)),
+        };
+        self.roi = Some(roi);
+        self.stats.is_dirty = true;
+    }
+
+    /// Removes the active ROI, the whole image is processed.
+    pub fn reset_roi(&mut self) {
+        self.roi = None;
+        self.roi_rect = Rectangle::new(0, 0, self.width as i32, self.height as i32);
+        self.mask = None;
+        self.stats.is_dirty = true;
+    }
+
+    pub fn get_roi(&self) -> Option<&Roi> {
+        self.roi.as_ref()
+    }
+
+    /// Returns the bounding box of the ROI clipped to the image, or the whole image without ROI.
+    pub fn get_roi_rect(&self) -> Rectangle {
+        self.roi_rect
+    }
+
+    /// Returns the mask of a non-rectangular ROI, with the size of [get_roi_rect()](MetaData::get_roi_rect).
+    pub fn get_mask(&self) -> Option<&Vec<u8>> {
+        self.mask.as_ref()
+    }
+
+    ///
+    /// Returns `true` if the pixel (x,y) belongs to the active ROI (always `true` without ROI).
+    ///
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        let r = self.roi_rect;
+        if !r.contains(x as i32, y as i32) {
+            return false;
+        }
+        match &self.mask {
+            Some(mask) => {
+                mask[(x as i32 - r.x + (y as i32 - r.y) * r.width) as usize] == MASK_INSIDE
+            }
+            None => true,
+        }
+    }
+
+    ///
+    /// Returns the indices (in a plane) of the pixels belonging to the active ROI.
+    ///
+    pub fn roi_indices(&self) -> Vec<usize> {
+        let r = self.roi_rect;
+        let mut indices = Vec::<usize>::with_capacity((r.width.max(0) * r.height.max(0)) as usize);
+        for y in r.y..r.y + r.height {
+            for x in r.x..r.x + r.width {
+                if self.contains(x as u32, y as u32) {
+                    indices.push((x + y * self.width as i32) as usize);
+                }
+            }
+        }
+        indices
+    }
+
+    /// Interpolation method used by the geometric transforms (bilinear by default).
+    pub fn get_interpolation(&self) -> InterpolationMode {
+        self.interpolation
+    }
+    pub fn set_interpolation(&mut self, method: InterpolationMode) {
+        self.interpolation = method;
+    }
+
+    /// Value of the pixels uncovered by the geometric transforms (0 by default).
+    pub fn get_background(&self) -> f64 {
+        self.background
+    }
+    pub fn set_background(&mut self, value: f64) {
+        self.background = value;
+    }
+
+    /// Flags the statistics as obsolete after a modification of the pixels.
+    pub fn set_dirty(&mut self) {
+        self.stats.is_dirty = true;
+    }
+
     pub fn get_min(&self) -> f64 {
         self.stats.min
     }
@@ -147,6 +229,10 @@ impl MetaData {
     pub fn set_stats(&mut self, hist: &Vec<u32>, mi: f64, mx: f64, mean: f64, stddev: f64) {
         self.stats.histogram = hist.to_vec();
         self.stats.n_buckets = hist.len();
+        self.stats.min = mi;
+        self.stats.max = mx;
+        self.stats.mean = mean;
+        self.stats.std_dev = stddev;
         self.stats.is_dirty = false;
     }
 }
@@ -195,4 +281,40 @@ mod tests {
         meta.set_histogram(&hist);
         assert_eq!(meta.get_histogram_max(), 255);
     }
+
+    #[test]
+    fn roi_clipped_to_image() {
+        let mut meta = MetaData::new(5, 5);
+        meta.set_roi(Roi::rectangle(3.0, -1.0, 4.0, 3.0));
+        assert_eq!(meta.get_roi_rect(), Rectangle::new(3, 0, 2, 2));
+        assert!(meta.contains(4, 1));
+        assert!(!meta.contains(2, 1));
+        assert_eq!(meta.roi_indices(), vec![3, 4, 8, 9]);
+        meta.reset_roi();
+        assert_eq!(meta.roi_indices().len(), 25);
+    }
+
+    #[test]
+    fn roi_mask_cropped() {
+        let mut meta = MetaData::new(4, 4);
+        meta.set_roi(Roi::oval(-2.0, -2.0, 4.0, 4.0));
+        assert_eq!(meta.get_roi_rect(), Rectangle::new(0, 0, 2, 2));
+        assert_eq!(meta.get_mask().unwrap().len(), 4);
+        assert!(meta.contains(0, 0));
+        assert!(!meta.contains(1, 1));
+    }
+
+    #[test]
+    fn roi_fractional_rectangle() {
+        let mut meta = MetaData::new(4, 4);
+        meta.set_roi(Roi::rectangle(0.0, 0.0, 2.0, 2.0));
+        assert!(meta.get_mask().is_none());
+        // Only the pixels whose center is inside belong to the ROI
+        meta.set_roi(Roi::rectangle(0.6, 0.6, 2.0, 2.0));
+        assert_eq!(meta.get_roi_rect(), Rectangle::new(0, 0, 3, 3));
+        assert!(!meta.contains(0, 1));
+        assert!(meta.contains(1, 1));
+        assert!(meta.contains(2, 2));
+        assert_eq!(meta.roi_indices(), vec![5, 6, 9, 10]);
+    }
 }
@@ -27,6 +27,8 @@ pub trait PixelType {
     fn to_f32(&self) -> f32;
     fn to_value(&self) -> Self;
     fn clamp_pixel(v: f32) -> Self;
+    /// Rounds (integer types only) then clamps a computed value.
+    fn round_pixel(v: f32) -> Self;
 }
 
 // u8
@@ -50,6 +52,9 @@ impl PixelType for u8 {
             v as u8
         }
     }
+    fn round_pixel(v: f32) -> u8 {
+        Self::clamp_pixel(v + 0.5)
+    }
 }
 
 // u16
@@ -67,12 +72,15 @@ impl PixelType for u16 {
     fn clamp_pixel(v: f32) -> u16 {
         if v < u16::min_value() as f32 {
             u16::min_value()
-        } else if v > u8::max_value() as f32 {
+        } else if v > u16::max_value() as f32 {
             u16::max_value()
         } else {
             v as u16
         }
     }
+    fn round_pixel(v: f32) -> u16 {
+        Self::clamp_pixel(v + 0.5)
+    }
 }
 
 // u32
@@ -90,12 +98,15 @@ impl PixelType for u32 {
     fn clamp_pixel(v: f32) -> u32 {
         if v < u32::min_value() as f32 {
             u32::min_value()
-        } else if v > u8::max_value() as f32 {
+        } else if v > u32::max_value() as f32 {
             u32::max_value()
         } else {
             v as u32
         }
     }
+    fn round_pixel(v: f32) -> u32 {
+        Self::clamp_pixel(v + 0.5)
+    }
 }
 
 // f32
@@ -114,6 +125,9 @@ impl PixelType for f32 {
         // No clamping is done, TODO?
         v
     }
+    fn round_pixel(v: f32) -> f32 {
+        v
+    }
 }
 
 // f64
@@ -134,4 +148,7 @@ impl PixelType for f64 {
         // No clamping is done
         v as f64
     }
+    fn round_pixel(v: f32) -> f64 {
+        v as f64
+    }
 }
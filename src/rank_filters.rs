@@ -0,0 +1,348 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::grayscale::Gray;
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::pixel::PixelType;
+
+///
+/// Rank filter types available in [rank()](RankFilters::rank).
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RankType {
+    Mean,
+    Min,
+    Max,
+    /// Population variance of the neighborhood.
+    Variance,
+    Median,
+    /// Median filter of radius 1.
+    Despeckle,
+}
+
+///
+/// Rank filters with a circular kernel, compatible with ImageJ `RankFilters`.
+///
+/// The kernel of a given (float) radius is made of the pixels whose distance to the
+/// center is less or equal to the radius. Out-of-image pixels take the value of the
+/// nearest edge pixel.
+///
+/// For 8- and 16-bit images, median, min and max are computed with a sliding histogram.
+/// For an ImageProcessor, only the pixels of the active ROI are modified.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::rank_filters::{RankFilters, RankType};
+/// // Remove hot pixels of a detector frame
+/// // ip.rank(3.0, RankType::Median);
+/// ```
+pub trait RankFilters<T: PixelType> {
+    /// Filters the image (or each slice of a stack) with a circular kernel of the given `radius`.
+    fn rank(&mut self, radius: f64, kind: RankType);
+
+    /// A median filter of radius 1.
+    fn despeckle(&mut self) {
+        self.rank(1.0, RankType::Despeckle);
+    }
+}
+
+///
+/// Returns the kernel of a given radius as pairs of (dy, dx) where each
+/// line dy of the kernel spans from -dx to +dx (ImageJ `makeLineRadii`).
+///
+pub fn line_radii(radius: f64) -> Vec<(i64, i64)> {
+    let radius = if (1.5..1.75).contains(&radius) {
+        1.75
+    } else if (2.5..2.85).contains(&radius) {
+        2.85
+    } else {
+        radius
+    };
+    let r2 = (radius * radius) as i64 + 1;
+    let k_radius = ((r2 as f64 + 1e-10).sqrt()) as i64;
+    (-k_radius..=k_radius)
+        .map(|dy| (dy, (((r2 - dy * dy) as f64 + 1e-10).sqrt()) as i64))
+        .collect()
+}
+
+//
+// Private function: filter one plane.
+// `bins` is the histogram size for integer pixels, `None` for floating-point pixels.
+//
+pub(crate) fn rank_plane<T: PixelType>(
+    src: &[T],
+    w: usize,
+    h: usize,
+    radius: f64,
+    kind: RankType,
+    bins: Option<usize>,
+) -> Vec<T> {
+    let (radius, kind) = match kind {
+        RankType::Despeckle => (1.0, RankType::Median),
+        _ => (radius, kind),
+    };
+    let lines = line_radii(radius);
+    let count: usize = lines.iter().map(|(_, dx)| (2 * dx + 1) as usize).sum();
+    let pixels: Vec<f32> = src.iter().map(|v| v.to_f32()).collect();
+    let at = |x: i64, y: i64| -> f32 {
+        let xx = x.clamp(0, w as i64 - 1) as usize;
+        let yy = y.clamp(0, h as i64 - 1) as usize;
+        pixels[xx + yy * w]
+    };
+    let mut out = Vec::<T>::with_capacity(w * h);
+    match (kind, bins) {
+        (RankType::Mean, _) | (RankType::Variance, _) => {
+            for y in 0..h as i64 {
+                let mut sum = 0.0f64;
+                let mut sum2 = 0.0f64;
+                for (dy, dx) in lines.iter() {
+                    for u in -dx..=*dx {
+                        let v = at(u, y + dy) as f64;
+                        sum += v;
+                        sum2 += v * v;
+                    }
+                }
+                for x in 0..w as i64 {
+                    if x > 0 {
+                        // Slide the kernel: remove the left pixels, add the right ones
+                        for (dy, dx) in lines.iter() {
+                            let old = at(x - dx - 1, y + dy) as f64;
+                            let new = at(x + dx, y + dy) as f64;
+                            sum += new - old;
+                            sum2 += new * new - old * old;
+                        }
+                    }
+                    let mean = sum / count as f64;
+                    let value = if kind == RankType::Mean {
+                        mean
+                    } else {
+                        (sum2 / count as f64 - mean * mean).max(0.0)
+                    };
+                    out.push(T::round_pixel(value as f32));
+                }
+            }
+        }
+        (_, Some(nbins)) => {
+            let mut hist = SlidingHistogram::new(nbins);
+            for y in 0..h as i64 {
+                hist.clear();
+                for (dy, dx) in lines.iter() {
+                    for u in -dx..=*dx {
+                        hist.add(at(u, y + dy) as usize);
+                    }
+                }
+                for x in 0..w as i64 {
+                    if x > 0 {
+                        for (dy, dx) in lines.iter() {
+                            hist.remove(at(x - dx - 1, y + dy) as usize);
+                            hist.add(at(x + dx, y + dy) as usize);
+                        }
+                    }
+                    let bin = match kind {
+                        RankType::Min => hist.rank(0),
+                        RankType::Max => hist.rank(count - 1),
+                        _ => hist.rank(count / 2),
+                    };
+                    out.push(T::round_pixel(bin as f32));
+                }
+            }
+        }
+        (_, None) => {
+            let mut values = Vec::<f32>::with_capacity(count);
+            for y in 0..h as i64 {
+                for x in 0..w as i64 {
+                    values.clear();
+                    for (dy, dx) in lines.iter() {
+                        for u in -dx..=*dx {
+                            values.push(at(x + u, y + dy));
+                        }
+                    }
+                    let value = match kind {
+                        RankType::Min => values.iter().fold(f32::INFINITY, |a, b| a.min(*b)),
+                        RankType::Max => values.iter().fold(f32::NEG_INFINITY, |a, b| a.max(*b)),
+                        _ => *values.select_nth_unstable_by(count / 2, |a, b| a.total_cmp(b)).1,
+                    };
+                    out.push(T::round_pixel(value));
+                }
+            }
+        }
+    }
+    out
+}
+
+///
+/// Histogram of integer pixel values with a coarse level for faster rank search.
+///
+struct SlidingHistogram {
+    fine: Vec<u32>,
+    coarse: Vec<u32>,
+    shift: usize,
+}
+
+impl SlidingHistogram {
+    fn new(nbins: usize) -> Self {
+        let shift = if nbins > 256 { 8 } else { 4 };
+        SlidingHistogram {
+            fine: vec![0; nbins],
+            coarse: vec![0; (nbins >> shift) + 1],
+            shift,
+        }
+    }
+    fn clear(&mut self) {
+        self.fine.iter_mut().for_each(|v| *v = 0);
+        self.coarse.iter_mut().for_each(|v| *v = 0);
+    }
+    fn add(&mut self, bin: usize) {
+        self.fine[bin] += 1;
+        self.coarse[bin >> self.shift] += 1;
+    }
+    fn remove(&mut self, bin: usize) {
+        self.fine[bin] -= 1;
+        self.coarse[bin >> self.shift] -= 1;
+    }
+    // Returns the bin of the k-th value (0-based) in ascending order
+    fn rank(&self, k: usize) -> usize {
+        let mut remaining = k as u32;
+        for (c, n) in self.coarse.iter().enumerate() {
+            if remaining < *n {
+                let start = c << self.shift;
+                let end = ((c + 1) << self.shift).min(self.fine.len());
+                for bin in start..end {
+                    if remaining < self.fine[bin] {
+                        return bin;
+                    }
+                    remaining -= self.fine[bin];
+                }
+            }
+            remaining -= n;
+        }
+        self.fine.len() - 1
+    }
+}
+
+///
+/// Macro implementing RankFilters for the gray processors and stacks
+///
+macro_rules! impl_rank_filters {
+    ($pixel:ty, $bins:expr) => {
+        impl RankFilters<$pixel> for ImageProcessor<$pixel, Gray<$pixel>> {
+            fn rank(&mut self, radius: f64, kind: RankType) {
+                let w = self.get_width() as usize;
+                let h = self.get_height() as usize;
+                let indices = self.metadata.roi_indices();
+                for plane in 0..self.depth as usize {
+                    let range = plane * w * h..(plane + 1) * w * h;
+                    let filtered = rank_plane(&self.data[range.clone()], w, h, radius, kind, $bins);
+                    for i in indices.iter() {
+                        self.data[range.start + i] = filtered[*i];
+                    }
+                }
+                self.metadata.set_dirty();
+            }
+        }
+
+        impl RankFilters<$pixel> for ImageStack<$pixel, Gray<$pixel>> {
+            fn rank(&mut self, radius: f64, kind: RankType) {
+                let w = self.get_width() as usize;
+                let h = self.get_height() as usize;
+                for slice in self.data.iter_mut() {
+                    *slice = rank_plane(slice, w, h, radius, kind, $bins);
+                }
+            }
+        }
+    };
+}
+
+impl_rank_filters!(u8, Some(256));
+impl_rank_filters!(u16, Some(65536));
+impl_rank_filters!(f32, None);
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::color_space::ColorSpace;
+    use crate::grayscale::*;
+
+    #[test]
+    fn line_radii_like_imagej() {
+        // Radius 0.5: cross of 5 pixels, radius 1: 3x3 square
+        assert_eq!(line_radii(0.5), vec![(-1, 0), (0, 1), (1, 0)]);
+        assert_eq!(line_radii(1.0), vec![(-1, 1), (0, 1), (1, 1)]);
+        // Radius 2: 21 pixels
+        let count: i64 = line_radii(2.0).iter().map(|(_, dx)| 2 * dx + 1).sum();
+        assert_eq!(count, 21);
+    }
+
+    #[test]
+    fn median_removes_hot_pixel_u16() {
+        let mut pixels = vec![100u16; 100];
+        pixels[55] = 60000;
+        let mut ip = ImageProcessor::<u16, Gray16>::new(10, 10, pixels, Gray16::new());
+        ip.rank(2.0, RankType::Median);
+        assert!(ip.data().iter().all(|v| *v == 100));
+    }
+
+    #[test]
+    fn u8_histogram_and_f32_sorting_agree() {
+        let pixels: Vec<u8> = (0..144).map(|i| ((i * 37) % 251) as u8).collect();
+        for kind in [RankType::Median, RankType::Min, RankType::Max] {
+            let mut ip8 = ImageProcessor::<u8, Gray8>::new(12, 12, pixels.clone(), Gray8::new());
+            ip8.rank(2.5, kind);
+            let mut ip32 = ImageProcessor::<f32, Gray32>::new(
+                12,
+                12,
+                pixels.iter().map(|v| *v as f32).collect(),
+                Gray32::new(),
+            );
+            ip32.rank(2.5, kind);
+            assert!(ip8
+                .data()
+                .iter()
+                .zip(ip32.data())
+                .all(|(a, b)| *a as f32 == *b));
+        }
+    }
+
+    #[test]
+    fn mean_and_variance() {
+        // Radius 0.5 kernel is a cross of 5 pixels
+        let mut pixels = vec![0.0f32; 25];
+        pixels[12] = 5.0;
+        let mut ip = ImageProcessor::<f32, Gray32>::new(5, 5, pixels.clone(), Gray32::new());
+        ip.rank(0.5, RankType::Mean);
+        assert_eq!(ip.data()[12], 1.0);
+        assert_eq!(ip.data()[11], 1.0);
+        assert_eq!(ip.data()[6], 0.0);
+        let mut ip = ImageProcessor::<f32, Gray32>::new(5, 5, pixels, Gray32::new());
+        ip.rank(0.5, RankType::Variance);
+        assert!((ip.data()[12] - 4.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn despeckle_stack() {
+        let mut slice = vec![10u8; 36];
+        slice[14] = 255;
+        let mut stack = ImageStack::<u8, Gray8>::new(6, 6, vec![slice.clone(), slice], Gray8::new());
+        stack.despeckle();
+        assert!(stack.data().iter().all(|s| s.iter().all(|v| *v == 10)));
+    }
+}
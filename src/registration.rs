@@ -0,0 +1,680 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+//!
+//! Registration of images by FFT cross-correlation or phase correlation.
+//!
+//! The alignment `(dx, dy, angle)` of the moving image relative to the reference means that
+//! the moving image is the reference rotated by `angle` (see [rotate()](Transform::rotate))
+//! around the center `((w - 1) / 2, (h - 1) / 2)`, then translated by `(dx, dy)`.
+//!
+//! The rotation is estimated first from the amplitude spectra resampled in polar (or
+//! log-polar) coordinates, since they do not depend on the translation. The ambiguity of
+//! 180 degrees is solved by the translation score.
+//!
+
+use crate::color_space::ColorSpace;
+use crate::fft::fft_3d;
+use crate::float_processor::FloatProcessor;
+use crate::grayscale::{Gray, Gray32};
+use crate::image_processor::ImageProcessor;
+use crate::image_stack::ImageStack;
+use crate::pixel::PixelType;
+use crate::results_table::{Cell, ResultsTable};
+use crate::transformable::{Canvas, Transform};
+use num::complex::Complex64;
+use std::f64::consts::PI;
+
+///
+/// Correlation methods
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Correlation {
+    /// Normalized cross-correlation
+    Cross,
+    /// Phase correlation: the cross-power spectrum is normalized to unit amplitudes
+    Phase,
+}
+
+///
+/// Radial sampling of the amplitude spectra for the rotation
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolarSampling {
+    Linear,
+    Logarithmic,
+}
+
+///
+/// References of the stack alignment
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackReference {
+    /// Every slice is registered to the slice of this index
+    Slice(usize),
+    /// Every slice is registered to the previous one and the transforms are accumulated
+    Neighbour,
+}
+
+///
+/// Transform of the moving image relative to the reference
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Alignment {
+    pub dx: f64,
+    pub dy: f64,
+    /// Rotation in degrees
+    pub angle: f64,
+    /// Correlation peak
+    pub score: f64,
+}
+
+impl Alignment {
+    /// Identity transform
+    pub fn identity() -> Self {
+        Alignment {
+            dx: 0.0,
+            dy: 0.0,
+            angle: 0.0,
+            score: 1.0,
+        }
+    }
+
+    ///
+    /// Returns the alignment of `self` followed by `next` (the score of `next` is kept).
+    ///
+    pub fn then(&self, next: &Alignment) -> Alignment {
+        let (s, c) = next.angle.to_radians().sin_cos();
+        Alignment {
+            dx: c * self.dx - s * self.dy + next.dx,
+            dy: s * self.dx + c * self.dy + next.dy,
+            angle: normalize(self.angle + next.angle),
+            score: next.score,
+        }
+    }
+
+    ///
+    /// Transforms the moving image `ip` onto the reference (inverse of the alignment).
+    ///
+    pub fn apply<T: PixelType>(&self, ip: &mut ImageProcessor<T, Gray<T>>) {
+        if self.dx != 0.0 || self.dy != 0.0 {
+            ip.translate(-self.dx, -self.dy);
+        }
+        if self.angle != 0.0 {
+            ip.rotate(-self.angle, Canvas::Keep);
+        }
+    }
+}
+
+///
+/// Image registration
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::grayscale::Gray32;
+/// use rim::image_processor::ImageProcessor;
+/// use rim::registration::{Correlation, Registration};
+///
+/// let spot = |x: f64, y: f64| (-((x - 20.0).powi(2) + (y - 12.0).powi(2)) / 8.0).exp() as f32;
+/// let image = |dx: f64, dy: f64| {
+///     let pixels = (0..32 * 32).map(|i| spot((i % 32) as f64 - dx, (i / 32) as f64 - dy)).collect();
+///     ImageProcessor::<f32, Gray32>::new(32, 32, pixels, Gray32::new())
+/// };
+/// let registration = Registration::new(Correlation::Cross);
+/// let alignment = registration.register(&image(0.0, 0.0), &image(2.5, -1.0));
+/// assert!((alignment.dx - 2.5).abs() < 0.1 && (alignment.dy + 1.0).abs() < 0.1);
+/// ```
+pub struct Registration {
+    method: Correlation,
+    rotation: bool,
+    sampling: PolarSampling,
+    max_shift: f64,
+}
+
+impl Registration {
+    /// Creates a registration of translations only.
+    pub fn new(method: Correlation) -> Self {
+        Registration {
+            method,
+            rotation: false,
+            sampling: PolarSampling::Linear,
+            max_shift: f64::INFINITY,
+        }
+    }
+
+    pub fn get_method(&self) -> Correlation {
+        self.method
+    }
+
+    /// Enables the estimation of the rotation.
+    pub fn set_rotation(&mut self, rotation: bool) {
+        self.rotation = rotation;
+    }
+
+    pub fn get_rotation(&self) -> bool {
+        self.rotation
+    }
+
+    pub fn set_polar_sampling(&mut self, sampling: PolarSampling) {
+        self.sampling = sampling;
+    }
+
+    pub fn get_polar_sampling(&self) -> PolarSampling {
+        self.sampling
+    }
+
+    /// Sets the maximum translation in pixels (unlimited by default).
+    pub fn set_max_shift(&mut self, max_shift: f64) {
+        self.max_shift = max_shift;
+    }
+
+    pub fn get_max_shift(&self) -> f64 {
+        self.max_shift
+    }
+
+    ///
+    /// Returns the alignment of `moving` relative to `reference` (same sizes).
+    ///
+    pub fn register<T: PixelType, C: ColorSpace>(
+        &self,
+        reference: &ImageProcessor<T, C>,
+        moving: &ImageProcessor<T, C>,
+    ) -> Alignment {
+        assert_eq!(
+            (reference.width, reference.height),
+            (moving.width, moving.height),
+            "Registration of images of different sizes"
+        );
+        let reference = Image::new(reference);
+        let moving = Image::new(moving);
+        if !self.rotation {
+            return self.translation(&reference, &moving);
+        }
+        let angle = self.rotation_angle(&reference, &moving);
+        [angle, angle + 180.0]
+            .iter()
+            .map(|angle| {
+                let (s, c) = angle.to_radians().sin_cos();
+                let mut rotated = moving.processor();
+                rotated.rotate(-angle, Canvas::Keep);
+                let t = self.translation(&reference, &Image::new(&rotated));
+                Alignment {
+                    dx: c * t.dx - s * t.dy,
+                    dy: s * t.dx + c * t.dy,
+                    angle: normalize(*angle),
+                    score: t.score,
+                }
+            })
+            .fold(None, |best: Option<Alignment>, a| match best {
+                Some(b) if b.score >= a.score => Some(b),
+                _ => Some(a),
+            })
+            .unwrap()
+    }
+
+    ///
+    /// Returns the alignments of the slices of `stack` relative to the reference slice,
+    /// or relative to the first slice with [StackReference::Neighbour].
+    ///
+    pub fn register_stack<T: PixelType + Clone>(
+        &self,
+        stack: &ImageStack<T, Gray<T>>,
+        reference: StackReference,
+    ) -> Result<Vec<Alignment>, String> {
+        let n = stack.data.len();
+        let slice = |i: usize| {
+            ImageProcessor::new(
+                stack.width,
+                stack.height,
+                stack.data[i].clone(),
+                Gray::<T>::new(),
+            )
+        };
+        match reference {
+            StackReference::Slice(index) => {
+                if index >= n {
+                    return Err(format!("No slice {} in a stack of {} slices", index, n));
+                }
+                let reference = slice(index);
+                Ok((0..n)
+                    .map(|i| {
+                        if i == index {
+                            Alignment::identity()
+                        } else {
+                            self.register(&reference, &slice(i))
+                        }
+                    })
+                    .collect())
+            }
+            StackReference::Neighbour => {
+                let mut alignments = Vec::<Alignment>::with_capacity(n);
+                for i in 0..n {
+                    let alignment = if i == 0 {
+                        Alignment::identity()
+                    } else {
+                        alignments[i - 1].then(&self.register(&slice(i - 1), &slice(i)))
+                    };
+                    alignments.push(alignment);
+                }
+                Ok(alignments)
+            }
+        }
+    }
+
+    //
+    // Private methods
+    //
+
+    fn translation(&self, reference: &Image, moving: &Image) -> Alignment {
+        let (w, h) = (reference.width, reference.height);
+        let mut cc: Vec<Complex64> = moving
+            .spectrum
+            .iter()
+            .zip(reference.spectrum.iter())
+            .map(|(m, r)| {
+                let c = m * r.conj();
+                match self.method {
+                    Correlation::Cross => c / (moving.norm * reference.norm),
+                    Correlation::Phase => c / (c.norm() + 1e-12 * moving.norm * reference.norm),
+                }
+            })
+            .collect();
+        fft_3d(&mut cc, w, h, 1, true);
+        let (dx, dy, score) = peak(&cc, w, h, (0.0, 0.0), self.max_shift);
+        Alignment {
+            dx,
+            dy,
+            angle: 0.0,
+            score,
+        }
+    }
+
+    // Angle in degrees modulo 180 from the amplitude spectra
+    fn rotation_angle(&self, reference: &Image, moving: &Image) -> f64 {
+        let n_angles = (4 * reference.width.max(reference.height)).max(360);
+        let a = self.polar(reference, n_angles);
+        let b = self.polar(moving, n_angles);
+        // Sum of the circular cross-power spectra of the rings
+        let mut sum = vec![Complex64::new(0.0, 0.0); n_angles];
+        for (ra, rb) in a.chunks(n_angles).zip(b.chunks(n_angles)) {
+            let mut fa: Vec<Complex64> = ra.iter().map(|v| Complex64::new(*v, 0.0)).collect();
+            let mut fb: Vec<Complex64> = rb.iter().map(|v| Complex64::new(*v, 0.0)).collect();
+            fft_3d(&mut fa, n_angles, 1, 1, false);
+            fft_3d(&mut fb, n_angles, 1, 1, false);
+            for (s, (a, b)) in sum.iter_mut().zip(fa.iter().zip(fb.iter())) {
+                *s += b * a.conj();
+            }
+        }
+        if self.method == Correlation::Phase {
+            sum.iter_mut().for_each(|v| *v /= v.norm() + 1e-12);
+        }
+        fft_3d(&mut sum, n_angles, 1, 1, true);
+        let (lag, _, _) = peak(&sum, n_angles, 1, (0.0, 0.0), f64::INFINITY);
+        lag * 180.0 / n_angles as f64
+    }
+
+    // Rings (rows) of the amplitude spectrum weighted by the frequency, over 180 degrees
+    fn polar(&self, image: &Image, n_angles: usize) -> Vec<f64> {
+        let (w, h) = (image.width, image.height);
+        let amplitudes: Vec<f64> = image.spectrum.iter().map(|v| v.norm()).collect();
+        // Amplitude at the frequency (fx, fy) in cycles per pixel (bilinear)
+        let at = |fx: f64, fy: f64| {
+            let (x, y) = (fx * w as f64, fy * h as f64);
+            let (x0, y0) = (x.floor(), y.floor());
+            let (tx, ty) = (x - x0, y - y0);
+            let value = |i: f64, j: f64| {
+                amplitudes[(i as i64).rem_euclid(w as i64) as usize
+                    + (j as i64).rem_euclid(h as i64) as usize * w]
+            };
+            (1.0 - ty) * ((1.0 - tx) * value(x0, y0) + tx * value(x0 + 1.0, y0))
+                + ty * ((1.0 - tx) * value(x0, y0 + 1.0) + tx * value(x0 + 1.0, y0 + 1.0))
+        };
+        let size = w.min(h) as f64;
+        let (r_min, r_max) = (2.0 / size, 0.45);
+        let n_radii = (size * (r_max - r_min)).ceil().max(2.0) as usize;
+        let mut rings = Vec::<f64>::with_capacity(n_radii * n_angles);
+        for i in 0..n_radii {
+            let t = i as f64 / (n_radii - 1) as f64;
+            let radius = match self.sampling {
+                PolarSampling::Linear => r_min + t * (r_max - r_min),
+                PolarSampling::Logarithmic => r_min * (r_max / r_min).powf(t),
+            };
+            let ring: Vec<f64> = (0..n_angles)
+                .map(|k| {
+                    let (s, c) = (PI * k as f64 / n_angles as f64).sin_cos();
+                    at(radius * c, radius * s) * radius
+                })
+                .collect();
+            let mean = ring.iter().sum::<f64>() / n_angles as f64;
+            rings.extend(ring.iter().map(|v| v - mean));
+        }
+        rings
+    }
+}
+
+///
+/// Returns the table of the alignments (columns `slice`, `dx`, `dy`, `angle` and `score`).
+///
+pub fn to_table(alignments: &[Alignment]) -> ResultsTable {
+    let mut table = ResultsTable::new(String::from("Registration"));
+    for (i, a) in alignments.iter().enumerate() {
+        table.add_row();
+        for (column, value) in [
+            ("slice", (i + 1) as f64),
+            ("dx", a.dx),
+            ("dy", a.dy),
+            ("angle", a.angle),
+            ("score", a.score),
+        ] {
+            table.add_value(&column.to_string(), Cell::Number(value));
+        }
+    }
+    table
+}
+
+///
+/// Returns the stack of the slices of `stack` transformed by their `alignments`.
+///
+pub fn align_stack<T: PixelType + Clone>(
+    stack: &ImageStack<T, Gray<T>>,
+    alignments: &[Alignment],
+) -> ImageStack<T, Gray<T>> {
+    let slices = stack
+        .data
+        .iter()
+        .zip(alignments.iter())
+        .map(|(slice, alignment)| {
+            let mut ip =
+                ImageProcessor::new(stack.width, stack.height, slice.clone(), Gray::<T>::new());
+            alignment.apply(&mut ip);
+            ip.data
+        })
+        .collect();
+    ImageStack::new(stack.width, stack.height, slices, Gray::<T>::new())
+}
+
+//
+// Private structures and functions
+//
+
+// Apodized image and its spectrum
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+    spectrum: Vec<Complex64>,
+    norm: f64,
+}
+
+impl Image {
+    fn new<T: PixelType, C: ColorSpace>(ip: &ImageProcessor<T, C>) -> Self {
+        let (w, h) = (ip.width as usize, ip.height as usize);
+        let pixels: Vec<f32> = ip.data[0..w * h].iter().map(|v| v.to_f32()).collect();
+        let mean = pixels.iter().map(|v| *v as f64).sum::<f64>() / (w * h) as f64;
+        let (wx, wy) = (taper(w), taper(h));
+        let mut spectrum: Vec<Complex64> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Complex64::new((*v as f64 - mean) * wx[i % w] * wy[i / w], 0.0))
+            .collect();
+        let norm = spectrum
+            .iter()
+            .map(|v| v.norm_sqr())
+            .sum::<f64>()
+            .sqrt()
+            .max(1e-12);
+        fft_3d(&mut spectrum, w, h, 1, false);
+        Image {
+            width: w,
+            height: h,
+            pixels,
+            spectrum,
+            norm,
+        }
+    }
+
+    fn processor(&self) -> FloatProcessor {
+        ImageProcessor::new(
+            self.width as u32,
+            self.height as u32,
+            self.pixels.clone(),
+            Gray32::new(),
+        )
+    }
+}
+
+// Cosine edges over 1/8 of the length
+pub(crate) fn taper(n: usize) -> Vec<f64> {
+    let edge = (n / 8).max(1) as f64;
+    (0..n)
+        .map(|i| {
+            let d = (i.min(n - 1 - i) as f64 + 0.5) / edge;
+            if d >= 1.0 {
+                1.0
+            } else {
+                0.5 * (1.0 - (PI * d).cos())
+            }
+        })
+        .collect()
+}
+
+// Angle in ]-180, 180]
+fn normalize(angle: f64) -> f64 {
+    let a = angle.rem_euclid(360.0);
+    if a > 180.0 {
+        a - 360.0
+    } else {
+        a
+    }
+}
+
+// Highest correlation within `max_shift` of `center` with a parabolic subpixel fit
+pub(crate) fn peak(
+    cc: &[Complex64],
+    nx: usize,
+    ny: usize,
+    center: (f64, f64),
+    max_shift: f64,
+) -> (f64, f64, f64) {
+    let at = |x: i64, y: i64| {
+        cc[x.rem_euclid(nx as i64) as usize + y.rem_euclid(ny as i64) as usize * nx].re
+    };
+    // Searching further than half the size would only revisit the same (periodic) positions
+    let limit = max_shift.max(0.0);
+    let range = |c: f64, n: usize| {
+        let l = limit.min((n / 2) as f64);
+        ((c - l).ceil() as i64, (c + l).floor() as i64)
+    };
+    let ((x0, x1), (y0, y1)) = (range(center.0, nx), range(center.1, ny));
+    let (mut best, mut bx, mut by) = (f64::MIN, center.0.round() as i64, center.1.round() as i64);
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let (dx, dy) = (x as f64 - center.0, y as f64 - center.1);
+            if dx * dx + dy * dy <= limit * limit + 1e-9 && at(x, y) > best {
+                best = at(x, y);
+                bx = x;
+                by = y;
+            }
+        }
+    }
+    if best == f64::MIN {
+        best = at(bx, by);
+    }
+    let offset = |m: f64, c: f64, p: f64| {
+        let d = m - 2.0 * c + p;
+        if d < 0.0 {
+            (0.5 * (m - p) / d).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        }
+    };
+    let fx = if nx > 2 {
+        offset(at(bx - 1, by), best, at(bx + 1, by))
+    } else {
+        0.0
+    };
+    let fy = if ny > 2 {
+        offset(at(bx, by - 1), best, at(bx, by + 1))
+    } else {
+        0.0
+    };
+    (bx as f64 + fx, by as f64 + fy, best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 64;
+
+    // Gaussian spots moved by the alignment
+    fn image(alignment: &Alignment) -> FloatProcessor {
+        let spots = [
+            (20.0, 15.0, 3.0, 1.0),
+            (40.0, 30.0, 2.0, 0.7),
+            (28.0, 44.0, 4.0, 0.5),
+            (36.0, 22.0, 1.5, 0.8),
+        ];
+        let c = (SIZE - 1) as f64 / 2.0;
+        let (s, co) = alignment.angle.to_radians().sin_cos();
+        let pixels = (0..SIZE * SIZE)
+            .map(|i| {
+                // p = R^T (q - c - t) + c
+                let (qx, qy) = (
+                    (i % SIZE) as f64 - c - alignment.dx,
+                    (i / SIZE) as f64 - c - alignment.dy,
+                );
+                let (x, y) = (co * qx + s * qy + c, -s * qx + co * qy + c);
+                spots
+                    .iter()
+                    .map(|(sx, sy, r, v)| {
+                        v * (-((x - sx).powi(2) + (y - sy).powi(2)) / (2.0 * r * r)).exp()
+                    })
+                    .sum::<f64>() as f32
+            })
+            .collect();
+        ImageProcessor::new(SIZE, SIZE, pixels, Gray32::new())
+    }
+
+    fn shifted(dx: f64, dy: f64) -> Alignment {
+        Alignment {
+            dx,
+            dy,
+            angle: 0.0,
+            score: 1.0,
+        }
+    }
+
+    #[test]
+    fn subpixel_translations() {
+        let reference = image(&Alignment::identity());
+        let moving = image(&shifted(3.3, -5.7));
+        for method in [Correlation::Cross, Correlation::Phase] {
+            let a = Registration::new(method).register(&reference, &moving);
+            assert!(
+                (a.dx - 3.3).abs() < 0.15 && (a.dy + 5.7).abs() < 0.15,
+                "{:?} {:?}",
+                method,
+                a
+            );
+            assert!(a.score > 0.2, "{:?} {:?}", method, a);
+        }
+        let mut limited = Registration::new(Correlation::Cross);
+        limited.set_max_shift(3.0);
+        let a = limited.register(&reference, &moving);
+        assert!(a.dx * a.dx + a.dy * a.dy <= 3.6 * 3.6, "{:?}", a);
+    }
+
+    #[test]
+    fn rotation_and_translation() {
+        let reference = image(&Alignment::identity());
+        let truth = Alignment {
+            dx: 2.0,
+            dy: -3.0,
+            angle: 25.0,
+            score: 1.0,
+        };
+        let moving = image(&truth);
+        for sampling in [PolarSampling::Linear, PolarSampling::Logarithmic] {
+            let mut registration = Registration::new(Correlation::Cross);
+            registration.set_rotation(true);
+            registration.set_polar_sampling(sampling);
+            let a = registration.register(&reference, &moving);
+            assert!((a.angle - 25.0).abs() < 1.0, "{:?} {:?}", sampling, a);
+            assert!(
+                (a.dx - 2.0).abs() < 0.5 && (a.dy + 3.0).abs() < 0.5,
+                "{:?} {:?}",
+                sampling,
+                a
+            );
+        }
+    }
+
+    #[test]
+    fn stack_alignment() {
+        let shifts = [(0.0, 0.0), (1.5, 0.5), (3.0, -1.0), (4.0, -2.5)];
+        let slices = shifts
+            .iter()
+            .map(|(x, y)| image(&shifted(*x, *y)).data)
+            .collect();
+        let stack = ImageStack::new(SIZE, SIZE, slices, Gray32::new());
+        let registration = Registration::new(Correlation::Cross);
+        for reference in [StackReference::Slice(0), StackReference::Neighbour] {
+            let alignments = registration.register_stack(&stack, reference).unwrap();
+            for (a, (x, y)) in alignments.iter().zip(shifts.iter()) {
+                assert!(
+                    (a.dx - x).abs() < 0.2 && (a.dy - y).abs() < 0.2,
+                    "{:?} {:?}",
+                    reference,
+                    a
+                );
+            }
+            let table = to_table(&alignments);
+            assert_eq!(table.size(), 4);
+            let dx = table.get_column_as_floats(String::from("dx")).unwrap();
+            assert_eq!(dx[2], alignments[2].dx);
+        }
+        assert!(registration
+            .register_stack(&stack, StackReference::Slice(4))
+            .is_err());
+        // The aligned slices match the first one
+        let alignments = [
+            shifted(0.0, 0.0),
+            shifted(2.0, 0.0),
+            shifted(3.0, -1.0),
+            shifted(4.0, -2.0),
+        ];
+        let slices = alignments.iter().map(|a| image(a).data).collect();
+        let aligned = align_stack(
+            &ImageStack::new(SIZE, SIZE, slices, Gray32::new()),
+            &alignments,
+        );
+        let first = &aligned.data[0];
+        for slice in aligned.data.iter() {
+            let error: f32 = slice
+                .iter()
+                .zip(first.iter())
+                .map(|(a, b)| (a - b).abs())
+                .sum();
+            assert!(error < 1e-3, "{}", error);
+        }
+    }
+}
@@ -1,26 +1,409 @@
-pub trait ROI {
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
 
-    /// Returns a Rectangle that represents the current region of interest.
-    java.awt.Rectangle get_roi() {}
+use crate::color_space::ColorSpace;
+use crate::grayscale::Gray8;
+use crate::image_processor::ImageProcessor;
 
-   /// Sets the ROI (Region of interest) and clipping rectangle to the entire image.
-    fn reset_Roi() {}
+/// Value of the mask pixels located inside a ROI.
+pub const MASK_INSIDE: u8 = 255;
 
-    fn set_mask​(Image_Processor mask) {}
-    /// Defines a byte mask that limits processing to an irregular ROI.
+///
+/// Integer rectangle used as bounding box and clipping rectangle of a ROI.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rectangle {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rectangle {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns `true` if the pixel (x,y) is located in this rectangle.
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    /// Returns the intersection of two rectangles (possibly empty).
+    pub fn intersection(&self, other: &Rectangle) -> Rectangle {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let x2 = (self.x + self.width).min(other.x + other.width);
+        let y2 = (self.y + self.height).min(other.y + other.height);
+        Rectangle::new(x, y, (x2 - x).max(0), (y2 - y).max(0))
+    }
+
+    /// Returns the smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &Rectangle) -> Rectangle {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let x2 = (self.x + self.width).max(other.x + other.width);
+        let y2 = (self.y + self.height).max(other.y + other.height);
+        Rectangle::new(x, y, x2 - x, y2 - y)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+}
 
-    fn set_roi​(i32 x, i32 y, i32 rwidth, i32 rheight) {}
-    /// Defines a rectangular region of i32erest and sets the mask to null if this ROI is not the same size as the previous one.
+///
+/// Region Of Interest.
+///
+/// The coordinates are expressed in pixels, the origin being the top-left corner
+/// of the image. Like ImageJ, a pixel (x,y) belongs to an area ROI if its center
+/// (x+0.5,y+0.5) is located inside the shape.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::roi::Roi;
+///
+/// let roi = Roi::oval(10.0, 10.0, 20.0, 10.0);
+/// assert!(roi.contains(20.0, 15.0));
+/// let mask = roi.get_mask();
+/// assert_eq!(mask.get_width(), 20);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum Roi {
+    Rectangle {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+    /// Ellipse inscribed in the rectangle (x, y, width, height).
+    Oval {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+    /// Closed polygon.
+    Polygon(Vec<(f64, f64)>),
+    /// Closed polygon usually drawn with the mouse (many vertices).
+    Freehand(Vec<(f64, f64)>),
+    /// Straight line from (x1, y1) to (x2, y2).
+    Line { x1: f64, y1: f64, x2: f64, y2: f64 },
+    /// Open segmented line.
+    Polyline(Vec<(f64, f64)>),
+    /// Multi-point selection.
+    Point(Vec<(f64, f64)>),
+    /// Combination of area ROIs: a pixel is inside if it belongs to an odd number
+    /// of components (even-odd rule), allowing ROIs with holes.
+    Composite(Vec<Roi>),
+}
+
+impl Roi {
+    /// Creates a rectangular ROI.
+    pub fn rectangle(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Roi::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Creates an elliptical ROI inscribed in the given rectangle.
+    pub fn oval(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Roi::Oval {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    ///
+    /// Returns `true` if this ROI encloses an area (rectangle, oval, polygon, freehand or composite).
+    ///
+    pub fn is_area(&self) -> bool {
+        !matches!(self, Roi::Line { .. } | Roi::Polyline(_) | Roi::Point(_))
+    }
 
-    fn set_roi​(Roi roi) {}
-    /// Defines a non-rectangular region of i32erest that will consist of a rectangular ROI and a mask.
+    ///
+    /// Returns the integer bounding box of this ROI.
+    ///
+    pub fn bounds(&self) -> Rectangle {
+        match self {
+            Roi::Rectangle {
+                x,
+                y,
+                width,
+                height,
+            }
+            | Roi::Oval {
+                x,
+                y,
+                width,
+                height,
+            } => area_bounds(*x, *y, x + width, y + height),
+            Roi::Polygon(points) | Roi::Freehand(points) => {
+                let (x0, y0, x1, y1) = extent(points);
+                area_bounds(x0, y0, x1, y1)
+            }
+            Roi::Line { x1, y1, x2, y2 } => Roi::Polyline(vec![(*x1, *y1), (*x2, *y2)]).bounds(),
+            Roi::Polyline(points) | Roi::Point(points) => {
+                let (x0, y0, x1, y1) = extent(points);
+                let (x, y) = (x0.floor() as i32, y0.floor() as i32);
+                Rectangle::new(x, y, x1.floor() as i32 - x + 1, y1.floor() as i32 - y + 1)
+            }
+            Roi::Composite(rois) => rois
+                .iter()
+                .map(|r| r.bounds())
+                .reduce(|a, b| a.union(&b))
+                .unwrap_or(Rectangle::new(0, 0, 0, 0)),
+        }
+    }
 
-    fn set_roi​(java.awt.Polygon roi) {}
-    /// Defines a polygonal region of i32erest that will consist of a rectangular ROI and a mask.
+    ///
+    /// Returns `true` if the point (x,y) is located inside this ROI.
+    ///
+    /// For line and point ROIs, returns `true` if the point is located in one of the
+    /// pixels covered by the selection.
+    ///
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        match self {
+            Roi::Rectangle {
+                x: rx,
+                y: ry,
+                width,
+                height,
+            } => x >= *rx && y >= *ry && x < rx + width && y < ry + height,
+            Roi::Oval {
+                x: rx,
+                y: ry,
+                width,
+                height,
+            } => {
+                let (a, b) = (width / 2.0, height / 2.0);
+                if a <= 0.0 || b <= 0.0 {
+                    return false;
+                }
+                let dx = (x - rx - a) / a;
+                let dy = (y - ry - b) / b;
+                dx * dx + dy * dy <= 1.0
+            }
+            Roi::Polygon(points) | Roi::Freehand(points) => polygon_contains(points, x, y),
+            Roi::Line { .. } | Roi::Polyline(_) | Roi::Point(_) => self
+                .pixels()
+                .contains(&(x.floor() as i32, y.floor() as i32)),
+            Roi::Composite(rois) => rois.iter().filter(|r| r.contains(x, y)).count() % 2 == 1,
+        }
+    }
 
-    fn set_roi​(java.awt.Rectangle roi) {}
-    /// Defines a rectangular region of i32erest and sets the mask to null if this ROI is not the same size as the previous one.
+    ///
+    /// Returns `true` if the pixel (x,y) belongs to this ROI.
+    ///
+    pub fn contains_pixel(&self, x: i32, y: i32) -> bool {
+        if self.is_area() {
+            self.contains(x as f64 + 0.5, y as f64 + 0.5)
+        } else {
+            self.contains(x as f64, y as f64)
+        }
+    }
 
+    ///
+    /// Returns the mask of this ROI as a ByteProcessor with the size of the bounding box.
+    ///
+    /// The pixels inside the ROI are set to 255 ([MASK_INSIDE]), the other ones to 0.
+    ///
+    pub fn get_mask(&self) -> ImageProcessor<u8, Gray8> {
+        let r = self.bounds();
+        let (w, h) = (r.width.max(0) as u32, r.height.max(0) as u32);
+        ImageProcessor::new(w, h, self.mask_within(&r), Gray8::new())
+    }
+
+    ///
+    /// Returns the mask of this ROI restricted to the rectangle `r` (row by row,
+    /// of size `r.width` x `r.height`).
+    ///
+    pub(crate) fn mask_within(&self, r: &Rectangle) -> Vec<u8> {
+        let (w, h) = (r.width.max(0) as usize, r.height.max(0) as usize);
+        let mut mask = vec![0u8; w * h];
+        if self.is_area() {
+            for y in 0..h {
+                for x in 0..w {
+                    if self.contains_pixel(r.x + x as i32, r.y + y as i32) {
+                        mask[x + y * w] = MASK_INSIDE;
+                    }
+                }
+            }
+        } else {
+            for (x, y) in self.pixels() {
+                if r.contains(x, y) {
+                    mask[(x - r.x) as usize + (y - r.y) as usize * w] = MASK_INSIDE;
+                }
+            }
+        }
+        mask
+    }
+
+    //
+    // Private function: pixels covered by a line or point ROI.
+    //
+    fn pixels(&self) -> Vec<(i32, i32)> {
+        let pixel = |p: &(f64, f64)| (p.0.floor() as i32, p.1.floor() as i32);
+        match self {
+            Roi::Point(points) => points.iter().map(pixel).collect(),
+            Roi::Line { x1, y1, x2, y2 } => Roi::Polyline(vec![(*x1, *y1), (*x2, *y2)]).pixels(),
+            Roi::Polyline(points) => {
+                let mut pixels: Vec<(i32, i32)> = points.iter().take(1).map(pixel).collect();
+                for seg in points.windows(2) {
+                    let (dx, dy) = (seg[1].0 - seg[0].0, seg[1].1 - seg[0].1);
+                    let n = dx.abs().max(dy.abs()).ceil().max(1.0) as usize;
+                    for i in 1..=n {
+                        let t = i as f64 / n as f64;
+                        let p = pixel(&(seg[0].0 + t * dx, seg[0].1 + t * dy));
+                        if pixels.last() != Some(&p) {
+                            pixels.push(p);
+                        }
+                    }
+                }
+                pixels
+            }
+            _ => vec![],
+        }
+    }
+}
 
+//
+// Private functions
+//
+fn extent(points: &[(f64, f64)]) -> (f64, f64, f64, f64) {
+    points.iter().fold(
+        (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+        |(x0, y0, x1, y1), (x, y)| (x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y)),
+    )
 }
 
+fn area_bounds(x0: f64, y0: f64, x1: f64, y1: f64) -> Rectangle {
+    if x0 > x1 || y0 > y1 {
+        return Rectangle::new(0, 0, 0, 0);
+    }
+    let (x, y) = (x0.floor() as i32, y0.floor() as i32);
+    Rectangle::new(x, y, x1.ceil() as i32 - x, y1.ceil() as i32 - y)
+}
+
+// Even-odd rule
+fn polygon_contains(points: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let n = points.len();
+    let mut inside = false;
+    let mut j = n.wrapping_sub(1);
+    for i in 0..n {
+        let (xi, yi) = points[i];
+        let (xj, yj) = points[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn count(mask: &ImageProcessor<u8, Gray8>) -> usize {
+        mask.data().iter().filter(|v| **v == MASK_INSIDE).count()
+    }
+
+    #[test]
+    fn rectangle_mask() {
+        let roi = Roi::rectangle(2.0, 3.0, 4.0, 5.0);
+        assert_eq!(roi.bounds(), Rectangle::new(2, 3, 4, 5));
+        assert_eq!(count(&roi.get_mask()), 20);
+        assert!(roi.contains_pixel(5, 7));
+        assert!(!roi.contains_pixel(6, 7));
+    }
+
+    #[test]
+    fn oval_mask_is_symmetric() {
+        let roi = Roi::oval(0.0, 0.0, 10.0, 6.0);
+        let mask = roi.get_mask();
+        assert_eq!(mask.get_width(), 10);
+        assert_eq!(mask.get_height(), 6);
+        // Corners are outside, center is inside
+        assert_eq!(mask.data()[0], 0);
+        assert_eq!(mask.data()[5 + 3 * 10], MASK_INSIDE);
+        for y in 0..6 {
+            for x in 0..10 {
+                assert_eq!(mask.data()[x + y * 10], mask.data()[9 - x + (5 - y) * 10]);
+            }
+        }
+    }
+
+    #[test]
+    fn triangle_polygon() {
+        let roi = Roi::Polygon(vec![(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)]);
+        assert_eq!(roi.bounds(), Rectangle::new(0, 0, 4, 4));
+        // Pixel centers (x + 0.5, y + 0.5) below the diagonal, i.e. x + y < 3
+        assert_eq!(count(&roi.get_mask()), 3 + 2 + 1);
+        assert!(roi.contains(1.0, 1.0));
+        assert!(!roi.contains(3.0, 3.0));
+    }
+
+    #[test]
+    fn line_like_polyline() {
+        let line = Roi::Line {
+            x1: 0.5,
+            y1: 0.5,
+            x2: 3.5,
+            y2: 3.5,
+        };
+        assert!(!line.is_area());
+        assert_eq!(line.bounds(), Rectangle::new(0, 0, 4, 4));
+        assert_eq!(count(&line.get_mask()), 4);
+    }
+
+    #[test]
+    fn polyline_and_points() {
+        let line = Roi::Polyline(vec![(0.5, 0.5), (4.5, 0.5), (4.5, 2.5)]);
+        assert_eq!(line.bounds(), Rectangle::new(0, 0, 5, 3));
+        assert_eq!(count(&line.get_mask()), 7);
+        let points = Roi::Point(vec![(1.0, 1.0), (3.0, 2.0)]);
+        assert!(points.contains_pixel(3, 2));
+        assert!(!points.contains_pixel(2, 2));
+        assert_eq!(count(&points.get_mask()), 2);
+    }
+
+    #[test]
+    fn composite_with_hole() {
+        let ring = Roi::Composite(vec![
+            Roi::rectangle(0.0, 0.0, 6.0, 6.0),
+            Roi::rectangle(2.0, 2.0, 2.0, 2.0),
+        ]);
+        assert_eq!(ring.bounds(), Rectangle::new(0, 0, 6, 6));
+        assert_eq!(count(&ring.get_mask()), 32);
+        assert!(!ring.contains_pixel(2, 3));
+    }
+}
@@ -41,24 +41,19 @@ impl Statistics<u16> for ShortProcessor {
             let mut sum: f64 = 0.0;
             let mut sum2: f64 = 0.0;
             let mut hist = vec![0u32; 65536];
-            let start = (self.metadata.roi.y() * self.width + self.metadata.roi.x()) as usize;
-            let mut mi: f64 = self.data[start] as f64;
-            let mut mx = mi;
+            let mut mi = f64::MAX;
+            let mut mx = f64::MIN;
             let mut count: u32 = 0;
-            for y in self.metadata.roi.y()..(self.metadata.roi.y() + self.metadata.roi.height()) {
-                let mut i = y * self.width + self.metadata.roi.x();
-                for x in self.metadata.roi.x()..(self.metadata.roi.x() + self.metadata.roi.width())
-                {
-                    let v: f64 = self.getf(i as usize) as f64;
-                    let index: usize = self.get(i as usize) as usize;
-                    sum += v as f64;
-                    sum2 += (v * v) as f64;
-                    i += 1;
-                    hist[v as usize] += 1;
-                    mi = if v < mi { v } else { mi };
-                    mx = if v > mx { v } else { mx };
-                    count += 1;
-                }
+            // Pixels of the active ROI (whole image without ROI)
+            for i in self.metadata.roi_indices() {
+                let v: f64 = self.getf(i) as f64;
+                let index: usize = self.get(i) as usize;
+                sum += v as f64;
+                sum2 += (v * v) as f64;
+                hist[v as usize] += 1;
+                mi = if v < mi { v } else { mi };
+                mx = if v > mx { v } else { mx };
+                count += 1;
             }
             let mut std_dev = (count as f64 * sum2 - sum * sum) / count as f64;
             std_dev = if std_dev > 0.0 {
@@ -99,6 +94,7 @@ mod tests {
     use crate::image_processor::*;
     use crate::image_traits::Access;
     use crate::operator::Operator;
+    use crate::roi::Roi;
 
     #[test]
     fn get_pixel_at_xy() {
@@ -250,5 +246,23 @@ mod tests {
         assert_eq!(ip.get(3), 1);
     }
 
+    #[test]
+    fn stats_restricted_to_roi() {
+        let mut ip = ShortProcessor::new(
+            4,
+            3,
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+            Gray16::new(),
+        );
+        ip.set_roi(Roi::rectangle(1.0, 1.0, 2.0, 2.0));
+        ip.update_stats();
+        assert_eq!(ip.min_value(), 6.0);
+        assert_eq!(ip.max_value(), 11.0);
+        assert_eq!(ip.mean(), 8.5);
+        ip.reset_roi();
+        ip.update_stats();
+        assert_eq!(ip.mean(), 6.5);
+    }
+
     //gamma
 }
@@ -24,61 +24,112 @@
 #![warn(unused)]
 #![warn(dead_code)]
 
-use std::process::exit;
-use rim::grayscale::Gray16;
-use rim::image_processor::ImageProcessor;
+use rim::color_space::ColorSpace;
+use rim::cryoem::euler::{angle_between, read_angles, Convention};
+use rim::cryoem::projection::{ProjectionMode, Projector};
+use rim::cryoem::projection_matching::ProjectionMatching;
+use rim::grayscale::{Gray16, Gray32};
 use rim::image_stack::ImageStack;
+use rim::io::file_info::FileInfo;
 use rim::io::image_reader::{FileOpener, OutputProcessor};
 use rim::io::text_reader::TextReader;
 use rim::results_table::ResultsTable;
+use rim::volume_processor::VolumeProcessor;
+use std::path::Path;
+use std::process::exit;
 
-// fn image_projection(ImageStack ist) -> Vec<ImageStack> {
-//    let img_projection_stack: Vec<ImageStack> = Vec::new();
-//    for _ in 10 {
-//         img_projection_stack.push(ist);
-//    }
-//    return img_projection_stack;
-// }
-//
-// fn image_stack_projection(ImageStacks ist_stack) -> Vec<ImageStacks> {
-//     let all_img_projection: Vec<ImageStacks> = Vec::new();
-//     for img_stack in ist_stack{
-//         all_img_projection.push(image_projection(img_stack);
-//     }
-//     return all_img_projection;
-// }
+const VOLUME: &str = "samples/T1_head_128x128x128.tif";
+const ANGLES: &str = "samples/psi-theta-phi-50.csv";
+// Sampling of the reference projections (degrees)
+const ANGULAR_STEP: f64 = 15.0;
 
 pub struct SPR {}
 
 impl SPR {
-    fn read_angles() -> ResultsTable {
-        return TextReader::open_csv("samples/psi-theta-phi-50.csv", Option::Some(',')).unwrap();
+    fn read_angles() -> Result<ResultsTable, String> {
+        TextReader::open_csv(ANGLES, Option::Some(','))
+            .map_err(|e| format!("Unable to read {}: {}", ANGLES, e))
     }
 
-    fn read_image() -> Option<&'static ImageProcessor<u16, Gray16>> {
-        let processor =  FileOpener::open_stack("samples/T1_head_128x128x128.tif", 128, 128, 128);
-        if let OutputProcessor::ShortProcessor(ip) = &processor {
-            return Some(ip)
+    fn read_volume() -> Option<VolumeProcessor<u16>> {
+        if !Path::new(VOLUME).exists() {
+            return None;
         }
-        return None
+        let processor = FileOpener::open_stack(VOLUME, 128, 128, FileInfo::GRAY16_UNSIGNED);
+        if let OutputProcessor::ShortStack(stack) = processor {
+            return Some(Self::to_volume(stack));
+        }
+        None
     }
 
-    pub fn start() -> () {
-        let angles: ResultsTable = Self::read_angles();
-        let processor: Option<&ImageProcessor<u16, Gray16>> = Self::read_image();
-        let ip: &Vec<u16>;
+    // Slices of the stack stored one after the other in a volume
+    fn to_volume(stack: ImageStack<u16, Gray16>) -> VolumeProcessor<u16> {
+        let (w, h, d) = (stack.get_width(), stack.get_height(), stack.n_slices());
+        let voxels = stack.data.into_iter().flatten().collect();
+        VolumeProcessor::new_volume(w, h, d, voxels, Gray16::new())
+    }
 
-        match processor {
-            Some(x) => {
-                ip = x.data();
+    pub fn start() {
+        let angles = match Self::read_angles() {
+            Ok(angles) => angles,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
             }
+        };
+        let volume = match Self::read_volume() {
+            Some(vol) => vol,
             None => {
+                eprintln!("Unable to read the volume {}", VOLUME);
                 exit(1);
             }
+        };
+        let projector = Projector::new(ProjectionMode::RayDriven);
+        let projections = match projector.project_table(&volume, &angles, Convention::Yxz) {
+            Ok(projections) => projections,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        };
+        for (label, slice) in projections.labels().iter().zip(projections.data()) {
+            let sum: f64 = slice.iter().map(|v| *v as f64).sum();
+            println!("Projection {}: integrated density {}", label, sum);
         }
+        Self::match_projections(&volume, &projections, &angles);
+    }
 
-        for val in ip {
-            print!("{}", val);
+    // Projection matching of the projections and angular error of the assignments
+    fn match_projections(
+        volume: &VolumeProcessor<u16>,
+        projections: &ImageStack<f32, Gray32>,
+        angles: &ResultsTable,
+    ) {
+        let known = match read_angles(angles) {
+            Ok(known) => known,
+            Err(e) => {
+                eprintln!("Invalid angles in {}: {}", ANGLES, e);
+                exit(1);
+            }
+        };
+        let matching = ProjectionMatching::new(ANGULAR_STEP);
+        let references = matching.references(volume);
+        let assignments = matching.align_stack(projections, &references);
+        for (i, (a, e)) in assignments.iter().zip(known.iter()).enumerate() {
+            let truth = e.to_matrix(Convention::Yxz);
+            let found = a.angles.to_matrix(Convention::Relion);
+            let error = angle_between(&truth, &found);
+            println!(
+                "Projection {}: psi {:.1} theta {:.1} phi {:.1} shift ({:.2}, {:.2}) score {:.3} error {:.1} degrees",
+                i + 1,
+                a.angles.psi,
+                a.angles.theta,
+                a.angles.phi,
+                a.shift_x,
+                a.shift_y,
+                a.score,
+                error
+            );
         }
     }
-}
\ No newline at end of file
+}
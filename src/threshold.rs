@@ -17,67 +17,941 @@
 //  You should have received a copy of the GNU General Public License
 //  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
 
+#![allow(clippy::needless_range_loop)]
 
-pub trait Threshold {
+use crate::color_space::ColorSpace;
+use crate::grayscale::{Gray, Gray8};
+use crate::image_processor::ImageProcessor;
+use crate::pixel::PixelType;
+use crate::statistics::Statistics;
 
-    fn auto_threshold() {}
-    /// Converts the image to binary using an automatically determined threshold.
-    /// Image_Processor bin​(i32 shrink_Factor) {}
-    /// Returns a copy of this image that has been reduced in size using binning.
+///
+/// Automatic thresholding methods of ImageJ `AutoThresholder` (G. Landini).
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    /// Variation of IsoData used by the ImageJ "Default" method
+    Default,
+    Huang,
+    Intermodes,
+    IsoData,
+    Li,
+    MaxEntropy,
+    Mean,
+    MinError,
+    Minimum,
+    Moments,
+    Otsu,
+    Percentile,
+    RenyiEntropy,
+    Shanbhag,
+    Triangle,
+    Yen,
+}
+
+///
+/// Thresholding of gray-level images
+///
+/// The automatic threshold is computed on the 256-bin histogram of the active ROI
+/// (see [Statistics]). For 16- and 32-bit images, the histogram spans the range `min..max`
+/// and the threshold levels are expressed as pixel values.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::grayscale::Gray8;
+/// use rim::image_processor::ImageProcessor;
+/// use rim::threshold::{Method, Threshold};
+///
+/// let mut ip = ImageProcessor::<u8, Gray8>::new(4, 1, vec![10, 12, 200, 210], Gray8::new());
+/// let (lower, upper) = ip.get_auto_threshold(Method::Otsu, true);
+/// let binary = ip.threshold(lower - 1.0);
+/// assert_eq!(*binary.data(), vec![0, 0, 255, 255]);
+/// ```
+pub trait Threshold<T: PixelType> {
+    ///
+    /// Returns the lower and upper threshold levels of the objects computed with `method`.
+    ///
+    /// With `dark_background`, the objects are brighter than the background.
+    ///
+    fn get_auto_threshold(&mut self, method: Method, dark_background: bool) -> (f64, f64);
+
+    ///
+    /// Returns a binary image where the pixels less than or equal to `level` are set to 0
+    /// and all other pixels to 255.
+    ///
+    fn threshold(&self, level: f64) -> ImageProcessor<u8, Gray8>;
+
+    ///
+    /// Returns a binary image with the objects set to 255, using an automatic threshold.
+    ///
+    fn auto_threshold(
+        &mut self,
+        method: Method,
+        dark_background: bool,
+    ) -> ImageProcessor<u8, Gray8>;
+}
+
+impl<T: PixelType> Threshold<T> for ImageProcessor<T, Gray<T>>
+where
+    ImageProcessor<T, Gray<T>>: Statistics<T>,
+{
+    fn get_auto_threshold(&mut self, method: Method, dark_background: bool) -> (f64, f64) {
+        self.update_stats();
+        let (min, max) = (self.min_value(), self.max_value());
+        let hist = self.histogram();
+        let mut hist = if hist.len() == 256 {
+            hist.clone()
+        } else {
+            // Full-range histogram (16-bit): 256 bins between min and max
+            let mut bins = vec![0u32; 256];
+            let scale = if max > min { 256.0 / (max - min) } else { 0.0 };
+            for v in min as usize..=max as usize {
+                let index = ((v as f64 - min) * scale) as usize;
+                bins[index.min(255)] += hist[v];
+            }
+            bins
+        };
+        let level = get_threshold(method, &mut hist) as f64;
+        let (mut lower, upper) = if dark_background {
+            (level + 1.0, 255.0)
+        } else {
+            (0.0, level)
+        };
+        lower = lower.min(255.0);
+        if self.get_bit_depth() == 8 {
+            (lower, upper)
+        } else if max > min {
+            (
+                min + (lower / 255.0) * (max - min),
+                min + (upper / 255.0) * (max - min),
+            )
+        } else {
+            (min, min)
+        }
+    }
+
+    fn threshold(&self, level: f64) -> ImageProcessor<u8, Gray8> {
+        let pixels = self
+            .data
+            .iter()
+            .map(|v| if v.to_f32() as f64 <= level { 0 } else { 255 })
+            .collect();
+        ImageProcessor::new_volume(self.width, self.height, self.depth, pixels, Gray8::new())
+    }
+
+    fn auto_threshold(
+        &mut self,
+        method: Method,
+        dark_background: bool,
+    ) -> ImageProcessor<u8, Gray8> {
+        let (lower, upper) = self.get_auto_threshold(method, dark_background);
+        let pixels = self
+            .data
+            .iter()
+            .map(|v| {
+                let v = v.to_f32() as f64;
+                if v >= lower && v <= upper {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+        ImageProcessor::new_volume(self.width, self.height, self.depth, pixels, Gray8::new())
+    }
+}
+
+///
+/// Returns the threshold level (bin index) of a histogram computed with `method`.
+///
+/// The pixels of the bins less than or equal to the level belong to the first class.
+/// The histogram is restored after the computation.
+///
+pub fn get_threshold(method: Method, histogram: &mut [u32]) -> i32 {
+    let data: Vec<f64> = histogram.iter().map(|v| *v as f64).collect();
+    let threshold = match method {
+        Method::Default => ij_default(histogram),
+        Method::Huang => huang(&data),
+        Method::Intermodes => intermodes(&data),
+        Method::IsoData => iso_data(histogram),
+        Method::Li => li(&data),
+        Method::MaxEntropy => max_entropy(&data),
+        Method::Mean => mean(&data),
+        Method::MinError => min_error(&data),
+        Method::Minimum => minimum(&data),
+        Method::Moments => moments(&data),
+        Method::Otsu => otsu(&data),
+        Method::Percentile => percentile(&data),
+        Method::RenyiEntropy => renyi_entropy(&data),
+        Method::Shanbhag => shanbhag(&data),
+        Method::Triangle => triangle(histogram),
+        Method::Yen => yen(&data),
+    };
+    threshold.max(0)
+}
+
+//
+// Private functions: ports of ImageJ AutoThresholder
+//
+
+// Modified IsoData method used by the "Threshold" widget in "Default" mode
+fn ij_default(data: &mut [u32]) -> i32 {
+    let max_value = data.len() - 1;
+    let count0 = data[0];
+    data[0] = 0; // set to zero so erased areas aren't included
+    let count_max = data[max_value];
+    data[max_value] = 0;
+    let mut min = 0;
+    while data[min] == 0 && min < max_value {
+        min += 1;
+    }
+    let mut max = max_value;
+    while data[max] == 0 && max > 0 {
+        max -= 1;
+    }
+    if min >= max {
+        data[0] = count0;
+        data[max_value] = count_max;
+        return (data.len() / 2) as i32;
+    }
+    let mut moving_index = min;
+    let mut result;
+    loop {
+        let (mut sum1, mut sum2, mut sum3, mut sum4) = (0.0, 0.0, 0.0, 0.0);
+        for (i, v) in data.iter().enumerate().take(moving_index + 1).skip(min) {
+            sum1 += i as f64 * *v as f64;
+            sum2 += *v as f64;
+        }
+        for (i, v) in data.iter().enumerate().take(max + 1).skip(moving_index + 1) {
+            sum3 += i as f64 * *v as f64;
+            sum4 += *v as f64;
+        }
+        result = (sum1 / sum2 + sum3 / sum4) / 2.0;
+        moving_index += 1;
+        if !((moving_index + 1) as f64 <= result && moving_index < max - 1) {
+            break;
+        }
+    }
+    data[0] = count0;
+    data[max_value] = count_max;
+    result.round() as i32
+}
+
+// Huang L-K & Wang M-J J. (1995) Image Thresholding by Minimizing the Measures of Fuzziness
+fn huang(data: &[f64]) -> i32 {
+    let n = data.len();
+    let first_bin = data.iter().position(|v| *v != 0.0).unwrap_or(0);
+    let last_bin = (first_bin..n)
+        .rev()
+        .find(|i| data[*i] != 0.0)
+        .unwrap_or(n - 1);
+    let term = 1.0 / (last_bin as f64 - first_bin as f64);
+    let mut mu_0 = vec![0.0; n];
+    let (mut sum_pix, mut num_pix) = (0.0, 0.0);
+    for ih in first_bin..n {
+        sum_pix += ih as f64 * data[ih];
+        num_pix += data[ih];
+        mu_0[ih] = sum_pix / num_pix;
+    }
+    let mut mu_1 = vec![0.0; n];
+    let (mut sum_pix, mut num_pix) = (0.0, 0.0);
+    for ih in (1..=last_bin).rev() {
+        sum_pix += ih as f64 * data[ih];
+        num_pix += data[ih];
+        mu_1[ih - 1] = sum_pix / num_pix;
+    }
+    let entropy = |ih: usize, mu: f64| -> f64 {
+        let mu_x = 1.0 / (1.0 + term * (ih as f64 - mu).abs());
+        if !(1e-06..=0.999999).contains(&mu_x) {
+            0.0
+        } else {
+            data[ih] * (-mu_x * mu_x.ln() - (1.0 - mu_x) * (1.0 - mu_x).ln())
+        }
+    };
+    let mut threshold = -1;
+    let mut min_ent = f64::MAX;
+    for it in 0..n {
+        let mut ent = 0.0;
+        for ih in 0..=it {
+            ent += entropy(ih, mu_0[it]);
+        }
+        for ih in it + 1..n {
+            ent += entropy(ih, mu_1[it]);
+        }
+        if ent < min_ent {
+            min_ent = ent;
+            threshold = it as i32;
+        }
+    }
+    threshold
+}
+
+fn bimodal_test(y: &[f64]) -> bool {
+    let mut modes = 0;
+    for k in 1..y.len() - 1 {
+        if y[k - 1] < y[k] && y[k + 1] < y[k] {
+            modes += 1;
+            if modes > 2 {
+                return false;
+            }
+        }
+    }
+    modes == 2
+}
+
+// Prewitt & Mendelsohn (1966): mean of the two peaks of the smoothed histogram
+fn intermodes(data: &[f64]) -> i32 {
+    let n = data.len();
+    let mut histo = data.to_vec();
+    let mut iter = 0;
+    while !bimodal_test(&histo) {
+        // Smooth with a 3 point running mean filter
+        let (mut current, mut next) = (0.0, histo[0]);
+        for i in 0..n - 1 {
+            let previous = current;
+            current = next;
+            next = histo[i + 1];
+            histo[i] = (previous + current + next) / 3.0;
+        }
+        histo[n - 1] = (current + next) / 3.0;
+        iter += 1;
+        if iter > 10000 {
+            return -1;
+        }
+    }
+    let tt: usize = (1..n - 1)
+        .filter(|i| histo[i - 1] < histo[*i] && histo[i + 1] < histo[*i])
+        .sum();
+    (tt as f64 / 2.0).floor() as i32
+}
+
+// Ridler & Calvard (1978) iterative intermeans
+fn iso_data(data: &[u32]) -> i32 {
+    let n = data.len();
+    let mut g = match (1..n).find(|i| data[*i] > 0) {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    loop {
+        let (mut l, mut totl) = (0i64, 0i64);
+        for (i, v) in data.iter().enumerate().take(g + 1) {
+            totl += *v as i64;
+            l += *v as i64 * i as i64;
+        }
+        let (mut h, mut toth) = (0i64, 0i64);
+        for (i, v) in data.iter().enumerate().skip(g + 1) {
+            toth += *v as i64;
+            h += *v as i64 * i as i64;
+        }
+        if totl > 0 && toth > 0 {
+            // Integer means like ImageJ
+            l /= totl;
+            h /= toth;
+            if g as i64 == ((l + h) as f64 / 2.0).round() as i64 {
+                return g as i32;
+            }
+        }
+        g += 1;
+        if g > n - 2 {
+            return -1;
+        }
+    }
+}
+
+// Li & Tam (1998) iterative minimum cross entropy
+fn li(data: &[f64]) -> i32 {
+    let n = data.len();
+    let num_pixels: f64 = data.iter().sum();
+    let mean: f64 = data
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, v)| i as f64 * v)
+        .sum::<f64>()
+        / num_pixels;
+    let tolerance = 0.5;
+    let mut new_thresh = mean;
+    let mut threshold;
+    loop {
+        let old_thresh = new_thresh;
+        threshold = (old_thresh + 0.5) as i32;
+        let t = (threshold.max(-1) + 1) as usize;
+        let (mut sum_back, mut num_back) = (0.0, 0.0);
+        for (ih, v) in data.iter().enumerate().take(t.min(n)) {
+            sum_back += ih as f64 * v;
+            num_back += v;
+        }
+        let mean_back = if num_back == 0.0 {
+            0.0
+        } else {
+            sum_back / num_back
+        };
+        let (mut sum_obj, mut num_obj) = (0.0, 0.0);
+        for (ih, v) in data.iter().enumerate().skip(t) {
+            sum_obj += ih as f64 * v;
+            num_obj += v;
+        }
+        let mean_obj = if num_obj == 0.0 {
+            0.0
+        } else {
+            sum_obj / num_obj
+        };
+        let temp = (mean_back - mean_obj) / (mean_back.ln() - mean_obj.ln());
+        new_thresh = if temp < -f64::EPSILON {
+            (temp - 0.5) as i32 as f64
+        } else {
+            (temp + 0.5) as i32 as f64
+        };
+        if (new_thresh - old_thresh).abs() <= tolerance {
+            break;
+        }
+    }
+    threshold
+}
 
+// Normalized histogram and cumulative sums P1 and P2 = 1 - P1
+fn cumulative(data: &[f64]) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    let total: f64 = data.iter().sum();
+    let norm: Vec<f64> = data.iter().map(|v| v / total).collect();
+    let mut p1 = vec![0.0; data.len()];
+    let mut p2 = vec![0.0; data.len()];
+    p1[0] = norm[0];
+    p2[0] = 1.0 - p1[0];
+    for ih in 1..data.len() {
+        p1[ih] = p1[ih - 1] + norm[ih];
+        p2[ih] = 1.0 - p1[ih];
+    }
+    (norm, p1, p2)
+}
 
-    /// Returns a pixel value (threshold) that can be used to divide the image i32o objects and background.
-    fn  get_auto_threshold() -> T {};
+// First and last bins with non-null cumulative sums
+fn entropy_bins(p1: &[f64], p2: &[f64]) -> (usize, usize) {
+    let n = p1.len();
+    let first_bin = (0..n).find(|i| p1[*i].abs() >= f64::EPSILON).unwrap_or(0);
+    let last_bin = (first_bin..n)
+        .rev()
+        .find(|i| p2[*i].abs() >= f64::EPSILON)
+        .unwrap_or(n - 1);
+    (first_bin, last_bin)
+}
 
-    /// This is a version of get_Auto_Threshold() that uses a histogram passed as an argument.
-    fn get_auto_threshold​(i32[] histogram) -> T {};
+// Shannon entropies of background and object at a given threshold
+fn shannon_entropy(data: &[f64], norm: &[f64], p1: &[f64], p2: &[f64], it: usize) -> f64 {
+    let mut ent_back = 0.0;
+    for ih in 0..=it {
+        if data[ih] != 0.0 {
+            ent_back -= (norm[ih] / p1[it]) * (norm[ih] / p1[it]).ln();
+        }
+    }
+    let mut ent_obj = 0.0;
+    for ih in it + 1..data.len() {
+        if data[ih] != 0.0 {
+            ent_obj -= (norm[ih] / p2[it]) * (norm[ih] / p2[it]).ln();
+        }
+    }
+    ent_back + ent_obj
+}
 
-    /// Returns the upper threshold level.
-    f64 get_Max_Threshold() {};
+// Kapur, Sahoo & Wong (1985) maximum entropy
+fn max_entropy(data: &[f64]) -> i32 {
+    let (norm, p1, p2) = cumulative(data);
+    let (first_bin, last_bin) = entropy_bins(&p1, &p2);
+    let mut threshold = -1;
+    // Double.MIN_VALUE of Java
+    let mut max_ent = f64::from_bits(1);
+    for it in first_bin..=last_bin {
+        let tot_ent = shannon_entropy(data, &norm, &p1, &p2, it);
+        if max_ent < tot_ent {
+            max_ent = tot_ent;
+            threshold = it as i32;
+        }
+    }
+    threshold
+}
 
-    /// Returns the lower threshold level.
-    f64 get_min_threshold() {};
+// Glasbey (1993): mean of the gray levels
+fn mean(data: &[f64]) -> i32 {
+    let tot: f64 = data.iter().sum();
+    let sum: f64 = data.iter().enumerate().map(|(i, v)| i as f64 * v).sum();
+    (sum / tot).floor() as i32
+}
 
-    /// Resets the threshold if mi32hreshold=max_Threshold and lut_Update_Mode=NO_LUT_UPDATE.
-    fn reset_binary_threshold() {};
+// Partial sums of the moments of order 0, 1 and 2
+fn moment_sum(y: &[f64], j: i32, order: i32) -> f64 {
+    y.iter()
+        .enumerate()
+        .take((j + 1).max(0) as usize)
+        .map(|(i, v)| (i as f64).powi(order) * v)
+        .sum()
+}
 
-    /// Disables thresholding.
-    /// Image_Processor resize​(i32 dst_Width) {}
-    /// Returns a new Image_Processor containing a scaled copy of this image or ROI, with the aspect ratio mai32ained.
-    fn reset_threshold() {};
-    
-    /// Automatically sets the lower and upper threshold levels, 
-    /// where 'method' must be ISODATA or ISODATA2 and 
-    /// 'lut_Update' must be RED_LUT, BLACK_AND_WHITE_LUT, OVER_UNDER_LUT or NO_LUT_UPDATE.
-    fn set_Auto_Threshold​(i32 method, i32 lut_Update) {};
+// Kittler & Illingworth (1986) minimum error thresholding
+fn min_error(data: &[f64]) -> i32 {
+    let end = data.len() as i32 - 1;
+    let a = |j: i32| moment_sum(data, j, 0);
+    let b = |j: i32| moment_sum(data, j, 1);
+    let c = |j: i32| moment_sum(data, j, 2);
+    // Initial estimate with the Mean method
+    let mut threshold = mean(data);
+    let mut t_prev = -2;
+    while threshold != t_prev {
+        let mu = b(threshold) / a(threshold);
+        let nu = (b(end) - b(threshold)) / (a(end) - a(threshold));
+        let p = a(threshold) / a(end);
+        let q = (a(end) - a(threshold)) / a(end);
+        let sigma2 = c(threshold) / a(threshold) - mu * mu;
+        let tau2 = (c(end) - c(threshold)) / (a(end) - a(threshold)) - nu * nu;
+        // Terms of the quadratic equation
+        let w0 = 1.0 / sigma2 - 1.0 / tau2;
+        let w1 = mu / sigma2 - nu / tau2;
+        let w2 =
+            (mu * mu) / sigma2 - (nu * nu) / tau2 + ((sigma2 * (q * q)) / (tau2 * (p * p))).log10();
+        // If the next threshold would be imaginary, return with the current one
+        let sqterm = w1 * w1 - w0 * w2;
+        if sqterm < 0.0 {
+            break;
+        }
+        t_prev = threshold;
+        let temp = (w1 + sqterm.sqrt()) / w0;
+        threshold = if temp.is_nan() {
+            t_prev
+        } else {
+            temp.floor() as i32
+        };
+    }
+    threshold
+}
 
-    fn set_Auto_Threshold​(Auto_Thresholder.Method method, bool dark_Background;
+// Prewitt & Mendelsohn (1966): minimum between the two peaks of the smoothed histogram
+fn minimum(data: &[f64]) -> i32 {
+    let n = data.len();
+    if n < 2 {
+        return 0;
+    }
+    let max = data.iter().rposition(|v| *v > 0.0).map_or(-1, |i| i as i32);
+    let mut histo = data.to_vec();
+    let mut t_histo = vec![0.0; n];
+    let mut iter = 0;
+    while !bimodal_test(&histo) {
+        // Smooth with a 3 point running mean filter
+        for i in 1..n - 1 {
+            t_histo[i] = (histo[i - 1] + histo[i] + histo[i + 1]) / 3.0;
+        }
+        t_histo[0] = (histo[0] + histo[1]) / 3.0;
+        t_histo[n - 1] = (histo[n - 2] + histo[n - 1]) / 3.0;
+        histo.copy_from_slice(&t_histo);
+        iter += 1;
+        if iter > 10000 {
+            return -1;
+        }
+    }
+    (1..max.max(0) as usize)
+        .find(|i| histo[i - 1] > histo[*i] && histo[i + 1] >= histo[*i])
+        .map_or(-1, |i| i as i32)
+}
+
+// Tsai (1985) moment-preserving thresholding
+fn moments(data: &[f64]) -> i32 {
+    let total: f64 = data.iter().sum();
+    let histo: Vec<f64> = data.iter().map(|v| v / total).collect();
+    let m0 = 1.0;
+    let (mut m1, mut m2, mut m3) = (0.0, 0.0, 0.0);
+    for (i, h) in histo.iter().enumerate() {
+        let di = i as f64;
+        m1 += di * h;
+        m2 += di * di * h;
+        m3 += di * di * di * h;
+    }
+    let cd = m0 * m2 - m1 * m1;
+    let c0 = (-m2 * m2 + m1 * m3) / cd;
+    let c1 = (m0 * -m3 + m2 * m1) / cd;
+    let z0 = 0.5 * (-c1 - (c1 * c1 - 4.0 * c0).sqrt());
+    let z1 = 0.5 * (-c1 + (c1 * c1 - 4.0 * c0).sqrt());
+    // Fraction of the object pixels in the target binary image
+    let p0 = (z1 - m1) / (z1 - z0);
+    let mut sum = 0.0;
+    for (i, h) in histo.iter().enumerate() {
+        sum += h;
+        if sum > p0 {
+            return i as i32;
+        }
+    }
+    -1
+}
 
-    fn set_Auto_Threshold​(Auto_Thresholder.Method method, bool dark_Background, i32 lut_Update;
+// Otsu (1979) maximum between-class variance
+fn otsu(data: &[f64]) -> i32 {
+    let l = data.len();
+    let n: f64 = data.iter().sum();
+    let s: f64 = data.iter().enumerate().map(|(k, v)| k as f64 * v).sum();
+    let mut sk = 0.0;
+    let mut n1 = data[0];
+    let mut bcv_max = 0.0;
+    let mut k_star = 0;
+    for k in 1..l - 1 {
+        sk += k as f64 * data[k];
+        n1 += data[k];
+        let denom = n1 * (n - n1);
+        let bcv = if denom != 0.0 {
+            let num = (n1 / n) * s - sk;
+            (num * num) / denom
+        } else {
+            0.0
+        };
+        if bcv >= bcv_max {
+            bcv_max = bcv;
+            k_star = k as i32;
+        }
+    }
+    k_star
+}
+
+// Doyle (1962): 50% of the pixels in the foreground
+fn percentile(data: &[f64]) -> i32 {
+    let ptile = 0.5;
+    let total: f64 = data.iter().sum();
+    let mut threshold = -1;
+    let mut temp = 1.0;
+    let mut partial = 0.0;
+    for (i, v) in data.iter().enumerate() {
+        partial += v;
+        let avec = (partial / total - ptile).abs();
+        if avec < temp {
+            temp = avec;
+            threshold = i as i32;
+        }
+    }
+    threshold
+}
 
-    /// Automatically sets the lower and upper threshold levels, 
-    /// where 'method' must be "Default", "Huang", "i32ermodes", 
-    /// "Iso_Data", "IJ_Iso_Data", "Li", "Max_Entropy", "Mean", 
-    /// "Min_Error", "Minimum", "Moments", "Otsu", "Percentile", 
-    /// "Renyi_Entropy", "Shanbhag", "Triangle" or "Yen".
-    fn set_Auto_Threshold​(java.lang.String method) {};
+// Kapur, Sahoo & Wong (1985) with the Renyi entropies of order 0.5, 1 and 2
+fn renyi_entropy(data: &[f64]) -> i32 {
+    let n = data.len();
+    let (norm, p1, p2) = cumulative(data);
+    let (first_bin, last_bin) = entropy_bins(&p1, &p2);
 
-    fn set_Auto_Threshold​(java.lang.String m_String, bool dark_Background, i32 lut_Update)  {};
+    // Maximum entropy (alpha = 1)
+    let mut threshold = 0;
+    let mut max_ent = 0.0;
+    for it in first_bin..=last_bin {
+        let tot_ent = shannon_entropy(data, &norm, &p1, &p2, it);
+        if max_ent < tot_ent {
+            max_ent = tot_ent;
+            threshold = it;
+        }
+    }
+    let t_star2 = threshold;
 
-    fn set_Binary_Threshold() {};
+    let renyi = |alpha: f64| -> usize {
+        let term = 1.0 / (1.0 - alpha);
+        let mut threshold = 0;
+        let mut max_ent = 0.0;
+        for it in first_bin..=last_bin {
+            let (mut ent_back, mut ent_obj) = (0.0, 0.0);
+            if alpha == 0.5 {
+                for ih in 0..=it {
+                    ent_back += (norm[ih] / p1[it]).sqrt();
+                }
+                for ih in it + 1..n {
+                    ent_obj += (norm[ih] / p2[it]).sqrt();
+                }
+            } else {
+                for ih in 0..=it {
+                    ent_back += (norm[ih] * norm[ih]) / (p1[it] * p1[it]);
+                }
+                for ih in it + 1..n {
+                    ent_obj += (norm[ih] * norm[ih]) / (p2[it] * p2[it]);
+                }
+            }
+            let product = ent_back * ent_obj;
+            let tot_ent = term * if product > 0.0 { product.ln() } else { 0.0 };
+            if tot_ent > max_ent {
+                max_ent = tot_ent;
+                threshold = it;
+            }
+        }
+        threshold
+    };
+    let t_star1 = renyi(0.5);
+    let t_star3 = renyi(2.0);
 
-   fn set_threshold​(f64 min_threshold, f64 max_threshold) {}
-    /// Sets the lower and upper threshold levels using NO_LUT_UPDATE.
+    // Sort the t_star values
+    let mut t = [t_star1, t_star2, t_star3];
+    t.sort_unstable();
+    let [t_star1, t_star2, t_star3] = t;
 
-    fn set_threshold​(f64 min_threshold, f64 max_threshold, i32 lut_Update) {}
-    /// Sets the lower and upper threshold levels.
+    // Adjust the beta values
+    let close12 = t_star2 - t_star1 <= 5;
+    let close23 = t_star3 - t_star2 <= 5;
+    let (beta1, beta2, beta3) = match (close12, close23) {
+        (true, true) => (1.0, 2.0, 1.0),
+        (true, false) => (0.0, 1.0, 3.0),
+        (false, true) => (3.0, 1.0, 0.0),
+        (false, false) => (1.0, 2.0, 1.0),
+    };
+    let omega = p1[t_star3] - p1[t_star1];
+    (t_star1 as f64 * (p1[t_star1] + 0.25 * omega * beta1)
+        + 0.25 * t_star2 as f64 * omega * beta2
+        + t_star3 as f64 * (p2[t_star3] + 0.25 * omega * beta3)) as i32
+}
 
-    fn threshold​(i32 level) {}
-    /// Sets pixels less than or equal to level to 0 and all other pixels to 255.
+// Shanbhag (1994) fuzzy entropy
+fn shanbhag(data: &[f64]) -> i32 {
+    let n = data.len();
+    let (norm, p1, p2) = cumulative(data);
+    let (first_bin, last_bin) = entropy_bins(&p1, &p2);
+    let mut threshold = -1;
+    let mut min_ent = f64::MAX;
+    for it in first_bin..=last_bin {
+        let term = 0.5 / p1[it];
+        let mut ent_back = 0.0;
+        for ih in 1..=it {
+            ent_back -= norm[ih] * (1.0 - term * p1[ih - 1]).ln();
+        }
+        ent_back *= term;
+        let term = 0.5 / p2[it];
+        let mut ent_obj = 0.0;
+        for ih in it + 1..n {
+            ent_obj -= norm[ih] * (1.0 - term * p2[ih]).ln();
+        }
+        ent_obj *= term;
+        let tot_ent = (ent_back - ent_obj).abs();
+        if tot_ent < min_ent {
+            min_ent = tot_ent;
+            threshold = it as i32;
+        }
+    }
+    threshold
+}
 
-    /// Set the lower Over/Under thresholding color.
-    static fn set_under_color​(i32 red, i32 green, i32 blue) {}
+// Zack, Rogers & Latt (1977) triangle method
+fn triangle(data: &mut [u32]) -> i32 {
+    let n = data.len();
+    // Line to the (p==0) point, not to data[min]
+    let mut min = data.iter().position(|v| *v > 0).unwrap_or(0).saturating_sub(1);
+    let mut min2 = (1..n).rev().find(|i| data[*i] > 0).unwrap_or(0);
+    if min2 < n - 1 {
+        min2 += 1;
+    }
+    let (mut max, mut dmax) = (0, 0);
+    for (i, v) in data.iter().enumerate() {
+        if *v > dmax {
+            max = i;
+            dmax = *v;
+        }
+    }
+    // Process the furthest side of the peak
+    let inverted = (max as i64 - min as i64) < (min2 as i64 - max as i64);
+    if inverted {
+        data.reverse();
+        min = n - 1 - min2;
+        max = n - 1 - max;
+    }
+    if min == max {
+        if inverted {
+            data.reverse();
+        }
+        return min as i32;
+    }
+    // Line described by nx * x + ny * y - d = 0
+    let mut nx = data[max] as f64;
+    let mut ny = min as f64 - max as f64;
+    let d = (nx * nx + ny * ny).sqrt();
+    nx /= d;
+    ny /= d;
+    let d = nx * min as f64 + ny * data[min] as f64;
+    // Split point
+    let mut split = min as i32;
+    let mut split_distance = 0.0;
+    for i in min + 1..=max {
+        let new_distance = nx * i as f64 + ny * data[i] as f64 - d;
+        if new_distance > split_distance {
+            split = i as i32;
+            split_distance = new_distance;
+        }
+    }
+    split -= 1;
+    if inverted {
+        data.reverse();
+        n as i32 - 1 - split
+    } else {
+        split
+    }
+}
 
- 
+// Yen, Chang & Chang (1995) maximum correlation criterion
+fn yen(data: &[f64]) -> i32 {
+    let n = data.len();
+    let (norm, p1, _) = cumulative(data);
+    let mut p1_sq = vec![0.0; n];
+    p1_sq[0] = norm[0] * norm[0];
+    for ih in 1..n {
+        p1_sq[ih] = p1_sq[ih - 1] + norm[ih] * norm[ih];
+    }
+    let mut p2_sq = vec![0.0; n];
+    for ih in (0..n - 1).rev() {
+        p2_sq[ih] = p2_sq[ih + 1] + norm[ih + 1] * norm[ih + 1];
+    }
+    let mut threshold = -1;
+    let mut max_crit = f64::from_bits(1);
+    for it in 0..n {
+        let sq = p1_sq[it] * p2_sq[it];
+        let p = p1[it] * (1.0 - p1[it]);
+        let crit =
+            -(if sq > 0.0 { sq.ln() } else { 0.0 }) + 2.0 * if p > 0.0 { p.ln() } else { 0.0 };
+        if crit > max_crit {
+            max_crit = crit;
+            threshold = it as i32;
+        }
+    }
+    threshold
 }
 
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::io::tiff_decoder::TiffDecoder;
+
+    // Two Gaussian-like peaks centered at 60 (dark, larger) and 180 (bright)
+    fn bimodal() -> Vec<u32> {
+        (0..256)
+            .map(|i| {
+                let g1 = 3000.0 * (-((i as f64 - 60.0) / 12.0).powi(2) / 2.0).exp();
+                let g2 = 1000.0 * (-((i as f64 - 180.0) / 15.0).powi(2) / 2.0).exp();
+                (g1 + g2).round() as u32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn all_methods_split_bimodal_histogram() {
+        let methods = [
+            Method::Default,
+            Method::Huang,
+            Method::Intermodes,
+            Method::IsoData,
+            Method::Li,
+            Method::MaxEntropy,
+            Method::Mean,
+            Method::MinError,
+            Method::Minimum,
+            Method::Moments,
+            Method::Otsu,
+            Method::RenyiEntropy,
+            Method::Shanbhag,
+            Method::Triangle,
+            Method::Yen,
+        ];
+        for method in methods {
+            let mut hist = bimodal();
+            let t = get_threshold(method, &mut hist);
+            assert!(t > 75 && t < 165, "{:?} gives {}", method, t);
+            // The histogram is restored
+            assert_eq!(hist, bimodal());
+        }
+    }
+
+    // Histogram of the first projection of the sample tilt series (levels 0..174)
+    fn sample_histogram() -> Vec<u32> {
+        let (output, _) =
+            TiffDecoder::open("./samples/projections-t1-head-psi-theta-phi-50.tif").unwrap();
+        let stack = output.into_float_stack().unwrap();
+        let mut hist = vec![0u32; 256];
+        for v in stack.data[0].iter() {
+            hist[*v as usize] += 1;
+        }
+        hist
+    }
+
+    #[test]
+    fn imagej_thresholds_of_sample() {
+        // Levels given by ImageJ AutoThresholder for the whole histogram and without
+        // the background (bin 0)
+        let expected = [
+            (Method::Default, 79, 79),
+            (Method::Huang, 14, 112),
+            (Method::Intermodes, 76, 77),
+            (Method::IsoData, 60, 80),
+            (Method::Li, 18, 42),
+            (Method::MaxEntropy, 15, 97),
+            (Method::Mean, 21, 93),
+            (Method::MinError, 0, 132),
+            (Method::Minimum, 37, 33),
+            (Method::Moments, 74, 83),
+            (Method::Otsu, 60, 80),
+            (Method::Percentile, 0, 105),
+            (Method::RenyiEntropy, 20, 97),
+            (Method::Shanbhag, 167, 101),
+            (Method::Triangle, 2, 13),
+            (Method::Yen, 7, 104),
+        ];
+        let mut hist = sample_histogram();
+        let mut foreground = hist.clone();
+        foreground[0] = 0;
+        for (method, all, without_background) in expected {
+            assert_eq!(get_threshold(method, &mut hist), all, "{:?}", method);
+            assert_eq!(
+                get_threshold(method, &mut foreground),
+                without_background,
+                "{:?} without background",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn simple_histograms() {
+        // Mean of 0 x 1 and 10 x 1 is 5
+        let mut hist = vec![0u32; 256];
+        hist[0] = 1;
+        hist[10] = 1;
+        assert_eq!(get_threshold(Method::Mean, &mut hist), 5);
+        // Half of the pixels below the percentile threshold
+        let mut hist = vec![1u32; 256];
+        assert_eq!(get_threshold(Method::Percentile, &mut hist), 127);
+        // Two Diracs: all the levels in between have the same between-class variance
+        // and Otsu keeps the last one
+        let mut hist = vec![0u32; 256];
+        hist[50] = 100;
+        hist[200] = 100;
+        assert_eq!(get_threshold(Method::Otsu, &mut hist), 199);
+        assert_eq!(get_threshold(Method::Default, &mut hist), 125);
+        assert_eq!(get_threshold(Method::IsoData, &mut hist), 125);
+    }
+
+    #[test]
+    fn threshold_byte_and_float_processors() {
+        let pixels: Vec<u8> = vec![10, 12, 11, 200, 210, 205, 10, 202];
+        let mut ip = ImageProcessor::<u8, Gray8>::new(4, 2, pixels.clone(), Gray8::new());
+        let (lower, upper) = ip.get_auto_threshold(Method::Otsu, true);
+        assert!(lower > 12.0 && lower <= 200.0 && upper == 255.0);
+        let binary = ip.auto_threshold(Method::Otsu, true);
+        assert_eq!(*binary.data(), vec![0, 0, 0, 255, 255, 255, 0, 255]);
+        let binary = ip.auto_threshold(Method::Otsu, false);
+        assert_eq!(*binary.data(), vec![255, 255, 255, 0, 0, 0, 255, 0]);
+        assert_eq!(
+            *ip.threshold(11.0).data(),
+            vec![0, 255, 0, 255, 255, 255, 0, 255]
+        );
+
+        let mut fp = ImageProcessor::<f32, Gray<f32>>::new(
+            4,
+            2,
+            pixels.iter().map(|v| *v as f32 / 100.0).collect(),
+            Gray::<f32>::new(),
+        );
+        let binary = fp.auto_threshold(Method::Default, true);
+        assert_eq!(*binary.data(), vec![0, 0, 0, 255, 255, 255, 0, 255]);
+    }
+
+    #[test]
+    fn threshold_short_processor() {
+        let pixels: Vec<u16> = vec![1000, 1200, 1100, 40000, 42000, 41000, 1000, 40500];
+        let mut ip = ImageProcessor::<u16, Gray<u16>>::new(4, 2, pixels, Gray::<u16>::new());
+        let binary = ip.auto_threshold(Method::Default, true);
+        assert_eq!(*binary.data(), vec![0, 0, 0, 255, 255, 255, 0, 255]);
+    }
+}
@@ -16,86 +16,628 @@
 //
 //  You should have received a copy of the GNU General Public License
 //  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
- 
 
-enum InterpolationMode {
-  Nearest,
-  Bilinear,
-  Bicubic
+use crate::color_space::ColorSpace;
+use crate::grayscale::Gray;
+use crate::image_processor::ImageProcessor;
+use crate::meta_data::MetaData;
+use crate::pixel::PixelType;
+use crate::vecmath::matrix4::Matrix4;
+
+///
+/// Interpolation methods used by the geometric transforms
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterpolationMode {
+    Nearest,
+    Bilinear,
+    /// Catmull-Rom kernel, see [cubic()]
+    Bicubic,
 }
 
-pub trait Transform<T: PixelType> {
+///
+/// Canvas handling of [rotate()](Transform::rotate)
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Canvas {
+    /// The image keeps its size, the corners of the rotated image are cropped.
+    Keep,
+    /// The canvas is enlarged (and centered) to fit the whole rotated image. The ROI is removed.
+    Enlarge,
+}
 
-    static fn cubic​( x: f64) -> f64;
-    
-    /// Flips the image or ROI horizontally.
-    fn flip_horizontal(&self);
+///
+/// Kernel of the bicubic interpolation (Catmull-Rom, a = 0.5) as defined in ImageJ.
+///
+pub fn cubic(x: f64) -> f64 {
+    let a = 0.5;
+    let x = x.abs();
+    if x < 1.0 {
+        x * x * (x * (-a + 2.0) + (a - 3.0)) + 1.0
+    } else if x < 2.0 {
+        -a * x * x * x + 5.0 * a * x * x - 8.0 * a * x + 4.0 * a
+    } else {
+        0.0
+    }
+}
 
-    /// Flips the image or ROI vertically.
-    fn flip_vertical(&self);
-    
-    /// This method is from Chapter 16 of "Digital Image Processing: An Algorithmic introduction Using Java" by Burger and Burge 
-    /// (http://www.imagingbook.com/).
-    fn f64 get_bicubic_interpolated_pixel​(f64 x0, f64 y0, Image_Processor ip2) {}
+///
+/// Geometric transforms of 2D images (each plane of a volume is transformed independently).
+///
+/// The transforms are restricted to the active ROI, except [resize()](Transform::resize)
+/// which crops the image to the bounding box of the ROI. The pixels uncovered by the
+/// transform are set to the background value.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::grayscale::Gray8;
+/// use rim::image_processor::ImageProcessor;
+/// use rim::transformable::{Canvas, InterpolationMode, Transform};
+///
+/// let mut ip = ImageProcessor::<u8, Gray8>::new(2, 2, vec![1, 2, 3, 4], Gray8::new());
+/// ip.set_interpolation_method(InterpolationMode::Nearest);
+/// ip.rotate(90.0, Canvas::Keep);
+/// assert_eq!(*ip.data(), vec![3, 1, 4, 2]);
+/// ```
+pub trait Transform {
+    /// Sets the interpolation method used by `rotate()`, `scale()`, `translate()` and `resize()`.
+    fn set_interpolation_method(&mut self, method: InterpolationMode);
 
-    /// Returns the value of the interpolate field.
-    fn  get_interpolate() -> bool;
+    /// Returns the current interpolation method (bilinear by default).
+    fn get_interpolation_method(&self) -> InterpolationMode;
 
-    /// Uses the current interpolation method (bilinear or bicubic) to find the pixel value at real coordinates (x,y).
-    fn  get_interpolated_pixel​(f64 x, f64 y) -> f64;
+    /// Sets the value of the pixels uncovered by the transforms (0 by default).
+    fn set_background_value(&mut self, value: f64);
 
-    /// Uses the current interpolation method to find the pixel value at real coordinates (x,y).
-    fn get_Pixel_interpolated​(f64 x, f64 y) -> i32;
+    fn get_background_value(&self) -> f64;
 
-    /// Uses bilinear i32erpolation to find the pixel value at real coordinates (x,y).
-    fn get_interpolated_value​(f64 x, f64 y) -> f64;
+    ///
+    /// Uses the current interpolation method to find the pixel value at real coordinates (x,y)
+    /// of the first plane. Outside of the image, returns the background value.
+    ///
+    fn get_interpolated_pixel(&self, x: f64, y: f64) -> f64;
 
-    /// Returns the current i32erpolation method (NONE, BILINEAR or BICUBIC).
-    fn i32 get_interpolation_method();
+    ///
+    /// Bicubic interpolation at (x0,y0) from Chapter 16 of "Digital Image Processing: An
+    /// Algorithmic introduction Using Java" by Burger and Burge. Near the edges, falls back
+    /// to bilinear interpolation like ImageJ.
+    ///
+    fn get_bicubic_interpolated_pixel(&self, x0: f64, y0: f64) -> f64;
 
-    static java.lang.String[] get_interpolation_methods();
+    /// Flips the image or ROI horizontally.
+    fn flip_horizontal(&mut self);
 
-    /// Returns a new Image_Processor containing a scaled copy of this image or ROI.
-    /// Image_Processor resize​(i32 dst_Width, i32 dst_Height, bool use_Averging) {}
-    /// Returns a new Image_Processor containing a scaled copy of this image or ROI.
-    fn resize​(i32 dst_width, i32 dst_height) -> ImageProcessor<T,C>;
+    /// Flips the image or ROI vertically.
+    fn flip_vertical(&mut self);
 
-    /// Use linear interpolation to resize images that have a width or height of one.
-    fn resize_linearly​( width2: i32, height2: i32) -> ImageProcessor<T,C>;
+    /// Rotates the image or ROI `angle` degrees clockwise around its center.
+    fn rotate(&mut self, angle: f64, canvas: Canvas);
 
-    /// Rotates the image or selection 'angle' degrees clockwise.
-    /// Image_Processor rotate_Left() {}
     /// Rotates the entire image 90 degrees counter-clockwise.
-    /// Image_Processor rotate_Right() {}
+    fn rotate_left(&mut self);
+
     /// Rotates the entire image 90 degrees clockwise.
-    fn rotate​(&self, angle: f64);
+    fn rotate_right(&mut self);
+
+    /// Scales the image or ROI by the specified factors around its center. The size of the image is unchanged.
+    fn scale(&mut self, x_scale: f64, y_scale: f64);
+
+    /// Moves the image or ROI by a (possibly subpixel) offset.
+    fn translate(&mut self, x_offset: f64, y_offset: f64);
+
+    /// Returns a new processor containing a scaled copy of this image or ROI.
+    fn resize(&self, dst_width: u32, dst_height: u32) -> Self;
+}
+
+impl<T: PixelType> Transform for ImageProcessor<T, Gray<T>> {
+    fn set_interpolation_method(&mut self, method: InterpolationMode) {
+        self.metadata.set_interpolation(method);
+    }
+
+    fn get_interpolation_method(&self) -> InterpolationMode {
+        self.metadata.get_interpolation()
+    }
+
+    fn set_background_value(&mut self, value: f64) {
+        self.metadata.set_background(value);
+    }
+
+    fn get_background_value(&self) -> f64 {
+        self.metadata.get_background()
+    }
+
+    fn get_interpolated_pixel(&self, x: f64, y: f64) -> f64 {
+        self.plane(0).sample(x, y, self.get_interpolation_method())
+    }
+
+    fn get_bicubic_interpolated_pixel(&self, x0: f64, y0: f64) -> f64 {
+        self.plane(0).bicubic(x0, y0)
+    }
+
+    fn flip_horizontal(&mut self) {
+        let r = self.metadata.get_roi_rect();
+        self.remap(|x, y| (2 * r.x + r.width - 1 - x, y));
+    }
+
+    fn flip_vertical(&mut self) {
+        let r = self.metadata.get_roi_rect();
+        self.remap(|x, y| (x, 2 * r.y + r.height - 1 - y));
+    }
+
+    fn rotate(&mut self, angle: f64, canvas: Canvas) {
+        if canvas == Canvas::Enlarge {
+            self.enlarge_canvas(angle);
+        }
+        let r = self.metadata.get_roi_rect();
+        let center_x = r.x as f64 + (r.width - 1) as f64 / 2.0;
+        let center_y = r.y as f64 + (r.height - 1) as f64 / 2.0;
+        let angle_radians = -angle.to_radians();
+        let (sa, ca) = angle_radians.sin_cos();
+        let tmp1 = center_y * sa - center_x * ca;
+        let tmp2 = -center_x * sa - center_y * ca;
+        let (dwidth, dheight) = (self.width as f64, self.height as f64);
+        let method = self.get_interpolation_method();
+        self.map_geometry(|plane, x, y| {
+            let (x, y) = (x as f64, y as f64);
+            let xs = x * ca + tmp1 - y * sa + center_x;
+            let ys = x * sa + tmp2 + y * ca + center_y;
+            if method == InterpolationMode::Bicubic {
+                plane.bicubic(xs, ys)
+            } else if xs >= -0.01 && xs < dwidth && ys >= -0.01 && ys < dheight {
+                match method {
+                    InterpolationMode::Nearest => plane.get((xs + 0.5) as i64, (ys + 0.5) as i64),
+                    _ => plane.bilinear(plane.clamp_x(xs), plane.clamp_y(ys)),
+                }
+            } else {
+                plane.background
+            }
+        });
+    }
+
+    fn rotate_left(&mut self) {
+        let w = self.width as usize;
+        self.transpose(|x, y| (w - 1 - y) + x * w);
+    }
+
+    fn rotate_right(&mut self) {
+        let (w, h) = (self.width as usize, self.height as usize);
+        self.transpose(|x, y| y + (h - 1 - x) * w);
+    }
+
+    fn scale(&mut self, x_scale: f64, y_scale: f64) {
+        let r = self.metadata.get_roi_rect();
+        let x_center = r.x as f64 + r.width as f64 / 2.0;
+        let y_center = r.y as f64 + r.height as f64 / 2.0;
+        let (xmin, xmax) = (r.x as f64, (r.x + r.width - 1) as f64);
+        let (ymin, ymax) = (r.y as f64, (r.y + r.height - 1) as f64);
+        let check_coordinates = x_scale < 1.0 || y_scale < 1.0;
+        let method = self.get_interpolation_method();
+        self.map_geometry(|plane, x, y| {
+            let xs = (x as f64 - x_center) / x_scale + x_center;
+            let ys = (y as f64 - y_center) / y_scale + y_center;
+            if method == InterpolationMode::Bicubic {
+                plane.bicubic(xs, ys)
+            } else if check_coordinates && (xs < xmin || xs > xmax || ys < ymin || ys > ymax) {
+                plane.background
+            } else if method == InterpolationMode::Bilinear {
+                plane.bilinear(plane.clamp_x(xs), plane.clamp_y(ys))
+            } else {
+                plane.get(xs as i64, ys as i64)
+            }
+        });
+    }
+
+    fn translate(&mut self, x_offset: f64, y_offset: f64) {
+        // Integer offsets are plain shifts
+        let method = if x_offset.fract() == 0.0 && y_offset.fract() == 0.0 {
+            InterpolationMode::Nearest
+        } else {
+            self.get_interpolation_method()
+        };
+        self.map_geometry(|plane, x, y| {
+            plane.sample(x as f64 - x_offset, y as f64 - y_offset, method)
+        });
+    }
+
+    fn resize(&self, dst_width: u32, dst_height: u32) -> Self {
+        let r = self.metadata.get_roi_rect();
+        let src_center_x = r.x as f64 + r.width as f64 / 2.0;
+        let src_center_y = r.y as f64 + r.height as f64 / 2.0;
+        let mut dst_center_x = dst_width as f64 / 2.0;
+        let mut dst_center_y = dst_height as f64 / 2.0;
+        let x_scale = dst_width as f64 / r.width as f64;
+        let y_scale = dst_height as f64 / r.height as f64;
+        let method = self.get_interpolation_method();
+        if method != InterpolationMode::Nearest {
+            if dst_width != self.width {
+                dst_center_x += x_scale / 4.0;
+            }
+            if dst_height != self.height {
+                dst_center_y += y_scale / 4.0;
+            }
+        }
+        let mut data = Vec::<T>::with_capacity((dst_width * dst_height * self.depth) as usize);
+        for z in 0..self.depth as usize {
+            let plane = self.plane(z);
+            for y in 0..dst_height {
+                let ys = (y as f64 - dst_center_y) / y_scale + src_center_y;
+                for x in 0..dst_width {
+                    let xs = (x as f64 - dst_center_x) / x_scale + src_center_x;
+                    let value = match method {
+                        InterpolationMode::Nearest => plane.get(xs as i64, ys as i64),
+                        InterpolationMode::Bilinear => {
+                            plane.bilinear(plane.clamp_x(xs), plane.clamp_y(ys))
+                        }
+                        InterpolationMode::Bicubic => plane.bicubic(xs, ys),
+                    };
+                    data.push(T::round_pixel(value as f32));
+                }
+            }
+        }
+        let mut ip =
+            ImageProcessor::new_volume(dst_width, dst_height, self.depth, data, Gray::<T>::new());
+        ip.metadata = settings_of(&self.metadata, dst_width, dst_height);
+        ip
+    }
+}
+
+//
+// Private helpers
+//
+impl<T: PixelType> ImageProcessor<T, Gray<T>> {
+    fn plane(&self, z: usize) -> Plane<'_, T> {
+        let size = self.get_size();
+        Plane {
+            pixels: &self.data[z * size..(z + 1) * size],
+            width: self.width as i64,
+            height: self.height as i64,
+            background: self.metadata.get_background(),
+        }
+    }
 
-    /// Scales the image by the specified factors.
-    ///n scale_And_Set_Threshold​(f64 lower, f64 upper, i32 lut_Update) {}
-    ///et the threshold using a 0-255 range.
-    ///n set​(f64 value) {}
-    /// Assigns 'value' to each pixel in the image or ROI.
-    fn scale​(&self,f64 x_Scale, f64 y_Scale);
+    // Computes the value of each pixel of the ROI from the source plane
+    fn map_geometry<F: Fn(&Plane<T>, u32, u32) -> f64>(&mut self, f: F) {
+        let size = self.get_size();
+        let w = self.width as usize;
+        let indices = self.metadata.roi_indices();
+        for z in 0..self.depth as usize {
+            let values: Vec<T> = {
+                let plane = self.plane(z);
+                indices
+                    .iter()
+                    .map(|i| T::round_pixel(f(&plane, (i % w) as u32, (i / w) as u32) as f32))
+                    .collect()
+            };
+            for (i, v) in indices.iter().zip(values) {
+                self.data[z * size + i] = v;
+            }
+        }
+        self.metadata.set_dirty();
+    }
 
-    fn set_interpolation_method​( method: InterpolationMode);
-    /// Use this method to set the i32erpolation method (NONE, BILINEAR or BICUBIC) used by scale(), resize() and rotate().
+    // Copies (without interpolation) the source pixel `src(x, y)` in each pixel of the ROI
+    fn remap<F: Fn(i32, i32) -> (i32, i32)>(&mut self, src: F) {
+        let size = self.get_size();
+        let w = self.width as usize;
+        let indices = self.metadata.roi_indices();
+        for z in 0..self.depth as usize {
+            let values: Vec<T> = indices
+                .iter()
+                .map(|i| {
+                    let (sx, sy) = src((i % w) as i32, (i / w) as i32);
+                    self.data[z * size + sx as usize + sy as usize * w].to_value()
+                })
+                .collect();
+            for (i, v) in indices.iter().zip(values) {
+                self.data[z * size + i] = v;
+            }
+        }
+        self.metadata.set_dirty();
+    }
 
-    static fn set_use_bicubic​(bool b);
+    // Swaps width and height. `src(x, y)` is the index in the source plane of the pixel (x,y).
+    fn transpose<F: Fn(usize, usize) -> usize>(&mut self, src: F) {
+        let (w, h) = (self.height, self.width);
+        let size = self.get_size();
+        let mut data = Vec::<T>::with_capacity(self.data.len());
+        for z in 0..self.depth as usize {
+            for y in 0..h as usize {
+                for x in 0..w as usize {
+                    data.push(self.data[z * size + src(x, y)].to_value());
+                }
+            }
+        }
+        self.metadata = settings_of(&self.metadata, w, h);
+        self.width = w;
+        self.height = h;
+        self.data = data;
+    }
 
-    /// Moves the image or selection vertically or horizontally by a specified number of pixels.
-    fn translate​(&self,f64 x_offset, f64 y_offset);
+    // Enlarges the canvas to the bounding box of the image rotated by `angle`
+    fn enlarge_canvas(&mut self, angle: f64) {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let (sa, ca) = angle.to_radians().sin_cos();
+        let new_w = ((w * ca.abs() + h * sa.abs()).round() as u32).max(self.width);
+        let new_h = ((w * sa.abs() + h * ca.abs()).round() as u32).max(self.height);
+        let (dx, dy) = (
+            ((new_w - self.width) / 2) as usize,
+            ((new_h - self.height) / 2) as usize,
+        );
+        let size = self.get_size();
+        let mut data = Vec::<T>::with_capacity((new_w * new_h * self.depth) as usize);
+        for z in 0..self.depth as usize {
+            for y in 0..new_h as usize {
+                for x in 0..new_w as usize {
+                    let inside = x >= dx
+                        && x < dx + self.width as usize
+                        && y >= dy
+                        && y < dy + self.height as usize;
+                    data.push(if inside {
+                        self.data[z * size + (x - dx) + (y - dy) * self.width as usize].to_value()
+                    } else {
+                        T::round_pixel(self.metadata.get_background() as f32)
+                    });
+                }
+            }
+        }
+        self.metadata = settings_of(&self.metadata, new_w, new_h);
+        self.width = new_w;
+        self.height = new_h;
+        self.data = data;
+    }
+}
 
+// New metadata (without ROI) keeping the transform settings
+fn settings_of(metadata: &MetaData, w: u32, h: u32) -> MetaData {
+    let mut out = MetaData::new(w, h);
+    out.set_interpolation(metadata.get_interpolation());
+    out.set_background(metadata.get_background());
+    out
 }
 
-// Use of nalgebra
 //
+// One plane of the source image used for the interpolations
+//
+struct Plane<'a, T> {
+    pixels: &'a [T],
+    width: i64,
+    height: i64,
+    background: f64,
+}
+
+impl<'a, T: PixelType> Plane<'a, T> {
+    // Clamped to the image
+    fn get(&self, x: i64, y: i64) -> f64 {
+        let x = x.clamp(0, self.width - 1);
+        let y = y.clamp(0, self.height - 1);
+        self.pixels[(x + y * self.width) as usize].to_f32() as f64
+    }
+
+    fn clamp_x(&self, x: f64) -> f64 {
+        if x >= self.width as f64 - 1.0 {
+            self.width as f64 - 1.001
+        } else {
+            x.max(0.0)
+        }
+    }
+
+    fn clamp_y(&self, y: f64) -> f64 {
+        if y >= self.height as f64 - 1.0 {
+            self.height as f64 - 1.001
+        } else {
+            y.max(0.0)
+        }
+    }
+
+    // Bilinear interpolation without bounds checking
+    fn bilinear(&self, x: f64, y: f64) -> f64 {
+        let (xbase, ybase) = (x as i64, y as i64);
+        let (x_fraction, y_fraction) = (x - xbase as f64, y - ybase as f64);
+        let lower_left = self.get(xbase, ybase);
+        let lower_right = self.get(xbase + 1, ybase);
+        let upper_right = self.get(xbase + 1, ybase + 1);
+        let upper_left = self.get(xbase, ybase + 1);
+        let upper_average = upper_left + x_fraction * (upper_right - upper_left);
+        let lower_average = lower_left + x_fraction * (lower_right - lower_left);
+        lower_average + y_fraction * (upper_average - lower_average)
+    }
+
+    fn bilinear_or_background(&self, x: f64, y: f64) -> f64 {
+        if x >= -1.0 && x < self.width as f64 && y >= -1.0 && y < self.height as f64 {
+            self.bilinear(self.clamp_x(x), self.clamp_y(y))
+        } else {
+            self.background
+        }
+    }
+
+    fn bicubic(&self, x0: f64, y0: f64) -> f64 {
+        let u0 = x0.floor() as i64;
+        let v0 = y0.floor() as i64;
+        if u0 <= 0 || v0 <= 0 || u0 >= self.width - 2 || v0 >= self.height - 2 {
+            return self.bilinear_or_background(x0, y0);
+        }
+        let mut q = 0.0;
+        for j in 0..=3 {
+            let v = v0 - 1 + j;
+            let mut p = 0.0;
+            for i in 0..=3 {
+                let u = u0 - 1 + i;
+                p += self.get(u, v) * cubic(x0 - u as f64);
+            }
+            q += p * cubic(y0 - v as f64);
+        }
+        q
+    }
+
+    fn nearest(&self, x: f64, y: f64) -> f64 {
+        let (ix, iy) = ((x + 0.5).floor() as i64, (y + 0.5).floor() as i64);
+        if ix >= 0 && ix < self.width && iy >= 0 && iy < self.height {
+            self.get(ix, iy)
+        } else {
+            self.background
+        }
+    }
+
+    fn sample(&self, x: f64, y: f64, method: InterpolationMode) -> f64 {
+        match method {
+            InterpolationMode::Nearest => self.nearest(x, y),
+            InterpolationMode::Bilinear => self.bilinear_or_background(x, y),
+            InterpolationMode::Bicubic => self.bicubic(x, y),
+        }
+    }
+}
+
+///
+/// Geometric transforms of volumes (see [VolumeProcessor](crate::volume_processor::VolumeProcessor)).
+///
+/// The transforms are applied around the center of the volume, the voxel `(w/2, h/2, d/2)`.
+/// The interpolation method set by [set_interpolation_method()](Transform::set_interpolation_method)
+/// is used in 3D: `Bilinear` is trilinear and `Bicubic` is tricubic. The voxels mapped from
+/// outside of the volume are set to the background value.
+///
 pub trait Transform3D {
-  fn translate(tx:f32,ty: f32, tz: f32);
-  
-  fn rotate(rx: f32, ry:f32, rz: f32);
+    ///
+    /// Uses the current interpolation method to find the voxel value at real coordinates (x,y,z).
+    ///
+    fn get_interpolated_voxel(&self, x: f64, y: f64, z: f64) -> f64;
+
+    ///
+    /// Returns a new volume transformed by the affine matrix `mat`, stored column by column
+    /// like the matrices built with [Matrix4::translate] and [Matrix4::rotate_z] (the
+    /// translation is in the elements 12, 13 and 14).
+    ///
+    fn transform_3d(&self, mat: &Matrix4) -> Self;
+
+    ///
+    /// Returns a new volume rotated by the Euler angles (in degrees) of the ZYZ convention
+    /// of RELION: `psi` is the in-plane rotation, `theta` the tilt and `phi` the first rotation.
+    ///
+    fn rotate_euler(&self, psi: f64, theta: f64, phi: f64) -> Self;
+
+    /// Returns a new volume moved by a (possibly subvoxel) offset.
+    fn translate_3d(&self, tx: f64, ty: f64, tz: f64) -> Self;
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::grayscale::{Gray32, Gray8};
+    use crate::roi::Roi;
+
+    fn ramp(w: u32, h: u32) -> ImageProcessor<u8, Gray8> {
+        let pixels = (0..w * h).map(|i| i as u8).collect();
+        ImageProcessor::new(w, h, pixels, Gray8::new())
+    }
+
+    #[test]
+    fn cubic_kernel() {
+        assert_eq!(cubic(0.0), 1.0);
+        assert_eq!(cubic(1.0), 0.0);
+        assert_eq!(cubic(-2.0), 0.0);
+        assert_eq!(cubic(0.5), 0.5625);
+        assert_eq!(cubic(-1.5), -0.0625);
+    }
+
+    #[test]
+    fn flips() {
+        let mut ip = ramp(3, 2);
+        ip.flip_horizontal();
+        assert_eq!(*ip.data(), vec![2, 1, 0, 5, 4, 3]);
+        ip.flip_vertical();
+        assert_eq!(*ip.data(), vec![5, 4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn flip_in_roi() {
+        let mut ip = ramp(4, 1);
+        ip.set_roi(Roi::rectangle(1.0, 0.0, 3.0, 1.0));
+        ip.flip_horizontal();
+        assert_eq!(*ip.data(), vec![0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn rotate_90_is_exact() {
+        for method in [InterpolationMode::Nearest, InterpolationMode::Bilinear] {
+            let mut ip = ramp(3, 3);
+            let mut expected = ramp(3, 3);
+            expected.rotate_right();
+            ip.set_interpolation_method(method);
+            ip.rotate(90.0, Canvas::Keep);
+            assert_eq!(ip.data(), expected.data(), "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn rotate_left_right() {
+        let mut ip = ramp(3, 2);
+        ip.rotate_right();
+        assert_eq!((ip.get_width(), ip.get_height()), (2, 3));
+        assert_eq!(*ip.data(), vec![3, 0, 4, 1, 5, 2]);
+        ip.rotate_left();
+        assert_eq!(*ip.data(), *ramp(3, 2).data());
+    }
+
+    #[test]
+    fn rotate_enlarge_canvas() {
+        let mut ip = ImageProcessor::<u8, Gray8>::new(4, 2, vec![9; 8], Gray8::new());
+        ip.set_background_value(1.0);
+        ip.set_interpolation_method(InterpolationMode::Nearest);
+        ip.rotate(90.0, Canvas::Enlarge);
+        assert_eq!((ip.get_width(), ip.get_height()), (4, 4));
+        let expected = vec![1, 9, 9, 1];
+        for y in 0..4 {
+            assert_eq!(ip.data()[y * 4..(y + 1) * 4], expected);
+        }
+    }
+
+    #[test]
+    fn translate_with_background() {
+        let mut ip = ramp(3, 1);
+        ip.set_background_value(50.0);
+        ip.translate(1.0, 0.0);
+        assert_eq!(*ip.data(), vec![50, 0, 1]);
+
+        let mut ip = ImageProcessor::<f32, Gray32>::new(3, 1, vec![0.0, 2.0, 4.0], Gray32::new());
+        ip.translate(0.5, 0.0);
+        assert_eq!(ip.data()[1..], [1.0, 3.0]);
+    }
 
-  fn apply_transform(mat: Vec<f32>);
+    #[test]
+    fn scale_down_fills_background() {
+        let mut ip = ImageProcessor::<u8, Gray8>::new(4, 4, vec![100; 16], Gray8::new());
+        ip.set_interpolation_method(InterpolationMode::Nearest);
+        ip.scale(0.5, 0.5);
+        let expected = vec![
+            0, 0, 0, 0, //
+            0, 100, 100, 0, //
+            0, 100, 100, 0, //
+            0, 0, 0, 0,
+        ];
+        assert_eq!(*ip.data(), expected);
+    }
 
+    #[test]
+    fn resize_nearest() {
+        let mut ip = ramp(2, 2);
+        ip.set_interpolation_method(InterpolationMode::Nearest);
+        let big = ip.resize(4, 4);
+        let expected = vec![0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 3, 3, 2, 2, 3, 3];
+        assert_eq!(*big.data(), expected);
+        assert_eq!(big.get_interpolation_method(), InterpolationMode::Nearest);
+    }
 
+    #[test]
+    fn interpolated_pixels() {
+        let pixels = (0..36).map(|i| (i % 6) as f32).collect();
+        let mut ip = ImageProcessor::<f32, Gray32>::new(6, 6, pixels, Gray32::new());
+        assert_eq!(ip.get_interpolated_pixel(0.5, 0.5), 0.5);
+        // Catmull-Rom reproduces a linear ramp
+        assert!((ip.get_bicubic_interpolated_pixel(2.25, 2.5) - 2.25).abs() < 1e-9);
+        ip.set_interpolation_method(InterpolationMode::Nearest);
+        assert_eq!(ip.get_interpolated_pixel(1.6, 0.0), 2.0);
+        assert_eq!(ip.get_interpolated_pixel(-3.0, 0.0), 0.0);
+    }
 }
- 
@@ -17,6 +17,9 @@
 //  You should have received a copy of the GNU General Public License
 //  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::vecmath::vector3::Vector3;
+use std::ops::Mul;
+
 /// Adapted from JS package
 /// https://github.com/mattdesl/vecmath
 
@@ -68,6 +71,31 @@ impl Matrix3 {
         Matrix3 { val: out }
     }
 
+    ///
+    /// Create the rotation matrix of the Euler angles (in radians) `alpha`, `beta`, `gamma`
+    /// following the ZYZ convention of Xmipp/RELION (`Euler_angles2matrix`).
+    ///
+    pub fn from_euler_zyz(alpha: f64, beta: f64, gamma: f64) -> Self {
+        let (sa, ca) = alpha.sin_cos();
+        let (sb, cb) = beta.sin_cos();
+        let (sg, cg) = gamma.sin_cos();
+        let cc = cb * ca;
+        let cs = cb * sa;
+        let sc = sb * ca;
+        let ss = sb * sa;
+        Matrix3::from_array([
+            cg * cc - sg * sa,
+            cg * cs + sg * ca,
+            -cg * sb,
+            -sg * cc - cg * sa,
+            -sg * cs + cg * ca,
+            sg * sb,
+            sc,
+            ss,
+            cb,
+        ])
+    }
+
     ///
     ///
     ///
@@ -81,4 +109,41 @@ impl Matrix3 {
     pub fn values(&self) -> &[f64; 9] {
         &self.val
     }
+
+    ///
+    /// Transpose
+    ///
+    pub fn transpose(&mut self) -> &mut Self {
+        self.val.swap(1, 3);
+        self.val.swap(2, 6);
+        self.val.swap(5, 7);
+        self
+    }
+
+    ///
+    /// Returns the product of this matrix (row-major) by the column vector `v`.
+    ///
+    pub fn mul_vector(&self, v: &Vector3) -> Vector3 {
+        let m = self.val;
+        Vector3::new(
+            m[0] * v.x + m[1] * v.y + m[2] * v.z,
+            m[3] * v.x + m[4] * v.y + m[5] * v.z,
+            m[6] * v.x + m[7] * v.y + m[8] * v.z,
+        )
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let (a, b) = (self.val, rhs.val);
+        let mut out = [0.0f64; 9];
+        for i in 0..3 {
+            for j in 0..3 {
+                out[i * 3 + j] = a[i * 3] * b[j] + a[i * 3 + 1] * b[3 + j] + a[i * 3 + 2] * b[6 + j];
+            }
+        }
+        Matrix3 { val: out }
+    }
 }
@@ -1,4 +1,5 @@
 pub mod matrix3;
 pub mod matrix4;
+pub mod quaternion;
 pub mod vector3;
 pub mod vector4;
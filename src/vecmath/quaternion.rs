@@ -0,0 +1,120 @@
+//
+//  RIM - Rust Image
+//  Copyright (C) 2022  Jean-Christophe Taveau.
+//
+//  This file is part of RIM
+//
+// This program is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+//  You should have received a copy of the GNU General Public License
+//  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::vecmath::matrix3::Matrix3;
+
+///
+/// Unit quaternion `w + xi + yj + zk` representing a 3D rotation.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub w: f64,
+}
+
+impl Quaternion {
+    pub fn new(x: f64, y: f64, z: f64, w: f64) -> Self {
+        Quaternion { x, y, z, w }
+    }
+
+    pub fn identity() -> Self {
+        Quaternion::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    pub fn length(&self) -> f64 {
+        (self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w).sqrt()
+    }
+
+    pub fn normalize(&mut self) -> &mut Self {
+        let len = self.length();
+        if len > 0.0 {
+            self.x /= len;
+            self.y /= len;
+            self.z /= len;
+            self.w /= len;
+        }
+        self
+    }
+
+    ///
+    /// Create the quaternion of a rotation matrix (row-major).
+    /// The scalar part `w` is always positive.
+    ///
+    pub fn from_matrix3(mat: &Matrix3) -> Self {
+        let m = mat.values();
+        let trace = m[0] + m[4] + m[8];
+        let mut q = if trace > 0.0 {
+            let s = 0.5 / (trace + 1.0).sqrt();
+            Quaternion::new(
+                (m[7] - m[5]) * s,
+                (m[2] - m[6]) * s,
+                (m[3] - m[1]) * s,
+                0.25 / s,
+            )
+        } else if m[0] > m[4] && m[0] > m[8] {
+            let s = 2.0 * (1.0 + m[0] - m[4] - m[8]).sqrt();
+            Quaternion::new(
+                0.25 * s,
+                (m[1] + m[3]) / s,
+                (m[2] + m[6]) / s,
+                (m[7] - m[5]) / s,
+            )
+        } else if m[4] > m[8] {
+            let s = 2.0 * (1.0 + m[4] - m[0] - m[8]).sqrt();
+            Quaternion::new(
+                (m[1] + m[3]) / s,
+                0.25 * s,
+                (m[5] + m[7]) / s,
+                (m[2] - m[6]) / s,
+            )
+        } else {
+            let s = 2.0 * (1.0 + m[8] - m[0] - m[4]).sqrt();
+            Quaternion::new(
+                (m[2] + m[6]) / s,
+                (m[5] + m[7]) / s,
+                0.25 * s,
+                (m[3] - m[1]) / s,
+            )
+        };
+        if q.w < 0.0 {
+            q = Quaternion::new(-q.x, -q.y, -q.z, -q.w);
+        }
+        *q.normalize()
+    }
+
+    ///
+    /// Returns the rotation matrix (row-major) of this unit quaternion.
+    ///
+    pub fn to_matrix3(&self) -> Matrix3 {
+        let (x, y, z, w) = (self.x, self.y, self.z, self.w);
+        Matrix3::from_array([
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - z * w),
+            2.0 * (x * z + y * w),
+            2.0 * (x * y + z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - x * w),
+            2.0 * (x * z - y * w),
+            2.0 * (y * z + x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ])
+    }
+}
@@ -16,11 +16,248 @@
 //
 //  You should have received a copy of the GNU General Public License
 //  along with RIM.  If not, see <http://www.gnu.org/licenses/>.
- 
- 
- 
- 
-/// VolumeProcessor is an ImageProcesssor only supporting 3D operations...
 
+use crate::color_space::ColorSpace;
+use crate::grayscale::Gray;
+use crate::image_processor::ImageProcessor;
+use crate::pixel::PixelType;
+use crate::transformable::{cubic, InterpolationMode, Transform3D};
+use crate::vecmath::matrix3::Matrix3;
+use crate::vecmath::matrix4::Matrix4;
+use crate::vecmath::vector3::Vector3;
 
-impl Transformable3D for VolumeProcessor
+///
+/// VolumeProcessor is an ImageProcessor created with [ImageProcessor::new_volume()]
+/// supporting 3D operations.
+///
+/// # Example
+///
+/// ```rust
+/// use rim::color_space::ColorSpace;
+/// use rim::grayscale::Gray32;
+/// use rim::transformable::Transform3D;
+/// use rim::volume_processor::VolumeProcessor;
+///
+/// let mut voxels = vec![0.0f32; 27];
+/// voxels[2 + 3 + 9] = 1.0;
+/// let vol = VolumeProcessor::new_volume(3, 3, 3, voxels, Gray32::new());
+/// let rotated = vol.rotate_euler(90.0, 0.0, 0.0);
+/// assert_eq!(rotated.data()[1 + 9], 1.0);
+/// ```
+pub type VolumeProcessor<T> = ImageProcessor<T, Gray<T>>;
+
+impl<T: PixelType> Transform3D for VolumeProcessor<T> {
+    fn get_interpolated_voxel(&self, x: f64, y: f64, z: f64) -> f64 {
+        let method = self.metadata.get_interpolation();
+        self.sample(x, y, z, method, self.metadata.get_background())
+    }
+
+    fn transform_3d(&self, mat: &Matrix4) -> Self {
+        // Each output voxel is fetched from the inverse transform. The matrix is stored
+        // column by column like the ones built by `Matrix4::translate` and `rotate_*`.
+        let mut inv = mat.build();
+        inv.invert();
+        let m = inv.values();
+        let (cx, cy, cz) = (
+            (self.width / 2) as f64,
+            (self.height / 2) as f64,
+            (self.depth / 2) as f64,
+        );
+        let method = self.metadata.get_interpolation();
+        let background = self.metadata.get_background();
+        let mut data = Vec::<T>::with_capacity(self.data.len());
+        for z in 0..self.depth {
+            let dz = z as f64 - cz;
+            for y in 0..self.height {
+                let dy = y as f64 - cy;
+                for x in 0..self.width {
+                    let dx = x as f64 - cx;
+                    let xs = m[0] * dx + m[4] * dy + m[8] * dz + m[12] + cx;
+                    let ys = m[1] * dx + m[5] * dy + m[9] * dz + m[13] + cy;
+                    let zs = m[2] * dx + m[6] * dy + m[10] * dz + m[14] + cz;
+                    let value = self.sample(xs, ys, zs, method, background);
+                    data.push(T::round_pixel(value as f32));
+                }
+            }
+        }
+        let mut vol =
+            ImageProcessor::new_volume(self.width, self.height, self.depth, data, Gray::<T>::new());
+        vol.metadata.set_interpolation(method);
+        vol.metadata.set_background(background);
+        vol
+    }
+
+    fn rotate_euler(&self, psi: f64, theta: f64, phi: f64) -> Self {
+        let r = Matrix3::from_euler_zyz(phi.to_radians(), theta.to_radians(), psi.to_radians());
+        let a = r.values();
+        self.transform_3d(&Matrix4::from_array([
+            a[0], a[3], a[6], 0.0, //
+            a[1], a[4], a[7], 0.0, //
+            a[2], a[5], a[8], 0.0, //
+            0.0, 0.0, 0.0, 1.0,
+        ]))
+    }
+
+    fn translate_3d(&self, tx: f64, ty: f64, tz: f64) -> Self {
+        let mut mat = Matrix4::identity();
+        mat.translate(Vector3::new(tx, ty, tz));
+        self.transform_3d(&mat)
+    }
+}
+
+//
+// Private interpolations
+//
+impl<T: PixelType> VolumeProcessor<T> {
+    // Voxel value or `background` outside of the volume
+    pub(crate) fn voxel(&self, x: i64, y: i64, z: i64, background: f64) -> f64 {
+        let (w, h, d) = (self.width as i64, self.height as i64, self.depth as i64);
+        if x < 0 || x >= w || y < 0 || y >= h || z < 0 || z >= d {
+            background
+        } else {
+            self.data[(x + y * w + z * w * h) as usize].to_f32() as f64
+        }
+    }
+
+    fn sample(&self, x: f64, y: f64, z: f64, method: InterpolationMode, background: f64) -> f64 {
+        match method {
+            InterpolationMode::Nearest => self.voxel(
+                (x + 0.5).floor() as i64,
+                (y + 0.5).floor() as i64,
+                (z + 0.5).floor() as i64,
+                background,
+            ),
+            InterpolationMode::Bilinear => self.trilinear(x, y, z, background),
+            InterpolationMode::Bicubic => self.tricubic(x, y, z, background),
+        }
+    }
+
+    pub(crate) fn trilinear(&self, x: f64, y: f64, z: f64, background: f64) -> f64 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (ix, iy, iz) = (x0 as i64, y0 as i64, z0 as i64);
+        let mut value = 0.0;
+        for (k, wz) in [(0, 1.0 - fz), (1, fz)] {
+            for (j, wy) in [(0, 1.0 - fy), (1, fy)] {
+                for (i, wx) in [(0, 1.0 - fx), (1, fx)] {
+                    let weight = wx * wy * wz;
+                    if weight != 0.0 {
+                        value += weight * self.voxel(ix + i, iy + j, iz + k, background);
+                    }
+                }
+            }
+        }
+        value
+    }
+
+    // Catmull-Rom kernel of ImageJ, trilinear near the borders
+    fn tricubic(&self, x: f64, y: f64, z: f64, background: f64) -> f64 {
+        let (u0, v0, w0) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+        let (w, h, d) = (self.width as i64, self.height as i64, self.depth as i64);
+        if u0 <= 0 || v0 <= 0 || w0 <= 0 || u0 >= w - 2 || v0 >= h - 2 || w0 >= d - 2 {
+            return self.trilinear(x, y, z, background);
+        }
+        let mut r = 0.0;
+        for k in 0..=3 {
+            let zz = w0 - 1 + k;
+            let mut q = 0.0;
+            for j in 0..=3 {
+                let yy = v0 - 1 + j;
+                let mut p = 0.0;
+                for i in 0..=3 {
+                    let xx = u0 - 1 + i;
+                    p += self.voxel(xx, yy, zz, background) * cubic(x - xx as f64);
+                }
+                q += p * cubic(y - yy as f64);
+            }
+            r += q * cubic(z - zz as f64);
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::grayscale::Gray32;
+    use crate::transformable::Transform;
+
+    fn volume(w: u32, h: u32, d: u32, f: fn(u32, u32, u32) -> f32) -> VolumeProcessor<f32> {
+        let mut voxels = Vec::<f32>::new();
+        for z in 0..d {
+            for y in 0..h {
+                for x in 0..w {
+                    voxels.push(f(x, y, z));
+                }
+            }
+        }
+        VolumeProcessor::new_volume(w, h, d, voxels, Gray32::new())
+    }
+
+    #[test]
+    fn identity() {
+        let vol = volume(4, 3, 2, |x, y, z| (x + 4 * y + 12 * z) as f32);
+        let out = vol.transform_3d(&Matrix4::identity());
+        assert_eq!(out.data(), vol.data());
+        assert_eq!((out.width, out.height, out.depth), (4, 3, 2));
+    }
+
+    #[test]
+    fn rotate_quarter_turns() {
+        let vol = volume(
+            3,
+            3,
+            3,
+            |x, y, z| if (x, y, z) == (2, 1, 1) { 100.0 } else { 0.0 },
+        );
+        // In-plane rotation
+        let out = vol.rotate_euler(90.0, 0.0, 0.0);
+        assert_eq!(out.data()[1 + 9], 100.0);
+        // Tilt around Y
+        let out = vol.rotate_euler(0.0, 90.0, 0.0);
+        assert_eq!(out.data()[1 + 3 + 2 * 9], 100.0);
+        assert_eq!(out.data().iter().sum::<f32>(), 100.0);
+    }
+
+    #[test]
+    fn translations() {
+        let vol = volume(4, 1, 1, |x, _, _| x as f32);
+        let mut out = vol.translate_3d(1.0, 0.0, 0.0);
+        assert_eq!(*out.data(), vec![0.0, 0.0, 1.0, 2.0]);
+        out = vol.translate_3d(0.5, 0.0, 0.0);
+        assert_eq!(out.data()[1..], [0.5, 1.5, 2.5]);
+    }
+
+    #[test]
+    fn matrices_built_with_matrix4() {
+        let vol = volume(
+            5,
+            5,
+            5,
+            |x, y, z| if (x, y, z) == (3, 2, 2) { 100.0 } else { 0.0 },
+        );
+        let mut mat = Matrix4::identity();
+        mat.translate(Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(vol.transform_3d(&mat).data()[4 + 2 * 5 + 2 * 25], 100.0);
+        // Quarter turn around Z: (1, 0, 0) from the center moves to (0, 1, 0)
+        let mut mat = Matrix4::identity();
+        mat.rotate_z(std::f64::consts::FRAC_PI_2);
+        assert_eq!(vol.transform_3d(&mat).data()[2 + 3 * 5 + 2 * 25], 100.0);
+        // Rotation then translation
+        let mut mat = Matrix4::identity();
+        mat.translate(Vector3::new(0.0, 1.0, 0.0));
+        mat.rotate_z(std::f64::consts::FRAC_PI_2);
+        let out = vol.transform_3d(&mat);
+        assert_eq!(out.data()[2 + 4 * 5 + 2 * 25], 100.0);
+        assert_eq!(out.data().iter().sum::<f32>(), 100.0);
+    }
+
+    #[test]
+    fn tricubic_reproduces_linear_ramp() {
+        let mut vol = volume(6, 6, 6, |x, y, z| (x + 2 * y + 3 * z) as f32);
+        vol.set_interpolation_method(InterpolationMode::Bicubic);
+        let v = vol.get_interpolated_voxel(2.25, 2.5, 2.75);
+        assert!((v - (2.25 + 5.0 + 8.25)).abs() < 1e-9);
+    }
+}